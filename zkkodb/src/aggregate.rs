@@ -0,0 +1,138 @@
+use std::collections::{BTreeMap, HashMap};
+use serde_json::Value;
+
+use crate::database::{ExecError, Key, Row};
+use crate::parser::{AggregateFunction, AggregateSpec, Collation};
+use crate::result::ColumnType;
+use crate::value::Value as Typed;
+
+impl AggregateSpec {
+    pub fn output_name(&self) -> String {
+        if let Some(alias) = &self.alias {
+            return alias.clone();
+        }
+        let function = match self.function {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Avg => "avg",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+        };
+        format!("{}({})", function, self.column.as_deref().unwrap_or("*"))
+    }
+}
+
+// partitions rows by the group_by columns and computes every aggregate per group.
+// groups come out sorted by their grouping values; without group_by all rows form one group.
+// values are read through `types`, the column types, so a sum over an int column
+// is an int and one over a float column a float. min and max of text follow
+// the column's collation; grouping goes by bytes
+pub(crate) fn aggregate(
+    rows: Vec<Row>,
+    group_by: &[String],
+    specs: &[AggregateSpec],
+    types: &HashMap<String, ColumnType>,
+    collations: &HashMap<String, Collation>,
+) -> Result<Vec<Row>, ExecError> {
+    let mut groups: BTreeMap<Vec<Key>, Vec<Row>> = BTreeMap::new();
+    if group_by.is_empty() {
+        groups.insert(Vec::new(), rows);
+    } else {
+        for row in rows {
+            let key = group_by
+                .iter()
+                .map(|column| Key(row.get(column).cloned().unwrap_or(Value::Null)))
+                .collect();
+            groups.entry(key).or_default().push(row);
+        }
+    }
+
+    let mut results = Vec::with_capacity(groups.len());
+    for (key, members) in groups {
+        let mut result: Row = group_by
+            .iter()
+            .cloned()
+            .zip(key.into_iter().map(|key| key.0))
+            .collect();
+        for spec in specs {
+            result.insert(spec.output_name(), compute(spec, &members, types, collations)?.to_json());
+        }
+        results.push(result);
+    }
+    Ok(results)
+}
+
+pub(crate) fn check_spec(spec: &AggregateSpec) -> Result<(), ExecError> {
+    if spec.column.is_none() && spec.function != AggregateFunction::Count {
+        return Err(ExecError::InvalidQuery(format!(
+            "aggregate '{}' needs a column",
+            spec.output_name()
+        )));
+    }
+    Ok(())
+}
+
+fn compute(
+    spec: &AggregateSpec,
+    rows: &[Row],
+    types: &HashMap<String, ColumnType>,
+    collations: &HashMap<String, Collation>,
+) -> Result<Typed, ExecError> {
+    let column = match &spec.column {
+        Some(column) => column,
+        None => return Ok(Typed::Int(rows.len() as i64)),
+    };
+    let typed = |json: &Value| match types.get(column) {
+        Some(col_type) => Typed::from_json(*col_type, json).unwrap_or_else(|| Typed::infer(json)),
+        None => Typed::infer(json),
+    };
+    let collation = collations.get(column).copied().unwrap_or_default();
+    let collated = |a: &Typed, b: &Typed| match (a, b) {
+        (Typed::Text(a), Typed::Text(b)) => collation.compare(a, b),
+        _ => a.cmp(b),
+    };
+    // nulls never take part in an aggregate
+    let values: Vec<Typed> = rows
+        .iter()
+        .filter_map(|row| row.get(column))
+        .map(typed)
+        .filter(|value| !value.is_null())
+        .collect();
+
+    match spec.function {
+        AggregateFunction::Count => Ok(Typed::Int(values.len() as i64)),
+        AggregateFunction::Sum | AggregateFunction::Avg => {
+            if values.is_empty() {
+                return Ok(Typed::Null);
+            }
+            // stays an int sum while every value is an int and it doesn't overflow
+            let mut int_sum = Some(0i64);
+            let mut float_sum = 0.0;
+            for value in &values {
+                match value {
+                    Typed::Int(i) => {
+                        int_sum = int_sum.and_then(|sum| sum.checked_add(*i));
+                        float_sum += *i as f64;
+                    }
+                    Typed::Float(f) => {
+                        int_sum = None;
+                        float_sum += f;
+                    }
+                    _ => {
+                        return Err(ExecError::TypeMismatch {
+                            column: column.clone(),
+                            expected: "number".to_string(),
+                        })
+                    }
+                }
+            }
+            Ok(match (spec.function, int_sum) {
+                (AggregateFunction::Sum, Some(sum)) => Typed::Int(sum),
+                (AggregateFunction::Sum, None) => Typed::Float(float_sum),
+                _ => Typed::Float(float_sum / values.len() as f64),
+            })
+        }
+        AggregateFunction::Min => Ok(values.into_iter().min_by(|a, b| collated(a, b)).unwrap_or(Typed::Null)),
+        AggregateFunction::Max => Ok(values.into_iter().max_by(|a, b| collated(a, b)).unwrap_or(Typed::Null)),
+    }
+}
@@ -0,0 +1,103 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Bound;
+
+use crate::codec::Codec;
+use crate::database::{Database, Key, Row};
+use crate::idempotency::SeenKeys;
+
+// where the rows of a table with the `rows` layout live, one store per table.
+// the engine keeps indexes, unique values and ttl stamps itself and only asks
+// the store for rows by primary key. columnar tables always use the built-in
+// column store. a clone holds the same rows, and later writes to either don't
+// reach the other, for a table written while a snapshot or transaction shares it
+pub trait StorageBackend: fmt::Debug + Clone + Send + Sync + 'static {
+    fn get(&self, key: &Key) -> Option<Cow<'_, Row>>;
+
+    // inserts the row or replaces the one with the same key
+    fn put(&mut self, key: Key, row: Row);
+
+    fn delete(&mut self, key: &Key);
+
+    // rows in primary key order, only those whose key sorts after `after`
+    // when it is given
+    fn scan<'a>(&'a self, after: Option<&Key>) -> Box<dyn Iterator<Item = (&'a Key, Cow<'a, Row>)> + 'a>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // an empty store of the same kind, for a new or rebuilt table
+    fn empty(&self) -> Self;
+}
+
+// the built-in store: rows in a map ordered by key
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    rows: BTreeMap<Key, Row>,
+}
+
+impl MemoryBackend {
+    pub const fn new() -> MemoryBackend {
+        MemoryBackend { rows: BTreeMap::new() }
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn get(&self, key: &Key) -> Option<Cow<'_, Row>> {
+        self.rows.get(key).map(Cow::Borrowed)
+    }
+
+    fn put(&mut self, key: Key, row: Row) {
+        self.rows.insert(key, row);
+    }
+
+    fn delete(&mut self, key: &Key) {
+        self.rows.remove(key);
+    }
+
+    fn scan<'a>(&'a self, after: Option<&Key>) -> Box<dyn Iterator<Item = (&'a Key, Cow<'a, Row>)> + 'a> {
+        let rows = match after {
+            Some(after) => self.rows.range::<Key, _>((Bound::Excluded(after), Bound::Unbounded)),
+            None => self.rows.range::<Key, _>(..),
+        };
+        Box::new(rows.map(|(key, row)| (key, Cow::Borrowed(row))))
+    }
+
+    fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn empty(&self) -> MemoryBackend {
+        MemoryBackend::new()
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    // an empty database whose tables keep their rows in stores made by
+    // `backend`, see `open_with_backend` for one stored in a directory
+    pub fn with_backend(backend: B) -> Database<B> {
+        Database {
+            tables: HashMap::new(),
+            views: HashMap::new(),
+            subscribers: HashMap::new(),
+            wal: None,
+            command_log: None,
+            max_rows: None,
+            buffered_events: None,
+            metrics: None,
+            read_only: false,
+            idempotency: SeenKeys::default(),
+            backend,
+            encryption: Codec::default(),
+        }
+    }
+
+    // what new tables store their rows in
+    pub(crate) fn backend(&self) -> &B {
+        &self.backend
+    }
+}
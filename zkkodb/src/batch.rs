@@ -0,0 +1,79 @@
+use serde::{Serialize, Serializer};
+
+use crate::backend::StorageBackend;
+use crate::database::{Database, ExecError, Output};
+use crate::parser::Command;
+
+impl<B: StorageBackend> Database<B> {
+    // runs the commands of a batch in order. without `atomic` every command
+    // runs whatever the others do and the batch returns each one's result; an
+    // atomic batch stops at the first failure and takes everything before it
+    // back, subscribers only hearing of its changes once all of them applied
+    pub(crate) fn apply_batch(&mut self, commands: Vec<Command>, atomic: bool) -> Result<Output, ExecError> {
+        if !atomic {
+            return Ok(Output::Batch(commands.into_iter().map(|cmd| self.apply(cmd)).collect()));
+        }
+        let (tables, views, idempotency) = (self.tables.clone(), self.views.clone(), self.idempotency.clone());
+        // in a transaction events are buffered already, up to this length
+        let buffered = self.buffered_events.as_ref().map(Vec::len);
+        self.buffered_events.get_or_insert_with(Vec::new);
+
+        let mut results = Vec::with_capacity(commands.len());
+        for (index, cmd) in commands.into_iter().enumerate() {
+            match self.apply(cmd) {
+                Ok(output) => results.push(Ok(output)),
+                Err(error) => {
+                    self.tables = tables;
+                    self.views = views;
+                    self.idempotency = idempotency;
+                    match buffered {
+                        Some(len) => self.buffered_events.iter_mut().for_each(|events| events.truncate(len)),
+                        None => self.buffered_events = None,
+                    }
+                    return Err(ExecError::Batch { index, error: Box::new(error) });
+                }
+            }
+        }
+        if buffered.is_none() {
+            for event in self.buffered_events.take().unwrap_or_default() {
+                self.notify(event.kind, &event.table, event.keys);
+            }
+        }
+        Ok(Output::Batch(results))
+    }
+
+    // a batch of commands that don't mutate, through a shared reference
+    pub(crate) fn query_batch(
+        &self,
+        commands: Vec<Command>,
+        atomic: bool,
+        max_rows: Option<usize>,
+    ) -> Result<Output, ExecError> {
+        let mut results = Vec::with_capacity(commands.len());
+        for (index, cmd) in commands.into_iter().enumerate() {
+            match self.query_capped(cmd, max_rows) {
+                Err(error) if atomic => return Err(ExecError::Batch { index, error: Box::new(error) }),
+                result => results.push(result),
+            }
+        }
+        Ok(Output::Batch(results))
+    }
+}
+
+// each result as its output, or as {"error": "..."} the way the server reports
+// a failed command
+pub(crate) fn serialize_results<S: Serializer>(
+    results: &[Result<Output, ExecError>],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    #[serde(untagged)]
+    enum Outcome<'a> {
+        Done(&'a Output),
+        Failed { error: String },
+    }
+    serializer.collect_seq(results.iter().map(|result| match result {
+        Ok(output) => Outcome::Done(output),
+        Err(err) => Outcome::Failed { error: err.to_string() },
+    }))
+}
@@ -0,0 +1,175 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::backend::StorageBackend;
+use crate::database::{Database, ExecError, Output};
+use crate::parser::Command;
+use crate::users;
+use crate::utils::Stamp;
+
+// cancels a command run with `execute_cancellable` or `query_cancellable`,
+// from any thread; clones share the signal. scans check it as they go, and a
+// cancelled command fails with `Cancelled` and changes nothing
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    signal: Arc<Signal>,
+}
+
+#[derive(Debug, Default)]
+struct Signal {
+    cancelled: AtomicBool,
+    deadline: Option<Instant>,
+    // how often a scan looked at the token
+    checks: AtomicUsize,
+}
+
+thread_local! {
+    // the token of the command running on this thread
+    static ACTIVE: RefCell<Option<CancelToken>> = const { RefCell::new(None) };
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    // a token that also cancels itself once `timeout` has passed
+    pub fn with_timeout(timeout: Duration) -> CancelToken {
+        CancelToken {
+            signal: Arc::new(Signal {
+                deadline: Some(Instant::now() + timeout),
+                ..Signal::default()
+            }),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.signal.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.signal.cancelled.load(Ordering::Relaxed)
+            || self.signal.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // how many times scans looked at the token, a rough measure of progress
+    pub fn checks(&self) -> usize {
+        self.signal.checks.load(Ordering::Relaxed)
+    }
+
+    fn check(&self) -> Result<(), ExecError> {
+        match self.is_cancelled() {
+            true => Err(ExecError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+// makes `token` the one `requested` sees on this thread until the guard drops
+struct Active {
+    previous: Option<CancelToken>,
+}
+
+fn activate(token: &CancelToken) -> Active {
+    let previous = ACTIVE.with(|active| active.borrow_mut().replace(token.clone()));
+    Active { previous }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| *active.borrow_mut() = self.previous.take());
+    }
+}
+
+// whether the command running on this thread was cancelled. scans stop once
+// it is; the command then fails, so a cut-off scan is never used
+pub(crate) fn requested() -> bool {
+    ACTIVE.with(|active| {
+        active.borrow().as_ref().is_some_and(|token| {
+            token.signal.checks.fetch_add(1, Ordering::Relaxed);
+            token.is_cancelled()
+        })
+    })
+}
+
+// fails with `Cancelled` before a command writes what a cut-off scan found
+pub(crate) fn check() -> Result<(), ExecError> {
+    match requested() {
+        true => Err(ExecError::Cancelled),
+        false => Ok(()),
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    // like `execute`, failing with `Cancelled` once `token` is cancelled. a
+    // cancelled or failed mutation leaves every table and idempotency key as
+    // it was, subscribers only hear of its changes once it ran to completion,
+    // and it is only written to the write-ahead log then, so a cancelled
+    // command is never replayed
+    pub fn execute_cancellable(&mut self, cmd: Command, token: &CancelToken) -> Result<Output, ExecError> {
+        token.check()?;
+        let _active = activate(token);
+        if !cmd.is_mutating() {
+            let output = self.execute(cmd)?;
+            token.check()?;
+            return Ok(output);
+        }
+        if self.read_only {
+            return Err(ExecError::ReadOnly);
+        }
+        let cmd = self.resolve_defaults(users::hash_passwords(cmd)?)?;
+        let timer = self.start_timer(&cmd);
+        let (tables, views, idempotency) = (self.tables.clone(), self.views.clone(), self.idempotency.clone());
+        // in a transaction events are buffered already, up to this length
+        let buffered = self.buffered_events.as_ref().map(Vec::len);
+        self.buffered_events.get_or_insert_with(Vec::new);
+        let stamp = Stamp::now();
+        let logged = cmd.clone();
+        // logged before it applies like `log_and_apply`, and taken back when it fails
+        let result = match &mut self.wal {
+            Some(wal) => wal.append(stamp, &logged).map_err(|err| ExecError::Io(err.to_string())),
+            None => Ok(()),
+        };
+        let result = result.and_then(|()| {
+            let result = stamp.run(|| self.apply(cmd)).and_then(|output| {
+                token.check()?;
+                Ok(output)
+            });
+            match (&result, &mut self.wal) {
+                (Err(_), Some(wal)) => wal.discard_last().map_err(|err| ExecError::Io(err.to_string())).and(result),
+                _ => result,
+            }
+        });
+        match &result {
+            Ok(_) => {
+                self.record(logged);
+                if buffered.is_none() {
+                    for event in self.buffered_events.take().unwrap_or_default() {
+                        self.notify(event.kind, &event.table, event.keys);
+                    }
+                }
+            }
+            Err(_) => {
+                self.tables = tables;
+                self.views = views;
+                self.idempotency = idempotency;
+                match buffered {
+                    Some(len) => self.buffered_events.iter_mut().for_each(|events| events.truncate(len)),
+                    None => self.buffered_events = None,
+                }
+            }
+        }
+        self.finish_timer(timer, result)
+    }
+
+    // like `query`, failing with `Cancelled` once `token` is cancelled
+    pub fn query_cancellable(&self, cmd: Command, token: &CancelToken) -> Result<Output, ExecError> {
+        token.check()?;
+        let _active = activate(token);
+        let output = self.query(cmd)?;
+        token.check()?;
+        Ok(output)
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use crate::storage::Compression;
+
+// marks an encrypted file: magic, 12-byte nonce, then ciphertext with its tag
+const ENCRYPTED_MAGIC: &[u8; 8] = b"ZKKOENC1";
+const NONCE_LEN: usize = 12;
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+// how snapshot files are encoded on disk: contents are compressed first, then
+// encrypted. both are recognised by their header when reading
+#[derive(Clone, Default)]
+pub(crate) struct Codec {
+    key: Option<[u8; 32]>,
+    compression: Compression,
+}
+
+#[derive(Debug)]
+pub(crate) enum DecodeError {
+    // the key is wrong or the file was modified
+    Decrypt,
+    // an encrypted file read without a key
+    NeedsKey,
+    // a plain file read with a key
+    NotEncrypted,
+    Decompress(io::Error),
+}
+
+// the key stays out of debug output
+impl fmt::Debug for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Codec")
+            .field("encrypted", &self.key.is_some())
+            .field("compression", &self.compression)
+            .finish()
+    }
+}
+
+impl Codec {
+    pub(crate) fn encrypted(key: &[u8; 32]) -> Codec {
+        Codec {
+            key: Some(*key),
+            ..Codec::default()
+        }
+    }
+
+    pub(crate) fn compressed(compression: Compression) -> Codec {
+        Codec {
+            compression,
+            ..Codec::default()
+        }
+    }
+
+    pub(crate) fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    // the same encryption without the compression
+    pub(crate) fn encryption(&self) -> Codec {
+        Codec {
+            key: self.key,
+            ..Codec::default()
+        }
+    }
+
+    // `name` is authenticated with the contents so files can't be swapped
+    pub(crate) fn encode(&self, name: &str, plain: Vec<u8>) -> io::Result<Vec<u8>> {
+        let plain = compress(self.compression, plain)?;
+        let Some(key) = &self.key else {
+            return Ok(plain);
+        };
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload { msg: &plain, aad: name.as_bytes() };
+        let sealed = cipher.encrypt(&nonce, payload).expect("in-memory encryption does not fail");
+
+        let mut out = Vec::with_capacity(ENCRYPTED_MAGIC.len() + NONCE_LEN + sealed.len());
+        out.extend_from_slice(ENCRYPTED_MAGIC);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    pub(crate) fn decode(&self, name: &str, bytes: Vec<u8>) -> Result<Vec<u8>, DecodeError> {
+        let sealed = bytes.strip_prefix(ENCRYPTED_MAGIC.as_slice());
+        let (key, sealed) = match (&self.key, sealed) {
+            (None, None) => return decompress(bytes),
+            (Some(key), Some(sealed)) => (key, sealed),
+            (None, Some(_)) => return Err(DecodeError::NeedsKey),
+            (Some(_), None) => return Err(DecodeError::NotEncrypted),
+        };
+        if sealed.len() < NONCE_LEN {
+            return Err(DecodeError::Decrypt);
+        }
+        let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+        let payload = Payload { msg: sealed, aad: name.as_bytes() };
+        let plain = cipher.decrypt(Nonce::from_slice(nonce), payload).map_err(|_| DecodeError::Decrypt)?;
+        decompress(plain)
+    }
+}
+
+fn compress(compression: Compression, plain: Vec<u8>) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(plain),
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&plain)?;
+            encoder.finish()
+        }
+        Compression::Zstd => zstd::encode_all(plain.as_slice(), 0),
+    }
+}
+
+// the format is detected from the header, so readers never need to know it
+fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>, DecodeError> {
+    let mut plain = Vec::new();
+    if bytes.starts_with(GZIP_MAGIC) {
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut plain).map_err(DecodeError::Decompress)?;
+    } else if bytes.starts_with(ZSTD_MAGIC) {
+        plain = zstd::decode_all(bytes.as_slice()).map_err(DecodeError::Decompress)?;
+    } else {
+        return Ok(bytes);
+    }
+    Ok(plain)
+}
@@ -0,0 +1,235 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde_json::Value;
+
+use crate::backend::StorageBackend;
+use crate::database::{Database, ExecError, Key, Row, Table};
+use crate::parser::{ColumnDefinition, ForeignKey, OnDelete, PrimaryKey};
+use crate::utils::values_equal;
+
+impl<B: StorageBackend> Database<B> {
+    // a foreign key must point at the primary key or a unique column of an existing
+    // table. tables with a ttl can't be referenced since their rows vanish on their own
+    pub(crate) fn check_foreign_keys(
+        &self,
+        table_name: &str,
+        primary_key: &PrimaryKey,
+        columns: &std::collections::HashMap<String, ColumnDefinition>,
+        ttl_seconds: Option<u64>,
+    ) -> Result<(), ExecError> {
+        for (column, def) in columns {
+            let Some(fk) = &def.references else {
+                continue;
+            };
+            let (target_key, target_column, target_ttl) = if fk.table == table_name {
+                (primary_key.single(), columns.get(&fk.column), ttl_seconds)
+            } else {
+                let target = self.table(&fk.table)?;
+                (target.primary_key.single(), target.columns.get(&fk.column), target.ttl_seconds)
+            };
+            if target_ttl.is_some() {
+                return Err(ExecError::InvalidQuery(format!(
+                    "{}.{} references table '{}', which has a ttl",
+                    table_name, column, fk.table
+                )));
+            }
+            let Some(target_column) = target_column else {
+                return Err(ExecError::ColumnNotFound {
+                    table: fk.table.clone(),
+                    column: fk.column.clone(),
+                });
+            };
+            if target_key != Some(fk.column.as_str()) && !target_column.unique {
+                return Err(ExecError::InvalidQuery(format!(
+                    "{}.{} references {}.{}, which is neither the primary key nor unique",
+                    table_name, column, fk.table, fk.column
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // every non-null foreign key value of `row` must exist in the referenced column
+    pub(crate) fn check_references(&self, table_name: &str, row: &Row) -> Result<(), ExecError> {
+        let table = self.table(table_name)?;
+        for (column, def) in &table.columns {
+            let Some(fk) = &def.references else {
+                continue;
+            };
+            let value = row.get(column).unwrap_or(&Value::Null);
+            if value.is_null() {
+                continue;
+            }
+            if !value_exists(self.table(&fk.table)?, &fk.column, value) {
+                return Err(ExecError::ForeignKeyViolation {
+                    table: table_name.to_string(),
+                    column: column.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // changing a referenced value would orphan the rows pointing at the old one
+    pub(crate) fn check_referenced_update(
+        &self,
+        table_name: &str,
+        old: &Row,
+        new: &Row,
+    ) -> Result<(), ExecError> {
+        for (child_name, column, fk) in self.referencing(table_name) {
+            let before = &old[&fk.column];
+            if before.is_null() || values_equal(before, &new[&fk.column]) {
+                continue;
+            }
+            if !children_of(self.table(child_name)?, column, before).is_empty() {
+                return Err(ExecError::ForeignKeyViolation {
+                    table: child_name.to_string(),
+                    column: column.to_string(),
+                    value: before.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // collects every row removed by deleting `keys` from `table_name`, following
+    // `cascade` foreign keys and failing on `restrict` ones
+    pub(crate) fn plan_delete(
+        &self,
+        table_name: &str,
+        keys: Vec<Key>,
+    ) -> Result<BTreeMap<String, BTreeSet<Key>>, ExecError> {
+        let mut plan: BTreeMap<String, BTreeSet<Key>> = BTreeMap::new();
+        let mut pending = vec![(table_name.to_string(), keys)];
+
+        while let Some((name, keys)) = pending.pop() {
+            let table = self.table(&name)?;
+            let planned = plan.entry(name.clone()).or_default();
+            let fresh: Vec<Key> = keys.into_iter().filter(|key| planned.insert(key.clone())).collect();
+
+            for (child_name, column, fk) in self.referencing(&name) {
+                let child = self.table(child_name)?;
+                for key in &fresh {
+                    let Some(row) = table.get(key) else {
+                        continue;
+                    };
+                    let value = &row[&fk.column];
+                    if value.is_null() {
+                        continue;
+                    }
+                    let children = children_of(child, column, value);
+                    if children.is_empty() {
+                        continue;
+                    }
+                    match fk.on_delete {
+                        OnDelete::Restrict => {
+                            let already = plan.get(child_name);
+                            if !children.iter().all(|k| already.is_some_and(|keys| keys.contains(k))) {
+                                return Err(ExecError::ForeignKeyViolation {
+                                    table: child_name.to_string(),
+                                    column: column.to_string(),
+                                    value: value.clone(),
+                                });
+                            }
+                        }
+                        OnDelete::Cascade => pending.push((child_name.to_string(), children)),
+                    }
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    pub(crate) fn check_drop(&self, dropped: &[&str]) -> Result<(), ExecError> {
+        for table_name in dropped {
+            let kept_child = self
+                .referencing(table_name)
+                .into_iter()
+                .find(|(child_name, _, _)| !dropped.contains(child_name));
+            if let Some((child_name, column, _)) = kept_child {
+                return Err(ExecError::InvalidQuery(format!(
+                    "table '{}' is referenced by {}.{}",
+                    table_name, child_name, column
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // (table, column, foreign key) for every column referencing `table_name`
+    pub(crate) fn referencing(&self, table_name: &str) -> Vec<(&str, &str, &ForeignKey)> {
+        let mut found = Vec::new();
+        for (name, table) in &self.tables {
+            for (column, def) in &table.columns {
+                if let Some(fk) = def.references.as_ref().filter(|fk| fk.table == table_name) {
+                    found.push((name.as_str(), column.as_str(), fk));
+                }
+            }
+        }
+        found.sort_by_key(|(name, column, _)| (*name, *column));
+        found
+    }
+}
+
+// `rows` are about to be written to the table, replacing the rows whose keys
+// `replaced` accepts. their unique columns and unique indexes must not repeat
+// a value among themselves or of a live row that stays
+pub(crate) fn check_unique<'a, B: StorageBackend>(
+    table_name: &str,
+    table: &Table<B>,
+    rows: impl IntoIterator<Item = &'a Row> + Clone,
+    replaced: impl Fn(&Key) -> bool,
+) -> Result<(), ExecError> {
+    let mut columns: Vec<(&String, &BTreeMap<Key, Key>)> = table.unique.iter().collect();
+    columns.sort_by_key(|(column, _)| *column);
+    for (column, values) in columns {
+        let mut seen = BTreeSet::new();
+        for row in rows.clone() {
+            let Some(value) = row.get(column).filter(|value| !value.is_null()) else {
+                continue;
+            };
+            let taken = values
+                .get(&Key(value.clone()))
+                .is_some_and(|holder| !replaced(holder) && table.get(holder).is_some());
+            if taken || !seen.insert(Key(value.clone())) {
+                return Err(ExecError::UniqueViolation {
+                    table: table_name.to_string(),
+                    column: column.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+    }
+    for index in table.indexes.iter().filter(|index| index.definition.unique) {
+        let mut seen = BTreeSet::new();
+        for row in rows.clone() {
+            let Some(value) = index.unique_value(row) else {
+                continue;
+            };
+            let taken = index.holders(&value).any(|holder| !replaced(holder) && table.get(holder).is_some());
+            if taken || !seen.insert(value.clone()) {
+                return Err(index.violation(table_name, value));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn value_exists<B: StorageBackend>(table: &Table<B>, column: &str, value: &Value) -> bool {
+    if table.primary_key.single() == Some(column) {
+        table.get(&Key(value.clone())).is_some()
+    } else if let Some(values) = table.unique.get(column) {
+        values.get(&Key(value.clone())).is_some_and(|key| table.get(key).is_some())
+    } else {
+        table.rows().any(|row| row.get(column).is_some_and(|v| values_equal(v, value)))
+    }
+}
+
+fn children_of<B: StorageBackend>(table: &Table<B>, column: &str, value: &Value) -> Vec<Key> {
+    table
+        .entries()
+        .filter(|(_, row)| row.get(column).is_some_and(|v| values_equal(v, value)))
+        .map(|(key, _)| key.clone())
+        .collect()
+}
@@ -0,0 +1,1461 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use serde_json::Value;
+
+use crate::aggregate;
+use crate::backend::StorageBackend;
+use crate::cancel;
+use crate::constraints::check_unique;
+use crate::database::{Database, ExecError, Key, Output, Row, Table, CREATED_AT_FIELD, UPDATED_AT_FIELD};
+use crate::events::ChangeKind;
+use crate::parser::{
+    and_filters, parse_filter, Collation, ColumnChange, ColumnDefinition, Command, CreateCommand, Direction,
+    InsertCommand, JoinClause, JoinKind, OnError, OrderBy, ReadCommand,
+};
+use crate::filter::{column_conditions, equality_operand, is_logical, Filter};
+use crate::index::{index_lookup, Index, IndexDefinition};
+use crate::result::ColumnType;
+use crate::utils::{parse_rfc3339, values_equal, Rng, Stamp};
+use crate::validator;
+use crate::value::Value as Typed;
+
+// the rows an update's filter matched and the rows it changed. they differ
+// when the `if` condition fails or `on_error` skips rows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct UpdateCount {
+    pub(crate) matched: usize,
+    pub(crate) applied: usize,
+}
+
+impl<B: StorageBackend> Database<B> {
+    pub(crate) fn insert(&mut self, table_name: &str, row: Row) -> Result<(), ExecError> {
+        let key = self.insert_unnotified(table_name, row)?;
+        self.notify(ChangeKind::Insert, table_name, vec![key.0]);
+        Ok(())
+    }
+
+    // inserts all of `rows` or none. the batch is checked against the table's
+    // quota as a whole first; a row that fails takes the earlier ones back out.
+    // each row is logged like a single insert once the whole batch is in, and
+    // subscribers get one event for the batch
+    pub fn insert_many(&mut self, table_name: &str, mut rows: Vec<Row>) -> Result<usize, ExecError> {
+        if self.read_only {
+            return Err(ExecError::ReadOnly);
+        }
+        let table = self.table(table_name)?;
+        check_quota(table_name, table, rows.len())?;
+        rows.iter_mut().for_each(|row| validator::fill_function_defaults(table, row));
+        let logged = (self.wal.is_some() || self.command_log.is_some()).then(|| rows.clone());
+
+        let before = Arc::clone(&self.tables[table_name]);
+        let stamp = Stamp::now();
+        let inserted = stamp.run(|| {
+            rows.into_iter()
+                .map(|row| self.insert_unnotified(table_name, row).map(|key| key.0))
+                .collect::<Result<Vec<_>, _>>()
+        });
+        let keys = match inserted {
+            Ok(keys) => keys,
+            Err(err) => {
+                self.tables.insert(table_name.to_string(), before);
+                return Err(err);
+            }
+        };
+        let logged: Vec<Command> = logged
+            .into_iter()
+            .flatten()
+            .map(|rows| Command::Insert(InsertCommand { table: table_name.to_string(), rows, idempotency_key: None }))
+            .collect();
+        if let Some(wal) = &mut self.wal {
+            for cmd in &logged {
+                if let Err(err) = wal.append(stamp, cmd) {
+                    self.tables.insert(table_name.to_string(), before);
+                    return Err(ExecError::Io(err.to_string()));
+                }
+            }
+        }
+        logged.into_iter().for_each(|cmd| self.record(cmd));
+        let inserted = keys.len();
+        self.notify(ChangeKind::Insert, table_name, keys);
+        Ok(inserted)
+    }
+
+    fn insert_unnotified(&mut self, table_name: &str, row: Row) -> Result<Key, ExecError> {
+        let table = self.table(table_name)?;
+        check_quota(table_name, table, 1)?;
+        let row = validator::validate_insert(table_name, table, row)?;
+
+        let key = table.key_of(&row);
+        if table.get(&key).is_some() {
+            return Err(ExecError::DuplicateKey {
+                table: table_name.to_string(),
+                key: key.0,
+            });
+        }
+        check_unique(table_name, table, [&row], |_| false)?;
+        self.check_references(table_name, &row)?;
+
+        self.table_mut(table_name)?.insert_row(key.clone(), row, None);
+        Ok(key)
+    }
+
+    pub(crate) fn update_content(
+        &mut self,
+        table_name: &str,
+        filter: &str,
+        updates: Row,
+        on_error: OnError,
+        condition: Option<&str>,
+    ) -> Result<UpdateCount, ExecError> {
+        let table = self.table(table_name)?;
+        let (lookup, filter) = compile_write_filter(table_name, table, filter)?;
+        let condition = condition.map(|condition| compile_write_filter(table_name, table, condition)).transpose()?;
+        let assignments = validator::validate_update(table_name, table, updates)?;
+
+        let mut matched: BTreeMap<Key, Row> = matching(table, &lookup, &filter)
+            .into_iter()
+            .map(|(key, row)| (key, row.into_owned()))
+            .collect();
+        cancel::check()?;
+        let found = matched.len();
+        if let Some((_, condition)) = &condition {
+            matched.retain(|_, row| condition.matches(row));
+        }
+        let applied = self.update_matched(table_name, matched, &assignments, on_error)?;
+        Ok(UpdateCount { matched: found, applied })
+    }
+
+    // applies `assignments` to the `matched` rows and returns how many changed
+    fn update_matched(
+        &mut self,
+        table_name: &str,
+        mut matched: BTreeMap<Key, Row>,
+        assignments: &[(String, validator::Assignment)],
+        on_error: OnError,
+    ) -> Result<usize, ExecError> {
+        let table = self.table(table_name)?;
+        let mut changed: Vec<Row> = Vec::with_capacity(matched.len());
+        let mut skipped = Vec::new();
+        for (key, old) in &matched {
+            match validator::apply_update(table, old, assignments) {
+                Ok(row) => changed.push(row),
+                Err(_) if on_error == OnError::Skip => skipped.push(key.clone()),
+                Err(err) => return Err(err),
+            }
+        }
+        for key in &skipped {
+            matched.remove(key);
+        }
+
+        // a primary key change must not collide with another row
+        let mut new_keys = BTreeSet::new();
+        for row in &changed {
+            let key = table.key_of(row);
+            if (table.get(&key).is_some() && !matched.contains_key(&key)) || !new_keys.insert(key.clone()) {
+                return Err(ExecError::DuplicateKey {
+                    table: table_name.to_string(),
+                    key: key.0,
+                });
+            }
+        }
+        check_unique(table_name, table, &changed, |key| matched.contains_key(key))?;
+        for (old, row) in matched.values().zip(&changed) {
+            self.check_references(table_name, row)?;
+            self.check_referenced_update(table_name, old, row)?;
+        }
+
+        // updated rows keep their original insertion time
+        let table = self.table_mut(table_name)?;
+        let stamps: Vec<Option<u64>> = matched.keys().map(|key| table.remove_row(key)).collect();
+        let mut keys = Vec::with_capacity(changed.len());
+        for (row, stamp) in changed.into_iter().zip(stamps) {
+            let key = table.key_of(&row);
+            keys.push(key.0.clone());
+            table.insert_row(key, row, stamp);
+        }
+
+        let applied = keys.len();
+        if applied > 0 {
+            self.notify(ChangeKind::Update, table_name, keys);
+        }
+        Ok(applied)
+    }
+
+    // inserts `row` unless a row already holds its values in the `on_conflict`
+    // columns, the primary key when empty. that row then takes the other
+    // columns of `row`, and its conflict columns stay as they are
+    pub(crate) fn upsert(&mut self, table_name: &str, mut row: Row, on_conflict: &[String]) -> Result<(), ExecError> {
+        let table = self.table(table_name)?;
+        let columns = match on_conflict {
+            [] => table.primary_key.columns(),
+            columns => columns,
+        };
+        for column in columns {
+            require_column(table_name, table, column)?;
+        }
+        let unique = match columns {
+            [column] => table.columns[column].unique && !table.primary_key.contains(column),
+            _ => false,
+        };
+        let is_key = columns.len() == table.primary_key.columns().len()
+            && columns.iter().all(|column| table.primary_key.contains(column));
+        if !is_key && !unique {
+            return Err(ExecError::InvalidQuery(format!(
+                "on_conflict of an upsert into '{}' needs the primary key or one unique column",
+                table_name
+            )));
+        }
+        if let Some(missing) = columns.iter().find(|column| row.get(*column).is_none_or(Value::is_null)) {
+            return Err(ExecError::InvalidQuery(format!(
+                "an upsert into '{}' needs a value for '{}'",
+                table_name, missing
+            )));
+        }
+
+        let existing = if is_key {
+            let key = table.key_of(&row);
+            table.get(&key).map(|old| (key, old.into_owned()))
+        } else {
+            let value = Key(row[&columns[0]].clone());
+            table.unique[&columns[0]]
+                .get(&value)
+                .and_then(|key| table.get(key).map(|old| (key.clone(), old.into_owned())))
+        };
+        let Some((key, old)) = existing else {
+            return self.insert(table_name, row);
+        };
+        for column in columns {
+            row.remove(column);
+        }
+        if row.is_empty() {
+            return Ok(());
+        }
+        let assignments = validator::validate_update(table_name, table, row)?;
+        self.update_matched(table_name, BTreeMap::from([(key, old)]), &assignments, OnError::Abort)?;
+        Ok(())
+    }
+
+    pub(crate) fn delete_content(
+        &mut self,
+        table_name: &str,
+        filter: &str,
+        limit: Option<usize>,
+    ) -> Result<usize, ExecError> {
+        let table = self.table(table_name)?;
+        let (lookup, filter) = compile_write_filter(table_name, table, filter)?;
+
+        let matched: Vec<Key> = matching(table, &lookup, &filter)
+            .into_iter()
+            .map(|(key, _)| key)
+            .take(limit.unwrap_or(usize::MAX))
+            .collect();
+        cancel::check()?;
+        let count = matched.len();
+
+        // cascaded deletes are planned up front so a restrict violation deletes nothing
+        let plan = self.plan_delete(table_name, matched)?;
+        for (name, keys) in plan {
+            if keys.is_empty() {
+                continue;
+            }
+            let table = self.table_mut(&name)?;
+            for key in &keys {
+                table.remove_row(key);
+            }
+            self.notify(ChangeKind::Delete, &name, keys.into_iter().map(|key| key.0).collect());
+        }
+        Ok(count)
+    }
+
+    // deletes every row, keeping the schema and indexes. without rows in other
+    // tables pointing at it the table is swapped for an empty one instead of
+    // deleting row by row. expired rows go too but, unseen as they were, aren't
+    // counted or announced
+    pub(crate) fn truncate_table(&mut self, table_name: &str) -> Result<usize, ExecError> {
+        let table = self.table(table_name)?;
+        let keys: Vec<Key> = table.entries().map(|(key, _)| key.clone()).collect();
+        cancel::check()?;
+        let referenced = self
+            .referencing(table_name)
+            .into_iter()
+            .any(|(child, _, _)| child != table_name && !self.tables[child].is_empty());
+        if referenced {
+            let plan = self.plan_delete(table_name, keys.clone())?;
+            for (name, keys) in plan.into_iter().filter(|(name, _)| name != table_name) {
+                if keys.is_empty() {
+                    continue;
+                }
+                let child = self.table_mut(&name)?;
+                for key in &keys {
+                    child.remove_row(key);
+                }
+                self.notify(ChangeKind::Delete, &name, keys.into_iter().map(|key| key.0).collect());
+            }
+        }
+
+        let table = self.table(table_name)?;
+        let indexes = table.indexes.iter().map(|index| index.definition.clone()).collect();
+        let emptied = rebuild(table_name, table, &self.backend, table.columns.clone(), Vec::new(), indexes)?;
+        self.tables.insert(table_name.to_string(), Arc::new(emptied));
+        let count = keys.len();
+        if count > 0 {
+            self.notify(ChangeKind::Delete, table_name, keys.into_iter().map(|key| key.0).collect());
+        }
+        Ok(count)
+    }
+
+    // ttl tables can't be referenced by foreign keys, so expired rows are
+    // removed without checking for dependents
+    pub(crate) fn purge_expired(&mut self, table_name: &str) -> Result<usize, ExecError> {
+        let table = self.table_mut(table_name)?;
+        let expired = table.expired_keys();
+        for key in &expired {
+            table.remove_row(key);
+        }
+
+        let count = expired.len();
+        if count > 0 {
+            self.notify(ChangeKind::Delete, table_name, expired.into_iter().map(|key| key.0).collect());
+        }
+        Ok(count)
+    }
+
+    pub(crate) fn drop_table(&mut self, table_name: &str) -> Result<(), ExecError> {
+        self.table(table_name)?;
+        self.check_drop(&[table_name])?;
+        self.remove_table(table_name);
+        Ok(())
+    }
+
+    // drops all tables matching the glob or, when one is still referenced by a
+    // table that stays, none of them
+    pub(crate) fn drop_tables(&mut self, pattern: &str) -> Result<usize, ExecError> {
+        let names = self.list_tables(Some(pattern));
+        let dropped: Vec<&str> = names.iter().map(String::as_str).collect();
+        self.check_drop(&dropped)?;
+        for name in &dropped {
+            self.remove_table(name);
+        }
+        Ok(dropped.len())
+    }
+
+    fn remove_table(&mut self, table_name: &str) {
+        self.forget_grants(table_name);
+        self.tables.remove(table_name);
+        self.idempotency.forget_table(table_name);
+        self.notify(ChangeKind::Drop, table_name, Vec::new());
+    }
+
+    // adds columns to a table. existing rows get each new column's default,
+    // evaluated per row, or null; the rows must then meet the new columns'
+    // constraints or nothing changes
+    pub(crate) fn add_columns(&mut self, table_name: &str, add: HashMap<String, ColumnDefinition>) -> Result<(), ExecError> {
+        let table = self.table(table_name)?;
+        let mut columns = table.columns.clone();
+        for (name, def) in &add {
+            if columns.contains_key(name) {
+                return Err(ExecError::InvalidQuery(format!(
+                    "column '{}' already exists in table '{}'",
+                    name, table_name
+                )));
+            }
+            if def.generated.is_some() {
+                return Err(ExecError::Unsupported("adding a generated column".to_string()));
+            }
+            columns.insert(name.clone(), def.clone());
+        }
+        check_columns(table_name, table, &columns)?;
+
+        let mut rows = Vec::with_capacity(table.len());
+        for (key, row) in table.entries() {
+            let mut row = row.into_owned();
+            for (name, def) in &add {
+                let value = validator::column_default(name, def)?;
+                if value.is_null() && def.not_null {
+                    return Err(ExecError::NotNull { column: name.clone() });
+                }
+                row.insert(name.clone(), value);
+            }
+            rows.push((key.clone(), row, table.inserted_at.get(key).copied()));
+        }
+        let indexes = table.indexes.iter().map(|index| index.definition.clone()).collect();
+        let widened = rebuild(table_name, table, &self.backend, columns, rows, indexes)?;
+
+        // references of the new columns are checked against the widened table
+        let widened = Arc::new(widened);
+        let previous = self.tables.insert(table_name.to_string(), Arc::clone(&widened));
+        let checked = widened.rows().try_for_each(|row| self.check_references(table_name, &row));
+        if let (Err(_), Some(previous)) = (&checked, previous) {
+            self.tables.insert(table_name.to_string(), previous);
+        }
+        checked
+    }
+
+    // adds the columns of `updates` missing from the table, nullable and with
+    // the type of their value
+    pub(crate) fn add_inferred_columns(&mut self, table_name: &str, updates: &Row) -> Result<(), ExecError> {
+        let table = self.table(table_name)?;
+        let mut add = HashMap::new();
+        for (column, value) in updates {
+            if table.columns.contains_key(column) {
+                continue;
+            }
+            let Some(col_type) = ColumnType::infer(value) else {
+                return Err(ExecError::InvalidQuery(format!(
+                    "can't infer the type of new column '{}' from null",
+                    column
+                )));
+            };
+            let def = ColumnDefinition { col_type, ..ColumnDefinition::default() };
+            add.insert(column.clone(), def);
+        }
+        if add.is_empty() {
+            return Ok(());
+        }
+        self.add_columns(table_name, add)
+    }
+
+    // changes the not_null, unique and default of existing columns. the rows are
+    // checked against every change first: a column made not_null may hold no
+    // nulls and one made unique no duplicates, else nothing changes
+    pub(crate) fn alter_columns(&mut self, table_name: &str, alter: HashMap<String, ColumnChange>) -> Result<(), ExecError> {
+        let table = self.table(table_name)?;
+        let mut columns = table.columns.clone();
+        for (name, change) in &alter {
+            let Some(def) = columns.get_mut(name) else {
+                return Err(ExecError::ColumnNotFound {
+                    table: table_name.to_string(),
+                    column: name.clone(),
+                });
+            };
+            if change.not_null == Some(false) && table.primary_key.contains(name) {
+                return Err(ExecError::InvalidQuery(format!("key column '{}' can't be made nullable", name)));
+            }
+            if change.not_null == Some(true) && !def.not_null {
+                let nulls = table.rows().filter(|row| row.get(name).is_none_or(Value::is_null)).count();
+                if nulls > 0 {
+                    return Err(ExecError::NullsPresent { column: name.clone(), rows: nulls });
+                }
+            }
+            def.not_null = change.not_null.unwrap_or(def.not_null);
+            def.unique = change.unique.unwrap_or(def.unique);
+            if let Some(default) = &change.default {
+                def.default = default.clone();
+            }
+        }
+        check_columns(table_name, table, &columns)?;
+
+        let rows = table
+            .entries()
+            .map(|(key, row)| (key.clone(), row.into_owned(), table.inserted_at.get(key).copied()))
+            .collect();
+        let indexes = table.indexes.iter().map(|index| index.definition.clone()).collect();
+        let altered = rebuild(table_name, table, &self.backend, columns, rows, indexes)?;
+        self.tables.insert(table_name.to_string(), Arc::new(altered));
+        Ok(())
+    }
+
+    // removes columns and their values from every row. key, timestamp and
+    // indexed columns and those other columns reference stay
+    pub(crate) fn drop_columns(&mut self, table_name: &str, drop: &[String]) -> Result<(), ExecError> {
+        let table = self.table(table_name)?;
+        let mut columns = table.columns.clone();
+        for name in drop {
+            check_schema_change(table_name, table, name, "dropped")?;
+            let predicate_uses = |index: &Index| index.definition.predicate.as_ref().is_some_and(|p| p.contains_key(name));
+            if let Some(index) = table
+                .indexes
+                .iter()
+                .find(|index| index.definition.columns.contains(name) || predicate_uses(index))
+            {
+                return Err(ExecError::InvalidQuery(format!(
+                    "column '{}' is used by index '{}'",
+                    name, index.definition.name
+                )));
+            }
+            if let Some((child, column, _)) = self
+                .referencing(table_name)
+                .into_iter()
+                .find(|(_, _, fk)| fk.column == *name)
+            {
+                return Err(ExecError::InvalidQuery(format!(
+                    "column '{}' is referenced by {}.{}",
+                    name, child, column
+                )));
+            }
+            columns.remove(name);
+        }
+        check_columns(table_name, table, &columns)?;
+
+        let rows = table
+            .entries()
+            .map(|(key, row)| {
+                let mut row = row.into_owned();
+                row.retain(|column, _| !drop.contains(column));
+                (key.clone(), row, table.inserted_at.get(key).copied())
+            })
+            .collect();
+        let indexes = table.indexes.iter().map(|index| index.definition.clone()).collect();
+        let narrowed = rebuild(table_name, table, &self.backend, columns, rows, indexes)?;
+        self.tables.insert(table_name.to_string(), Arc::new(narrowed));
+        Ok(())
+    }
+
+    // renames columns, old name to new, in the rows, the table's indexes and
+    // the foreign keys pointing at them. key and timestamp columns keep their
+    // names
+    pub(crate) fn rename_columns(&mut self, table_name: &str, rename: &HashMap<String, String>) -> Result<(), ExecError> {
+        let table = self.table(table_name)?;
+        let renamed = |column: &String| rename.get(column).unwrap_or(column).clone();
+        for (from, to) in rename {
+            check_schema_change(table_name, table, from, "renamed")?;
+            let taken = table.columns.contains_key(to) && !rename.contains_key(to);
+            if taken || rename.values().filter(|other| *other == to).count() > 1 {
+                return Err(ExecError::InvalidQuery(format!(
+                    "column '{}' already exists in table '{}'",
+                    to, table_name
+                )));
+            }
+        }
+        let columns = table.columns.iter().map(|(name, def)| (renamed(name), def.clone())).collect();
+        check_columns(table_name, table, &columns)?;
+
+        let rows = table
+            .entries()
+            .map(|(key, row)| {
+                let row = row.into_owned().into_iter().map(|(column, value)| (renamed(&column), value)).collect();
+                (key.clone(), row, table.inserted_at.get(key).copied())
+            })
+            .collect();
+        let indexes = table
+            .indexes
+            .iter()
+            .map(|index| {
+                let mut definition = index.definition.clone();
+                definition.columns = definition.columns.iter().map(renamed).collect();
+                definition.predicate = definition
+                    .predicate
+                    .map(|predicate| predicate.into_iter().map(|(column, value)| (renamed(&column), value)).collect());
+                definition
+            })
+            .collect();
+        let result = Arc::new(rebuild(table_name, table, &self.backend, columns, rows, indexes)?);
+        self.tables.insert(table_name.to_string(), result);
+
+        // foreign keys, in this table or others, follow the columns they point at
+        let referencing: Vec<(String, String)> = self
+            .referencing(table_name)
+            .into_iter()
+            .filter(|(_, _, fk)| rename.contains_key(&fk.column))
+            .map(|(child, column, _)| (child.to_string(), column.to_string()))
+            .collect();
+        for (child, column) in referencing {
+            if let Some(fk) = self.table_mut(&child)?.columns.get_mut(&column).and_then(|def| def.references.as_mut()) {
+                fk.column = rename[&fk.column].clone();
+            }
+        }
+        Ok(())
+    }
+
+    // a copy with data shares the source's storage until either table is written
+    pub(crate) fn copy_table(&mut self, from: &str, to: String, include_data: bool) -> Result<(), ExecError> {
+        let source = self.table(from)?;
+        if self.tables.contains_key(&to) {
+            return Err(ExecError::TableExists(to));
+        }
+        if self.views.contains_key(&to) {
+            return Err(ExecError::ViewExists(to));
+        }
+        let copy = if include_data {
+            Arc::clone(&self.tables[from])
+        } else {
+            let mut copy = Table::new(
+                source.primary_key.clone(),
+                source.columns.clone(),
+                source.ttl_seconds,
+                source.storage(),
+                self.backend(),
+            );
+            copy.timestamps = source.timestamps;
+            copy.max_rows = source.max_rows;
+            for index in &source.indexes {
+                let index = Index::new(&to, &copy, index.definition.clone())?;
+                copy.add_index(index);
+            }
+            Arc::new(copy)
+        };
+        self.tables.insert(to, copy);
+        Ok(())
+    }
+
+    // gives a table a new name, with its rows and indexes. foreign keys
+    // pointing at it, grants on it and views reading it, through their joins
+    // and subqueries too, follow. past the checks nothing can fail, so a
+    // rename is never left half done
+    pub(crate) fn rename_table(&mut self, from: &str, to: String) -> Result<(), ExecError> {
+        self.table(from)?;
+        if self.tables.contains_key(&to) {
+            return Err(ExecError::TableExists(to));
+        }
+        if self.views.contains_key(&to) {
+            return Err(ExecError::ViewExists(to));
+        }
+
+        let referencing: Vec<(String, String)> = self
+            .referencing(from)
+            .into_iter()
+            .map(|(child, column, _)| (child.to_string(), column.to_string()))
+            .collect();
+        let table = self.tables.remove(from).expect("the table exists");
+        self.tables.insert(to.clone(), table);
+        for (child, column) in referencing {
+            let child = if child == from { to.as_str() } else { child.as_str() };
+            let child = Arc::make_mut(self.tables.get_mut(child).expect("referencing tables exist"));
+            if let Some(fk) = child.columns.get_mut(&column).and_then(|def| def.references.as_mut()) {
+                fk.table = to.clone();
+            }
+        }
+        self.rename_grants(from, &to);
+        for view in self.views.values_mut() {
+            rename_in_read(view, from, &to);
+        }
+        self.idempotency.forget_table(from);
+        self.notify(ChangeKind::Drop, from, Vec::new());
+        Ok(())
+    }
+
+    // a point lookup through the primary key, without compiling a filter
+    pub fn get(&self, table_name: &str, key: Value) -> Result<Option<Row>, ExecError> {
+        let table = self.table(table_name)?;
+        let columns = table.primary_key.columns().len();
+        let valid = match &key {
+            Value::Array(values) => columns > 1 && values.len() == columns,
+            _ => columns == 1,
+        };
+        if !valid {
+            return Err(ExecError::InvalidQuery(format!(
+                "table '{}' has a key of {} column(s), got {}",
+                table_name, columns, key
+            )));
+        }
+        Ok(table.get(&Key(key)).map(Cow::into_owned))
+    }
+
+    pub fn read(&self, cmd: &ReadCommand) -> Result<Vec<Row>, ExecError> {
+        Ok(self.read_page(cmd)?.0)
+    }
+
+    // the rows of a read and, for a paginated read with more rows left, the
+    // cursor to continue from
+    pub(crate) fn read_page(&self, cmd: &ReadCommand) -> Result<(Vec<Row>, Option<String>), ExecError> {
+        let cmd = &*merge_where(cmd)?;
+        if let Some(view) = self.views.get(&cmd.table) {
+            return Ok((self.read_view(view, cmd)?, None));
+        }
+
+        let table = self.table(&cmd.table)?;
+        let (filter, having) = self.check_read(cmd)?;
+        let grouped = is_grouped(cmd);
+        let after = cmd.after.as_deref().map(decode_cursor).transpose()?;
+        let after = after.as_ref();
+        let is_after = |key: &Key| after.is_none_or(|after| key > after);
+
+        let mut rows = match (&cmd.join, key_lookup(table, cmd), index_lookup(table, cmd)) {
+            (Some(join), _, _) => self
+                .join_rows(&cmd.table, table, join)?
+                .into_iter()
+                .filter(|row| filter.matches(row))
+                .collect(),
+            (None, Some(key), _) => table
+                .get(&key)
+                .filter(|row| is_after(&key) && filter.matches(row))
+                .map(Cow::into_owned)
+                .into_iter()
+                .collect(),
+            (None, None, Some((_, keys))) => keys
+                .iter()
+                .filter(|key| is_after(key))
+                .filter_map(|key| table.get(key))
+                .filter(|row| filter.matches(row))
+                .map(Cow::into_owned)
+                .collect(),
+            // aggregates only need the columns they and the filter read
+            (None, None, None) if grouped => {
+                let filtered = column_conditions(&cmd.filter).into_iter().map(|(column, _)| column);
+                let mut needed: Vec<String> = filtered.chain(&cmd.group_by).cloned().collect();
+                needed.extend(cmd.aggregates.iter().filter_map(|spec| spec.column.clone()));
+                needed.sort();
+                needed.dedup();
+                table.project(&needed).filter(|row| filter.matches(row)).collect()
+            }
+            (None, None, None) => table
+                .entries_after(after)
+                .map(|(_, row)| row)
+                .filter(|row| filter.matches(row))
+                .map(Cow::into_owned)
+                .collect::<Vec<_>>(),
+        };
+
+        if let Some(since) = check_changed_since(cmd, table)? {
+            rows = changed_since(rows, since);
+        }
+        if grouped {
+            rows = aggregate::aggregate(
+                rows,
+                &cmd.group_by,
+                &cmd.aggregates,
+                &self.read_column_types(cmd),
+                &self.read_column_collations(cmd),
+            )?;
+        }
+        if let Some(having) = having {
+            rows.retain(|row| having.matches(row));
+        }
+        // rows are still whole and, as paginated reads can't use changed_since, in key order here
+        let next_cursor = match cmd.limit {
+            Some(limit) if cmd.is_paginated() && rows.len() > limit => {
+                Some(encode_cursor(&table.key_of(&rows[limit - 1])))
+            }
+            _ => None,
+        };
+        let finished = finish(rows, cmd, &self.read_column_types(cmd), &self.read_column_collations(cmd));
+        Ok((finished, next_cursor))
+    }
+
+    // validates the columns a read of a table references and compiles its
+    // filter and, for a grouped read, its `having`
+    pub(crate) fn check_read(&self, cmd: &ReadCommand) -> Result<(Filter, Option<Filter>), ExecError> {
+        let table = self.table(&cmd.table)?;
+        let joined = match &cmd.join {
+            Some(join) => Some(self.table(&join.table)?),
+            None => None,
+        };
+        let grouped = is_grouped(cmd);
+        check_sample(cmd)?;
+        check_changed_since(cmd, table)?;
+        if cmd.is_paginated() {
+            if cmd.join.is_some() || grouped || cmd.distinct || !cmd.order_by.is_empty() {
+                return Err(ExecError::InvalidQuery(
+                    "a paginated read can't have a join, aggregates, distinct or order_by".to_string(),
+                ));
+            }
+            if cmd.offset.is_some() {
+                return Err(ExecError::InvalidQuery(
+                    "a paginated read can't have an offset, it continues from its cursor".to_string(),
+                ));
+            }
+            if cmd.limit.unwrap_or(0) == 0 {
+                return Err(ExecError::InvalidQuery("a paginated read needs a limit above 0".to_string()));
+            }
+        }
+
+        let referenced = column_conditions(&cmd.filter)
+            .into_iter()
+            .map(|(column, _)| column)
+            .chain(&cmd.group_by)
+            .chain(cmd.aggregates.iter().filter_map(|spec| spec.column.as_ref()));
+        for column in referenced {
+            read_column_exists(cmd, table, joined, column)?;
+        }
+        if !grouped {
+            for column in cmd.columns.iter().chain(cmd.order_by.iter().map(|order| &order.column)) {
+                read_column_exists(cmd, table, joined, column)?;
+            }
+        }
+        let resolved = self.resolve_subqueries(&cmd.filter)?;
+        let filter = Filter::compile(&resolved, &self.read_column_types(cmd), &self.read_column_collations(cmd))?;
+        for spec in &cmd.aggregates {
+            aggregate::check_spec(spec)?;
+        }
+        if !grouped && !cmd.having.is_empty() {
+            return Err(ExecError::InvalidQuery("having needs group_by or aggregates".to_string()));
+        }
+        if !grouped {
+            return Ok((filter, None));
+        }
+        let having_columns = column_conditions(&cmd.having).into_iter().map(|(column, _)| column);
+        let referenced = cmd.columns.iter().chain(cmd.order_by.iter().map(|order| &order.column));
+        for column in referenced.chain(having_columns) {
+            let known = cmd.group_by.contains(column)
+                || cmd.aggregates.iter().any(|spec| spec.output_name() == *column);
+            if !known {
+                return Err(ExecError::ColumnNotFound {
+                    table: cmd.table.clone(),
+                    column: column.clone(),
+                });
+            }
+        }
+        let having = match cmd.having.is_empty() {
+            true => None,
+            false => {
+                let resolved = self.resolve_subqueries(&cmd.having)?;
+                Some(Filter::compile(&resolved, &self.read_column_types(cmd), &self.read_column_collations(cmd))?)
+            }
+        };
+        Ok((filter, having))
+    }
+
+    // runs the sub-read of every {"$in_query": read} condition, nested ones
+    // included, and replaces it with {"$in": [values]}. sub-reads are held to
+    // the database's row cap
+    fn resolve_subqueries<'a>(
+        &self,
+        filter: &'a HashMap<String, Value>,
+    ) -> Result<Cow<'a, HashMap<String, Value>>, ExecError> {
+        let has_subquery = |(_, expected): (&String, &Value)| expected.get("$in_query").is_some();
+        if !column_conditions(filter).into_iter().any(has_subquery) {
+            return Ok(Cow::Borrowed(filter));
+        }
+        let mut resolved = filter.clone();
+        for (column, expected) in &mut resolved {
+            self.resolve_subquery(column, expected)?;
+        }
+        Ok(Cow::Owned(resolved))
+    }
+
+    fn resolve_subquery(&self, column: &str, expected: &mut Value) -> Result<(), ExecError> {
+        if is_logical(column) {
+            let nested: Vec<&mut Value> = match expected {
+                Value::Array(filters) => filters.iter_mut().collect(),
+                filter => vec![filter],
+            };
+            for filter in nested.into_iter().filter_map(Value::as_object_mut) {
+                for (column, expected) in filter.iter_mut() {
+                    self.resolve_subquery(column, expected)?;
+                }
+            }
+            return Ok(());
+        }
+        let Some(query) = expected.as_object_mut().and_then(|ops| ops.remove("$in_query")) else {
+            return Ok(());
+        };
+        let values = self.subquery_values(column, query)?;
+        if let Some(ops) = expected.as_object_mut() {
+            if ops.insert("$in".to_string(), values).is_some() {
+                return Err(ExecError::InvalidQuery(format!(
+                    "column '{}' has both $in and $in_query",
+                    column
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn subquery_values(&self, column: &str, query: Value) -> Result<Value, ExecError> {
+        let invalid = |reason: String| ExecError::InvalidQuery(format!("$in_query on column '{}' {}", column, reason));
+        let query: ReadCommand = serde_json::from_value(query).map_err(|err| invalid(format!("is not a read: {}", err)))?;
+        let [selected] = query.columns.as_slice() else {
+            return Err(invalid("needs exactly one column".to_string()));
+        };
+        if query.is_paginated() {
+            return Err(invalid("can't be paginated".to_string()));
+        }
+        let selected = selected.clone();
+        let rows = match self.read_capped(query, self.max_rows)? {
+            Output::Truncated { .. } => {
+                return Err(invalid(format!(
+                    "returns more than the maximum of {} rows",
+                    self.max_rows.unwrap_or_default()
+                )))
+            }
+            Output::Rows(rows) => rows,
+            other => return Err(invalid(format!("returned {:?}", other))),
+        };
+        let values = rows.into_iter().map(|mut row| row.remove(&selected).unwrap_or(Value::Null));
+        Ok(Value::Array(values.collect()))
+    }
+
+    // reading a view reads the rows its query returns, narrowed by the
+    // caller's filter and limit
+    fn read_view(&self, view: &ReadCommand, cmd: &ReadCommand) -> Result<Vec<Row>, ExecError> {
+        let filter = self.check_view_read(view, cmd)?;
+        let rows: Vec<Row> = self
+            .read(view)?
+            .into_iter()
+            .filter(|row| filter.matches(row))
+            .collect();
+        Ok(finish(rows, cmd, &self.read_column_types(view), &self.read_column_collations(view)))
+    }
+
+    // like `check_read` for a read `cmd` of `view`, without reading its rows
+    pub(crate) fn check_view_read(&self, view: &ReadCommand, cmd: &ReadCommand) -> Result<Filter, ExecError> {
+        let unsupported = cmd.join.is_some() || !cmd.aggregates.is_empty() || !cmd.group_by.is_empty();
+        if unsupported || !cmd.having.is_empty() || cmd.is_paginated() || cmd.changed_since.is_some() {
+            return Err(ExecError::InvalidQuery(format!(
+                "view '{}' can't be read with a join, aggregates, group_by, having, pagination or changed_since",
+                cmd.table
+            )));
+        }
+        check_sample(cmd)?;
+        let resolved = self.resolve_subqueries(&cmd.filter)?;
+        let filter = Filter::compile(&resolved, &self.read_column_types(view), &self.read_column_collations(view))?;
+
+        let grouped = !view.aggregates.is_empty() || !view.group_by.is_empty();
+        let referenced = column_conditions(&cmd.filter).into_iter().map(|(column, _)| column);
+        for column in referenced.chain(cmd.order_by.iter().map(|order| &order.column)) {
+            if grouped {
+                let known = view.group_by.contains(column)
+                    || view.aggregates.iter().any(|spec| spec.output_name() == *column);
+                if !known {
+                    return Err(ExecError::ColumnNotFound {
+                        table: cmd.table.clone(),
+                        column: column.clone(),
+                    });
+                }
+            } else if !self.views.contains_key(&view.table) {
+                let table = self.table(&view.table)?;
+                let joined = match &view.join {
+                    Some(join) => Some(self.table(&join.table)?),
+                    None => None,
+                };
+                read_column_exists(view, table, joined, column)?;
+            }
+        }
+        Ok(filter)
+    }
+
+    // declared types of the columns a read can reference, keyed like its filter
+    pub(crate) fn read_column_types(&self, cmd: &ReadCommand) -> HashMap<String, ColumnType> {
+        self.read_columns(cmd, col_type_of)
+    }
+
+    // collations of the columns a read can reference that don't compare by bytes
+    fn read_column_collations(&self, cmd: &ReadCommand) -> HashMap<String, Collation> {
+        self.read_columns(cmd, collation_of)
+    }
+
+    fn read_columns<T>(&self, cmd: &ReadCommand, of: fn(&ColumnDefinition) -> Option<T>) -> HashMap<String, T> {
+        if let Some(view) = self.views.get(&cmd.table) {
+            return self.read_columns(view, of);
+        }
+        let Ok(table) = self.table(&cmd.table) else {
+            return HashMap::new();
+        };
+        match &cmd.join {
+            Some(join) => {
+                let mut columns = columns_with(Some(&cmd.table), table, of);
+                if let Ok(joined) = self.table(&join.table) {
+                    columns.extend(columns_with(Some(&join.table), joined, of));
+                }
+                columns
+            }
+            None => columns_with(None, table, of),
+        }
+    }
+
+    // joined rows carry every column prefixed with its table name, e.g. "orders.id".
+    // when one side joins on its primary key, or on the leading column of a
+    // secondary index, that side is probed and the other side drives the join
+    // (and the output order). a left join is always driven by the read table.
+    // a row without the join column joins like one holding null, to nothing
+    fn join_rows(
+        &self,
+        left_name: &str,
+        left: &Table<B>,
+        join: &JoinClause,
+    ) -> Result<Vec<Row>, ExecError> {
+        let right = self.table(&join.table)?;
+        require_column(left_name, left, &join.on.left)?;
+        require_column(&join.table, right, &join.on.right)?;
+        let outer = join.kind == JoinKind::Left;
+        let unmatched: Row = right.columns.keys().map(|column| (column.clone(), Value::Null)).collect();
+
+        let mut joined = Vec::new();
+        if let Some(probe) = join_probe(right, &join.on.right) {
+            for l in left.rows() {
+                let matches = probe.rows(right, column_value(&l, &join.on.left));
+                if outer && matches.is_empty() {
+                    joined.push(merge_rows(left_name, &l, &join.table, &unmatched));
+                }
+                for r in matches {
+                    joined.push(merge_rows(left_name, &l, &join.table, &r));
+                }
+            }
+        } else if let Some(probe) = join_probe(left, &join.on.left).filter(|_| !outer) {
+            for r in right.rows() {
+                for l in probe.rows(left, column_value(&r, &join.on.right)) {
+                    joined.push(merge_rows(left_name, &l, &join.table, &r));
+                }
+            }
+        } else {
+            for l in left.rows() {
+                let value = column_value(&l, &join.on.left);
+                let before = joined.len();
+                if !value.is_null() {
+                    for r in right.rows() {
+                        if values_equal(value, column_value(&r, &join.on.right)) {
+                            joined.push(merge_rows(left_name, &l, &join.table, &r));
+                        }
+                    }
+                }
+                if outer && joined.len() == before {
+                    joined.push(merge_rows(left_name, &l, &join.table, &unmatched));
+                }
+            }
+        }
+        Ok(joined)
+    }
+}
+
+pub(crate) fn is_grouped(cmd: &ReadCommand) -> bool {
+    !cmd.aggregates.is_empty() || !cmd.group_by.is_empty()
+}
+
+// the primary key to probe when an unjoined read's filter pins its single
+// key column to one value; the rest of the filter still applies to that row
+pub(crate) fn key_lookup<B: StorageBackend>(table: &Table<B>, cmd: &ReadCommand) -> Option<Key> {
+    if cmd.join.is_some() {
+        return None;
+    }
+    let column = table.primary_key.single()?;
+    // keys are found by their bytes, which a collated column doesn't match by
+    if table.columns.get(column).is_some_and(|def| !def.collation.is_binary()) {
+        return None;
+    }
+    let value = equality_operand(cmd.filter.get(column)?)?;
+    Some(Key(value.clone())).filter(|key| !key.0.is_null())
+}
+
+// the string filter of an update or delete checked against `table` and
+// compiled, with the read of its conditions that finds the rows to look at
+pub(crate) fn compile_write_filter<B: StorageBackend>(
+    table_name: &str,
+    table: &Table<B>,
+    filter: &str,
+) -> Result<(ReadCommand, Filter), ExecError> {
+    let filter = parse_filter(filter).map_err(ExecError::InvalidQuery)?;
+    for (column, _) in column_conditions(&filter) {
+        require_column(table_name, table, column)?;
+    }
+    let compiled = Filter::compile(&filter, &column_types(table), &column_collations(table))?;
+    Ok((ReadCommand { table: table_name.to_string(), filter, ..ReadCommand::default() }, compiled))
+}
+
+// the live rows of `table` that `filter` matches in key order, found through
+// the primary key or a secondary index like a read when `lookup` pins one
+fn matching<'t, B: StorageBackend>(table: &'t Table<B>, lookup: &ReadCommand, filter: &Filter) -> Vec<(Key, Cow<'t, Row>)> {
+    let keys: Vec<Key> = match (key_lookup(table, lookup), index_lookup(table, lookup)) {
+        (Some(key), _) => vec![key],
+        (None, Some((_, keys))) => keys.into_iter().collect(),
+        (None, None) => {
+            return table
+                .entries()
+                .filter(|(_, row)| filter.matches(row))
+                .map(|(key, row)| (key.clone(), row))
+                .collect()
+        }
+    };
+    keys.into_iter()
+        .filter_map(|key| table.get(&key).map(|row| (key, row)))
+        .filter(|(_, row)| filter.matches(row))
+        .collect()
+}
+
+// a cursor is the key of the last row of a page as hex-encoded JSON, opaque
+// to clients
+fn encode_cursor(key: &Key) -> String {
+    key.0.to_string().bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Result<Key, ExecError> {
+    let invalid = || ExecError::InvalidQuery(format!("invalid cursor '{}'", cursor));
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| cursor.get(i..i + 2).and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(invalid)?;
+    serde_json::from_slice(&bytes).map(Key).map_err(|_| invalid())
+}
+
+// projection, distinct and limit, in that order. distinct keeps the first of
+// equal rows so the result order stays that of the rows read
+fn finish(
+    mut rows: Vec<Row>,
+    cmd: &ReadCommand,
+    types: &HashMap<String, ColumnType>,
+    collations: &HashMap<String, Collation>,
+) -> Vec<Row> {
+    sort_rows(&mut rows, &cmd.order_by, types, collations);
+    if !cmd.columns.is_empty() {
+        for row in &mut rows {
+            *row = cmd
+                .columns
+                .iter()
+                .map(|column| (column.clone(), row.remove(column).unwrap_or(Value::Null)))
+                .collect();
+        }
+    }
+    if cmd.distinct {
+        let mut seen = BTreeSet::new();
+        rows.retain(|row| seen.insert(Key(Value::Object(row.clone().into_iter().collect()))));
+    }
+    if let Some(offset) = cmd.offset {
+        rows.drain(..offset.min(rows.len()));
+    }
+    match cmd.sample {
+        Some(size) => sample(rows, size, cmd.seed),
+        None => {
+            if let Some(limit) = cmd.limit {
+                rows.truncate(limit);
+            }
+            rows
+        }
+    }
+}
+
+// a stable sort, so rows that tie on every `order_by` column keep their order.
+// values are compared typed by their column's type
+fn sort_rows(
+    rows: &mut [Row],
+    order_by: &[OrderBy],
+    types: &HashMap<String, ColumnType>,
+    collations: &HashMap<String, Collation>,
+) {
+    if order_by.is_empty() {
+        return;
+    }
+    rows.sort_by(|a, b| {
+        order_by
+            .iter()
+            .map(|order| {
+                let col_type = types.get(&order.column).copied();
+                let typed = |row: &Row| Typed::of(col_type, row.get(&order.column).unwrap_or(&Value::Null));
+                let ord = match (typed(a), typed(b)) {
+                    (Typed::Text(a), Typed::Text(b)) => {
+                        collations.get(&order.column).copied().unwrap_or_default().compare(&a, &b)
+                    }
+                    (a, b) => a.cmp(&b),
+                };
+                match order.direction {
+                    Direction::Asc => ord,
+                    Direction::Desc => ord.reverse(),
+                }
+            })
+            .find(|ord| ord.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+// reservoir sampling: one pass in which every row is equally likely to end up
+// among the `size` kept. the kept rows stay in their original order
+fn sample(rows: impl IntoIterator<Item = Row>, size: usize, seed: Option<u64>) -> Vec<Row> {
+    let mut rng = seed.map_or_else(Rng::from_entropy, Rng::seeded);
+    let mut reservoir: Vec<(usize, Row)> = Vec::new();
+    for (i, row) in rows.into_iter().enumerate() {
+        if reservoir.len() < size {
+            reservoir.push((i, row));
+            continue;
+        }
+        let slot = rng.below(i as u64 + 1) as usize;
+        if slot < size {
+            reservoir[slot] = (i, row);
+        }
+    }
+    reservoir.sort_by_key(|(i, _)| *i);
+    reservoir.into_iter().map(|(_, row)| row).collect()
+}
+
+// the read with its `where` string folded into its map filter
+pub(crate) fn merge_where(cmd: &ReadCommand) -> Result<Cow<'_, ReadCommand>, ExecError> {
+    let Some(where_filter) = &cmd.where_filter else {
+        return Ok(Cow::Borrowed(cmd));
+    };
+    let invalid = |reason: String| ExecError::InvalidQuery(format!("invalid where '{}': {}", where_filter, reason));
+    let parsed = parse_filter(where_filter).map_err(invalid)?;
+    let mut merged = cmd.clone();
+    merged.filter = and_filters(merged.filter, parsed).map_err(invalid)?;
+    merged.where_filter = None;
+    Ok(Cow::Owned(merged))
+}
+
+// whether `table` can take `adding` more rows under its quota
+fn check_quota<B: StorageBackend>(table_name: &str, table: &Table<B>, adding: usize) -> Result<(), ExecError> {
+    let Some(limit) = table.max_rows else {
+        return Ok(());
+    };
+    let count = table.len();
+    if count.saturating_add(adding) > limit {
+        return Err(ExecError::QuotaExceeded {
+            table: table_name.to_string(),
+            limit,
+            count,
+        });
+    }
+    Ok(())
+}
+
+// checks a table's changed set of columns like a new table's schema
+fn check_columns<B: StorageBackend>(table_name: &str, table: &Table<B>, columns: &HashMap<String, ColumnDefinition>) -> Result<(), ExecError> {
+    let schema = CreateCommand::Table {
+        table: table_name.to_string(),
+        primary_key: table.primary_key.clone(),
+        rows: columns.clone(),
+        ttl_seconds: table.ttl_seconds,
+        storage: table.storage(),
+        implicit_default: false,
+        timestamps: false,
+        max_rows: None,
+    };
+    validator::validate_schema(&schema).map_err(|error| ExecError::InvalidSchema {
+        table: table_name.to_string(),
+        error,
+    })?;
+    validator::validate_create_table(table_name, &table.primary_key, columns)
+}
+
+// whether `column` of `table` can be dropped or renamed
+fn check_schema_change<B: StorageBackend>(table_name: &str, table: &Table<B>, column: &str, change: &str) -> Result<(), ExecError> {
+    if !table.columns.contains_key(column) {
+        return Err(ExecError::ColumnNotFound {
+            table: table_name.to_string(),
+            column: column.to_string(),
+        });
+    }
+    if table.primary_key.contains(column) {
+        return Err(ExecError::InvalidQuery(format!("key column '{}' can't be {}", column, change)));
+    }
+    if table.timestamps && [CREATED_AT_FIELD, UPDATED_AT_FIELD].contains(&column) {
+        return Err(ExecError::InvalidQuery(format!("timestamp column '{}' can't be {}", column, change)));
+    }
+    Ok(())
+}
+
+// `table` with new columns, rows and indexes, its settings kept. the rows must
+// meet the columns' unique constraints
+fn rebuild<B: StorageBackend>(
+    table_name: &str,
+    table: &Table<B>,
+    backend: &B,
+    columns: HashMap<String, ColumnDefinition>,
+    rows: Vec<(Key, Row, Option<u64>)>,
+    indexes: Vec<IndexDefinition>,
+) -> Result<Table<B>, ExecError> {
+    cancel::check()?;
+    let mut rebuilt = Table::new(
+        table.primary_key.clone(),
+        columns,
+        table.ttl_seconds,
+        table.storage(),
+        backend,
+    );
+    rebuilt.timestamps = table.timestamps;
+    rebuilt.max_rows = table.max_rows;
+    check_unique(table_name, &rebuilt, rows.iter().map(|(_, row, _)| row), |_| false)?;
+    for (key, row, stamp) in rows {
+        rebuilt.insert_row(key, row, stamp);
+    }
+    for definition in indexes {
+        let index = Index::new(table_name, &rebuilt, definition)?;
+        rebuilt.add_index(index);
+    }
+    Ok(rebuilt)
+}
+
+// the `changed_since` time of a read in microseconds since the epoch
+fn check_changed_since<B: StorageBackend>(cmd: &ReadCommand, table: &Table<B>) -> Result<Option<i64>, ExecError> {
+    let Some(since) = &cmd.changed_since else {
+        return Ok(None);
+    };
+    if !table.timestamps {
+        return Err(ExecError::InvalidQuery(format!(
+            "changed_since needs a table with timestamps, but '{}' has none",
+            cmd.table
+        )));
+    }
+    if cmd.join.is_some() || is_grouped(cmd) || cmd.is_paginated() {
+        return Err(ExecError::InvalidQuery(
+            "a read with changed_since can't have a join, aggregates or pagination".to_string(),
+        ));
+    }
+    parse_rfc3339(since)
+        .map(Some)
+        .ok_or_else(|| ExecError::InvalidQuery(format!("changed_since '{}' is not an RFC 3339 time", since)))
+}
+
+// the rows updated after `since`, the least recently updated first
+fn changed_since(rows: Vec<Row>, since: i64) -> Vec<Row> {
+    let updated_at = |row: &Row| row.get(UPDATED_AT_FIELD).and_then(Value::as_str).and_then(parse_rfc3339);
+    let mut changed: Vec<(i64, Row)> = rows
+        .into_iter()
+        .filter_map(|row| updated_at(&row).filter(|at| *at > since).map(|at| (at, row)))
+        .collect();
+    changed.sort_by_key(|(at, _)| *at);
+    changed.into_iter().map(|(_, row)| row).collect()
+}
+
+fn check_sample(cmd: &ReadCommand) -> Result<(), ExecError> {
+    if cmd.sample.is_some() && (cmd.limit.is_some() || cmd.offset.is_some() || cmd.is_paginated()) {
+        return Err(ExecError::InvalidQuery(
+            "a sampled read can't have a limit, offset or pagination".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn require_column<B: StorageBackend>(table_name: &str, table: &Table<B>, column: &str) -> Result<(), ExecError> {
+    if table.columns.contains_key(column) {
+        Ok(())
+    } else {
+        Err(ExecError::ColumnNotFound {
+            table: table_name.to_string(),
+            column: column.to_string(),
+        })
+    }
+}
+
+// columns of a joined read are addressed as "table.column"
+fn read_column_exists<B: StorageBackend>(
+    cmd: &ReadCommand,
+    table: &Table<B>,
+    joined: Option<&Table<B>>,
+    column: &str,
+) -> Result<(), ExecError> {
+    let (Some(join), Some(joined)) = (&cmd.join, joined) else {
+        return require_column(&cmd.table, table, column);
+    };
+    let found = match column.split_once('.') {
+        Some((t, c)) if t == cmd.table => table.columns.contains_key(c),
+        Some((t, c)) if t == join.table => joined.columns.contains_key(c),
+        _ => false,
+    };
+    if found {
+        Ok(())
+    } else {
+        Err(ExecError::ColumnNotFound {
+            table: format!("{} join {}", cmd.table, join.table),
+            column: column.to_string(),
+        })
+    }
+}
+
+// points a read's table, join and subqueries that name `from` at `to`
+fn rename_in_read(read: &mut ReadCommand, from: &str, to: &str) {
+    let join = read.join.as_mut().map(|join| &mut join.table);
+    for table in std::iter::once(&mut read.table).chain(join) {
+        if table == from {
+            *table = to.to_string();
+        }
+    }
+    for expected in read.filter.values_mut().chain(read.having.values_mut()) {
+        rename_in_subqueries(expected, from, to);
+    }
+}
+
+// subqueries are kept as JSON in filters, {"$in_query": read} at any depth
+fn rename_in_subqueries(value: &mut Value, from: &str, to: &str) {
+    match value {
+        Value::Object(map) => {
+            if let Some(query) = map.get_mut("$in_query") {
+                for pointer in ["/table", "/join/table"] {
+                    if let Some(table) = query.pointer_mut(pointer).filter(|table| *table == from) {
+                        *table = Value::String(to.to_string());
+                    }
+                }
+            }
+            map.values_mut().for_each(|value| rename_in_subqueries(value, from, to));
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| rename_in_subqueries(value, from, to)),
+        _ => {}
+    }
+}
+
+pub(crate) fn column_types<B: StorageBackend>(table: &Table<B>) -> HashMap<String, ColumnType> {
+    columns_with(None, table, col_type_of)
+}
+
+pub(crate) fn column_collations<B: StorageBackend>(table: &Table<B>) -> HashMap<String, Collation> {
+    columns_with(None, table, collation_of)
+}
+
+fn col_type_of(def: &ColumnDefinition) -> Option<ColumnType> {
+    Some(def.col_type)
+}
+
+fn collation_of(def: &ColumnDefinition) -> Option<Collation> {
+    Some(def.collation).filter(|collation| !collation.is_binary())
+}
+
+// `of` of every column it gives a value for, keyed by the column's name and, as
+// in joined rows, prefixed with `table_name`
+fn columns_with<T, B: StorageBackend>(
+    table_name: Option<&str>,
+    table: &Table<B>,
+    of: fn(&ColumnDefinition) -> Option<T>,
+) -> HashMap<String, T> {
+    table
+        .columns
+        .iter()
+        .filter_map(|(column, def)| {
+            let name = match table_name {
+                Some(table_name) => format!("{}.{}", table_name, column),
+                None => column.clone(),
+            };
+            Some((name, of(def)?))
+        })
+        .collect()
+}
+
+// how one side of a join finds the rows holding a value in its join column
+pub(crate) enum JoinProbe<'a> {
+    Key,
+    Index(&'a Index),
+}
+
+// the primary key when it is the join column alone, else a secondary index
+// leading with the column that holds every row
+pub(crate) fn join_probe<'a, B: StorageBackend>(table: &'a Table<B>, column: &str) -> Option<JoinProbe<'a>> {
+    if table.primary_key.single() == Some(column) {
+        return Some(JoinProbe::Key);
+    }
+    // entries are found by their bytes, which a collated column doesn't match by
+    if table.columns.get(column).is_some_and(|def| !def.collation.is_binary()) {
+        return None;
+    }
+    table
+        .indexes
+        .iter()
+        .find(|index| index.definition.predicate.is_none() && index.definition.columns[0] == column)
+        .map(JoinProbe::Index)
+}
+
+impl JoinProbe<'_> {
+    // null joins to nothing
+    fn rows<'t, B: StorageBackend>(&self, table: &'t Table<B>, value: &Value) -> Vec<Cow<'t, Row>> {
+        if value.is_null() {
+            return Vec::new();
+        }
+        match self {
+            JoinProbe::Key => table.get(&Key(value.clone())).into_iter().collect(),
+            JoinProbe::Index(index) => index.leading(value).filter_map(|key| table.get(key)).collect(),
+        }
+    }
+
+    // e.g. "index_lookup on products.id", with the index named when it isn't
+    // the primary key
+    pub(crate) fn describe(&self, table_name: &str, column: &str) -> String {
+        match self {
+            JoinProbe::Key => format!("index_lookup on {}.{}", table_name, column),
+            JoinProbe::Index(index) => format!("index_lookup on {}.{} ({})", table_name, column, index.definition.name),
+        }
+    }
+}
+
+// a column's value, null when the row doesn't hold the column
+fn column_value<'a>(row: &'a Row, column: &str) -> &'a Value {
+    static NULL: Value = Value::Null;
+    row.get(column).unwrap_or(&NULL)
+}
+
+fn merge_rows(left_name: &str, left: &Row, right_name: &str, right: &Row) -> Row {
+    let prefixed = |table: &str, row: &Row| {
+        row.iter()
+            .map(|(column, value)| (format!("{}.{}", table, column), value.clone()))
+            .collect::<Vec<_>>()
+    };
+    prefixed(left_name, left)
+        .into_iter()
+        .chain(prefixed(right_name, right))
+        .collect()
+}
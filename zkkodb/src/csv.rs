@@ -0,0 +1,204 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::BufRead;
+use serde_json::Value;
+
+use crate::backend::StorageBackend;
+use crate::constraints::check_unique;
+use crate::database::{Database, ExecError, Key, Row};
+use crate::result::ColumnType;
+use crate::validator::{self, coerce_to_type};
+
+// why one record of a CSV file couldn't be inserted. `line` is the file's
+// 1-based line the record starts on, the header being line 1
+#[derive(Debug, PartialEq)]
+pub struct LineError {
+    pub line: usize,
+    pub error: ExecError,
+}
+
+impl<B: StorageBackend> Database<B> {
+    // checks a CSV file against `table` as if every record were inserted in
+    // order, without changing anything: the same type, not_null, primary key,
+    // unique and foreign key checks, with keys and unique values taken by
+    // earlier records counting as taken. the first line names the columns;
+    // an empty unquoted field is null. an empty list means every insert would
+    // succeed. an unreadable file or an unknown header column is an Err
+    pub fn validate_csv(&self, table_name: &str, reader: impl BufRead) -> Result<Vec<LineError>, ExecError> {
+        let table = self.table(table_name)?;
+        let mut records = Records::new(reader);
+        let header = match records.next_record()? {
+            Some((_, Ok(header))) => header,
+            Some((_, Err(reason))) => return Err(ExecError::InvalidQuery(format!("invalid CSV header: {}", reason))),
+            None => return Ok(Vec::new()),
+        };
+        let mut columns = Vec::with_capacity(header.len());
+        for field in header {
+            let column = field.text;
+            let Some(def) = table.columns.get(&column) else {
+                return Err(ExecError::ColumnNotFound {
+                    table: table_name.to_string(),
+                    column,
+                });
+            };
+            columns.push((column, def.col_type));
+        }
+
+        let mut errors = Vec::new();
+        let mut keys = BTreeSet::new();
+        let mut unique: HashMap<&String, BTreeSet<Key>> = HashMap::new();
+        while let Some((line, record)) = records.next_record()? {
+            let fields = match record {
+                Ok(fields) if fields.len() == columns.len() => fields,
+                Ok(fields) => {
+                    let reason = format!("line has {} fields, the header has {}", fields.len(), columns.len());
+                    errors.push(LineError { line, error: ExecError::InvalidQuery(reason) });
+                    continue;
+                }
+                Err(reason) => {
+                    errors.push(LineError { line, error: ExecError::InvalidQuery(reason) });
+                    continue;
+                }
+            };
+            let row: Row = columns
+                .iter()
+                .zip(fields)
+                .map(|((column, col_type), field)| (column.clone(), field.value(*col_type)))
+                .collect();
+
+            let checked = validator::validate_insert(table_name, table, row).and_then(|row| {
+                let key = table.key_of(&row);
+                if table.get(&key).is_some() || keys.contains(&key) {
+                    return Err(ExecError::DuplicateKey {
+                        table: table_name.to_string(),
+                        key: key.0,
+                    });
+                }
+                check_unique(table_name, table, [&row], |_| false)?;
+                for (column, taken) in &unique {
+                    if let Some(value) = row.get(*column).filter(|value| taken.contains(&Key((*value).clone()))) {
+                        return Err(ExecError::UniqueViolation {
+                            table: table_name.to_string(),
+                            column: column.to_string(),
+                            value: value.clone(),
+                        });
+                    }
+                }
+                self.check_references(table_name, &row)?;
+                Ok((key, row))
+            });
+            match checked {
+                Ok((key, row)) => {
+                    keys.insert(key);
+                    for column in table.unique.keys() {
+                        if let Some(value) = row.get(column).filter(|value| !value.is_null()) {
+                            unique.entry(column).or_default().insert(Key(value.clone()));
+                        }
+                    }
+                }
+                Err(error) => errors.push(LineError { line, error }),
+            }
+        }
+        Ok(errors)
+    }
+}
+
+#[derive(Debug)]
+struct Field {
+    text: String,
+    quoted: bool,
+}
+
+impl Field {
+    // the field as a value of the column's type where it converts, otherwise as
+    // a string that validation then rejects
+    fn value(self, col_type: ColumnType) -> Value {
+        if self.text.is_empty() && !self.quoted {
+            return Value::Null;
+        }
+        let text = Value::String(self.text);
+        coerce_to_type(col_type, &text).unwrap_or(text)
+    }
+}
+
+// a record's fields, or why they couldn't be split
+type Record = Result<Vec<Field>, String>;
+
+// splits a reader into CSV records, RFC 4180 style: fields are separated by
+// commas and may be quoted, with "" for a quote and newlines kept inside quotes
+struct Records<R> {
+    reader: R,
+    line: usize,
+}
+
+impl<R: BufRead> Records<R> {
+    fn new(reader: R) -> Records<R> {
+        Records { reader, line: 0 }
+    }
+
+    // the line a record starts on and its fields, or why it is malformed
+    fn next_record(&mut self) -> Result<Option<(usize, Record)>, ExecError> {
+        let io = |err: std::io::Error| ExecError::Io(err.to_string());
+        let mut text = String::new();
+        loop {
+            text.clear();
+            let start = self.line + 1;
+            loop {
+                let read = self.reader.read_line(&mut text).map_err(io)?;
+                if read == 0 {
+                    if text.is_empty() {
+                        return Ok(None);
+                    }
+                    return Ok(Some((start, Err("unterminated quoted field".to_string()))));
+                }
+                self.line += 1;
+                // an odd number of quotes so far means a quoted field goes on
+                if text.matches('"').count().is_multiple_of(2) {
+                    break;
+                }
+            }
+            let record = text.strip_suffix('\n').unwrap_or(&text);
+            let record = record.strip_suffix('\r').unwrap_or(record);
+            // blank lines separate nothing
+            if !record.is_empty() {
+                return Ok(Some((start, split_fields(record))));
+            }
+        }
+    }
+}
+
+fn split_fields(text: &str) -> Record {
+    let mut fields = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        let mut field = Field { text: String::new(), quoted: false };
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            field.quoted = true;
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.text.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.text.push(c),
+                    None => return Err("unterminated quoted field".to_string()),
+                }
+            }
+        }
+        while let Some(&c) = chars.peek() {
+            if c == ',' {
+                break;
+            }
+            if field.quoted {
+                return Err(format!("unexpected '{}' after a quoted field", c));
+            }
+            field.text.push(c);
+            chars.next();
+        }
+        fields.push(field);
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
@@ -0,0 +1,912 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::backend::{MemoryBackend, StorageBackend};
+use crate::cancel;
+use crate::codec::Codec;
+use crate::crud;
+use crate::events::ChangeEvent;
+use crate::parser::{
+    ColumnDefinition, Command, CreateCommand, DeleteCommand, ListCommand, PrimaryKey, ReadCommand,
+    StorageLayout, UpdateCommand,
+};
+use crate::explain::Plan;
+use crate::filter::uses_fuzzy;
+use crate::idempotency::SeenKeys;
+use crate::index::{Index, IndexDefinition};
+use crate::describe::{Description, TableSummary};
+use crate::metrics::{Metrics, MetricsSnapshot};
+use crate::result::{ColumnType, QueryResult};
+use crate::stats::Stats;
+use crate::storage::{VerifyReport, INSERTED_AT_FIELD};
+use crate::store::RowStore;
+use crate::users;
+use crate::utils::{compare_values, glob_match, now_millis, Stamp};
+use crate::validator;
+use crate::wal::{CommandLog, Wal};
+
+pub type Row = HashMap<String, Value>;
+
+// primary key value, ordered so a table's rows stay sorted by key.
+// composite keys hold an array and compare column by column
+#[derive(Debug, Clone)]
+pub struct Key(pub Value);
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_values(&self.0, &other.0)
+    }
+}
+
+// the columns a table created with `"timestamps": true` keeps up to date
+pub(crate) const CREATED_AT_FIELD: &str = "created_at";
+pub(crate) const UPDATED_AT_FIELD: &str = "updated_at";
+
+#[derive(Debug, Clone)]
+pub struct Table<B = MemoryBackend> {
+    pub primary_key: PrimaryKey,
+    pub columns: HashMap<String, ColumnDefinition>,
+    pub ttl_seconds: Option<u64>,
+    pub timestamps: bool,
+    // the row quota: inserts that would take the table past it fail
+    pub max_rows: Option<usize>,
+    pub(crate) rows: RowStore<B>,
+    // insertion time in unix milliseconds of every row, kept for ttl tables only
+    pub(crate) inserted_at: BTreeMap<Key, u64>,
+    pub(crate) indexes: Vec<Index>,
+    // value to primary key for every column flagged `unique` other than a
+    // single-column primary key. nulls aren't held, so they may repeat
+    pub(crate) unique: HashMap<String, BTreeMap<Key, Key>>,
+}
+
+impl<B: StorageBackend> Table<B> {
+    // rows-layout tables keep their rows in a new store from `backend`
+    pub(crate) fn new(
+        primary_key: PrimaryKey,
+        columns: HashMap<String, ColumnDefinition>,
+        ttl_seconds: Option<u64>,
+        storage: StorageLayout,
+        backend: &B,
+    ) -> Table<B> {
+        let unique = columns
+            .iter()
+            .filter(|(column, def)| def.unique && primary_key.single() != Some(column.as_str()))
+            .map(|(column, _)| (column.clone(), BTreeMap::new()))
+            .collect();
+        Table {
+            rows: RowStore::new(storage, &columns, backend),
+            unique,
+            primary_key,
+            columns,
+            ttl_seconds,
+            timestamps: false,
+            max_rows: None,
+            inserted_at: BTreeMap::new(),
+            indexes: Vec::new(),
+        }
+    }
+
+    pub fn storage(&self) -> StorageLayout {
+        self.rows.layout()
+    }
+
+    pub fn len(&self) -> usize {
+        match self.ttl_seconds {
+            Some(_) => {
+                let now = now_millis();
+                self.rows.keys().filter(|key| !self.is_expired(key, now)).count()
+            }
+            None => self.rows.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // composite keys are the array of their column values
+    pub fn key_of(&self, row: &Row) -> Key {
+        let value = |column: &str| row.get(column).cloned().unwrap_or(Value::Null);
+        match self.primary_key.single() {
+            Some(column) => Key(value(column)),
+            None => Key(Value::Array(
+                self.primary_key.columns().iter().map(|column| value(column)).collect(),
+            )),
+        }
+    }
+
+    // rows in primary key order. columnar tables assemble each row on the fly
+    pub fn rows(&self) -> impl Iterator<Item = Cow<'_, Row>> {
+        self.entries().map(|(_, row)| row)
+    }
+
+    // live rows with their keys; expired rows stay in `rows` until purged
+    // but are invisible to everything else
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&Key, Cow<'_, Row>)> {
+        self.entries_after(None)
+    }
+
+    // live rows whose key sorts after `after`, all of them for None. the scan
+    // stops early once the running command is cancelled
+    pub(crate) fn entries_after(&self, after: Option<&Key>) -> impl Iterator<Item = (&Key, Cow<'_, Row>)> {
+        let now = now_millis();
+        let rows = match after {
+            Some(after) => self.rows.iter_after(after),
+            None => self.rows.iter(),
+        };
+        rows.take_while(|_| !cancel::requested())
+            .filter(move |(key, _)| !self.is_expired(key, now))
+    }
+
+    // live rows holding only `columns`
+    pub(crate) fn project<'a>(&'a self, columns: &'a [String]) -> impl Iterator<Item = Row> + 'a {
+        let now = now_millis();
+        self.rows
+            .project(columns)
+            .take_while(|_| !cancel::requested())
+            .filter(move |(key, _)| !self.is_expired(key, now))
+            .map(|(_, row)| row)
+    }
+
+    pub(crate) fn get(&self, key: &Key) -> Option<Cow<'_, Row>> {
+        if self.is_expired(key, now_millis()) {
+            return None;
+        }
+        self.rows.get(key)
+    }
+
+    pub(crate) fn expired_keys(&self) -> Vec<Key> {
+        let now = now_millis();
+        self.rows.keys().filter(|key| self.is_expired(key, now)).cloned().collect()
+    }
+
+    // indexes every stored row, expired ones included since they may still be
+    // purged through the index-maintaining `remove_row`
+    pub(crate) fn add_index(&mut self, mut index: Index) {
+        for (key, row) in self.rows.iter() {
+            index.insert(key, &row);
+        }
+        self.indexes.push(index);
+    }
+
+    fn is_expired(&self, key: &Key, now: u64) -> bool {
+        let (Some(ttl), Some(at)) = (self.ttl_seconds, self.inserted_at.get(key)) else {
+            return false;
+        };
+        now.saturating_sub(*at) >= ttl.saturating_mul(1000)
+    }
+
+    // `inserted_at` is the row's original insertion time, None stamps it now
+    pub(crate) fn insert_row(&mut self, key: Key, row: Row, inserted_at: Option<u64>) {
+        if self.ttl_seconds.is_some() {
+            self.inserted_at.insert(key.clone(), inserted_at.unwrap_or_else(now_millis));
+        }
+        for index in &mut self.indexes {
+            index.insert(&key, &row);
+        }
+        for (column, values) in &mut self.unique {
+            if let Some(value) = row.get(column).filter(|value| !value.is_null()) {
+                values.insert(Key(value.clone()), key.clone());
+            }
+        }
+        self.rows.insert(key, row);
+    }
+
+    // the removed row's insertion time, for ttl tables
+    pub(crate) fn remove_row(&mut self, key: &Key) -> Option<u64> {
+        let tracked = !self.indexes.is_empty() || !self.unique.is_empty();
+        if let Some(row) = self.rows.get(key).filter(|_| tracked) {
+            for index in &mut self.indexes {
+                index.remove(key, &row);
+            }
+            // an expired row may have lost its value to a newer row already
+            for (column, values) in &mut self.unique {
+                let Some(value) = row.get(column).map(|value| Key(value.clone())) else {
+                    continue;
+                };
+                if values.get(&value) == Some(key) {
+                    values.remove(&value);
+                }
+            }
+        }
+        self.rows.remove(key);
+        self.inserted_at.remove(key)
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Output {
+    Done,
+    Rows(Vec<Row>),
+    // the result of a get, null when no row has the key
+    Row(Option<Row>),
+    Affected(usize),
+    Stats(Stats),
+    Plan(Plan),
+    // a page of a paginated read; reading again with `after` set to
+    // `next_cursor` returns the next one. null once there are no more rows
+    Page { rows: Vec<Row>, next_cursor: Option<String> },
+    // the first `max_rows` rows of a read without a limit that matched more
+    Truncated { rows: Vec<Row>, truncated: bool },
+    Metrics(MetricsSnapshot),
+    Description(Description),
+    // table names, sorted
+    Tables(Vec<String>),
+    // a `list_tables` with `details`, by name
+    TableSummaries(Vec<TableSummary>),
+    Verified(VerifyReport),
+    // a read with `with_types`
+    Result(QueryResult),
+    // recorded commands, in the order they ran
+    Commands(Vec<serde_json::Value>),
+    // an update with an `if` condition: the rows its filter found and how
+    // many of them also met the condition and were changed
+    Applied { matched: usize, applied: usize },
+    // the result of every command of a batch, in order
+    Batch(#[serde(serialize_with = "crate::batch::serialize_results")] Vec<Result<Output, ExecError>>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExecError {
+    TableExists(String),
+    TableNotFound(String),
+    ViewExists(String),
+    ColumnNotFound { table: String, column: String },
+    InvalidSchema { table: String, error: SchemaError },
+    TypeMismatch { column: String, expected: String },
+    NotNull { column: String },
+    // a value no column may hold, e.g. a computed NaN or infinite float
+    InvalidValue { column: String, reason: String },
+    DuplicateKey { table: String, key: Value },
+    UniqueViolation { table: String, column: String, value: Value },
+    ForeignKeyViolation { table: String, column: String, value: Value },
+    InvalidQuery(String),
+    // a transaction wrote a table another writer changed after it began
+    Conflict { table: String },
+    // a column can't be made not_null while `rows` rows hold null in it
+    NullsPresent { column: String, rows: usize },
+    // an insert would take `table` past its `max_rows` quota of `limit` rows;
+    // it holds `count`
+    QuotaExceeded { table: String, limit: usize, count: usize },
+    // a mutation against a database opened with `open_read_only`
+    ReadOnly,
+    // the session's role may not run the command
+    PermissionDenied(String),
+    // the session or user sent commands faster than the server's rate limit
+    RateLimited { key: String },
+    // the command's `CancelToken` was cancelled or timed out before it finished
+    Cancelled,
+    // the row with primary key `key` couldn't be backfilled
+    Backfill { key: Value, error: Box<ExecError> },
+    // command `index` of an atomic batch failed, so none of it applied
+    Batch { index: usize, error: Box<ExecError> },
+    Io(String),
+    Unsupported(String),
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecError::TableExists(table) => write!(f, "table '{}' already exists", table),
+            ExecError::TableNotFound(table) => write!(f, "table '{}' does not exist", table),
+            ExecError::ViewExists(view) => write!(f, "view '{}' already exists", view),
+            ExecError::ColumnNotFound { table, column } => {
+                write!(f, "column '{}' does not exist in table '{}'", column, table)
+            }
+            ExecError::InvalidSchema { table, error } => write!(f, "invalid schema for table '{}': {}", table, error),
+            ExecError::TypeMismatch { column, expected } => {
+                write!(f, "column '{}' expects a value of type '{}'", column, expected)
+            }
+            ExecError::NotNull { column } => write!(f, "column '{}' must not be null", column),
+            ExecError::InvalidValue { column, reason } => write!(f, "invalid value for column '{}': {}", column, reason),
+            ExecError::DuplicateKey { table, key } => {
+                write!(f, "duplicate primary key {} in table '{}'", key, table)
+            }
+            ExecError::UniqueViolation { table, column, value } => {
+                write!(f, "duplicate value {} in unique column {}.{}", value, table, column)
+            }
+            ExecError::ForeignKeyViolation { table, column, value } => write!(
+                f,
+                "foreign key {}.{} = {} violates referential integrity",
+                table, column, value
+            ),
+            ExecError::InvalidQuery(reason) => write!(f, "invalid query: {}", reason),
+            ExecError::Conflict { table } => write!(
+                f,
+                "transaction conflict: '{}' was changed by another writer since the transaction began",
+                table
+            ),
+            ExecError::NullsPresent { column, rows } => {
+                write!(f, "column '{}' can't be made not_null, {} row(s) hold null", column, rows)
+            }
+            ExecError::QuotaExceeded { table, limit, count } => {
+                write!(f, "table '{}' is limited to {} rows and holds {}", table, limit, count)
+            }
+            ExecError::ReadOnly => write!(f, "the database is read-only"),
+            ExecError::PermissionDenied(reason) => write!(f, "permission denied: {}", reason),
+            ExecError::RateLimited { key } => write!(f, "rate limit exceeded for {}", key),
+            ExecError::Cancelled => write!(f, "command cancelled"),
+            ExecError::Backfill { key, error } => write!(f, "backfill failed at key {}: {}", key, error),
+            ExecError::Batch { index, error } => write!(f, "command {} of the batch failed: {}", index, error),
+            ExecError::Io(err) => write!(f, "i/o error: {}", err),
+            ExecError::Unsupported(what) => write!(f, "unsupported command: {}", what),
+        }
+    }
+}
+
+impl ExecError {
+    // a stable name for the kind of error, e.g. "table_not_found"
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExecError::TableExists(_) => "table_exists",
+            ExecError::TableNotFound(_) => "table_not_found",
+            ExecError::ViewExists(_) => "view_exists",
+            ExecError::ColumnNotFound { .. } => "column_not_found",
+            ExecError::InvalidSchema { .. } => "invalid_schema",
+            ExecError::TypeMismatch { .. } => "type_mismatch",
+            ExecError::NotNull { .. } => "not_null",
+            ExecError::InvalidValue { .. } => "invalid_value",
+            ExecError::DuplicateKey { .. } => "duplicate_key",
+            ExecError::UniqueViolation { .. } => "unique_violation",
+            ExecError::ForeignKeyViolation { .. } => "foreign_key_violation",
+            ExecError::InvalidQuery(_) => "invalid_query",
+            ExecError::Conflict { .. } => "conflict",
+            ExecError::NullsPresent { .. } => "nulls_present",
+            ExecError::QuotaExceeded { .. } => "quota_exceeded",
+            ExecError::ReadOnly => "read_only",
+            ExecError::PermissionDenied(_) => "permission_denied",
+            ExecError::RateLimited { .. } => "rate_limited",
+            ExecError::Cancelled => "cancelled",
+            ExecError::Backfill { .. } => "backfill",
+            ExecError::Batch { .. } => "batch",
+            ExecError::Io(_) => "io",
+            ExecError::Unsupported(_) => "unsupported",
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+// why `validate_schema` rejected a table definition
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaError {
+    NoPrimaryKey,
+    PrimaryKeyColumnNotFound { column: String },
+    DuplicatePrimaryKeyColumn { column: String },
+    EmptyColumnName,
+    // a default whose JSON doesn't fit the column's type, e.g. "0" for an int
+    InvalidDefault { column: String, default: Value, col_type: ColumnType },
+    // a default like "today()" calling a function that doesn't exist
+    UnknownDefaultFunction { column: String, function: String },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::NoPrimaryKey => write!(f, "no primary key"),
+            SchemaError::PrimaryKeyColumnNotFound { column } => {
+                write!(f, "primary key column '{}' is not among the columns", column)
+            }
+            SchemaError::DuplicatePrimaryKeyColumn { column } => {
+                write!(f, "primary key lists column '{}' twice", column)
+            }
+            SchemaError::EmptyColumnName => write!(f, "a column has an empty name"),
+            SchemaError::InvalidDefault { column, default, col_type } => {
+                write!(f, "default {} of column '{}' is not a valid {}", default, column, col_type)
+            }
+            SchemaError::UnknownDefaultFunction { column, function } => {
+                write!(f, "default of column '{}' calls unknown function '{}'", column, function)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+#[derive(Debug, Default)]
+pub struct Database<B = MemoryBackend> {
+    // shared with snapshots; a write copies a table only while a snapshot
+    // still holds it
+    pub(crate) tables: HashMap<String, Arc<Table<B>>>,
+    pub(crate) views: HashMap<String, ReadCommand>,
+    pub(crate) subscribers: HashMap<String, Vec<Sender<ChangeEvent>>>,
+    pub(crate) wal: Option<Wal>,
+    // None until `enable_command_log`
+    pub(crate) command_log: Option<CommandLog>,
+    pub(crate) max_rows: Option<usize>,
+    // set inside a transaction: change events are held here until commit
+    pub(crate) buffered_events: Option<Vec<ChangeEvent>>,
+    // None until `enable_metrics`
+    pub(crate) metrics: Option<Metrics>,
+    // set by `open_read_only`: every mutation fails with `ReadOnly`
+    pub(crate) read_only: bool,
+    pub(crate) idempotency: SeenKeys,
+    // makes the stores of new tables
+    pub(crate) backend: B,
+    // the key of `open_encrypted` or the last `save_encrypted`, which backups
+    // and write-ahead log records are encrypted with
+    pub(crate) encryption: Codec,
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    // caps every read: reads without a limit return at most `max` rows and
+    // reads asking for more are rejected. None lifts the cap
+    pub fn set_max_rows(&mut self, max: Option<usize>) {
+        self.max_rows = max;
+    }
+
+    pub fn max_rows(&self) -> Option<usize> {
+        self.max_rows
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn table(&self, name: &str) -> Result<&Table<B>, ExecError> {
+        self.tables
+            .get(name)
+            .map(Arc::as_ref)
+            .ok_or_else(|| ExecError::TableNotFound(name.to_string()))
+    }
+
+    // table and view names share a namespace but are listed separately
+    pub fn table_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.tables.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    // names of the tables matching a glob like "log_*", all without one
+    pub fn list_tables(&self, pattern: Option<&str>) -> Vec<String> {
+        self.table_names()
+            .into_iter()
+            .filter(|name| pattern.is_none_or(|pattern| glob_match(pattern, name)))
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn view_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.views.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    pub(crate) fn table_mut(&mut self, name: &str) -> Result<&mut Table<B>, ExecError> {
+        self.tables
+            .get_mut(name)
+            .map(Arc::make_mut)
+            .ok_or_else(|| ExecError::TableNotFound(name.to_string()))
+    }
+
+    pub fn execute(&mut self, cmd: Command) -> Result<Output, ExecError> {
+        let timer = self.start_timer(&cmd);
+        let result = self.log_and_apply(cmd);
+        self.finish_timer(timer, result)
+    }
+
+    fn log_and_apply(&mut self, cmd: Command) -> Result<Output, ExecError> {
+        if self.read_only && cmd.is_mutating() {
+            return Err(ExecError::ReadOnly);
+        }
+        let cmd = self.resolve_defaults(users::hash_passwords(cmd)?)?;
+        let stamp = Stamp::now();
+        let mutating = cmd.is_mutating();
+        if let Some(wal) = self.wal.as_mut().filter(|_| mutating) {
+            wal.append(stamp, &cmd).map_err(|err| ExecError::Io(err.to_string()))?;
+        }
+        let logged = (mutating && self.command_log.is_some()).then(|| cmd.clone());
+        let output = match stamp.run(|| self.apply(cmd)) {
+            Ok(output) => output,
+            Err(err) => {
+                if let Some(wal) = self.wal.as_mut().filter(|_| mutating) {
+                    wal.discard_last().map_err(|err| ExecError::Io(err.to_string()))?;
+                }
+                return Err(err);
+            }
+        };
+        if let Some(cmd) = logged {
+            self.record(cmd);
+        }
+        Ok(output)
+    }
+
+    // runs a command that doesn't change the database through a shared reference
+    pub fn query(&self, cmd: Command) -> Result<Output, ExecError> {
+        let timer = self.start_timer(&cmd);
+        let result = self.query_capped(cmd, self.max_rows);
+        self.finish_timer(timer, result)
+    }
+
+    // like `query` with reads capped at `max_rows` instead of the database's cap
+    pub(crate) fn query_capped(&self, cmd: Command, max_rows: Option<usize>) -> Result<Output, ExecError> {
+        match cmd {
+            Command::Read(cmd) => self.read_capped(cmd, max_rows),
+            Command::Aggregate(cmd) if cmd.aggregates.is_empty() => {
+                Err(ExecError::InvalidQuery("aggregate needs at least one of `aggregates`".to_string()))
+            }
+            Command::Aggregate(cmd) => self.read_capped(cmd.into_read(), max_rows),
+            Command::Get { table, key } => Ok(Output::Row(self.get(&table, key)?)),
+            Command::Explain { inner } => Ok(Output::Plan(self.explain_command(&inner)?)),
+            Command::Stats { table } => Ok(Output::Stats(self.stats(table.as_deref())?)),
+            Command::Describe { table } => Ok(Output::Description(self.describe(&table)?)),
+            Command::ListTables { pattern, details: false } => Ok(Output::Tables(self.list_tables(pattern.as_deref()))),
+            Command::ListTables { pattern, details: true }
+            | Command::List(ListCommand::Tables { pattern }) => {
+                Ok(Output::TableSummaries(self.table_summaries(pattern.as_deref())))
+            }
+            Command::ListUsers => Ok(Output::Rows(self.list_users())),
+            Command::Metrics => self.metrics().map(Output::Metrics).ok_or_else(|| {
+                ExecError::InvalidQuery("metrics are not enabled, see `enable_metrics`".to_string())
+            }),
+            Command::ExportLog { since } => Ok(Output::Commands(self.export_log(since)?)),
+            Command::Backup { path } => {
+                self.backup(path).map_err(|err| ExecError::Io(err.to_string()))?;
+                Ok(Output::Done)
+            }
+            Command::Verify { dir, table } => Database::verify(dir, table.as_deref())
+                .map(Output::Verified)
+                .map_err(|err| ExecError::Io(err.to_string())),
+            Command::Begin
+            | Command::Commit
+            | Command::Rollback
+            | Command::Savepoint { .. }
+            | Command::RollbackTo { .. } => Err(ExecError::InvalidQuery(
+                "transactions need a session, see `execute_in`".to_string(),
+            )),
+            Command::Prepare { .. } | Command::Execute { .. } => Err(ExecError::InvalidQuery(
+                "prepared reads need a session, see `execute_in`".to_string(),
+            )),
+            Command::Batch { commands, atomic } if commands.iter().all(|cmd| !cmd.is_mutating()) => {
+                self.query_batch(commands, atomic, max_rows)
+            }
+            _ => Err(ExecError::InvalidQuery("query only runs commands that don't mutate".to_string())),
+        }
+    }
+
+    pub(crate) fn apply(&mut self, cmd: Command) -> Result<Output, ExecError> {
+        match cmd {
+            Command::Create(create) => {
+                if let CreateCommand::Table { table, .. } = &create {
+                    validator::validate_schema(&create)
+                        .map_err(|error| ExecError::InvalidSchema { table: table.clone(), error })?;
+                }
+                match create {
+                    CreateCommand::Table {
+                        table,
+                        primary_key,
+                        mut rows,
+                        ttl_seconds,
+                        storage,
+                        implicit_default,
+                        timestamps,
+                        max_rows,
+                    } => {
+                        if implicit_default {
+                            rows.values_mut().for_each(|def| def.implicit_default = true);
+                        }
+                        if timestamps {
+                            add_timestamp_columns(&mut rows)?;
+                        }
+                        self.create_table(table.clone(), primary_key, rows, ttl_seconds, storage)?;
+                        let created = self.table_mut(&table)?;
+                        created.timestamps = timestamps;
+                        created.max_rows = max_rows;
+                        Ok(Output::Done)
+                    }
+                    CreateCommand::User { username, password, password_hash, role } => {
+                        let password_hash = users::password_hash(password, password_hash)?
+                            .ok_or_else(|| ExecError::InvalidQuery(format!("user '{}' needs a password", username)))?;
+                        self.create_user(username, password_hash, role)?;
+                        Ok(Output::Done)
+                    }
+                    CreateCommand::Index { table, name, columns, predicate, unique } => {
+                        let name = name.unwrap_or_else(|| format!("{}_{}_idx", table, columns.join("_")));
+                        self.apply(Command::CreateIndex { table, name, columns, predicate, unique })
+                    }
+                }
+            }
+            Command::Insert(cmd) => {
+                let now = now_millis();
+                if let Some(key) = &cmd.idempotency_key {
+                    if self.idempotency.contains(&cmd.table, key, now) {
+                        return Ok(Output::Done);
+                    }
+                }
+                self.insert(&cmd.table, cmd.rows)?;
+                if let Some(key) = cmd.idempotency_key {
+                    self.idempotency.insert(&cmd.table, key, now);
+                }
+                Ok(Output::Done)
+            }
+            Command::Upsert(cmd) => {
+                self.upsert(&cmd.table, cmd.rows, &cmd.on_conflict)?;
+                Ok(Output::Done)
+            }
+            Command::Batch { commands, atomic } => self.apply_batch(commands, atomic),
+            Command::Read(_)
+            | Command::Get { .. }
+            | Command::Aggregate(_)
+            | Command::Explain { .. }
+            | Command::Stats { .. }
+            | Command::Describe { .. }
+            | Command::ListTables { .. }
+            | Command::List(_)
+            | Command::ListUsers
+            | Command::Metrics
+            | Command::ExportLog { .. }
+            | Command::Backup { .. }
+            | Command::Verify { .. }
+            | Command::Begin
+            | Command::Commit
+            | Command::Rollback
+            | Command::Savepoint { .. }
+            | Command::RollbackTo { .. }
+            | Command::Prepare { .. }
+            | Command::Execute { .. } => self.query_capped(cmd, self.max_rows),
+            Command::Restore { path, archive } => {
+                let restored = match archive {
+                    Some(archive) => self.restore_from(Path::new(&path), archive),
+                    None => self.restore(path),
+                };
+                restored.map_err(|err| ExecError::Io(err.to_string()))?;
+                self.idempotency.clear();
+                Ok(Output::Done)
+            }
+            Command::CreateView { name, query } => {
+                self.create_view(name, query)?;
+                Ok(Output::Done)
+            }
+            Command::CreateIndex { table, name, columns, predicate, unique } => {
+                self.create_index(&table, IndexDefinition { name, columns, predicate, unique })?;
+                Ok(Output::Done)
+            }
+            Command::CopyTable { from, to, include_data } => {
+                self.copy_table(&from, to, include_data)?;
+                Ok(Output::Done)
+            }
+            Command::PurgeExpired { table } => Ok(Output::Affected(self.purge_expired(&table)?)),
+            Command::Update(UpdateCommand::Content { table, filter, rows, on_error, condition, allow_new_columns }) => {
+                let before = self.tables.get(&table).cloned().filter(|_| allow_new_columns);
+                if allow_new_columns {
+                    self.add_inferred_columns(&table, &rows)?;
+                }
+                let count = match self.update_content(&table, &filter, rows, on_error, condition.as_deref()) {
+                    Ok(count) => count,
+                    Err(err) => {
+                        // the columns added above go again
+                        if let Some(before) = before {
+                            self.tables.insert(table, before);
+                        }
+                        return Err(err);
+                    }
+                };
+                Ok(match condition {
+                    Some(_) => Output::Applied { matched: count.matched, applied: count.applied },
+                    None => Output::Affected(count.applied),
+                })
+            }
+            Command::Update(UpdateCommand::User { username, password, password_hash, role }) => {
+                self.alter_user(&username, users::password_hash(password, password_hash)?, role)?;
+                Ok(Output::Done)
+            }
+            Command::Update(UpdateCommand::Rows { table, add, alter, drop, rename, max_rows }) => {
+                // renames reach the foreign keys of other tables too
+                let before = self.tables.clone();
+                let changed = (|| {
+                    if !drop.is_empty() {
+                        self.drop_columns(&table, &drop)?;
+                    }
+                    if !rename.is_empty() {
+                        self.rename_columns(&table, &rename)?;
+                    }
+                    if !add.is_empty() {
+                        self.add_columns(&table, add)?;
+                    }
+                    if !alter.is_empty() {
+                        self.alter_columns(&table, alter)?;
+                    }
+                    Ok(())
+                })();
+                if let Err(err) = changed {
+                    // the steps before the failed one are taken back
+                    self.tables = before;
+                    return Err(err);
+                }
+                if let Some(max_rows) = max_rows {
+                    self.table_mut(&table)?.max_rows = max_rows;
+                }
+                Ok(Output::Done)
+            }
+            Command::Update(UpdateCommand::Rename { table, to }) => {
+                self.rename_table(&table, to)?;
+                Ok(Output::Done)
+            }
+            Command::Delete(DeleteCommand::Content { table, filter, limit }) => {
+                Ok(Output::Affected(self.delete_content(&table, &filter, limit)?))
+            }
+            Command::Grant(grant) => Ok(Output::Affected(self.grant(grant)?)),
+            Command::Revoke(grant) => Ok(Output::Affected(self.revoke(grant)?)),
+            Command::Delete(DeleteCommand::Truncate { table }) => Ok(Output::Affected(self.truncate_table(&table)?)),
+            Command::Delete(DeleteCommand::User { username }) => {
+                self.delete_user(&username)?;
+                Ok(Output::Done)
+            }
+            Command::Delete(DeleteCommand::Index { table, name }) => {
+                self.drop_index(&table, &name)?;
+                Ok(Output::Done)
+            }
+            Command::Delete(DeleteCommand::Table { table }) => {
+                self.drop_table(&table)?;
+                Ok(Output::Done)
+            }
+            Command::DropTables { pattern, confirm } => {
+                if !confirm {
+                    return Err(ExecError::InvalidQuery(
+                        "drop_tables needs \"confirm\": true".to_string(),
+                    ));
+                }
+                Ok(Output::Affected(self.drop_tables(&pattern)?))
+            }
+        }
+    }
+
+    pub(crate) fn read_capped(&self, mut cmd: ReadCommand, max_rows: Option<usize>) -> Result<Output, ExecError> {
+        if cmd.with_types {
+            if cmd.is_paginated() {
+                return Err(ExecError::InvalidQuery("with_types can't be combined with pagination".to_string()));
+            }
+            let columns = self.result_columns(&cmd)?;
+            cmd.with_types = false;
+            let (rows, truncated) = match self.read_capped(cmd, max_rows)? {
+                Output::Rows(rows) => (rows, false),
+                Output::Truncated { rows, truncated } => (rows, truncated),
+                other => return Ok(other),
+            };
+            return Ok(Output::Result(QueryResult { columns, rows, truncated }));
+        }
+        // $fuzzy compares every row, so under a cap only tables within it may be scanned
+        let fuzzy_table = self.tables.get(&cmd.table).filter(|_| uses_fuzzy(&cmd.filter));
+        if let (Some(max), Some(table)) = (max_rows, fuzzy_table) {
+            // counting a ttl table's live rows is a scan of its own, done once
+            let len = table.len();
+            if len > max {
+                return Err(ExecError::InvalidQuery(format!(
+                    "$fuzzy scans every row and '{}' holds {}, more than the maximum of {} rows",
+                    cmd.table, len, max
+                )));
+            }
+        }
+        if cmd.is_paginated() {
+            if let (Some(max), Some(limit)) = (max_rows, cmd.limit) {
+                if limit > max {
+                    return Err(ExecError::InvalidQuery(format!(
+                        "limit {} exceeds the maximum of {} rows",
+                        limit, max
+                    )));
+                }
+            }
+            let (rows, next_cursor) = self.read_page(&cmd)?;
+            return Ok(Output::Page { rows, next_cursor });
+        }
+        let Some(max) = max_rows else {
+            return Ok(Output::Rows(self.read(&cmd)?));
+        };
+        // a sample is capped like a limit
+        match cmd.limit.or(cmd.sample) {
+            Some(limit) if limit > max => Err(ExecError::InvalidQuery(format!(
+                "{} {} exceeds the maximum of {} rows",
+                if cmd.sample.is_some() { "sample" } else { "limit" },
+                limit,
+                max
+            ))),
+            Some(_) => Ok(Output::Rows(self.read(&cmd)?)),
+            None => {
+                // one extra row tells whether the cap cut anything off
+                cmd.limit = Some(max.saturating_add(1));
+                let mut rows = self.read(&cmd)?;
+                if rows.len() <= max {
+                    return Ok(Output::Rows(rows));
+                }
+                rows.truncate(max);
+                Ok(Output::Truncated { rows, truncated: true })
+            }
+        }
+    }
+
+    fn create_table(
+        &mut self,
+        name: String,
+        primary_key: PrimaryKey,
+        columns: HashMap<String, ColumnDefinition>,
+        ttl_seconds: Option<u64>,
+        storage: StorageLayout,
+    ) -> Result<(), ExecError> {
+        if self.tables.contains_key(&name) {
+            return Err(ExecError::TableExists(name));
+        }
+        if self.views.contains_key(&name) {
+            return Err(ExecError::ViewExists(name));
+        }
+        validator::validate_create_table(&name, &primary_key, &columns)?;
+        if ttl_seconds == Some(0) {
+            return Err(ExecError::InvalidQuery("ttl_seconds must be at least 1".to_string()));
+        }
+        if ttl_seconds.is_some() && columns.contains_key(INSERTED_AT_FIELD) {
+            return Err(ExecError::InvalidQuery(format!(
+                "column name '{}' is reserved in tables with a ttl",
+                INSERTED_AT_FIELD
+            )));
+        }
+        self.check_foreign_keys(&name, &primary_key, &columns, ttl_seconds)?;
+
+        let table = Table::new(primary_key, columns, ttl_seconds, storage, self.backend());
+        self.tables.insert(name, Arc::new(table));
+        Ok(())
+    }
+
+    fn create_view(&mut self, name: String, query: ReadCommand) -> Result<(), ExecError> {
+        if self.tables.contains_key(&name) {
+            return Err(ExecError::TableExists(name));
+        }
+        if self.views.contains_key(&name) {
+            return Err(ExecError::ViewExists(name));
+        }
+        // checking the query surfaces unknown tables or columns up front
+        let merged = crud::merge_where(&query)?;
+        if let Some(view) = self.views.get(&merged.table) {
+            self.check_view_read(view, &merged)?;
+        } else {
+            self.check_read(&merged)?;
+        }
+
+        self.views.insert(name, query);
+        Ok(())
+    }
+}
+
+fn add_timestamp_columns(columns: &mut HashMap<String, ColumnDefinition>) -> Result<(), ExecError> {
+    for name in [CREATED_AT_FIELD, UPDATED_AT_FIELD] {
+        if columns.contains_key(name) {
+            return Err(ExecError::InvalidQuery(format!(
+                "column name '{}' is reserved in tables with timestamps",
+                name
+            )));
+        }
+        let def = ColumnDefinition {
+            col_type: ColumnType::Datetime,
+            ..ColumnDefinition::default()
+        };
+        columns.insert(name.to_string(), def);
+    }
+    Ok(())
+}
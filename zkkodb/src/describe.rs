@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+use serde::Serialize;
+
+use crate::backend::StorageBackend;
+use crate::database::{Database, ExecError};
+use crate::index::IndexDefinition;
+use crate::parser::{ColumnDefinition, PrimaryKey};
+
+// a table's schema as it was created, comments included
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Description {
+    pub primary_key: PrimaryKey,
+    pub columns: BTreeMap<String, ColumnDefinition>,
+    pub ttl_seconds: Option<u64>,
+    pub timestamps: bool,
+    pub max_rows: Option<usize>,
+    // the secondary indexes as they were created, in that order
+    pub indexes: Vec<IndexDefinition>,
+}
+
+// a table as `list` of `tables` (or `list_tables` with `details`) lists it
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TableSummary {
+    pub name: String,
+    pub rows: usize,
+    pub primary_key: PrimaryKey,
+}
+
+impl<B: StorageBackend> Database<B> {
+    pub fn describe(&self, table_name: &str) -> Result<Description, ExecError> {
+        let table = self.table(table_name)?;
+        Ok(Description {
+            primary_key: table.primary_key.clone(),
+            columns: table.columns.iter().map(|(name, def)| (name.clone(), def.clone())).collect(),
+            ttl_seconds: table.ttl_seconds,
+            timestamps: table.timestamps,
+            max_rows: table.max_rows,
+            indexes: table.indexes.iter().map(|index| index.definition.clone()).collect(),
+        })
+    }
+
+    // the tables matching the glob `pattern`, all without one
+    pub fn table_summaries(&self, pattern: Option<&str>) -> Vec<TableSummary> {
+        self.list_tables(pattern)
+            .into_iter()
+            .map(|name| {
+                let table = &self.tables[&name];
+                TableSummary { rows: table.len(), primary_key: table.primary_key.clone(), name }
+            })
+            .collect()
+    }
+}
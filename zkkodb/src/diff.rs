@@ -0,0 +1,354 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::backend::{MemoryBackend, StorageBackend};
+use crate::database::{Database, ExecError, Row, Table, CREATED_AT_FIELD, UPDATED_AT_FIELD};
+use crate::parser::{Command, CreateCommand, DeleteCommand, InsertCommand, OnError, UpdateCommand};
+use crate::session::Session;
+use crate::utils::values_equal;
+
+// what turns one database into another, from `Database::diff`. tables are
+// compared by primary key, columns, ttl and timestamps; views, indexes and row
+// quotas aren't compared
+#[derive(Debug, Serialize)]
+pub struct Diff<C = MemoryBackend> {
+    // tables only the other database has
+    pub added_tables: Vec<String>,
+    // tables only this database has
+    pub removed_tables: Vec<String>,
+    // tables both have with different schemas; their rows aren't compared
+    pub schema_changed: Vec<String>,
+    // row changes of the tables both have with the same schema, for the
+    // tables that have any
+    pub tables: BTreeMap<String, TableDiff>,
+    // the other database's copy of every added table
+    #[serde(skip)]
+    sources: BTreeMap<String, Arc<Table<C>>>,
+}
+
+impl<C> Default for Diff<C> {
+    fn default() -> Diff<C> {
+        Diff {
+            added_tables: Vec::new(),
+            removed_tables: Vec::new(),
+            schema_changed: Vec::new(),
+            tables: BTreeMap::new(),
+            sources: BTreeMap::new(),
+        }
+    }
+}
+
+// rows by primary key, in key order
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct TableDiff {
+    // rows only the other side has
+    pub added: Vec<Row>,
+    // rows only this side has, as they are here
+    pub removed: Vec<Row>,
+    pub changed: Vec<RowChange>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RowChange {
+    pub before: Row,
+    pub after: Row,
+}
+
+// what `apply_diff` does where the database it applies to no longer holds what
+// the diff started from: a table or row the diff adds already exists with
+// other contents, or one it changes or removes is different or gone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    // fail with `Conflict` and change nothing
+    #[default]
+    Fail,
+    // keep what the database holds
+    Ours,
+    // take the diff's side, overwriting what the database holds
+    Theirs,
+}
+
+impl<C> Diff<C> {
+    pub fn is_empty(&self) -> bool {
+        self.added_tables.is_empty()
+            && self.removed_tables.is_empty()
+            && self.schema_changed.is_empty()
+            && self.tables.is_empty()
+    }
+}
+
+impl TableDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    // the changes that turn this database into `other`, whose backend may differ
+    pub fn diff<C: StorageBackend>(&self, other: &Database<C>) -> Diff<C> {
+        let mut diff = Diff::default();
+        for name in self.table_names() {
+            if !other.tables.contains_key(name) {
+                diff.removed_tables.push(name.to_string());
+            }
+        }
+        for name in other.table_names() {
+            let theirs = &other.tables[name];
+            let Some(ours) = self.tables.get(name) else {
+                diff.added_tables.push(name.to_string());
+                diff.sources.insert(name.to_string(), Arc::clone(theirs));
+                continue;
+            };
+            if !same_schema(ours, theirs) {
+                diff.schema_changed.push(name.to_string());
+                continue;
+            }
+            let rows = diff_rows(ours, theirs);
+            if !rows.is_empty() {
+                diff.tables.insert(name.to_string(), rows);
+            }
+        }
+        diff
+    }
+
+    // applies `diff`, taken from another database, in one transaction and
+    // returns how many rows it inserted, updated or deleted. applied to the
+    // database it was taken from, that database ends up like the other one.
+    // tables with changed schemas have to be migrated first and fail here.
+    // ttl tables' rows start a new ttl
+    pub fn apply_diff<C: StorageBackend>(&mut self, diff: &Diff<C>, policy: ConflictPolicy) -> Result<usize, ExecError> {
+        if let Some(name) = diff.schema_changed.first() {
+            return Err(ExecError::InvalidQuery(format!(
+                "table '{}' has a different schema on each side, migrate it before applying",
+                name
+            )));
+        }
+        let mut plan = Plan { policy, commands: Vec::new(), rows: 0 };
+        for name in &diff.removed_tables {
+            if self.tables.contains_key(name) {
+                plan.commands.push(Command::Delete(DeleteCommand::Table { table: name.clone() }));
+            }
+        }
+        for (name, source) in &diff.sources {
+            if let Some(ours) = self.tables.get(name) {
+                if same_schema(ours, source) && diff_rows(ours, source).is_empty() {
+                    continue;
+                }
+                if !plan.conflict(name)? {
+                    continue;
+                }
+                plan.commands.push(Command::Delete(DeleteCommand::Table { table: name.clone() }));
+            }
+            plan.create(name, source);
+        }
+        for (name, changes) in &diff.tables {
+            plan.change_rows(name, self.table(name)?, changes)?;
+        }
+
+        let Plan { commands, rows, .. } = plan;
+        if commands.is_empty() {
+            return Ok(0);
+        }
+        // dropping the session before commit rolls everything back
+        let mut session = Session::default();
+        self.execute_in(&mut session, Command::Begin)?;
+        for cmd in commands {
+            self.execute_in(&mut session, cmd)?;
+        }
+        self.execute_in(&mut session, Command::Commit)?;
+        Ok(rows)
+    }
+}
+
+// the commands applying a diff, and how many rows they change
+struct Plan {
+    policy: ConflictPolicy,
+    commands: Vec<Command>,
+    rows: usize,
+}
+
+impl Plan {
+    // whether to take the diff's side of a conflict on `table`
+    fn conflict(&self, table: &str) -> Result<bool, ExecError> {
+        match self.policy {
+            ConflictPolicy::Fail => Err(ExecError::Conflict { table: table.to_string() }),
+            ConflictPolicy::Ours => Ok(false),
+            ConflictPolicy::Theirs => Ok(true),
+        }
+    }
+
+    // creates `name` like `source`, with its indexes and rows
+    fn create<B: StorageBackend>(&mut self, name: &str, source: &Table<B>) {
+        let mut columns = source.columns.clone();
+        if source.timestamps {
+            columns.remove(CREATED_AT_FIELD);
+            columns.remove(UPDATED_AT_FIELD);
+        }
+        self.commands.push(Command::Create(CreateCommand::Table {
+            table: name.to_string(),
+            primary_key: source.primary_key.clone(),
+            rows: columns,
+            ttl_seconds: source.ttl_seconds,
+            storage: source.storage(),
+            implicit_default: false,
+            timestamps: source.timestamps,
+            max_rows: source.max_rows,
+        }));
+        for index in &source.indexes {
+            let definition = index.definition.clone();
+            self.commands.push(Command::CreateIndex {
+                table: name.to_string(),
+                name: definition.name,
+                columns: definition.columns,
+                predicate: definition.predicate,
+                unique: definition.unique,
+            });
+        }
+        for row in source.rows() {
+            self.insert(name, source, row.into_owned());
+        }
+    }
+
+    // row changes against the rows `table` holds now
+    fn change_rows<B: StorageBackend>(&mut self, name: &str, table: &Table<B>, changes: &TableDiff) -> Result<(), ExecError> {
+        for before in &changes.removed {
+            let delete = match table.get(&table.key_of(before)) {
+                None => false,
+                Some(current) => same_row(table, &current, before) || self.conflict(name)?,
+            };
+            if delete {
+                self.delete(name, table, before)?;
+            }
+        }
+        for RowChange { before, after } in &changes.changed {
+            match table.get(&table.key_of(after)) {
+                Some(current) if same_row(table, &current, after) => {}
+                Some(current) => {
+                    if same_row(table, &current, before) || self.conflict(name)? {
+                        self.update(name, table, after)?;
+                    }
+                }
+                None => {
+                    if self.conflict(name)? {
+                        self.insert(name, table, after.clone());
+                    }
+                }
+            }
+        }
+        for row in &changes.added {
+            let update = match table.get(&table.key_of(row)) {
+                None => {
+                    self.insert(name, table, row.clone());
+                    false
+                }
+                Some(current) => !same_row(table, &current, row) && self.conflict(name)?,
+            };
+            if update {
+                self.update(name, table, row)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn insert<B: StorageBackend>(&mut self, name: &str, table: &Table<B>, mut row: Row) {
+        row.retain(|column, _| table.columns.get(column).is_some_and(|def| def.generated.is_none()));
+        self.commands.push(Command::Insert(InsertCommand {
+            table: name.to_string(),
+            rows: row,
+            idempotency_key: None,
+        }));
+        self.rows += 1;
+    }
+
+    // sets every column but the key and generated ones to `row`'s values
+    fn update<B: StorageBackend>(&mut self, name: &str, table: &Table<B>, row: &Row) -> Result<(), ExecError> {
+        let values = table
+            .columns
+            .iter()
+            .filter(|(column, def)| def.generated.is_none() && !table.primary_key.contains(column))
+            .map(|(column, _)| (column.clone(), row.get(column).cloned().unwrap_or(Value::Null)))
+            .collect();
+        self.commands.push(Command::Update(UpdateCommand::Content {
+            table: name.to_string(),
+            filter: key_filter(name, table, row)?,
+            rows: values,
+            on_error: OnError::default(),
+            condition: None,
+            allow_new_columns: false,
+        }));
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn delete<B: StorageBackend>(&mut self, name: &str, table: &Table<B>, row: &Row) -> Result<(), ExecError> {
+        self.commands.push(Command::Delete(DeleteCommand::Content {
+            table: name.to_string(),
+            filter: key_filter(name, table, row)?,
+            limit: None,
+        }));
+        self.rows += 1;
+        Ok(())
+    }
+}
+
+fn same_schema<B: StorageBackend, C: StorageBackend>(a: &Table<B>, b: &Table<C>) -> bool {
+    a.primary_key == b.primary_key
+        && a.columns == b.columns
+        && a.ttl_seconds == b.ttl_seconds
+        && a.timestamps == b.timestamps
+}
+
+// a column left out of a row is null in it
+fn same_row<B: StorageBackend>(table: &Table<B>, a: &Row, b: &Row) -> bool {
+    table.columns.keys().all(|column| {
+        let (a, b) = (a.get(column).unwrap_or(&Value::Null), b.get(column).unwrap_or(&Value::Null));
+        a.is_null() && b.is_null() || values_equal(a, b)
+    })
+}
+
+// both tables have the same schema
+fn diff_rows<B: StorageBackend, C: StorageBackend>(ours: &Table<B>, theirs: &Table<C>) -> TableDiff {
+    let mut diff = TableDiff::default();
+    for (key, row) in ours.entries() {
+        match theirs.get(key) {
+            None => diff.removed.push(row.into_owned()),
+            Some(after) if !same_row(ours, &row, &after) => diff.changed.push(RowChange {
+                before: row.into_owned(),
+                after: after.into_owned(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (key, row) in theirs.entries() {
+        if ours.get(key).is_none() {
+            diff.added.push(row.into_owned());
+        }
+    }
+    diff
+}
+
+// a string filter selecting the row with `row`'s primary key
+fn key_filter<B: StorageBackend>(name: &str, table: &Table<B>, row: &Row) -> Result<String, ExecError> {
+    let conditions = table
+        .primary_key
+        .columns()
+        .iter()
+        .map(|column| {
+            let literal = match row.get(column).unwrap_or(&Value::Null) {
+                Value::String(s) if !s.contains('\'') => format!("'{}'", s),
+                Value::String(s) if !s.contains('"') => format!("\"{}\"", s),
+                value @ (Value::Number(_) | Value::Bool(_)) => value.to_string(),
+                value => {
+                    return Err(ExecError::Unsupported(format!(
+                        "applying a diff to the row of '{}' with key {}",
+                        name, value
+                    )))
+                }
+            };
+            Ok(format!("{} = {}", column, literal))
+        })
+        .collect::<Result<Vec<_>, ExecError>>()?;
+    Ok(conditions.join(" AND "))
+}
@@ -0,0 +1,59 @@
+use std::sync::mpsc::{channel, Receiver};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::backend::StorageBackend;
+use crate::database::Database;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum ChangeKind {
+    #[serde(rename = "insert")]
+    Insert,
+    #[serde(rename = "update")]
+    Update,
+    #[serde(rename = "delete")]
+    Delete,
+    #[serde(rename = "drop")]
+    Drop,
+}
+
+// emitted once per committed mutation, `keys` are the primary keys it touched
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChangeEvent {
+    pub kind: ChangeKind,
+    pub table: String,
+    pub keys: Vec<Value>,
+}
+
+impl<B: StorageBackend> Database<B> {
+    pub fn subscribe(&mut self, table: &str) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.entry(table.to_string()).or_default().push(sender);
+        receiver
+    }
+
+    // channels are unbounded so sending never blocks the writer; subscribers
+    // whose receiver was dropped are forgotten on the next event
+    pub(crate) fn notify(&mut self, kind: ChangeKind, table: &str, keys: Vec<Value>) {
+        if let Some(buffered) = &mut self.buffered_events {
+            buffered.push(ChangeEvent {
+                kind,
+                table: table.to_string(),
+                keys,
+            });
+            return;
+        }
+        let Some(senders) = self.subscribers.get_mut(table) else {
+            return;
+        };
+        let event = ChangeEvent {
+            kind,
+            table: table.to_string(),
+            keys,
+        };
+        senders.retain(|sender| sender.send(event.clone()).is_ok());
+        if senders.is_empty() {
+            self.subscribers.remove(table);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use serde::Serialize;
+
+use crate::backend::StorageBackend;
+use crate::crud::{compile_write_filter, is_grouped, join_probe, key_lookup, merge_where};
+use crate::database::{Database, ExecError, Table};
+use crate::index::index_lookup;
+use crate::parser::{Command, DeleteCommand, JoinKind, ReadCommand, UpdateCommand};
+
+// how a command would run, without running it
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Plan {
+    pub table: String,
+    // "index_lookup" (primary key or secondary index) or "full_scan" of the
+    // read table, "view" for a view. an insert looks up nothing, "none"
+    pub access: String,
+    pub index: Option<String>,
+    // how a joined table is matched: "index_lookup on <table>.<column>" or "nested_loop"
+    pub join: Option<String>,
+    // upper bound of the rows the command examines
+    pub estimated_rows: usize,
+    // operations in the order they run
+    pub steps: Vec<String>,
+    // the plan of a view's query
+    pub view: Option<Box<Plan>>,
+}
+
+impl<B: StorageBackend> Database<B> {
+    // the plan of a read, an aggregate or a write: inserts, upserts, updates
+    // and deletes find their rows the way reads do
+    pub fn explain_command(&self, cmd: &Command) -> Result<Plan, ExecError> {
+        let write = |table: &str, access: (&str, Option<String>, usize), steps: &[&str]| {
+            let (access, index, estimated_rows) = access;
+            let steps = [access].iter().chain(steps).map(|step| step.to_string()).collect();
+            Plan {
+                table: table.to_string(),
+                access: access.to_string(),
+                index,
+                join: None,
+                estimated_rows,
+                steps,
+                view: None,
+            }
+        };
+        match cmd {
+            Command::Read(read) => self.explain(read),
+            Command::Aggregate(aggregate) => self.explain(&aggregate.clone().into_read()),
+            Command::Insert(insert) => {
+                self.table(&insert.table)?;
+                Ok(write(&insert.table, ("none", None, 0), &["validate", "insert"]))
+            }
+            Command::Upsert(upsert) => {
+                let table = self.table(&upsert.table)?;
+                let columns = match upsert.on_conflict.as_slice() {
+                    [] => table.primary_key.columns(),
+                    columns => columns,
+                };
+                // a unique column that isn't the key is looked for row by row
+                let access = match columns {
+                    [column] if !table.primary_key.contains(column) => ("full_scan", None, table.len()),
+                    columns => ("index_lookup", Some(format!("primary key ({})", columns.join(", "))), 1),
+                };
+                Ok(write(&upsert.table, access, &["validate", "upsert"]))
+            }
+            Command::Update(UpdateCommand::Content { table: table_name, filter, condition, .. }) => {
+                let table = self.table(table_name)?;
+                let (lookup, _) = compile_write_filter(table_name, table, filter)?;
+                let steps: &[&str] = match condition {
+                    Some(_) => &["filter", "condition", "update"],
+                    None => &["filter", "update"],
+                };
+                Ok(write(table_name, access(table, &lookup), steps))
+            }
+            Command::Delete(DeleteCommand::Content { table: table_name, filter, limit }) => {
+                let table = self.table(table_name)?;
+                let (lookup, _) = compile_write_filter(table_name, table, filter)?;
+                let steps: &[&str] = match limit {
+                    Some(_) => &["filter", "limit", "delete"],
+                    None => &["filter", "delete"],
+                };
+                Ok(write(table_name, access(table, &lookup), steps))
+            }
+            Command::Delete(DeleteCommand::Truncate { table: table_name }) => {
+                let table = self.table(table_name)?;
+                Ok(write(table_name, ("full_scan", None, table.len()), &["truncate"]))
+            }
+            _ => Err(ExecError::InvalidQuery(
+                "explain plans reads, aggregates, inserts, upserts, updates and deletes".to_string(),
+            )),
+        }
+    }
+
+    pub fn explain(&self, cmd: &ReadCommand) -> Result<Plan, ExecError> {
+        let cmd = &*merge_where(cmd)?;
+        if let Some(view) = self.views.get(&cmd.table) {
+            let inner = self.explain(view)?;
+            let mut steps = vec!["view"];
+            if !cmd.filter.is_empty() {
+                steps.push("filter");
+            }
+            return Ok(Plan {
+                table: cmd.table.clone(),
+                access: "view".to_string(),
+                index: None,
+                join: None,
+                estimated_rows: inner.estimated_rows,
+                steps: finishing_steps(cmd, steps),
+                view: Some(Box::new(inner)),
+            });
+        }
+
+        let table = self.table(&cmd.table)?;
+        self.check_read(cmd)?;
+
+        let (access, index, mut estimated_rows) = access(table, cmd);
+        let mut steps = vec![access];
+
+        let join = match &cmd.join {
+            Some(join) => {
+                let right = self.table(&join.table)?;
+                steps.push(match join.kind {
+                    JoinKind::Inner => "join",
+                    JoinKind::Left => "left_join",
+                });
+                // mirrors join_rows' choice of driving side
+                let left_probe = join_probe(table, &join.on.left).filter(|_| join.kind == JoinKind::Inner);
+                Some(if let Some(probe) = join_probe(right, &join.on.right) {
+                    estimated_rows += table.len();
+                    probe.describe(&join.table, &join.on.right)
+                } else if let Some(probe) = left_probe {
+                    estimated_rows = right.len() * 2;
+                    probe.describe(&cmd.table, &join.on.left)
+                } else {
+                    estimated_rows = table.len() * right.len();
+                    "nested_loop".to_string()
+                })
+            }
+            None => None,
+        };
+        if !cmd.filter.is_empty() {
+            steps.push("filter");
+        }
+        if cmd.changed_since.is_some() {
+            steps.push("changed_since");
+        }
+        if is_grouped(cmd) {
+            steps.push("aggregate");
+            if !cmd.having.is_empty() {
+                steps.push("having");
+            }
+        }
+
+        Ok(Plan {
+            table: cmd.table.clone(),
+            access: access.to_string(),
+            index,
+            join,
+            estimated_rows,
+            steps: finishing_steps(cmd, steps),
+            view: None,
+        })
+    }
+}
+
+// how the rows of a read of `table` are found, the index used and how many
+// rows that examines at most
+fn access<B: StorageBackend>(table: &Table<B>, cmd: &ReadCommand) -> (&'static str, Option<String>, usize) {
+    match (key_lookup(table, cmd), index_lookup(table, cmd)) {
+        (Some(_), _) => {
+            let column = table.primary_key.single().unwrap_or_default();
+            ("index_lookup", Some(format!("primary key ({})", column)), 1)
+        }
+        (None, Some((index, keys))) => ("index_lookup", Some(index.describe()), keys.len()),
+        (None, None) => ("full_scan", None, table.len()),
+    }
+}
+
+// appends the steps every read ends with, see `finish` in crud
+fn finishing_steps(cmd: &ReadCommand, mut steps: Vec<&str>) -> Vec<String> {
+    if !cmd.order_by.is_empty() {
+        steps.push("sort");
+    }
+    if !cmd.columns.is_empty() {
+        steps.push("project");
+    }
+    if cmd.distinct {
+        steps.push("distinct");
+    }
+    if cmd.offset.is_some() {
+        steps.push("offset");
+    }
+    if cmd.sample.is_some() {
+        steps.push("sample");
+    } else if cmd.limit.is_some() {
+        steps.push("limit");
+    }
+    steps.into_iter().map(str::to_string).collect()
+}
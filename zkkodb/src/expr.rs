@@ -0,0 +1,230 @@
+use serde_json::Value;
+
+use crate::database::Row;
+
+// arithmetic over the numeric columns of a row, e.g. "price * quantity - discount"
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Int(i64),
+    Float(f64),
+    Column(String),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+// why an expression has no value for a row
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum EvalError {
+    // e.g. a division by zero or a non-numeric column
+    Invalid(String),
+    // the result is NaN or infinite, which no column can hold
+    NotFinite(f64),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Num {
+    Int(i64),
+    Float(f64),
+}
+
+impl Expr {
+    pub(crate) fn parse(input: &str) -> Result<Expr, String> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.sum()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected '{}'", token)),
+        }
+    }
+
+    // every column the expression reads
+    pub(crate) fn columns(&self) -> Vec<&str> {
+        match self {
+            Expr::Int(_) | Expr::Float(_) => Vec::new(),
+            Expr::Column(column) => vec![column.as_str()],
+            Expr::Neg(inner) => inner.columns(),
+            Expr::Binary(_, left, right) => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+        }
+    }
+
+    // null when any column it reads is null. integer arithmetic stays integral
+    // unless a division has a remainder
+    pub(crate) fn eval(&self, row: &Row) -> Result<Value, EvalError> {
+        Ok(match self.eval_num(row).map_err(EvalError::Invalid)? {
+            Some(Num::Int(n)) => Value::from(n),
+            Some(Num::Float(f)) => serde_json::Number::from_f64(f)
+                .map(Value::Number)
+                .ok_or(EvalError::NotFinite(f))?,
+            None => Value::Null,
+        })
+    }
+
+    fn eval_num(&self, row: &Row) -> Result<Option<Num>, String> {
+        Ok(Some(match self {
+            Expr::Int(n) => Num::Int(*n),
+            Expr::Float(f) => Num::Float(*f),
+            Expr::Column(column) => match row.get(column) {
+                None | Some(Value::Null) => return Ok(None),
+                Some(Value::Number(n)) => match n.as_i64() {
+                    Some(i) => Num::Int(i),
+                    None => Num::Float(n.as_f64().unwrap_or(f64::NAN)),
+                },
+                Some(value) => return Err(format!("column '{}' holds non-numeric {}", column, value)),
+            },
+            Expr::Neg(inner) => match inner.eval_num(row)? {
+                Some(Num::Int(n)) => n.checked_neg().map_or(Num::Float(-(n as f64)), Num::Int),
+                Some(Num::Float(f)) => Num::Float(-f),
+                None => return Ok(None),
+            },
+            Expr::Binary(op, left, right) => {
+                let (Some(left), Some(right)) = (left.eval_num(row)?, right.eval_num(row)?) else {
+                    return Ok(None);
+                };
+                apply(*op, left, right)?
+            }
+        }))
+    }
+}
+
+fn apply(op: BinOp, left: Num, right: Num) -> Result<Num, String> {
+    if let (Num::Int(a), Num::Int(b)) = (left, right) {
+        let exact = match op {
+            BinOp::Add => a.checked_add(b),
+            BinOp::Sub => a.checked_sub(b),
+            BinOp::Mul => a.checked_mul(b),
+            BinOp::Div if b == 0 => return Err("division by zero".to_string()),
+            BinOp::Div => Some(a).filter(|a| a % b == 0).and_then(|a| a.checked_div(b)),
+        };
+        if let Some(n) = exact {
+            return Ok(Num::Int(n));
+        }
+    }
+    let (a, b) = (as_f64(left), as_f64(right));
+    Ok(Num::Float(match op {
+        BinOp::Add => a + b,
+        BinOp::Sub => a - b,
+        BinOp::Mul => a * b,
+        BinOp::Div if b == 0.0 => return Err("division by zero".to_string()),
+        BinOp::Div => a / b,
+    }))
+}
+
+fn as_f64(num: Num) -> f64 {
+    match num {
+        Num::Int(n) => n as f64,
+        Num::Float(f) => f,
+    }
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    // sum := product (("+" | "-") product)*
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.peek().and_then(|t| binop(t, &["+", "-"])) {
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    // product := unary (("*" | "/") unary)*
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.peek().and_then(|t| binop(t, &["*", "/"])) {
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next().as_deref() {
+            Some("-") => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some("(") => {
+                let expr = self.sum()?;
+                match self.next().as_deref() {
+                    Some(")") => Ok(expr),
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Some(token) if token.starts_with(|c: char| c.is_ascii_digit() || c == '.') => {
+                if let Ok(n) = token.parse::<i64>() {
+                    Ok(Expr::Int(n))
+                } else {
+                    token.parse::<f64>().map(Expr::Float).map_err(|_| format!("invalid number '{}'", token))
+                }
+            }
+            Some(token) if is_ident(token) => Ok(Expr::Column(token.to_string())),
+            Some(token) => Err(format!("unexpected '{}'", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+fn binop(token: &str, allowed: &[&str]) -> Option<BinOp> {
+    if !allowed.contains(&token) {
+        return None;
+    }
+    match token {
+        "+" => Some(BinOp::Add),
+        "-" => Some(BinOp::Sub),
+        "*" => Some(BinOp::Mul),
+        "/" => Some(BinOp::Div),
+        _ => None,
+    }
+}
+
+fn is_ident(token: &str) -> bool {
+    token.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn tokenize(input: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if "+-*/()".contains(c) {
+            tokens.push(c.to_string());
+            chars.next();
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '.') {
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(word);
+        } else {
+            return Err(format!("unexpected character '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
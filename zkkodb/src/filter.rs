@@ -0,0 +1,456 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::database::{ExecError, Row};
+use crate::parser::Collation;
+use crate::result::ColumnType;
+use crate::utils::{compare_same_type, levenshtein, values_equal};
+use crate::validator::coerce_to_type;
+use crate::value::Value as Typed;
+
+// a filter map compiled once per query: operators are validated and regex
+// patterns are built up front instead of for every row.
+// conditions follow SQL's three-valued logic: comparing a null (or missing)
+// value with anything, equality included, is unknown rather than true or false.
+// NOT keeps unknown unknown, and a row only matches when every condition is
+// true, so rows with a null column never match a comparison on it, negated or
+// not. $is_null is the only check a null passes. values and operands are
+// compared typed by the column's type, see `value::Value::of`
+#[derive(Debug, Clone)]
+pub(crate) struct Filter {
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    Column(String, Option<ColumnType>, Collation, Vec<Check>),
+    // {"$and": [...]}, {"$or": [...]} and {"$not": {...}} hold whole filters
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+#[derive(Debug, Clone)]
+enum Check {
+    Eq(Typed),
+    // string operands are lowercased when compiled
+    IEq(Value),
+    Ne(Typed),
+    Cmp(Ordering, bool, Typed),
+    Between(Typed, Typed),
+    In(Vec<Typed>),
+    Regex(Regex),
+    IsNull(bool),
+    Not(Vec<Check>),
+    // an array holding the value; anything but an array doesn't match
+    Contains(Value),
+    // checks on an array's length; anything but an array doesn't match
+    Size(Vec<Check>),
+    // a string within `distance` edits of the target, as a whole or in one of
+    // its whitespace-separated words
+    Fuzzy { target: String, distance: usize },
+}
+
+impl Filter {
+    // a filter value is either a literal to compare for equality or an operator
+    // object like {"$gt": 10, "$lte": 50} whose conditions must all hold.
+    // `types` maps column names to their declared type so operands like the
+    // bounds of $between can be coerced to it. `collations` holds the columns
+    // whose strings don't compare by bytes. the keys $and, $or and $not
+    // combine nested filters instead of naming a column
+    pub(crate) fn compile(
+        filter: &HashMap<String, Value>,
+        types: &HashMap<String, ColumnType>,
+        collations: &HashMap<String, Collation>,
+    ) -> Result<Filter, ExecError> {
+        Filter::compile_map(filter.iter(), types, collations)
+    }
+
+    fn compile_map<'a>(
+        filter: impl ExactSizeIterator<Item = (&'a String, &'a Value)>,
+        types: &HashMap<String, ColumnType>,
+        collations: &HashMap<String, Collation>,
+    ) -> Result<Filter, ExecError> {
+        let mut conditions = Vec::with_capacity(filter.len());
+        for (key, expected) in filter {
+            let nested = || -> Result<Vec<Filter>, ExecError> {
+                nested_filters(key, expected)?
+                    .into_iter()
+                    .map(|nested| Filter::compile_map(nested.iter(), types, collations))
+                    .collect()
+            };
+            conditions.push(match key.as_str() {
+                "$and" => Condition::And(nested()?),
+                "$or" => Condition::Or(nested()?),
+                "$not" => Condition::Not(Box::new(nested()?.remove(0))),
+                column => {
+                    let col_type = types.get(column).copied();
+                    let collation = collations.get(column).copied().unwrap_or_default();
+                    let checks = compile_checks(column, col_type, collation, expected)?;
+                    Condition::Column(key.clone(), col_type, collation, checks)
+                }
+            });
+        }
+        Ok(Filter { conditions })
+    }
+
+    pub(crate) fn matches(&self, row: &Row) -> bool {
+        self.eval(row) == Some(true)
+    }
+
+    fn eval(&self, row: &Row) -> Option<bool> {
+        all(self.conditions.iter().map(|condition| match condition {
+            Condition::Column(column, col_type, collation, checks) => {
+                let value = row.get(column).unwrap_or(&Value::Null);
+                all(checks.iter().map(|check| check.eval(value, *col_type, *collation)))
+            }
+            Condition::And(filters) => all(filters.iter().map(|filter| filter.eval(row))),
+            Condition::Or(filters) => any(filters.iter().map(|filter| filter.eval(row))),
+            Condition::Not(filter) => filter.eval(row).map(|truth| !truth),
+        }))
+    }
+}
+
+// whether a filter key combines nested filters rather than naming a column
+pub(crate) fn is_logical(key: &str) -> bool {
+    matches!(key, "$and" | "$or" | "$not")
+}
+
+// the filters under a logical key: a non-empty array of them for $and and
+// $or, one for $not
+fn nested_filters<'a>(key: &str, operand: &'a Value) -> Result<Vec<&'a serde_json::Map<String, Value>>, ExecError> {
+    let invalid = |expected: &str| ExecError::InvalidQuery(format!("{} needs {}", key, expected));
+    if key == "$not" {
+        return operand.as_object().map(|filter| vec![filter]).ok_or_else(|| invalid("a filter object"));
+    }
+    match operand.as_array() {
+        Some(filters) if !filters.is_empty() => filters
+            .iter()
+            .map(|filter| filter.as_object().ok_or_else(|| invalid("an array of filter objects")))
+            .collect(),
+        _ => Err(invalid("a non-empty array of filter objects")),
+    }
+}
+
+// every column condition of a filter, those nested under $and, $or and $not
+// included, so callers can check the columns a filter reads
+pub(crate) fn column_conditions(filter: &HashMap<String, Value>) -> Vec<(&String, &Value)> {
+    fn collect<'a>(filter: impl Iterator<Item = (&'a String, &'a Value)>, found: &mut Vec<(&'a String, &'a Value)>) {
+        for (key, expected) in filter {
+            if !is_logical(key) {
+                found.push((key, expected));
+                continue;
+            }
+            let nested: Vec<&Value> = match expected {
+                Value::Array(filters) => filters.iter().collect(),
+                filter => vec![filter],
+            };
+            for filter in nested.into_iter().filter_map(Value::as_object) {
+                collect(filter.iter(), found);
+            }
+        }
+    }
+    let mut found = Vec::new();
+    collect(filter.iter(), &mut found);
+    found
+}
+
+impl Check {
+    // None is unknown: the check compared a null
+    // `value` is typed by `col_type` for the checks that compare it
+    fn eval(&self, value: &Value, col_type: Option<ColumnType>, collation: Collation) -> Option<bool> {
+        let typed = || Typed::of(col_type, value);
+        match self {
+            Check::IsNull(expected) => Some(value.is_null() == *expected),
+            Check::Not(checks) => {
+                all(checks.iter().map(|check| check.eval(value, col_type, collation))).map(|truth| !truth)
+            }
+            _ if value.is_null() => None,
+            Check::Eq(operand) | Check::Ne(operand) if operand.is_null() => None,
+            Check::IEq(operand) if operand.is_null() => None,
+            Check::Eq(operand) => Some(collated_eq(collation, &typed(), operand)),
+            Check::IEq(Value::String(operand)) => {
+                Some(value.as_str().is_some_and(|s| s.to_lowercase() == *operand))
+            }
+            Check::IEq(operand) => Some(values_equal(value, operand)),
+            Check::Ne(operand) => Some(!collated_eq(collation, &typed(), operand)),
+            Check::Cmp(ord, or_equal, operand) => Some(match collated_cmp(collation, &typed(), operand) {
+                Some(found) => found == *ord || (*or_equal && found == Ordering::Equal),
+                None => false,
+            }),
+            Check::Between(low, high) => {
+                let typed = typed();
+                Some(
+                    collated_cmp(collation, &typed, low).is_some_and(|ord| ord != Ordering::Less)
+                        && collated_cmp(collation, &typed, high).is_some_and(|ord| ord != Ordering::Greater),
+                )
+            }
+            Check::Regex(regex) => Some(value.as_str().is_some_and(|s| regex.is_match(s))),
+            Check::Fuzzy { target, distance } => Some(value.as_str().is_some_and(|s| {
+                std::iter::once(s)
+                    .chain(s.split_whitespace())
+                    .any(|candidate| levenshtein(candidate, target) <= *distance)
+            })),
+            Check::Contains(operand) => Some(
+                value
+                    .as_array()
+                    .is_some_and(|items| items.iter().any(|item| values_equal(item, operand))),
+            ),
+            Check::Size(checks) => match value.as_array() {
+                Some(items) => all(checks.iter().map(|check| {
+                    check.eval(&Value::from(items.len()), Some(ColumnType::Int), Collation::Binary)
+                })),
+                None => Some(false),
+            },
+            // like SQL, a miss is unknown rather than false when the list holds a null
+            Check::In(values) => {
+                let typed = typed();
+                if values.iter().any(|operand| collated_eq(collation, &typed, operand)) {
+                    Some(true)
+                } else if values.iter().any(Typed::is_null) {
+                    None
+                } else {
+                    Some(false)
+                }
+            }
+        }
+    }
+}
+
+// text compares by its column's collation, other values as usual
+fn collated_cmp(collation: Collation, a: &Typed, b: &Typed) -> Option<Ordering> {
+    match (a, b) {
+        (Typed::Text(a), Typed::Text(b)) => Some(collation.compare(a, b)),
+        _ => a.compare_same_kind(b),
+    }
+}
+
+fn collated_eq(collation: Collation, a: &Typed, b: &Typed) -> bool {
+    match (a, b) {
+        (Typed::Text(a), Typed::Text(b)) => collation.compare(a, b) == Ordering::Equal,
+        _ => a == b,
+    }
+}
+
+// three-valued AND: false wins over unknown, unknown over true
+fn all(truths: impl Iterator<Item = Option<bool>>) -> Option<bool> {
+    let mut result = Some(true);
+    for truth in truths {
+        match truth {
+            Some(false) => return Some(false),
+            None => result = None,
+            Some(true) => {}
+        }
+    }
+    result
+}
+
+// three-valued OR: true wins over unknown, unknown over false
+fn any(truths: impl Iterator<Item = Option<bool>>) -> Option<bool> {
+    let mut result = Some(false);
+    for truth in truths {
+        match truth {
+            Some(true) => return Some(true),
+            None => result = None,
+            Some(false) => {}
+        }
+    }
+    result
+}
+
+// the value a filter condition requires the column to equal, if it does
+pub(crate) fn equality_operand(expected: &Value) -> Option<&Value> {
+    match operators(expected) {
+        Some(ops) => ops.get("$eq"),
+        None => Some(expected),
+    }
+}
+
+// whether every row matching `filter` also matches `predicate`. each condition
+// of the predicate needs a condition on the same column that is at least as
+// narrow, e.g. {"$gt": 150} implies {"$gt": 100}
+pub(crate) fn implies(filter: &HashMap<String, Value>, predicate: &HashMap<String, Value>) -> bool {
+    predicate.iter().all(|(column, expected)| {
+        let Some(given) = filter.get(column) else {
+            return false;
+        };
+        let given = conditions(given);
+        conditions(expected)
+            .into_iter()
+            .all(|required| given.iter().any(|&found| implies_condition(found, required)))
+    })
+}
+
+fn conditions(expected: &Value) -> Vec<(&str, &Value)> {
+    match operators(expected) {
+        Some(ops) => ops.iter().map(|(op, operand)| (op.as_str(), operand)).collect(),
+        None => vec![("$eq", expected)],
+    }
+}
+
+fn implies_condition((op, given): (&str, &Value), (required_op, required): (&str, &Value)) -> bool {
+    if op == required_op && values_equal(given, required) {
+        return true;
+    }
+    let bounds = match given.as_array().map(Vec::as_slice) {
+        Some([low, high]) if op == "$between" => Some((low, high)),
+        _ => None,
+    };
+    let cmp = |given: &Value| compare_same_type(given, required);
+    let above = |allow_equal: bool| {
+        let lowest = match (op, bounds) {
+            ("$between", Some((low, _))) => low,
+            ("$gt", _) => return cmp(given).is_some_and(|ord| ord != Ordering::Less),
+            ("$gte" | "$eq", _) => given,
+            _ => return false,
+        };
+        cmp(lowest).is_some_and(|ord| ord == Ordering::Greater || (allow_equal && ord == Ordering::Equal))
+    };
+    let below = |allow_equal: bool| {
+        let highest = match (op, bounds) {
+            ("$between", Some((_, high))) => high,
+            ("$lt", _) => return cmp(given).is_some_and(|ord| ord != Ordering::Greater),
+            ("$lte" | "$eq", _) => given,
+            _ => return false,
+        };
+        cmp(highest).is_some_and(|ord| ord == Ordering::Less || (allow_equal && ord == Ordering::Equal))
+    };
+    match required_op {
+        "$gt" => above(false),
+        "$gte" => above(true),
+        "$lt" => below(false),
+        "$lte" => below(true),
+        "$ne" => op == "$eq" && cmp(given).is_some_and(|ord| ord != Ordering::Equal),
+        _ => false,
+    }
+}
+
+// whether any condition of `filter` is a $fuzzy, which compares every row and
+// can't use an index
+pub(crate) fn uses_fuzzy(filter: &HashMap<String, Value>) -> bool {
+    fn fuzzy(expected: &Value) -> bool {
+        operators(expected).is_some_and(|ops| ops.contains_key("$fuzzy") || ops.get("$not").is_some_and(fuzzy))
+    }
+    column_conditions(filter).into_iter().any(|(_, expected)| fuzzy(expected))
+}
+
+fn operators(expected: &Value) -> Option<&serde_json::Map<String, Value>> {
+    match expected {
+        Value::Object(ops) if !ops.is_empty() && ops.keys().all(|op| op.starts_with('$')) => Some(ops),
+        _ => None,
+    }
+}
+
+fn compile_checks(
+    column: &str,
+    col_type: Option<ColumnType>,
+    collation: Collation,
+    expected: &Value,
+) -> Result<Vec<Check>, ExecError> {
+    match operators(expected) {
+        Some(ops) => ops
+            .iter()
+            .map(|(op, operand)| compile_operator(column, col_type, collation, op, operand))
+            .collect(),
+        None => Ok(vec![Check::Eq(Typed::of(col_type, expected))]),
+    }
+}
+
+fn compile_operator(
+    column: &str,
+    col_type: Option<ColumnType>,
+    collation: Collation,
+    op: &str,
+    operand: &Value,
+) -> Result<Check, ExecError> {
+    let typed = || Typed::of(col_type, operand);
+    let operand = operand.clone();
+    Ok(match op {
+        "$eq" => Check::Eq(typed()),
+        "$ieq" => match operand {
+            Value::String(s) => Check::IEq(Value::String(s.to_lowercase())),
+            operand => Check::IEq(operand),
+        },
+        "$ne" => Check::Ne(typed()),
+        "$gt" => Check::Cmp(Ordering::Greater, false, typed()),
+        "$gte" => Check::Cmp(Ordering::Greater, true, typed()),
+        "$lt" => Check::Cmp(Ordering::Less, false, typed()),
+        "$lte" => Check::Cmp(Ordering::Less, true, typed()),
+        "$between" => {
+            let invalid = |reason: &str| {
+                ExecError::InvalidQuery(format!("$between on column '{}' {}", column, reason))
+            };
+            let (low, high) = match operand.as_array().map(Vec::as_slice) {
+                Some([low, high]) => (low, high),
+                _ => return Err(invalid("needs an array of two bounds")),
+            };
+            let coerce = |bound: &Value| match col_type {
+                Some(col_type) => coerce_to_type(col_type, bound)
+                    .ok_or_else(|| invalid(&format!("has bound {} that is not a valid {}", bound, col_type))),
+                None => Ok(bound.clone()),
+            };
+            let (low, high) = (Typed::of(col_type, &coerce(low)?), Typed::of(col_type, &coerce(high)?));
+            match collated_cmp(collation, &low, &high) {
+                Some(Ordering::Greater) => return Err(invalid("has its lower bound above its upper bound")),
+                Some(_) => Check::Between(low, high),
+                None => return Err(invalid("has bounds that can't be compared")),
+            }
+        }
+        "$in" => match operand {
+            Value::Array(values) => Check::In(values.iter().map(|value| Typed::of(col_type, value)).collect()),
+            _ => {
+                return Err(ExecError::InvalidQuery(format!(
+                    "$in on column '{}' needs an array of values",
+                    column
+                )))
+            }
+        },
+        "$is_null" => match operand {
+            Value::Bool(expected) => Check::IsNull(expected),
+            _ => {
+                return Err(ExecError::InvalidQuery(format!(
+                    "$is_null on column '{}' needs true or false",
+                    column
+                )))
+            }
+        },
+        // negates a literal or an operator object like {"$gt": 10}
+        "$not" => Check::Not(compile_checks(column, col_type, collation, &operand)?),
+        "$contains" => Check::Contains(operand),
+        // a length or an operator object like {"$gte": 2}
+        "$size" => Check::Size(compile_checks(column, Some(ColumnType::Int), Collation::Binary, &operand)?),
+        "$regex" => {
+            let pattern = operand.as_str().ok_or_else(|| {
+                ExecError::InvalidQuery(format!("$regex on column '{}' needs a string pattern", column))
+            })?;
+            let regex = Regex::new(pattern).map_err(|err| {
+                ExecError::InvalidQuery(format!("invalid regex on column '{}': {}", column, err))
+            })?;
+            Check::Regex(regex)
+        }
+        // {"target": "Cocnut", "distance": 2}
+        "$fuzzy" => {
+            let invalid = |reason: &str| ExecError::InvalidQuery(format!("$fuzzy on column '{}' {}", column, reason));
+            if let Some(col_type) = col_type.filter(|t| !t.is_text()) {
+                return Err(invalid(&format!("needs a string column, not {}", col_type)));
+            }
+            let target = operand.get("target").and_then(Value::as_str).ok_or_else(|| invalid("needs a string target"))?;
+            let distance = operand
+                .get("distance")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| invalid("needs a non-negative distance"))?;
+            Check::Fuzzy {
+                target: target.to_string(),
+                distance: distance as usize,
+            }
+        }
+        _ => {
+            return Err(ExecError::InvalidQuery(format!(
+                "unknown filter operator '{}' on column '{}'",
+                op, column
+            )))
+        }
+    })
+}
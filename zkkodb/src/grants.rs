@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde_json::json;
+
+use crate::backend::StorageBackend;
+use crate::database::{Database, ExecError, Key, Row};
+use crate::events::ChangeKind;
+use crate::filter::column_conditions;
+use crate::migrations::command;
+use crate::parser::{Command, CreateCommand, DeleteCommand, GrantCommand, Privilege, ReadCommand, UpdateCommand};
+use crate::users::USERS_TABLE;
+
+// grants live in an ordinary table, one row per table, grantee and privilege,
+// so they are saved, loaded and logged like any other data
+pub const GRANTS_TABLE: &str = "_grants";
+
+impl<B: StorageBackend> Database<B> {
+    // returns how many of the privileges were new
+    pub(crate) fn grant(&mut self, cmd: GrantCommand) -> Result<usize, ExecError> {
+        let rows = grant_rows(&cmd)?;
+        self.table(&cmd.table)?;
+        if cmd.table == GRANTS_TABLE || cmd.table == USERS_TABLE {
+            return Err(ExecError::InvalidQuery(format!("'{}' is only open to the admin role", cmd.table)));
+        }
+        if !self.tables.contains_key(GRANTS_TABLE) {
+            self.apply(command(json!({
+                "command": "create",
+                "type": "table",
+                "table": GRANTS_TABLE,
+                "primary_key": ["table", "kind", "grantee", "privilege"],
+                "rows": {
+                    "table": { "type": "string" },
+                    "kind": { "type": "string" },
+                    "grantee": { "type": "string" },
+                    "privilege": { "type": "string" }
+                }
+            })))?;
+        }
+        let grants = self.table(GRANTS_TABLE)?;
+        let new: Vec<Row> = rows.into_iter().filter(|row| grants.get(&grants.key_of(row)).is_none()).collect();
+        let count = new.len();
+        for row in new {
+            self.insert(GRANTS_TABLE, row)?;
+        }
+        Ok(count)
+    }
+
+    // returns how many of the privileges had been granted
+    pub(crate) fn revoke(&mut self, cmd: GrantCommand) -> Result<usize, ExecError> {
+        let rows = grant_rows(&cmd)?;
+        let Some(grants) = self.tables.get(GRANTS_TABLE) else {
+            return Ok(0);
+        };
+        let keys = rows.iter().map(|row| grants.key_of(row)).filter(|key| grants.get(key).is_some()).collect();
+        Ok(self.remove_grants(keys))
+    }
+
+    // removes and returns the grants on a table that is dropped or renamed
+    pub(crate) fn forget_grants(&mut self, table_name: &str) -> Vec<Row> {
+        let Some(grants) = self.tables.get(GRANTS_TABLE) else {
+            return Vec::new();
+        };
+        let (keys, rows): (Vec<Key>, Vec<Row>) = grants
+            .entries()
+            .filter(|(_, row)| row["table"] == table_name)
+            .map(|(key, row)| (key.clone(), row.into_owned()))
+            .unzip();
+        self.remove_grants(keys);
+        rows
+    }
+
+    // removes the grants made to a user that is deleted
+    pub(crate) fn forget_user_grants(&mut self, username: &str) -> usize {
+        let Some(grants) = self.tables.get(GRANTS_TABLE) else {
+            return 0;
+        };
+        let keys = grants
+            .entries()
+            .filter(|(_, row)| row["kind"] == "user" && row["grantee"] == username)
+            .map(|(key, _)| key.clone())
+            .collect();
+        self.remove_grants(keys)
+    }
+
+    // moves the grants on `from` over to `to`, without the checks an insert
+    // makes since they held for the same rows already
+    pub(crate) fn rename_grants(&mut self, from: &str, to: &str) {
+        let grants = self.forget_grants(from);
+        let Some(table) = self.tables.get_mut(GRANTS_TABLE).filter(|_| !grants.is_empty()) else {
+            return;
+        };
+        let table = Arc::make_mut(table);
+        let mut keys = Vec::with_capacity(grants.len());
+        for mut grant in grants {
+            grant.insert("table".to_string(), json!(to));
+            let key = table.key_of(&grant);
+            keys.push(key.0.clone());
+            table.insert_row(key, grant, None);
+        }
+        self.notify(ChangeKind::Insert, GRANTS_TABLE, keys);
+    }
+
+    fn remove_grants(&mut self, keys: Vec<Key>) -> usize {
+        let Some(grants) = self.tables.get_mut(GRANTS_TABLE).filter(|_| !keys.is_empty()) else {
+            return 0;
+        };
+        let grants = Arc::make_mut(grants);
+        for key in &keys {
+            grants.remove_row(key);
+        }
+        let count = keys.len();
+        self.notify(ChangeKind::Delete, GRANTS_TABLE, keys.into_iter().map(|key| key.0).collect());
+        count
+    }
+
+    // whether a session of `user` and `role` may run `cmd`. before the first
+    // grant anyone may run anything. after it the admin role still may, others
+    // need a privilege on each table the command touches; commands changing a
+    // table's schema, and those reaching every table, need the admin role.
+    // the users table, with its password hashes, is the admin's from the start
+    pub(crate) fn check_privileges(&self, user: Option<&str>, role: Option<&str>, cmd: &Command) -> Result<(), ExecError> {
+        if role == Some("admin") {
+            return Ok(());
+        }
+        let touched = self.touched(cmd);
+        if touched.iter().any(|(table, _)| table == USERS_TABLE) {
+            return Err(ExecError::PermissionDenied(format!("table '{}' needs the admin role", USERS_TABLE)));
+        }
+        let Some(grants) = self.tables.get(GRANTS_TABLE) else {
+            return Ok(());
+        };
+        let granted: Vec<Row> = grants.rows().map(|row| row.into_owned()).collect();
+        let holds = |table: &str, privilege: Privilege| {
+            granted.iter().any(|row| {
+                let grantee = match row["kind"].as_str() {
+                    Some("user") => user,
+                    _ => role,
+                };
+                let named = grantee.is_some_and(|grantee| row["grantee"] == grantee);
+                row["table"] == table && row["privilege"] == name(privilege) && named
+            })
+        };
+        for (table, privilege) in touched {
+            let reason = match privilege {
+                Some(privilege) if holds(&table, privilege) => continue,
+                Some(privilege) => format!("{} on table '{}' isn't granted", name(privilege), table),
+                None => format!("changing table '{}' needs the admin role", table),
+            };
+            return Err(ExecError::PermissionDenied(reason));
+        }
+        Ok(())
+    }
+
+    // the tables `cmd` touches and the privilege each needs, None for the
+    // admin role
+    fn touched(&self, cmd: &Command) -> Vec<(String, Option<Privilege>)> {
+        let reads = |read: &ReadCommand| {
+            let mut tables = Vec::new();
+            self.tables_read(read, &mut tables);
+            tables.into_iter().map(|table| (table, Some(Privilege::Read))).collect()
+        };
+        let one = |table: &str, privilege: Option<Privilege>| vec![(table.to_string(), privilege)];
+        let every = || self.table_names().into_iter().map(|table| (table.to_string(), None)).collect();
+        match cmd {
+            Command::Read(read) | Command::Prepare { query: read, .. } => reads(read),
+            // explaining a command needs what running it would
+            Command::Explain { inner } => self.touched(inner),
+            // new tables and views are the admin's, like `create`
+            Command::Aggregate(aggregate) => reads(&aggregate.clone().into_read()),
+            Command::CreateView { name, query } => [one(name, None), reads(query)].concat(),
+            Command::Get { table, .. } | Command::Describe { table } => one(table, Some(Privilege::Read)),
+            Command::Stats { table: Some(table) } => one(table, Some(Privilege::Read)),
+            Command::Stats { table: None } => {
+                self.table_names().into_iter().map(|table| (table.to_string(), Some(Privilege::Read))).collect()
+            }
+            Command::CopyTable { from, to, .. } => [one(to, None), one(from, Some(Privilege::Read))].concat(),
+            Command::Insert(insert) => one(&insert.table, Some(Privilege::Insert)),
+            Command::Upsert(upsert) => vec![
+                (upsert.table.clone(), Some(Privilege::Insert)),
+                (upsert.table.clone(), Some(Privilege::Update)),
+            ],
+            Command::Update(UpdateCommand::Content { table, .. }) => one(table, Some(Privilege::Update)),
+            Command::Delete(DeleteCommand::Content { table, .. } | DeleteCommand::Truncate { table }) => {
+                one(table, Some(Privilege::Delete))
+            }
+            Command::Update(UpdateCommand::Rows { table, .. } | UpdateCommand::Rename { table, .. })
+            | Command::Delete(DeleteCommand::Table { table } | DeleteCommand::Index { table, .. })
+            | Command::Create(CreateCommand::Table { table, .. } | CreateCommand::Index { table, .. })
+            | Command::CreateIndex { table, .. }
+            | Command::PurgeExpired { table } => one(table, None),
+            Command::ExportLog { .. } | Command::Backup { .. } | Command::Restore { .. } => every(),
+            Command::Batch { commands, .. } => commands.iter().flat_map(|cmd| self.touched(cmd)).collect(),
+            // no table, or checked by the session: users, grants, drop_tables,
+            // verify and executes, which it binds to their reads first
+            Command::Create(CreateCommand::User { .. })
+            | Command::Update(UpdateCommand::User { .. })
+            | Command::Delete(DeleteCommand::User { .. })
+            | Command::ListTables { .. }
+            | Command::List(_)
+            | Command::ListUsers
+            | Command::DropTables { .. }
+            | Command::Grant(_)
+            | Command::Revoke(_)
+            | Command::Metrics
+            | Command::Verify { .. }
+            | Command::Begin
+            | Command::Commit
+            | Command::Rollback
+            | Command::Savepoint { .. }
+            | Command::RollbackTo { .. }
+            | Command::Execute { .. } => Vec::new(),
+        }
+    }
+
+    // the tables a read reaches: its own, a joined one, those of the
+    // subqueries in its filter and having, and for views the tables their
+    // queries read
+    fn tables_read(&self, read: &ReadCommand, tables: &mut Vec<String>) {
+        match self.views.get(&read.table) {
+            Some(view) => self.tables_read(view, tables),
+            None => tables.push(read.table.clone()),
+        }
+        if let Some(join) = &read.join {
+            tables.push(join.table.clone());
+        }
+        for (_, expected) in column_conditions(&read.filter).into_iter().chain(column_conditions(&read.having)) {
+            let subquery = expected.get("$in_query").and_then(|query| serde_json::from_value(query.clone()).ok());
+            if let Some(subquery) = subquery {
+                self.tables_read(&subquery, tables);
+            }
+        }
+    }
+}
+
+fn grant_rows(cmd: &GrantCommand) -> Result<Vec<Row>, ExecError> {
+    let (kind, grantee) = match (&cmd.user, &cmd.role) {
+        (Some(user), None) => ("user", user),
+        (None, Some(role)) => ("role", role),
+        _ => return Err(ExecError::InvalidQuery("a grant names either a user or a role".to_string())),
+    };
+    if cmd.privileges.is_empty() {
+        return Err(ExecError::InvalidQuery("a grant needs at least one privilege".to_string()));
+    }
+    let rows = cmd
+        .privileges
+        .iter()
+        .map(|privilege| {
+            HashMap::from([
+                ("table".to_string(), json!(cmd.table)),
+                ("kind".to_string(), json!(kind)),
+                ("grantee".to_string(), json!(grantee)),
+                ("privilege".to_string(), json!(name(*privilege))),
+            ])
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn name(privilege: Privilege) -> &'static str {
+    match privilege {
+        Privilege::Read => "read",
+        Privilege::Insert => "insert",
+        Privilege::Update => "update",
+        Privilege::Delete => "delete",
+    }
+}
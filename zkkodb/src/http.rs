@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::backend::StorageBackend;
+use crate::database::ExecError;
+use crate::parser::{parse_command, Command};
+use crate::server::{AsyncDatabase, MAX_LINE_BYTES};
+use crate::session::Session;
+use crate::wire::WireFormat;
+
+// the request line and headers together may not be longer than this
+const MAX_HEAD_BYTES: u64 = 8 * 1024;
+
+// who a bearer token authenticates as
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub user: String,
+    pub role: String,
+}
+
+// a REST front end over the same commands as the TCP server, one request per
+// connection. every request needs `Authorization: Bearer <token>` with a token
+// from `tokens` and runs in its own session as that token's user:
+//
+//   POST   /tables                  create a table, the body as for `create`
+//   GET    /tables/{t}?filter=&limit=&offset=  read, `filter` is a JSON filter map
+//   POST   /tables/{t}/rows         insert the row in the body
+//   PATCH  /tables/{t}/rows?filter=&if=  update matching rows with the body
+//   DELETE /tables/{t}/rows?filter=&limit=  delete matching rows
+//   DELETE /tables/{t}              drop the table
+//
+// update and delete filters use the string form, e.g. "id = 1". bodies are
+// decoded by their Content-Type, JSON or MessagePack, and responses encoded in
+// the format `Accept` names, else in the request's
+pub async fn serve_http<B: StorageBackend>(
+    listener: TcpListener,
+    db: Arc<AsyncDatabase<B>>,
+    tokens: Arc<HashMap<String, Credentials>>,
+) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let db = Arc::clone(&db);
+        let tokens = Arc::clone(&tokens);
+        tokio::spawn(async move {
+            let _ = handle_connection(stream, db, tokens).await;
+        });
+    }
+}
+
+#[derive(Debug)]
+struct Request {
+    method: String,
+    // percent-decoded path segments
+    segments: Vec<String>,
+    query: HashMap<String, String>,
+    // header names are lowercased
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+// a response that isn't a command's output
+type Failure = (u16, String);
+
+async fn handle_connection<B: StorageBackend>(
+    stream: TcpStream,
+    db: Arc<AsyncDatabase<B>>,
+    tokens: Arc<HashMap<String, Credentials>>,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let (status, body, format) = match read_request(&mut reader).await? {
+        Ok(request) => {
+            let format = response_format(&request);
+            let (status, body) = respond(&request, &db, &tokens).await;
+            (status, body, format)
+        }
+        Err((status, message)) => (status, json!({ "error": message }), WireFormat::Json),
+    };
+    write_response(&mut writer, status, &body, format).await
+}
+
+// the format `Accept` asks for when it names one, else that of the body
+fn response_format(request: &Request) -> WireFormat {
+    let accepted = request.headers.get("accept").and_then(|accept| {
+        accept.split(',').find_map(WireFormat::from_content_type)
+    });
+    accepted.or_else(|| body_format(request).ok()).unwrap_or_default()
+}
+
+// JSON for a request without a Content-Type
+fn body_format(request: &Request) -> Result<WireFormat, Failure> {
+    match request.headers.get("content-type") {
+        Some(content_type) => WireFormat::from_content_type(content_type)
+            .ok_or_else(|| (415, format!("unsupported content type '{}'", content_type))),
+        None => Ok(WireFormat::Json),
+    }
+}
+
+async fn respond<B: StorageBackend>(request: &Request, db: &AsyncDatabase<B>, tokens: &HashMap<String, Credentials>) -> (u16, Value) {
+    let token = request
+        .headers
+        .get("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let Some(credentials) = token.and_then(|token| tokens.get(token.trim())) else {
+        return (401, json!({ "error": "missing or unknown bearer token" }));
+    };
+    let cmd = match route(request) {
+        Ok(cmd) => cmd,
+        Err((status, message)) => return (status, json!({ "error": message })),
+    };
+    let mut session = Session::for_user(&credentials.user, &credentials.role);
+    match db.execute_in(&mut session, cmd).await {
+        Ok(output) => (200, serde_json::to_value(output).unwrap_or(Value::Null)),
+        Err(err) => (status_of(&err), json!({ "error": err.to_string() })),
+    }
+}
+
+// the command an endpoint stands for, parsed like a command sent over TCP
+fn route(request: &Request) -> Result<Command, Failure> {
+    let segments: Vec<&str> = request.segments.iter().map(String::as_str).collect();
+    let param = |name: &str| request.query.get(name).map(String::as_str);
+    let required = |name: &str| param(name).ok_or_else(|| (400, format!("missing query parameter '{}'", name)));
+    let number = |name: &str| match param(name) {
+        Some(value) => value
+            .parse::<usize>()
+            .map(Some)
+            .map_err(|_| (400, format!("{} '{}' is not a number", name, value))),
+        None => Ok(None),
+    };
+    let limit = number("limit")?;
+
+    let command = match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["tables"]) => {
+            let mut create = json_body(request)?;
+            let Some(fields) = create.as_object_mut() else {
+                return Err((400, "the body must be a JSON object".to_string()));
+            };
+            fields.insert("command".to_string(), json!("create"));
+            fields.insert("type".to_string(), json!("table"));
+            create
+        }
+        ("GET", ["tables", table]) => {
+            let filter = match param("filter") {
+                Some(filter) => serde_json::from_str(filter)
+                    .map_err(|err| (400, format!("filter is not valid JSON: {}", err)))?,
+                None => json!({}),
+            };
+            json!({ "command": "read", "table": table, "filter": filter, "limit": limit, "offset": number("offset")? })
+        }
+        ("POST", ["tables", table, "rows"]) => {
+            json!({ "command": "insert", "table": table, "rows": json_body(request)? })
+        }
+        ("PATCH", ["tables", table, "rows"]) => json!({
+            "command": "update",
+            "type": "content",
+            "table": table,
+            "filter": required("filter")?,
+            "rows": json_body(request)?,
+            "if": param("if"),
+        }),
+        ("DELETE", ["tables", table, "rows"]) => json!({
+            "command": "delete",
+            "type": "content",
+            "table": table,
+            "filter": required("filter")?,
+            "limit": limit,
+        }),
+        ("DELETE", ["tables", table]) => json!({ "command": "delete", "type": "table", "table": table }),
+        (_, ["tables"] | ["tables", _] | ["tables", _, "rows"]) => {
+            return Err((405, format!("{} is not allowed here", request.method)))
+        }
+        _ => return Err((404, "no such endpoint".to_string())),
+    };
+    parse_command(&command.to_string()).map_err(|err| (400, err.to_string()))
+}
+
+fn json_body(request: &Request) -> Result<Value, Failure> {
+    let format = body_format(request)?;
+    format.decode_value(&request.body).map_err(|err| match format {
+        WireFormat::Json => (400, format!("the body is not valid JSON: {}", err)),
+        WireFormat::MessagePack => (400, format!("the body is not valid MessagePack: {}", err)),
+    })
+}
+
+fn status_of(err: &ExecError) -> u16 {
+    match err {
+        ExecError::ReadOnly | ExecError::PermissionDenied(_) => 403,
+        ExecError::TableNotFound(_) => 404,
+        ExecError::TableExists(_)
+        | ExecError::ViewExists(_)
+        | ExecError::DuplicateKey { .. }
+        | ExecError::UniqueViolation { .. }
+        | ExecError::ForeignKeyViolation { .. }
+        | ExecError::Conflict { .. } => 409,
+        ExecError::RateLimited { .. } => 429,
+        ExecError::Io(_) => 500,
+        ExecError::Unsupported(_) => 501,
+        _ => 400,
+    }
+}
+
+// the outer Err is a failed connection, the inner one a malformed request
+async fn read_request(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Result<Request, Failure>> {
+    let mut head = reader.take(MAX_HEAD_BYTES);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(Err((400, "malformed request line".to_string())));
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line).await? == 0 {
+            return Ok(Err((431, "request head is incomplete or too large".to_string())));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    let reader = head.into_inner();
+
+    let length = match headers.get("content-length").map(|length| length.parse::<usize>()) {
+        Some(Ok(length)) => length,
+        Some(Err(_)) => return Ok(Err((400, "invalid content-length".to_string()))),
+        None => 0,
+    };
+    if length > MAX_LINE_BYTES {
+        return Ok(Err((413, format!("the body exceeds the maximum of {} bytes", MAX_LINE_BYTES))));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let Some(segments) = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(percent_decode)
+        .collect::<Option<Vec<_>>>()
+    else {
+        return Ok(Err((400, "malformed path".to_string())));
+    };
+    let mut params = HashMap::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        match (percent_decode(name), percent_decode(value)) {
+            (Some(name), Some(value)) => params.insert(name, value),
+            _ => return Ok(Err((400, "malformed query string".to_string()))),
+        };
+    }
+    Ok(Ok(Request {
+        method,
+        segments,
+        query: params,
+        headers,
+        body,
+    }))
+}
+
+// decodes %XX escapes and `+` as a space, None when that isn't valid UTF-8
+fn percent_decode(input: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut rest = input.bytes();
+    while let Some(byte) = rest.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [rest.next()?, rest.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+async fn write_response(
+    writer: &mut (impl AsyncWrite + Unpin),
+    status: u16,
+    body: &Value,
+    format: WireFormat,
+) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        501 => "Not Implemented",
+        _ => "Internal Server Error",
+    };
+    let body = format.encode(body).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        format.content_type(),
+        body.len()
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(&body).await?;
+    writer.shutdown().await
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::backend::StorageBackend;
+use crate::database::Database;
+
+const DEFAULT_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+// the idempotency keys of recent successful inserts, per table, with the unix
+// milliseconds they were first seen at
+#[derive(Debug, Clone)]
+pub(crate) struct SeenKeys {
+    window: Duration,
+    seen: HashMap<String, HashMap<String, u64>>,
+}
+
+impl Default for SeenKeys {
+    fn default() -> SeenKeys {
+        SeenKeys {
+            window: DEFAULT_WINDOW,
+            seen: HashMap::new(),
+        }
+    }
+}
+
+impl SeenKeys {
+    // whether `key` was seen in `table` within the window, forgetting the
+    // table's keys that are older
+    pub(crate) fn contains(&mut self, table: &str, key: &str, now: u64) -> bool {
+        let Some(keys) = self.seen.get_mut(table) else {
+            return false;
+        };
+        let window = self.window.as_millis() as u64;
+        keys.retain(|_, seen_at| now.saturating_sub(*seen_at) < window);
+        keys.contains_key(key)
+    }
+
+    pub(crate) fn insert(&mut self, table: &str, key: String, now: u64) {
+        self.seen.entry(table.to_string()).or_default().insert(key, now);
+    }
+
+    pub(crate) fn forget_table(&mut self, table: &str) {
+        self.seen.remove(table);
+    }
+
+    // takes over the keys a committed transaction saw
+    pub(crate) fn extend(&mut self, other: SeenKeys) {
+        for (table, keys) in other.seen {
+            self.seen.entry(table).or_default().extend(keys);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.seen.clear();
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    // how long an insert's `idempotency_key` is remembered, 10 minutes by
+    // default. a repeat within the window returns the first insert's result
+    // without inserting again
+    pub fn set_idempotency_window(&mut self, window: Duration) {
+        self.idempotency.window = window;
+    }
+}
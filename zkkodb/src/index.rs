@@ -0,0 +1,221 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::backend::StorageBackend;
+use crate::crud::{column_collations, column_types, key_lookup, require_column};
+use crate::database::{Database, ExecError, Key, Row, Table};
+use crate::filter::{equality_operand, implies, Filter};
+use crate::parser::{one_or_many, ReadCommand};
+use crate::utils::compare_values;
+
+// a secondary index over one column or, in order, several. a partial index
+// only holds the rows matching its `where` predicate, written like a read
+// filter. in a unique index no two rows share their values, rows holding a
+// null in any of the columns aside
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub name: String,
+    #[serde(alias = "column", deserialize_with = "one_or_many")]
+    pub columns: Vec<String>,
+    #[serde(default, rename = "where", skip_serializing_if = "Option::is_none")]
+    pub predicate: Option<HashMap<String, Value>>,
+    #[serde(default)]
+    pub unique: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Index {
+    pub(crate) definition: IndexDefinition,
+    predicate: Option<Filter>,
+    // the array of the indexed columns' values to the primary keys of the rows
+    // holding them. arrays sort column by column, so rows sharing a prefix of
+    // values are adjacent
+    entries: BTreeMap<Key, BTreeSet<Key>>,
+}
+
+impl Index {
+    pub(crate) fn new<B: StorageBackend>(table_name: &str, table: &Table<B>, definition: IndexDefinition) -> Result<Index, ExecError> {
+        if definition.columns.is_empty() {
+            return Err(ExecError::InvalidQuery(format!("index '{}' has no columns", definition.name)));
+        }
+        for column in &definition.columns {
+            require_column(table_name, table, column)?;
+        }
+        let predicate = match &definition.predicate {
+            Some(predicate) => {
+                for column in predicate.keys() {
+                    require_column(table_name, table, column)?;
+                }
+                Some(Filter::compile(predicate, &column_types(table), &column_collations(table))?)
+            }
+            None => None,
+        };
+        Ok(Index {
+            definition,
+            predicate,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    // rows outside a partial index's predicate are skipped
+    pub(crate) fn insert(&mut self, key: &Key, row: &Row) {
+        if self.predicate.as_ref().is_some_and(|predicate| !predicate.matches(row)) {
+            return;
+        }
+        self.entries.entry(self.value_of(row)).or_default().insert(key.clone());
+    }
+
+    pub(crate) fn remove(&mut self, key: &Key, row: &Row) {
+        let value = self.value_of(row);
+        if let Some(keys) = self.entries.get_mut(&value) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.entries.remove(&value);
+            }
+        }
+    }
+
+    fn value_of(&self, row: &Row) -> Key {
+        let value = |column: &String| row.get(column).cloned().unwrap_or(Value::Null);
+        Key(Value::Array(self.definition.columns.iter().map(value).collect()))
+    }
+
+    // the values a unique index holds `row` under, None when it isn't unique or
+    // doesn't hold the row to a unique value
+    pub(crate) fn unique_value(&self, row: &Row) -> Option<Key> {
+        if !self.definition.unique || self.predicate.as_ref().is_some_and(|predicate| !predicate.matches(row)) {
+            return None;
+        }
+        let value = self.value_of(row);
+        match &value.0 {
+            Value::Array(values) if values.iter().any(Value::is_null) => None,
+            _ => Some(value),
+        }
+    }
+
+    // the primary keys of the rows the index holds under `value`
+    pub(crate) fn holders(&self, value: &Key) -> impl Iterator<Item = &Key> {
+        self.entries.get(value).into_iter().flatten()
+    }
+
+    // the primary keys of the rows whose first indexed column holds `value`
+    pub(crate) fn leading(&self, value: &Value) -> impl Iterator<Item = &Key> {
+        let prefix = vec![value.clone()];
+        self.entries
+            .range(Key(Value::Array(prefix.clone()))..)
+            .take_while(move |(values, _)| starts_with(values, &prefix))
+            .flat_map(|(_, keys)| keys)
+    }
+
+    pub(crate) fn violation(&self, table_name: &str, value: Key) -> ExecError {
+        let value = match value.0 {
+            Value::Array(mut values) if values.len() == 1 => values.remove(0),
+            value => value,
+        };
+        ExecError::UniqueViolation {
+            table: table_name.to_string(),
+            column: self.definition.columns.join(", "),
+            value,
+        }
+    }
+
+    // the primary keys of the rows a read has to look at through this index,
+    // None when the index can't answer the read's filter
+    fn candidates(&self, filter: &HashMap<String, Value>) -> Option<BTreeSet<Key>> {
+        if self.definition.predicate.as_ref().is_some_and(|predicate| !implies(filter, predicate)) {
+            return None;
+        }
+        // the leading columns the filter pins to one value each
+        let prefix: Vec<Value> = self
+            .definition
+            .columns
+            .iter()
+            .map_while(|column| filter.get(column).and_then(equality_operand).filter(|value| !value.is_null()))
+            .cloned()
+            .collect();
+        if prefix.is_empty() {
+            // a partial index holds every row an implying filter can match
+            return self.predicate.as_ref().map(|_| self.entries.values().flatten().cloned().collect());
+        }
+        let keys = self
+            .entries
+            .range(Key(Value::Array(prefix.clone()))..)
+            .take_while(|(values, _)| starts_with(values, &prefix))
+            .flat_map(|(_, keys)| keys.iter().cloned())
+            .collect();
+        Some(keys)
+    }
+
+    // e.g. "by_category (category, price)"
+    pub(crate) fn describe(&self) -> String {
+        format!("{} ({})", self.definition.name, self.definition.columns.join(", "))
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    pub(crate) fn create_index(&mut self, table_name: &str, definition: IndexDefinition) -> Result<(), ExecError> {
+        let table = self.table(table_name)?;
+        if table.indexes.iter().any(|index| index.definition.name == definition.name) {
+            return Err(ExecError::InvalidQuery(format!(
+                "index '{}' already exists on table '{}'",
+                definition.name, table_name
+            )));
+        }
+        let index = Index::new(table_name, table, definition)?;
+        let mut seen = BTreeSet::new();
+        for row in table.rows() {
+            if let Some(value) = index.unique_value(&row) {
+                if !seen.insert(value.clone()) {
+                    return Err(index.violation(table_name, value));
+                }
+            }
+        }
+        self.table_mut(table_name)?.add_index(index);
+        Ok(())
+    }
+
+    // reads that used the index scan again; a unique index no longer checks writes
+    pub(crate) fn drop_index(&mut self, table_name: &str, name: &str) -> Result<(), ExecError> {
+        let table = self.table(table_name)?;
+        let Some(position) = table.indexes.iter().position(|index| index.definition.name == name) else {
+            return Err(ExecError::InvalidQuery(format!(
+                "index '{}' doesn't exist on table '{}'",
+                name, table_name
+            )));
+        };
+        self.table_mut(table_name)?.indexes.remove(position);
+        Ok(())
+    }
+}
+
+// the index an unjoined read goes through and the keys it yields, picking the
+// index that leaves the fewest rows to check. a primary key lookup is always
+// preferred, so no index is used then
+pub(crate) fn index_lookup<'a, B: StorageBackend>(table: &'a Table<B>, cmd: &ReadCommand) -> Option<(&'a Index, BTreeSet<Key>)> {
+    if cmd.join.is_some() || key_lookup(table, cmd).is_some() {
+        return None;
+    }
+    // entries are found by their bytes, which a collated column doesn't match by
+    let collated = |column: &String| table.columns.get(column).is_some_and(|def| !def.collation.is_binary());
+    table
+        .indexes
+        .iter()
+        .filter(|index| {
+            let predicate = index.definition.predicate.iter().flat_map(HashMap::keys);
+            !index.definition.columns.iter().chain(predicate).any(collated)
+        })
+        .filter_map(|index| index.candidates(&cmd.filter).map(|keys| (index, keys)))
+        .min_by_key(|(_, keys)| keys.len())
+}
+
+fn starts_with(values: &Key, prefix: &[Value]) -> bool {
+    match &values.0 {
+        Value::Array(values) => values
+            .iter()
+            .zip(prefix)
+            .all(|(value, expected)| compare_values(value, expected) == Ordering::Equal),
+        _ => false,
+    }
+}
@@ -12,6 +12,42 @@ mod tests {
         assert_eq!(result, 4);
     }
 }
-mod parser;
+pub mod parser;
+pub mod backend;
+pub mod cancel;
+pub mod prepared;
+pub mod result;
+pub mod server;
+pub mod session;
+pub mod snapshot;
+pub mod csv;
+pub mod database;
+pub mod describe;
+pub mod diff;
+pub mod events;
+pub mod explain;
+pub mod grants;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod metrics;
+pub mod migrations;
+pub mod stats;
+pub mod storage;
+pub mod users;
+pub mod value;
+pub mod wal;
+pub mod wire;
+mod aggregate;
+mod batch;
+mod codec;
+mod constraints;
+mod crud;
+mod expr;
+mod filter;
+mod idempotency;
+mod index;
+mod store;
+mod utils;
+mod validator;
 #[cfg(test)]
 mod zkkodb_tests;
\ No newline at end of file
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use serde::Serialize;
+
+use crate::backend::StorageBackend;
+use crate::database::{Database, ExecError};
+use crate::parser::Command;
+
+const KINDS: [&str; 6] = ["create", "read", "insert", "update", "delete", "other"];
+
+// upper bounds of the latency buckets in microseconds, the last bucket is unbounded
+const BUCKETS_MICROS: [u64; 6] = [10, 100, 1_000, 10_000, 100_000, 1_000_000];
+
+// counters kept while metrics are enabled. they are atomic so reads through
+// `&Database` update them too; a database without metrics only checks an Option
+#[derive(Debug, Default)]
+pub struct Metrics {
+    commands: [AtomicU64; KINDS.len()],
+    latency: [AtomicU64; BUCKETS_MICROS.len() + 1],
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+// what `Database::metrics` and the `metrics` command report
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    // commands run by kind: create, read, insert, update, delete and other
+    pub commands: BTreeMap<String, u64>,
+    // failed commands by `ExecError::code`
+    pub errors: BTreeMap<String, u64>,
+    pub latency: Vec<LatencyBucket>,
+}
+
+// the commands that took at most `le_micros`, or longer for the last bucket
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LatencyBucket {
+    pub le_micros: Option<u64>,
+    pub count: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Timer {
+    kind: usize,
+    started: Instant,
+}
+
+impl Metrics {
+    fn record<T>(&self, timer: Timer, result: &Result<T, ExecError>) {
+        self.commands[timer.kind].fetch_add(1, Ordering::Relaxed);
+        let micros = timer.started.elapsed().as_micros();
+        let bucket = BUCKETS_MICROS
+            .iter()
+            .position(|&bound| micros <= bound as u128)
+            .unwrap_or(BUCKETS_MICROS.len());
+        self.latency[bucket].fetch_add(1, Ordering::Relaxed);
+        if let Err(err) = result {
+            let mut errors = self.errors.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            *errors.entry(err.code()).or_default() += 1;
+        }
+    }
+
+    fn snapshot(&self) -> MetricsSnapshot {
+        let errors = self.errors.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        MetricsSnapshot {
+            commands: KINDS
+                .iter()
+                .zip(&self.commands)
+                .map(|(kind, count)| (kind.to_string(), count.load(Ordering::Relaxed)))
+                .collect(),
+            errors: errors.iter().map(|(code, count)| (code.to_string(), *count)).collect(),
+            latency: self
+                .latency
+                .iter()
+                .enumerate()
+                .map(|(i, count)| LatencyBucket {
+                    le_micros: BUCKETS_MICROS.get(i).copied(),
+                    count: count.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    // starts counting commands from zero; enabling twice keeps the counts
+    pub fn enable_metrics(&mut self) {
+        self.metrics.get_or_insert_with(Metrics::default);
+    }
+
+    pub fn metrics(&self) -> Option<MetricsSnapshot> {
+        self.metrics.as_ref().map(Metrics::snapshot)
+    }
+
+    pub(crate) fn start_timer(&self, cmd: &Command) -> Option<Timer> {
+        self.metrics.as_ref()?;
+        let kind = match cmd {
+            Command::Create(_) | Command::CreateView { .. } | Command::CreateIndex { .. } | Command::CopyTable { .. } => 0,
+            Command::Read(_) | Command::Get { .. } | Command::Aggregate(_) => 1,
+            Command::Insert(_) | Command::Upsert(_) => 2,
+            Command::Update(_) => 3,
+            Command::Delete(_) => 4,
+            _ => 5,
+        };
+        Some(Timer {
+            kind,
+            started: Instant::now(),
+        })
+    }
+
+    pub(crate) fn finish_timer<T>(&self, timer: Option<Timer>, result: Result<T, ExecError>) -> Result<T, ExecError> {
+        if let (Some(metrics), Some(timer)) = (&self.metrics, timer) {
+            metrics.record(timer, &result);
+        }
+        result
+    }
+}
@@ -0,0 +1,110 @@
+use serde_json::{json, Value};
+
+use crate::backend::StorageBackend;
+use crate::constraints::check_unique;
+use crate::database::{Database, ExecError, Key, Row};
+use crate::events::ChangeKind;
+use crate::parser::Command;
+use crate::utils::now_millis;
+use crate::validator;
+
+// applied migration ids live in an ordinary table, so they are saved, loaded
+// and logged like any other data
+pub const MIGRATIONS_TABLE: &str = "_migrations";
+
+impl<B: StorageBackend> Database<B> {
+    // runs `cmds` in order unless migration `id` was applied before. returns
+    // whether it ran. a failing command stops the migration without recording
+    // it; the commands before it stay applied
+    pub fn apply_migration(&mut self, id: &str, cmds: Vec<Command>) -> Result<bool, ExecError> {
+        if self.migration_applied(id) {
+            return Ok(false);
+        }
+        if !self.tables.contains_key(MIGRATIONS_TABLE) {
+            self.execute(command(json!({
+                "command": "create",
+                "type": "table",
+                "table": MIGRATIONS_TABLE,
+                "primary_key": "id",
+                "rows": {
+                    "id": { "type": "string" },
+                    "applied_at": { "type": "int", "not_null": true }
+                }
+            })))?;
+        }
+
+        for cmd in cmds {
+            self.execute(cmd)?;
+        }
+        self.execute(command(json!({
+            "command": "insert",
+            "table": MIGRATIONS_TABLE,
+            "rows": { "id": id, "applied_at": now_millis() }
+        })))?;
+        Ok(true)
+    }
+
+    // sets `column` of every row to `f(row)`, e.g. to fill a column just added
+    // with an `update` of type `rows`. every new value is checked like an
+    // update first, and the first row that fails is reported by its key with
+    // nothing changed. returns the number of rows. the closure can't be logged,
+    // so on a database with a write-ahead log `save` afterwards
+    pub fn backfill(&mut self, table_name: &str, column: &str, f: impl Fn(&Row) -> Value) -> Result<usize, ExecError> {
+        if self.read_only {
+            return Err(ExecError::ReadOnly);
+        }
+        let table = self.table(table_name)?;
+        if table.primary_key.contains(column) {
+            return Err(ExecError::InvalidQuery(format!(
+                "can't backfill primary key column '{}'",
+                column
+            )));
+        }
+        let at_key = |key: &Key, error: ExecError| ExecError::Backfill {
+            key: key.0.clone(),
+            error: Box::new(error),
+        };
+
+        let mut changed = Vec::with_capacity(table.len());
+        for (key, old) in table.entries() {
+            let assignment = Row::from_iter([(column.to_string(), f(&old))]);
+            let row = validator::validate_update(table_name, table, assignment)
+                .and_then(|assignments| validator::apply_update(table, &old, &assignments))
+                .map_err(|error| at_key(key, error))?;
+            self.check_references(table_name, &row).map_err(|error| at_key(key, error))?;
+            self.check_referenced_update(table_name, &old, &row).map_err(|error| at_key(key, error))?;
+            changed.push((key.clone(), row));
+        }
+        check_unique(table_name, table, changed.iter().map(|(_, row)| row), |_| true)?;
+
+        let table = self.table_mut(table_name)?;
+        let mut keys = Vec::with_capacity(changed.len());
+        for (key, row) in changed {
+            let stamp = table.remove_row(&key);
+            keys.push(key.0.clone());
+            table.insert_row(key, row, stamp);
+        }
+        let count = keys.len();
+        if count > 0 {
+            self.notify(ChangeKind::Update, table_name, keys);
+        }
+        Ok(count)
+    }
+
+    // ids of the applied migrations, sorted
+    pub fn applied_migrations(&self) -> Vec<String> {
+        let Ok(table) = self.table(MIGRATIONS_TABLE) else {
+            return Vec::new();
+        };
+        table.rows().filter_map(|row| row["id"].as_str().map(str::to_string)).collect()
+    }
+
+    pub fn migration_applied(&self, id: &str) -> bool {
+        self.table(MIGRATIONS_TABLE)
+            .is_ok_and(|table| table.get(&Key(json!(id))).is_some())
+    }
+}
+
+pub(crate) fn command(value: serde_json::Value) -> Command {
+    serde_json::from_value(value).expect("built-in command is valid")
+}
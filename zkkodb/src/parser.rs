@@ -1,7 +1,13 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use serde::Deserialize;
+use std::fmt;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize)]
+use crate::filter::is_logical;
+use crate::result::ColumnType;
+use crate::utils::natural_cmp;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "command")]
 pub enum Command {
     #[serde(rename = "create")]
@@ -18,64 +24,675 @@ pub enum Command {
     #[serde(rename = "insert")]
     Insert(InsertCommand),
 
+    // inserts the row, or updates the one that already holds its values in
+    // the `on_conflict` columns
+    #[serde(rename = "upsert")]
+    Upsert(UpsertCommand),
+
     
     #[serde(rename = "delete")]
     Delete(DeleteCommand),
 
+    // a named read that can later be read like a table
+    #[serde(rename = "create_view")]
+    CreateView {
+        name: String,
+        query: ReadCommand,
+    },
+
+    // a secondary index on `columns`; with `where` only the rows matching that
+    // filter are indexed, and a `unique` index refuses two rows with the same
+    // values in them
+    #[serde(rename = "create_index")]
+    CreateIndex {
+        table: String,
+        name: String,
+        // one column name or an ordered array of them
+        #[serde(alias = "column", deserialize_with = "one_or_many")]
+        columns: Vec<String>,
+        #[serde(default, rename = "where")]
+        predicate: Option<HashMap<String, serde_json::Value>>,
+        #[serde(default)]
+        unique: bool,
+    },
+
+    // a new table `to` with the schema and indexes of `from`, and its rows
+    // when `include_data` is true
+    #[serde(rename = "copy_table")]
+    CopyTable {
+        from: String,
+        to: String,
+        include_data: bool,
+    },
+
+    // physically removes the rows of a ttl table that have expired
+    #[serde(rename = "purge_expired")]
+    PurgeExpired {
+        table: String,
+    },
+
+    // sizes of one table, or totals over all tables when `table` is omitted
+    #[serde(rename = "stats")]
+    Stats {
+        #[serde(default)]
+        table: Option<String>,
+    },
+
+    // the names of the tables matching `pattern`, a glob like "log_*" where `*`
+    // stands for any run of characters and `?` for one. all tables without it.
+    // with `details` each comes with its row count and primary key
+    #[serde(rename = "list_tables")]
+    ListTables {
+        #[serde(default)]
+        pattern: Option<String>,
+        #[serde(default)]
+        details: bool,
+    },
+
+    // {"command": "list", "type": "tables"}, the tables with their row
+    // counts and primary keys like `list_tables` with `details`
+    #[serde(rename = "list")]
+    List(ListCommand),
+
+    // every user with their role, never their password. run in a session, needs
+    // the admin role once an admin user exists
+    #[serde(rename = "list_users")]
+    ListUsers,
+
+    // drops every table matching the glob `pattern` and returns how many. needs
+    // `confirm` and, run in a session, the admin role
+    #[serde(rename = "drop_tables")]
+    DropTables {
+        pattern: String,
+        #[serde(default)]
+        confirm: bool,
+    },
+
+    // gives a user or role privileges on a table. once anything was granted,
+    // sessions without the admin role only run what they were granted; run in
+    // a session, these need the admin role
+    #[serde(rename = "grant")]
+    Grant(GrantCommand),
+
+    #[serde(rename = "revoke")]
+    Revoke(GrantCommand),
+
+    // the schema of `table`: key, columns with their comments and indexes
+    #[serde(rename = "describe")]
+    Describe {
+        table: String,
+    },
+
+    // command counts, error counts and latencies since `enable_metrics`
+    #[serde(rename = "metrics")]
+    Metrics,
+
+    // the mutating commands recorded since `enable_command_log`, from position
+    // `since` on; replaying them into an empty database rebuilds this one
+    #[serde(rename = "export_log")]
+    ExportLog {
+        #[serde(default)]
+        since: usize,
+    },
+
+    // the row with primary key `key`, an array for composite keys, or null
+    #[serde(rename = "get")]
+    Get {
+        table: String,
+        key: serde_json::Value,
+    },
+
+    // writes the whole database into the single file `path`
+    #[serde(rename = "backup")]
+    Backup {
+        path: String,
+    },
+
+    // checks the snapshot files in `dir` against the checksums recorded by the
+    // last save, for `table` or every table
+    #[serde(rename = "verify")]
+    Verify {
+        dir: String,
+        #[serde(default)]
+        table: Option<String>,
+    },
+
+    // replaces the whole database with a file written by backup
+    #[serde(rename = "restore")]
+    Restore {
+        path: String,
+        // the file's contents, read in before the command is logged so that
+        // replaying it doesn't depend on the file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        archive: Option<serde_json::Value>,
+    },
+
+    // the aggregates of the rows matching `filter` without the rows
+    // themselves, one row per group with `group_by`; runs as the read with
+    // just these fields
+    #[serde(rename = "aggregate")]
+    Aggregate(AggregateCommand),
+
+    // describes how the command in `inner` would run without running it.
+    // `query` takes a bare read instead, like `{ "table": ... }`
+    #[serde(rename = "explain")]
+    Explain {
+        #[serde(alias = "query", deserialize_with = "explained")]
+        inner: Box<Command>,
+    },
+
+    // a transaction in a session: writes after begin are only seen by that
+    // session until commit, and rollback discards them
+    #[serde(rename = "begin")]
+    Begin,
+
+    #[serde(rename = "commit")]
+    Commit,
+
+    #[serde(rename = "rollback")]
+    Rollback,
+
+    // a named point inside the open transaction that `rollback_to` returns to,
+    // undoing later work but keeping the transaction open
+    #[serde(rename = "savepoint")]
+    Savepoint {
+        name: String,
+    },
+
+    #[serde(rename = "rollback_to")]
+    RollbackTo {
+        name: String,
+    },
+
+    // a read the session keeps under `name`, its filter holding placeholders
+    // like "?price" that each `execute` of it binds to `params`
+    #[serde(rename = "prepare")]
+    Prepare {
+        name: String,
+        query: ReadCommand,
+    },
+
+    #[serde(rename = "execute")]
+    Execute {
+        name: String,
+        #[serde(default)]
+        params: HashMap<String, serde_json::Value>,
+    },
+
+    // runs `commands` in order and returns their results. an atomic batch
+    // applies all of them or, when one fails, none. a top-level JSON array of
+    // commands parses as a batch that isn't atomic
+    #[serde(rename = "batch")]
+    Batch {
+        commands: Vec<Command>,
+        #[serde(default)]
+        atomic: bool,
+    },
+
     /*
     Unknown(String)
     */
 }
 
+impl Command {
+    // commands that change the database and therefore go through the write-ahead log
+    pub fn is_mutating(&self) -> bool {
+        if let Command::Batch { commands, .. } = self {
+            return commands.iter().any(Command::is_mutating);
+        }
+        !matches!(
+            self,
+            Command::Read(_)
+                | Command::Get { .. }
+                | Command::Stats { .. }
+                | Command::Describe { .. }
+                | Command::ListTables { .. }
+                | Command::List(_)
+                | Command::ListUsers
+                | Command::Metrics
+                | Command::ExportLog { .. }
+                | Command::Explain { .. }
+                | Command::Aggregate(_)
+                | Command::Backup { .. }
+                | Command::Verify { .. }
+                | Command::Begin
+                | Command::Commit
+                | Command::Rollback
+                | Command::Savepoint { .. }
+                | Command::RollbackTo { .. }
+                | Command::Prepare { .. }
+                | Command::Execute { .. }
+        )
+    }
+}
+
 // differentiates a User create from a table create
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum CreateCommand {
+    // the password is replaced by its hash before the command is logged, so
+    // logs and replays only ever carry `password_hash`
     #[serde(rename = "user")]
     User {
         username: String,
-        password: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password_hash: Option<String>,
         role: String,
     },
 
     #[serde(rename = "table")]
     Table {
         table: String,
-        primary_key: String,
-        rows: std::collections::HashMap<String, ColumnDefinition>,
+        primary_key: PrimaryKey,
+        rows: HashMap<String, ColumnDefinition>,
+        // rows expire this many seconds after they were inserted
+        #[serde(default)]
+        ttl_seconds: Option<u64>,
+        #[serde(default)]
+        storage: StorageLayout,
+        // turns on `implicit_default` for every column
+        #[serde(default)]
+        implicit_default: bool,
+        // adds `created_at` and `updated_at` datetime columns that the engine
+        // sets on insert and, for `updated_at`, on every update
+        #[serde(default)]
+        timestamps: bool,
+        // the most rows the table may hold, inserts past it fail
+        #[serde(default)]
+        max_rows: Option<usize>,
+    },
+
+    // the index `create_index` builds, named `<table>_<columns>_idx` unless
+    // it has a name
+    #[serde(rename = "index")]
+    Index {
+        table: String,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(alias = "column", deserialize_with = "one_or_many")]
+        columns: Vec<String>,
+        #[serde(default, rename = "where")]
+        predicate: Option<HashMap<String, serde_json::Value>>,
+        #[serde(default)]
+        unique: bool,
+    },
+}
+
+// how a table keeps its rows in memory. columnar tables hold one vector per
+// column so aggregates only touch the columns they read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum StorageLayout {
+    #[default]
+    #[serde(rename = "rows")]
+    Rows,
+    #[serde(rename = "columnar")]
+    Columnar,
+}
+
+// a single column name or, for a composite key, an array of column names
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PrimaryKey {
+    Single(String),
+    Composite(Vec<String>),
+}
+
+impl PrimaryKey {
+    pub fn columns(&self) -> &[String] {
+        match self {
+            PrimaryKey::Single(column) => std::slice::from_ref(column),
+            PrimaryKey::Composite(columns) => columns,
+        }
+    }
+
+    pub fn contains(&self, column: &str) -> bool {
+        self.columns().iter().any(|c| c == column)
+    }
+
+    // the key column when the key is made of exactly one column
+    pub fn single(&self) -> Option<&str> {
+        match self.columns() {
+            [column] => Some(column),
+            _ => None,
+        }
+    }
+}
+
+// the command of an explain: a whole command, or a read without "command"
+fn explained<'de, D>(deserializer: D) -> Result<Box<Command>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let inner = serde_json::Value::deserialize(deserializer)?;
+    let cmd = match inner.get("command") {
+        Some(_) => serde_json::from_value(inner),
+        None => serde_json::from_value(inner).map(Command::Read),
+    };
+    cmd.map(Box::new).map_err(serde::de::Error::custom)
+}
+
+// accepts "a" as well as ["a", "b"]
+pub(crate) fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
     }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(item) => vec![item],
+        OneOrMany::Many(items) => items,
+    })
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ReadCommand {
     pub table: String,
     #[serde(default)]
-    pub filter: std::collections::HashMap<String, serde_json::Value>,
+    pub filter: HashMap<String, serde_json::Value>,
+    // a string filter like those of updates, e.g. "price < 20". rows must
+    // match it and `filter` both
+    #[serde(default, rename = "where")]
+    pub where_filter: Option<String>,
     #[serde(default)]
     pub limit: Option<usize>,
+    // skips this many rows before `limit` applies. rows written meanwhile
+    // shift the pages; pagination's cursor doesn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    #[serde(default)]
+    pub join: Option<JoinClause>,
+    #[serde(default)]
+    pub aggregates: Vec<AggregateSpec>,
+    #[serde(default)]
+    pub group_by: Vec<String>,
+    // a filter on the grouped rows by group_by columns and aggregate names,
+    // e.g. {"count(*)": {"$gt": 1}}
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub having: HashMap<String, serde_json::Value>,
+    // columns to return, all of them when empty
+    #[serde(default)]
+    pub columns: Vec<String>,
+    // drops result rows equal to an earlier one
+    #[serde(default)]
+    pub distinct: bool,
+    // returns the rows in pages of `limit` along with a cursor to the next page
+    #[serde(default)]
+    pub paginate: bool,
+    // the `next_cursor` of the previous page, also accepted as `cursor`
+    #[serde(default, alias = "cursor")]
+    pub after: Option<String>,
+    // returns up to this many of the matching rows chosen at random, in key
+    // order. can't be combined with `limit` or pagination
+    #[serde(default)]
+    pub sample: Option<usize>,
+    // makes a sample repeatable: the same seed over the same rows picks the same ones
+    #[serde(default)]
+    pub seed: Option<u64>,
+    // only the rows updated (or inserted) after this RFC 3339 time, oldest
+    // change first. needs a table with timestamps
+    #[serde(default)]
+    pub changed_since: Option<String>,
+    // returns `{"columns": [{"name", "type"}], "rows"}`, the columns in order
+    // with their types. can't be combined with pagination
+    #[serde(default)]
+    pub with_types: bool,
+    // sorts the rows by these columns in turn before `columns`, `distinct` and
+    // `limit` apply; ties keep key order. can't be combined with pagination
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub order_by: Vec<OrderBy>,
+}
+
+impl ReadCommand {
+    pub fn is_paginated(&self) -> bool {
+        self.paginate || self.after.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggregateCommand {
+    pub table: String,
+    #[serde(default)]
+    pub filter: HashMap<String, serde_json::Value>,
+    pub aggregates: Vec<AggregateSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub group_by: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub having: HashMap<String, serde_json::Value>,
+}
+
+impl AggregateCommand {
+    pub fn into_read(self) -> ReadCommand {
+        ReadCommand {
+            table: self.table,
+            filter: self.filter,
+            aggregates: self.aggregates,
+            group_by: self.group_by,
+            having: self.having,
+            ..ReadCommand::default()
+        }
+    }
 }
-#[derive(Debug, Deserialize)]
+
+// e.g. {"column": "price", "direction": "desc"}. strings compare by their
+// column's collation; nulls and missing values sort first ascending and last
+// descending
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderBy {
+    pub column: String,
+    #[serde(default)]
+    pub direction: Direction,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Asc,
+    Desc,
+}
+
+// e.g. {"function": "sum", "column": "price"}, reported as "sum(price)" unless aliased
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggregateSpec {
+    pub function: AggregateFunction,
+    #[serde(default)]
+    pub column: Option<String>,
+    #[serde(default, rename = "as")]
+    pub alias: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AggregateFunction {
+    #[serde(rename = "count")]
+    Count,
+    #[serde(rename = "sum")]
+    Sum,
+    #[serde(rename = "avg")]
+    Avg,
+    #[serde(rename = "min")]
+    Min,
+    #[serde(rename = "max")]
+    Max,
+}
+
+// join of the read table with a second table on column equality
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinClause {
+    pub table: String,
+    pub on: JoinOn,
+    #[serde(default, rename = "type")]
+    pub kind: JoinKind,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinKind {
+    // only rows with a match on both sides
+    #[default]
+    Inner,
+    // every row of the read table, those without a match once with the joined
+    // table's columns null
+    Left,
+}
+
+// `left` is a column of the read table, `right` a column of the joined table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinOn {
+    pub left: String,
+    pub right: String,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum UpdateCommand {
+  // drops the columns in `drop`, renames those in `rename`, adds the columns
+  // in `add`, then applies the changes in `alter` by the new names. `max_rows`
+  // sets a new row quota, null removes it
   #[serde(rename = "rows")]
   Rows {
     table: String, 
-    add: std::collections::HashMap<String, ColumnDefinition>,
+    #[serde(default)]
+    add: HashMap<String, ColumnDefinition>,
+    #[serde(default)]
+    alter: HashMap<String, ColumnChange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    drop: Vec<String>,
+    // old column name to new
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    rename: HashMap<String, String>,
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    max_rows: Option<Option<usize>>,
+  },
+
+  // gives `table` the name `to`
+  #[serde(rename = "rename")]
+  Rename {
+    table: String,
+    to: String,
   },
 
   #[serde(rename = "content")]
   Content {
     table: String,
     filter: String,
-    rows: std::collections::HashMap<String, serde_json::Value>
+    rows: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    on_error: OnError,
+    // a string filter the matched rows must also meet right now for the update
+    // to change them, e.g. "price = 15"
+    #[serde(default, rename = "if")]
+    condition: Option<String>,
+    // adds the columns `rows` names that the table doesn't have, typed by
+    // their values, instead of failing
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    allow_new_columns: bool,
+  },
+
+  // gives a user a new password, role or both; those left out stay as they
+  // are. like a create, the password is logged as its hash
+  #[serde(rename = "user")]
+  User {
+    username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
   }
 }
-#[derive(Debug, Deserialize)]
+
+// changes to an existing column's constraints, fields left out stay as they are
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ColumnChange {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_null: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unique: Option<bool>,
+    // a new default, or null to remove it
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub default: Option<Option<serde_json::Value>>,
+}
+
+// a field that is there, even as null, as opposed to one left out
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// what an update does with a row whose `$expr` can't be evaluated, e.g. on a
+// division by zero: fail the whole update, or leave that row unchanged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum OnError {
+    #[default]
+    #[serde(rename = "abort")]
+    Abort,
+    #[serde(rename = "skip")]
+    Skip,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct  InsertCommand {
     pub table: String,
-    pub rows: std::collections::HashMap<String, serde_json::Value>
+    pub rows: HashMap<String, serde_json::Value>,
+    // a client-chosen id for the insert: repeating it within the database's
+    // idempotency window returns the first result instead of inserting again
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+}
+
+// exactly one of `user` and `role`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrantCommand {
+    pub table: String,
+    // one privilege or an array of them
+    #[serde(deserialize_with = "one_or_many")]
+    pub privileges: Vec<Privilege>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Privilege {
+    // reads, gets, explains and views over the table
+    Read,
+    Insert,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpsertCommand {
+    pub table: String,
+    pub rows: HashMap<String, serde_json::Value>,
+    // the primary key when empty, else the key's columns or one unique column;
+    // one column name or an array of them
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub on_conflict: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ListCommand {
+    #[serde(rename = "tables")]
+    Tables {
+        #[serde(default)]
+        pattern: Option<String>,
+    },
 }
-#[derive(Debug, Deserialize)]
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum DeleteCommand {
     #[serde(rename = "table")]
@@ -85,14 +702,33 @@ pub enum DeleteCommand {
     #[serde(rename = "content")]
     Content {
       table: String,
-      filter: String
-    }
+      filter: String,
+      // deletes at most this many matching rows, the first in key order
+      #[serde(default)]
+      limit: Option<usize>,
+    },
+    // every row, the schema and indexes stay
+    #[serde(rename = "truncate")]
+    Truncate {
+      table: String
+    },
+    // the secondary index `name` of `table`
+    #[serde(rename = "index")]
+    Index {
+      table: String,
+      name: String,
+    },
+    // the user and the grants made to them
+    #[serde(rename = "user")]
+    User {
+      username: String
+    },
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct ColumnDefinition {
     #[serde(rename = "type")]
-    pub col_type: String,
+    pub col_type: ColumnType,
 
     #[serde(default)]
     pub not_null: bool,
@@ -100,6 +736,566 @@ pub struct ColumnDefinition {
     #[serde(default)]
     pub unique: bool,
 
+    // JSON of the column's type, e.g. 0 for an int, or a call like "now()"
+    #[serde(default)]
+    pub default: Option<serde_json::Value>,
+
+    // a row that leaves the column out without a `default` gets the type's zero
+    // value (0, 0.0, "", false) instead of null
+    #[serde(default)]
+    pub implicit_default: bool,
+
+    #[serde(default)]
+    pub references: Option<ForeignKey>,
+
+    // arithmetic over sibling columns, e.g. "price * quantity", computed by the
+    // engine on every write
+    #[serde(default)]
+    pub generated: Option<String>,
+
+    // free text for people reading the schema, never checked
     #[serde(default)]
-    pub default: Option<String>,
+    pub comment: Option<String>,
+
+    // how the values of a string or char column compare in filters and
+    // min/max. indexes, unique and the primary key still go by bytes
+    #[serde(default, skip_serializing_if = "Collation::is_binary")]
+    pub collation: Collation,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Collation {
+    // by the bytes of the text
+    #[default]
+    Binary,
+    // ignoring case, so "A" equals "a"
+    NoCase,
+    // runs of digits by their value, so "item2" comes before "item10".
+    // only identical strings are equal
+    Numeric,
+}
+
+impl Collation {
+    pub fn is_binary(&self) -> bool {
+        *self == Collation::Binary
+    }
+
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            Collation::NoCase => a.to_lowercase().cmp(&b.to_lowercase()),
+            Collation::Numeric => natural_cmp(a, b),
+        }
+    }
+}
+
+// the column's non-null values must exist in `table.column`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForeignKey {
+    pub table: String,
+    pub column: String,
+    #[serde(default)]
+    pub on_delete: OnDelete,
+}
+
+// what happens to referencing rows when the referenced row is deleted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum OnDelete {
+    #[default]
+    #[serde(rename = "restrict")]
+    Restrict,
+    #[serde(rename = "cascade")]
+    Cascade,
+}
+
+// why `parse_command` rejected its input
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    // 1-based position of the syntax error
+    InvalidJson { line: usize, col: usize },
+    // the `command` (or its `type`) is not one the engine knows
+    UnknownCommand { command: String },
+    MissingField { field: String },
+    UnknownColumnType { got: String },
+    // rejected before parsing: longer than the caller's byte limit
+    TooLarge { max: usize },
+    // arrays and objects nested deeper than `MAX_NESTING`
+    TooDeep { max: usize },
+    // an object names a key twice, see `parse_command_strict`. `field` is its
+    // path, e.g. "rows.price"
+    DuplicateField { field: String },
+    // well-formed JSON that doesn't fit the command model otherwise
+    Invalid(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidJson { line, col } => write!(f, "invalid JSON at line {}, column {}", line, col),
+            ParseError::UnknownCommand { command } => write!(f, "unknown command '{}'", command),
+            ParseError::MissingField { field } => write!(f, "missing field '{}'", field),
+            ParseError::UnknownColumnType { got } => write!(f, "unknown column type '{}'", got),
+            ParseError::TooLarge { max } => write!(f, "input exceeds the maximum of {} bytes", max),
+            ParseError::TooDeep { max } => write!(f, "input nests deeper than {} levels", max),
+            ParseError::DuplicateField { field } => write!(f, "duplicate field '{}'", field),
+            ParseError::Invalid(reason) => write!(f, "invalid command: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// how deep arrays and objects may nest in a command; real commands stay far
+// below this, pathological documents are rejected before serde recurses
+pub const MAX_NESTING: usize = 64;
+
+// like `parse_command`, but input longer than `max_bytes` is rejected up front
+pub fn parse_command_limited(input: &str, max_bytes: usize) -> Result<Command, ParseError> {
+    if input.len() > max_bytes {
+        return Err(ParseError::TooLarge { max: max_bytes });
+    }
+    parse_command(input)
+}
+
+// like `parse_command`, but an object that repeats a key anywhere in the
+// command, such as a row with two "price" fields, is rejected instead of
+// keeping the last value
+pub fn parse_command_strict(input: &str) -> Result<Command, ParseError> {
+    if !nests_deeper_than(input, MAX_NESTING) {
+        let mut deserializer = serde_json::Deserializer::from_str(input);
+        // malformed JSON is left for `parse_command` to report
+        if let Ok(FirstDuplicate(Some(field))) = FirstDuplicate::deserialize(&mut deserializer) {
+            return Err(ParseError::DuplicateField { field });
+        }
+    }
+    parse_command(input)
+}
+
+// the path of the first key an object in a JSON document repeats
+struct FirstDuplicate(Option<String>);
+
+impl<'de> Deserialize<'de> for FirstDuplicate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(DuplicateVisitor)
+    }
+}
+
+struct DuplicateVisitor;
+
+impl<'de> serde::de::Visitor<'de> for DuplicateVisitor {
+    type Value = FirstDuplicate;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "any JSON value")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<FirstDuplicate, A::Error> {
+        let mut seen = std::collections::HashSet::new();
+        let mut found = None;
+        // every entry is read even after a find, serde expects the whole map consumed
+        while let Some(key) = map.next_key::<String>()? {
+            let FirstDuplicate(nested) = map.next_value()?;
+            if found.is_none() {
+                found = match nested {
+                    Some(path) if path.starts_with('[') => Some(format!("{}{}", key, path)),
+                    Some(path) => Some(format!("{}.{}", key, path)),
+                    None if !seen.insert(key.clone()) => Some(key),
+                    None => None,
+                };
+            }
+        }
+        Ok(FirstDuplicate(found))
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<FirstDuplicate, A::Error> {
+        let mut found = None;
+        let mut i = 0;
+        while let Some(FirstDuplicate(nested)) = seq.next_element()? {
+            if found.is_none() {
+                found = nested.map(|path| format!("[{}].{}", i, path));
+            }
+            i += 1;
+        }
+        Ok(FirstDuplicate(found))
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<FirstDuplicate, E> {
+        Ok(FirstDuplicate(None))
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<FirstDuplicate, E> {
+        Ok(FirstDuplicate(None))
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<FirstDuplicate, E> {
+        Ok(FirstDuplicate(None))
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<FirstDuplicate, E> {
+        Ok(FirstDuplicate(None))
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<FirstDuplicate, E> {
+        Ok(FirstDuplicate(None))
+    }
+
+    fn visit_unit<E>(self) -> Result<FirstDuplicate, E> {
+        Ok(FirstDuplicate(None))
+    }
+}
+
+// parses a JSON command, classifying failures instead of returning serde's message
+pub fn parse_command(input: &str) -> Result<Command, ParseError> {
+    if nests_deeper_than(input, MAX_NESTING) {
+        return Err(ParseError::TooDeep { max: MAX_NESTING });
+    }
+    let value: serde_json::Value = serde_json::from_str(input).map_err(|err| ParseError::InvalidJson {
+        line: err.line(),
+        col: err.column(),
+    })?;
+    let value = match value {
+        serde_json::Value::Array(commands) => serde_json::json!({ "command": "batch", "commands": commands }),
+        value => value,
+    };
+    let tag = |field: &str| value.get(field).and_then(serde_json::Value::as_str);
+    let Some(command) = tag("command") else {
+        return Err(ParseError::MissingField { field: "command".to_string() });
+    };
+
+    // the columns a table is created with, or those added to it
+    let defined = match (command, tag("type")) {
+        ("create", Some("table")) => value.get("rows"),
+        ("update", Some("rows")) => value.get("add"),
+        _ => None,
+    };
+    if let Some(columns) = defined.and_then(serde_json::Value::as_object) {
+        for def in columns.values() {
+            let Some(got) = def.get("type").and_then(serde_json::Value::as_str) else {
+                continue;
+            };
+            if ColumnType::parse(got).is_none() {
+                return Err(ParseError::UnknownColumnType { got: got.to_string() });
+            }
+        }
+    }
+
+    serde_json::from_value(value.clone()).map_err(|err| {
+        let message = err.to_string();
+        if let Some(field) = quoted_after(&message, "missing field ") {
+            return ParseError::MissingField { field };
+        }
+        match quoted_after(&message, "unknown variant ") {
+            Some(variant) if variant == command => ParseError::UnknownCommand { command: variant },
+            Some(variant) if tag("type") == Some(variant.as_str()) => ParseError::UnknownCommand {
+                command: format!("{} {}", command, variant),
+            },
+            _ => ParseError::Invalid(message),
+        }
+    })
+}
+
+// scans brackets outside of string literals, so it runs in constant space
+fn nests_deeper_than(input: &str, max: usize) -> bool {
+    let (mut depth, mut in_string, mut escaped) = (0_usize, false, false);
+    for byte in input.bytes() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max {
+                    return true;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
+
+// the `name` in serde messages like "missing field `name`"
+fn quoted_after(message: &str, prefix: &str) -> Option<String> {
+    let rest = message.strip_prefix(prefix)?.strip_prefix('`')?;
+    rest.split_once('`').map(|(name, _)| name.to_string())
+}
+
+// parses the string filters of update/delete commands, e.g. "id = 1",
+// "price > 10 AND name MATCHES '^Coco'", "NOT price > 10" or "note IS NULL",
+// into the map form used by reads. NOT binds tighter than AND, and AND
+// tighter than OR; OR becomes $or, a NOT before parentheses becomes $not
+pub fn parse_filter(input: &str) -> Result<HashMap<String, serde_json::Value>, String> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        return Err("empty filter".to_string());
+    }
+    let mut tokens = tokens.into_iter().peekable();
+    let filter = parse_or(&mut tokens)?;
+    match tokens.next() {
+        None => Ok(filter),
+        Some(Token::Close) => Err("unbalanced ')'".to_string()),
+        Some(other) => Err(format!("expected AND or OR, found {:?}", other)),
+    }
+}
+
+type Tokens = std::iter::Peekable<std::vec::IntoIter<Token>>;
+
+fn is_word(token: Option<&Token>, expected: &str) -> bool {
+    matches!(token, Some(Token::Word(word)) if word.eq_ignore_ascii_case(expected))
+}
+
+fn parse_or(tokens: &mut Tokens) -> Result<HashMap<String, serde_json::Value>, String> {
+    let mut alternatives = vec![parse_and(tokens)?];
+    while is_word(tokens.peek(), "or") {
+        tokens.next();
+        alternatives.push(parse_and(tokens)?);
+    }
+    if alternatives.len() == 1 {
+        return Ok(alternatives.remove(0));
+    }
+    let alternatives = alternatives.into_iter().map(|filter| serde_json::Value::Object(filter.into_iter().collect()));
+    Ok(HashMap::from([("$or".to_string(), serde_json::Value::Array(alternatives.collect()))]))
+}
+
+fn parse_and(tokens: &mut Tokens) -> Result<HashMap<String, serde_json::Value>, String> {
+    let mut filter: HashMap<String, serde_json::Value> = HashMap::new();
+    loop {
+        let negated = is_word(tokens.peek(), "not");
+        if negated {
+            tokens.next();
+        }
+        if matches!(tokens.peek(), Some(Token::Open)) {
+            tokens.next();
+            let group = parse_or(tokens)?;
+            match tokens.next() {
+                Some(Token::Close) => {}
+                other => return Err(format!("expected ')', found {:?}", other)),
+            }
+            let group = if negated {
+                let group = serde_json::Value::Object(group.into_iter().collect());
+                HashMap::from([("$not".to_string(), group)])
+            } else {
+                group
+            };
+            filter = and_filters(filter, group)?;
+        } else {
+            parse_comparison(tokens, negated, &mut filter)?;
+        }
+
+        if !is_word(tokens.peek(), "and") {
+            return Ok(filter);
+        }
+        tokens.next();
+    }
+}
+
+// one `column op value` condition, added to the operators `filter` already
+// holds for the column
+fn parse_comparison(
+    tokens: &mut Tokens,
+    negated: bool,
+    filter: &mut HashMap<String, serde_json::Value>,
+) -> Result<(), String> {
+    let column = match tokens.next() {
+        Some(Token::Word(word)) => word,
+        other => return Err(format!("expected a column name, found {:?}", other)),
+    };
+    let op = match tokens.next() {
+        Some(Token::Op(op)) => op,
+        Some(Token::Word(word)) if word.eq_ignore_ascii_case("is") => "IS",
+        Some(Token::Word(word)) if word.eq_ignore_ascii_case("matches") => "MATCHES",
+        Some(Token::Word(word)) if word.eq_ignore_ascii_case("between") => "BETWEEN",
+        other => return Err(format!("expected an operator after '{}', found {:?}", column, other)),
+    };
+    let mut literal = if op == "IS" {
+        let not = is_word(tokens.peek(), "not");
+        if not {
+            tokens.next();
+        }
+        match tokens.next() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("null") => serde_json::Value::Bool(!not),
+            other => return Err(format!("expected NULL after '{} IS', found {:?}", column, other)),
+        }
+    } else {
+        next_literal(tokens, &column, op)?
+    };
+    if op == "BETWEEN" {
+        match tokens.next() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("and") => {}
+            other => return Err(format!("expected AND in '{} BETWEEN', found {:?}", column, other)),
+        }
+        let upper = next_literal(tokens, &column, op)?;
+        literal = serde_json::Value::Array(vec![literal, upper]);
+    }
+
+    let key = match op {
+        "=" => "$eq",
+        "!=" | "<>" => "$ne",
+        ">" => "$gt",
+        ">=" => "$gte",
+        "<" => "$lt",
+        "<=" => "$lte",
+        "BETWEEN" => "$between",
+        "IS" => "$is_null",
+        _ => "$regex",
+    };
+    let (key, op) = if negated {
+        literal = serde_json::Value::Object(serde_json::Map::from_iter([(key.to_string(), literal)]));
+        ("$not", "NOT")
+    } else {
+        (key, op)
+    };
+    // a second NOT on the column can't share its operator object, so it joins
+    // the filter's $and instead
+    if negated && filter.get(&column).and_then(|ops| ops.get("$not")).is_some() {
+        let ops = serde_json::Map::from_iter([(key.to_string(), literal)]);
+        let condition = serde_json::Map::from_iter([(column, serde_json::Value::Object(ops))]);
+        return match filter.entry("$and".to_string()).or_insert_with(|| serde_json::Value::Array(Vec::new())) {
+            serde_json::Value::Array(all) => {
+                all.push(serde_json::Value::Object(condition));
+                Ok(())
+            }
+            _ => Err("$and needs an array of filter objects".to_string()),
+        };
+    }
+    match filter.remove(&column) {
+        None if key == "$eq" => {
+            filter.insert(column, literal);
+        }
+        existing => {
+            let mut ops = match existing {
+                Some(serde_json::Value::Object(ops)) => ops,
+                Some(value) => serde_json::Map::from_iter([("$eq".to_string(), value)]),
+                None => serde_json::Map::new(),
+            };
+            if ops.insert(key.to_string(), literal).is_some() {
+                return Err(format!("column '{}' has two '{}' conditions", column, op));
+            }
+            filter.insert(column, serde_json::Value::Object(ops));
+        }
+    }
+    Ok(())
+}
+
+// the conditions of both filters in one map. conditions on the same column are
+// merged into one operator object; the same operator on both sides is an error.
+// $and, $or and $not on both sides are kept apart under one $and
+pub fn and_filters(
+    mut filter: HashMap<String, serde_json::Value>,
+    other: HashMap<String, serde_json::Value>,
+) -> Result<HashMap<String, serde_json::Value>, String> {
+    let operators = |condition: serde_json::Value| match condition {
+        serde_json::Value::Object(ops) => ops,
+        value => serde_json::Map::from_iter([("$eq".to_string(), value)]),
+    };
+    for (column, condition) in other {
+        let Some(existing) = filter.remove(&column) else {
+            filter.insert(column, condition);
+            continue;
+        };
+        if is_logical(&column) {
+            let nested = |condition| serde_json::Value::Object(serde_json::Map::from_iter([(column.clone(), condition)]));
+            let (existing, condition) = (nested(existing), nested(condition));
+            let mut all = match filter.remove("$and") {
+                None => Vec::new(),
+                Some(serde_json::Value::Array(all)) => all,
+                Some(_) => return Err("$and needs an array of filter objects".to_string()),
+            };
+            all.extend([existing, condition]);
+            filter.insert("$and".to_string(), serde_json::Value::Array(all));
+            continue;
+        }
+        let mut ops = operators(existing);
+        for (op, operand) in operators(condition) {
+            if ops.insert(op.clone(), operand).is_some() {
+                return Err(format!("column '{}' has a '{}' condition in both filters", column, op));
+            }
+        }
+        filter.insert(column, serde_json::Value::Object(ops));
+    }
+    Ok(filter)
+}
+
+fn next_literal(
+    tokens: &mut impl Iterator<Item = Token>,
+    column: &str,
+    op: &str,
+) -> Result<serde_json::Value, String> {
+    match tokens.next() {
+        Some(Token::Str(s)) => Ok(serde_json::Value::String(s)),
+        Some(Token::Word(word)) => {
+            Ok(serde_json::from_str(&word).unwrap_or(serde_json::Value::String(word)))
+        }
+        other => Err(format!("expected a value after '{} {}', found {:?}", column, op, other)),
+    }
+}
+
+#[derive(Debug)]
+enum Token {
+    Word(String),
+    Str(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some(q) if q == c => break,
+                    Some(ch) => s.push(ch),
+                    None => return Err("unterminated string literal".to_string()),
+                }
+            }
+            tokens.push(Token::Str(s));
+        } else if "=!<>".contains(c) {
+            chars.next();
+            let next = chars.peek().copied();
+            let op = match (c, next) {
+                ('!', Some('=')) => "!=",
+                ('<', Some('>')) => "<>",
+                ('<', Some('=')) => "<=",
+                ('>', Some('=')) => ">=",
+                ('=', _) => "=",
+                ('<', _) => "<",
+                ('>', _) => ">",
+                _ => return Err("unexpected '!'".to_string()),
+            };
+            if op.len() == 2 {
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        } else if c == '(' || c == ')' {
+            chars.next();
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+        } else {
+            let mut word = String::new();
+            while let Some(&ch) = chars.peek() {
+                if ch.is_whitespace() || "=!<>'\"()".contains(ch) {
+                    break;
+                }
+                word.push(ch);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+    Ok(tokens)
 }
@@ -0,0 +1,85 @@
+use std::collections::{BTreeSet, HashMap};
+use serde_json::Value;
+
+use crate::backend::StorageBackend;
+use crate::database::{Database, ExecError, Output};
+use crate::parser::ReadCommand;
+
+// a read whose filter holds placeholders such as {"price": {"$gt": "?price"}}.
+// values are bound into the filter map directly, so they are never parsed as
+// part of a filter string
+#[derive(Debug, Clone)]
+pub struct PreparedRead {
+    read: ReadCommand,
+    params: BTreeSet<String>,
+}
+
+impl PreparedRead {
+    pub fn new(read: ReadCommand) -> PreparedRead {
+        let mut params = BTreeSet::new();
+        for value in read.filter.values() {
+            collect_params(value, &mut params);
+        }
+        PreparedRead { read, params }
+    }
+
+    // names of the placeholders, without their leading '?'
+    pub fn params(&self) -> impl Iterator<Item = &str> {
+        self.params.iter().map(String::as_str)
+    }
+
+    pub(crate) fn bind(&self, params: &HashMap<String, Value>) -> Result<ReadCommand, ExecError> {
+        if let Some(missing) = self.params.iter().find(|name| !params.contains_key(*name)) {
+            return Err(ExecError::InvalidQuery(format!("parameter '{}' is not bound", missing)));
+        }
+        if let Some(unknown) = params.keys().find(|name| !self.params.contains(*name)) {
+            return Err(ExecError::InvalidQuery(format!("unknown parameter '{}'", unknown)));
+        }
+        let mut read = self.read.clone();
+        for value in read.filter.values_mut() {
+            bind_params(value, params);
+        }
+        Ok(read)
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    pub fn execute_prepared(
+        &self,
+        prep: &PreparedRead,
+        params: HashMap<String, Value>,
+    ) -> Result<Output, ExecError> {
+        self.read_capped(prep.bind(&params)?, self.max_rows)
+    }
+}
+
+// "?name" where name is made of letters, digits and underscores
+fn placeholder(value: &Value) -> Option<&str> {
+    let name = value.as_str()?.strip_prefix('?')?;
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some(name)
+}
+
+fn collect_params(value: &Value, params: &mut BTreeSet<String>) {
+    if let Some(name) = placeholder(value) {
+        params.insert(name.to_string());
+        return;
+    }
+    match value {
+        Value::Array(items) => items.iter().for_each(|item| collect_params(item, params)),
+        Value::Object(ops) => ops.values().for_each(|operand| collect_params(operand, params)),
+        _ => {}
+    }
+}
+
+fn bind_params(value: &mut Value, params: &HashMap<String, Value>) {
+    if let Some(bound) = placeholder(value).and_then(|name| params.get(name)) {
+        *value = bound.clone();
+        return;
+    }
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(|item| bind_params(item, params)),
+        Value::Object(ops) => ops.values_mut().for_each(|operand| bind_params(operand, params)),
+        _ => {}
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fmt;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::backend::StorageBackend;
+use crate::crud::is_grouped;
+use crate::database::{Database, ExecError, Row, Table};
+use crate::parser::{AggregateFunction, ReadCommand};
+
+// the declared type of a column, or the type an aggregate produces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    Int,
+    Float,
+    Bool,
+    String,
+    Char,
+    Datetime,
+    Uuid,
+    // json columns, and values whose type isn't known
+    #[default]
+    Json,
+}
+
+// named in any case in column definitions, unknown names fail the parse
+impl<'de> Deserialize<'de> for ColumnType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ColumnType, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        ColumnType::parse(&name).ok_or_else(|| serde::de::Error::custom(format!("unknown column type '{}'", name)))
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl ColumnType {
+    // the type named like a column definition's, in any case
+    pub fn parse(col_type: &str) -> Option<ColumnType> {
+        Some(match col_type.to_ascii_lowercase().as_str() {
+            "int" => ColumnType::Int,
+            "float" => ColumnType::Float,
+            "bool" => ColumnType::Bool,
+            "string" => ColumnType::String,
+            "char" => ColumnType::Char,
+            "datetime" => ColumnType::Datetime,
+            "uuid" => ColumnType::Uuid,
+            "json" => ColumnType::Json,
+            _ => return None,
+        })
+    }
+
+    // the type a new column gets from a value written to it, none for null.
+    // objects and arrays, including `$expr` assignments, make json columns
+    pub fn infer(value: &serde_json::Value) -> Option<ColumnType> {
+        Some(match value {
+            serde_json::Value::Null => return None,
+            serde_json::Value::Bool(_) => ColumnType::Bool,
+            serde_json::Value::Number(n) if n.is_i64() || n.is_u64() => ColumnType::Int,
+            serde_json::Value::Number(_) => ColumnType::Float,
+            serde_json::Value::String(_) => ColumnType::String,
+            _ => ColumnType::Json,
+        })
+    }
+
+    // the type as column definitions name it
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Bool => "bool",
+            ColumnType::String => "string",
+            ColumnType::Char => "char",
+            ColumnType::Datetime => "datetime",
+            ColumnType::Uuid => "uuid",
+            ColumnType::Json => "json",
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, ColumnType::Int | ColumnType::Float)
+    }
+
+    // string and char, the types with a collation
+    pub fn is_text(&self) -> bool {
+        matches!(self, ColumnType::String | ColumnType::Char)
+    }
+}
+
+// a read's rows together with its columns in order. `truncated` is set when
+// the database's row cap cut the rows off, as for `Output::Truncated`
+#[derive(Debug, PartialEq, Serialize)]
+pub struct QueryResult {
+    pub columns: Vec<ResultColumn>,
+    pub rows: Vec<Row>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResultColumn {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub col_type: ColumnType,
+}
+
+impl<B: StorageBackend> Table<B> {
+    // the primary key's columns in key order, then the others by name
+    pub fn column_order(&self) -> Vec<&str> {
+        let key = self.primary_key.columns();
+        let mut rest: Vec<&str> = self
+            .columns
+            .keys()
+            .map(String::as_str)
+            .filter(|column| !self.primary_key.contains(column))
+            .collect();
+        rest.sort_unstable();
+        key.iter().map(String::as_str).chain(rest).collect()
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    // like `read`, with the columns of the rows and their types
+    pub fn read_typed(&self, cmd: &ReadCommand) -> Result<QueryResult, ExecError> {
+        Ok(QueryResult {
+            columns: self.result_columns(cmd)?,
+            rows: self.read(cmd)?,
+            truncated: false,
+        })
+    }
+
+    // the columns a read returns, in order: the projected ones as listed, else
+    // group_by columns followed by aggregates, else the table's columns in
+    // `column_order`, those of a join prefixed like its rows
+    pub fn result_columns(&self, cmd: &ReadCommand) -> Result<Vec<ResultColumn>, ExecError> {
+        let columns = if let Some(view) = self.views.get(&cmd.table) {
+            self.result_columns(view)?
+        } else {
+            let table = self.table(&cmd.table)?;
+            let types = self.read_column_types(cmd);
+            let typed = |name: String| {
+                let col_type = types.get(&name).copied().unwrap_or(ColumnType::Json);
+                ResultColumn { name, col_type }
+            };
+            if is_grouped(cmd) {
+                let mut columns: Vec<ResultColumn> = cmd.group_by.iter().cloned().map(typed).collect();
+                for spec in &cmd.aggregates {
+                    let of_column = spec.column.as_ref().map(|column| typed(column.clone()).col_type);
+                    let col_type = match (spec.function, of_column) {
+                        (AggregateFunction::Count, _) => ColumnType::Int,
+                        (AggregateFunction::Avg, _) => ColumnType::Float,
+                        (AggregateFunction::Sum, Some(ColumnType::Int)) => ColumnType::Int,
+                        (AggregateFunction::Sum, _) => ColumnType::Float,
+                        (AggregateFunction::Min | AggregateFunction::Max, of_column) => {
+                            of_column.unwrap_or(ColumnType::Json)
+                        }
+                    };
+                    columns.push(ResultColumn { name: spec.output_name(), col_type });
+                }
+                columns
+            } else {
+                let mut names: Vec<String> = match &cmd.join {
+                    Some(_) => prefixed(&cmd.table, table),
+                    None => table.column_order().into_iter().map(str::to_string).collect(),
+                };
+                if let Some(join) = &cmd.join {
+                    names.extend(prefixed(&join.table, self.table(&join.table)?));
+                }
+                names.into_iter().map(typed).collect()
+            }
+        };
+        if cmd.columns.is_empty() {
+            return Ok(columns);
+        }
+        let by_name: HashMap<&str, ColumnType> = columns.iter().map(|c| (c.name.as_str(), c.col_type)).collect();
+        Ok(cmd
+            .columns
+            .iter()
+            .map(|name| ResultColumn {
+                name: name.clone(),
+                col_type: by_name.get(name.as_str()).copied().unwrap_or(ColumnType::Json),
+            })
+            .collect())
+    }
+}
+
+fn prefixed<B: StorageBackend>(table_name: &str, table: &Table<B>) -> Vec<String> {
+    table
+        .column_order()
+        .into_iter()
+        .map(|column| format!("{}.{}", table_name, column))
+        .collect()
+}
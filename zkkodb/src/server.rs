@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use serde_json::json;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+
+use crate::backend::{MemoryBackend, StorageBackend};
+use crate::database::{Database, ExecError, Output};
+use crate::parser::{parse_command, Command, ParseError};
+use crate::session::Session;
+use crate::cancel::CancelToken;
+use crate::snapshot::Snapshot;
+use crate::wire::WireFormat;
+
+// the engine behind an async read-write lock: reads share the lock, mutating
+// commands take it exclusively. the sync `Database` API is unchanged
+#[derive(Debug, Default)]
+pub struct AsyncDatabase<B = MemoryBackend> {
+    pub(crate) db: RwLock<Database<B>>,
+    // applied to commands run through `execute_in`
+    pub(crate) limiter: Option<RateLimiter>,
+}
+
+impl<B: StorageBackend> AsyncDatabase<B> {
+    pub fn new(db: Database<B>) -> AsyncDatabase<B> {
+        AsyncDatabase {
+            db: RwLock::new(db),
+            limiter: None,
+        }
+    }
+
+    // limits how fast each session, or each user across their sessions, may
+    // send commands through `execute_in`, and so through `serve` and `serve_http`
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> AsyncDatabase<B> {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    pub async fn execute(&self, cmd: Command) -> Result<Output, ExecError> {
+        if cmd.is_mutating() {
+            self.db.write().await.execute(cmd)
+        } else {
+            self.db.read().await.query(cmd)
+        }
+    }
+
+    // like `execute`, see `Database::execute_cancellable`
+    pub async fn execute_cancellable(&self, cmd: Command, token: &CancelToken) -> Result<Output, ExecError> {
+        if cmd.is_mutating() {
+            self.db.write().await.execute_cancellable(cmd, token)
+        } else {
+            self.db.read().await.query_cancellable(cmd, token)
+        }
+    }
+
+    // a consistent view for long reads; the lock is only held while taking it
+    pub async fn snapshot(&self) -> Snapshot<B> {
+        self.db.read().await.snapshot()
+    }
+
+    pub fn into_inner(self) -> Database<B> {
+        self.db.into_inner()
+    }
+}
+
+// a token bucket per session or user: each holds up to `burst` tokens, refills
+// at `per_second` tokens a second and every command takes one. a command
+// finding its bucket empty fails with `RateLimited` without running
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    // sessions with the admin role aren't limited
+    pub exempt_admins: bool,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// buckets beyond this many are pruned of the full ones, which behave like new
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+impl RateLimiter {
+    pub fn new(per_second: f64, burst: u32) -> RateLimiter {
+        RateLimiter {
+            per_second,
+            burst: f64::from(burst),
+            exempt_admins: true,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn check<B: StorageBackend>(&self, session: &Session<B>) -> Result<(), ExecError> {
+        if self.exempt_admins && session.role.as_deref() == Some("admin") {
+            return Ok(());
+        }
+        let key = session.limit_key();
+        if self.take(&key, Instant::now()) {
+            Ok(())
+        } else {
+            Err(ExecError::RateLimited { key })
+        }
+    }
+
+    // whether `key`'s bucket had a token at `now`, taking it
+    pub(crate) fn take(&self, key: &str, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !buckets.contains_key(key) && buckets.len() >= MAX_IDLE_BUCKETS {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        if self.refill(bucket, now) < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now.max(bucket.updated);
+        bucket.tokens
+    }
+}
+
+// accepts connections until the listener fails. each connection sends one JSON
+// command per line and gets one JSON line back: the output, or {"error": "..."}.
+// a connection whose first line is a content type like
+// `content-type: application/msgpack` speaks frames in that format instead,
+// see `serve_frames`
+pub async fn serve<B: StorageBackend>(listener: TcpListener, db: Arc<AsyncDatabase<B>>) -> std::io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let db = Arc::clone(&db);
+        tokio::spawn(async move {
+            // a client going away mid-line only ends its own connection
+            let _ = handle_connection(stream, db).await;
+        });
+    }
+}
+
+// the longest command line a client may send; longer lines are answered with
+// an error without being buffered
+pub const MAX_LINE_BYTES: usize = 1 << 20;
+
+async fn handle_connection<B: StorageBackend>(stream: TcpStream, db: Arc<AsyncDatabase<B>>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    // a connection is one session, its open transaction ends with it
+    let mut session = Session::default();
+    let mut first = true;
+    while let Some(line) = next_line(&mut reader, MAX_LINE_BYTES).await? {
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => line,
+            Err(err) => {
+                write_line(&mut writer, &json!({ "error": err.to_string() })).await?;
+                continue;
+            }
+        };
+        let content_type = line
+            .split_once(':')
+            .filter(|(name, _)| first && name.trim().eq_ignore_ascii_case("content-type"))
+            .map(|(_, value)| value.trim());
+        first = false;
+        if let Some(content_type) = content_type {
+            match WireFormat::from_content_type(content_type) {
+                Some(WireFormat::Json) => continue,
+                Some(format) => return serve_frames(reader, writer, &db, session, format).await,
+                None => {
+                    let error = format!("unknown content type '{}'", content_type);
+                    write_line(&mut writer, &json!({ "error": error })).await?;
+                    continue;
+                }
+            }
+        }
+        let response = match parse_command(&line) {
+            Ok(cmd) => run_command(&db, &mut session, cmd).await,
+            Err(err) => json!({ "error": err.to_string() }),
+        };
+        write_line(&mut writer, &response).await?;
+    }
+    Ok(())
+}
+
+// frames carry one command or response each: its length as 4 big-endian
+// bytes, then that many bytes of it in `format`. frames longer than
+// MAX_LINE_BYTES are skipped and answered with an error
+async fn serve_frames<B: StorageBackend>(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    db: &AsyncDatabase<B>,
+    mut session: Session<B>,
+    format: WireFormat,
+) -> std::io::Result<()> {
+    loop {
+        let mut length = [0; 4];
+        match reader.read_exact(&mut length).await {
+            Ok(_) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err),
+        }
+        let length = u32::from_be_bytes(length) as usize;
+        let response = if length > MAX_LINE_BYTES {
+            tokio::io::copy(&mut (&mut reader).take(length as u64), &mut tokio::io::sink()).await?;
+            json!({ "error": ParseError::TooLarge { max: MAX_LINE_BYTES }.to_string() })
+        } else {
+            let mut frame = vec![0; length];
+            reader.read_exact(&mut frame).await?;
+            match format.decode_command(&frame) {
+                Ok(cmd) => run_command(db, &mut session, cmd).await,
+                Err(err) => json!({ "error": err.to_string() }),
+            }
+        };
+        let encoded = format
+            .encode(&response)
+            .or_else(|err| format.encode(&json!({ "error": err.to_string() })))
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        writer.write_all(&(encoded.len() as u32).to_be_bytes()).await?;
+        writer.write_all(&encoded).await?;
+    }
+}
+
+// the output of `cmd` run in `session`, or {"error": "..."}
+async fn run_command<B: StorageBackend>(db: &AsyncDatabase<B>, session: &mut Session<B>, cmd: Command) -> serde_json::Value {
+    match db.execute_in(session, cmd).await {
+        Ok(output) => serde_json::to_value(output).unwrap_or_else(|err| json!({ "error": err.to_string() })),
+        Err(err) => json!({ "error": err.to_string() }),
+    }
+}
+
+// the next line without its newline, or TooLarge when it is longer than `max`
+// bytes. the rest of an oversized line is read and dropped chunk by chunk
+async fn next_line(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    max: usize,
+) -> std::io::Result<Option<Result<String, ParseError>>> {
+    let mut line = Vec::new();
+    let mut too_long = false;
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            if line.is_empty() && !too_long {
+                return Ok(None);
+            }
+            break;
+        }
+        let newline = available.iter().position(|&byte| byte == b'\n');
+        let chunk = &available[..newline.unwrap_or(available.len())];
+        if line.len() + chunk.len() > max {
+            too_long = true;
+            line.clear();
+        } else if !too_long {
+            line.extend_from_slice(chunk);
+        }
+        let used = chunk.len() + newline.map_or(0, |_| 1);
+        reader.consume(used);
+        if newline.is_some() {
+            break;
+        }
+    }
+    if too_long {
+        return Ok(Some(Err(ParseError::TooLarge { max })));
+    }
+    let line = String::from_utf8(line)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    Ok(Some(Ok(line.strip_suffix('\r').map(str::to_string).unwrap_or(line))))
+}
+
+async fn write_line(writer: &mut (impl AsyncWrite + Unpin), value: &serde_json::Value) -> std::io::Result<()> {
+    let mut line = value.to_string();
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
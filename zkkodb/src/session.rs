@@ -0,0 +1,410 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::backend::{MemoryBackend, StorageBackend};
+use crate::database::{Database, ExecError, Output, Table};
+use crate::events::ChangeEvent;
+use crate::idempotency::SeenKeys;
+use crate::parser::{Command, CreateCommand, DeleteCommand, ReadCommand, UpdateCommand};
+use crate::prepared::PreparedRead;
+use crate::server::AsyncDatabase;
+use crate::users;
+use crate::utils::Stamp;
+
+// the state of one client: who it is, its settings and its open transaction.
+// `login` takes `user` and `role` from the users table; with `authenticated`
+// they are whatever the caller checked itself
+#[derive(Debug)]
+pub struct Session<B = MemoryBackend> {
+    pub user: Option<String>,
+    pub role: Option<String>,
+    pub settings: SessionSettings,
+    // unique within the process, tells anonymous sessions apart
+    id: u64,
+    transaction: Option<Transaction<B>>,
+    // reads prepared with `prepare`, by name
+    prepared: HashMap<String, PreparedRead>,
+}
+
+impl<B> Default for Session<B> {
+    fn default() -> Session<B> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Session {
+            user: None,
+            role: None,
+            settings: SessionSettings::default(),
+            id: NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed),
+            transaction: None,
+            prepared: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SessionSettings {
+    // a transaction open for longer is rolled back on the session's next command
+    pub timeout: Option<Duration>,
+    // caps this session's reads instead of the database's cap
+    pub max_rows: Option<usize>,
+}
+
+// writes go to a private copy-on-write copy of the database taken at `begin`,
+// so other sessions never see them before commit. reads in the transaction see
+// that copy: its own writes, and nothing committed by others since it began
+#[derive(Debug)]
+struct Transaction<B> {
+    base: HashMap<String, Arc<Table<B>>>,
+    base_views: HashMap<String, ReadCommand>,
+    work: Database<B>,
+    // mutating commands in order with the stamps they ran with, written to the
+    // log on commit
+    log: Vec<(Stamp, Command)>,
+    savepoints: Vec<Savepoint<B>>,
+    started: Instant,
+}
+
+// the transaction's state when the savepoint was set. tables are shared with
+// the working copy until it writes them again
+#[derive(Debug)]
+struct Savepoint<B> {
+    name: String,
+    tables: HashMap<String, Arc<Table<B>>>,
+    views: HashMap<String, ReadCommand>,
+    idempotency: SeenKeys,
+    log_len: usize,
+    events_len: usize,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    pub fn authenticated(user: &str, role: &str) -> Session {
+        Session::for_user(user, role)
+    }
+}
+
+impl<B: StorageBackend> Session<B> {
+    // like `authenticated`, for a database with any backend
+    pub fn for_user(user: &str, role: &str) -> Session<B> {
+        Session {
+            user: Some(user.to_string()),
+            role: Some(role.to_string()),
+            ..Session::default()
+        }
+    }
+
+    // a session for `username` with their role, when `password` is theirs
+    pub fn login(db: &Database<B>, username: &str, password: &str) -> Result<Session<B>, ExecError> {
+        let role = db.authenticate(username, password)?;
+        Ok(Session::for_user(username, &role))
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    // what rate limits count against: the user when there is one, else the session
+    pub(crate) fn limit_key(&self) -> String {
+        match &self.user {
+            Some(user) => format!("user '{}'", user),
+            None => format!("session {}", self.id),
+        }
+    }
+
+    // commands only the admin role may run, and the privileges `db` grants
+    fn check_allowed(&self, db: &Database<B>, cmd: &Command) -> Result<(), ExecError> {
+        let admin = self.role.as_deref() == Some("admin");
+        match cmd {
+            Command::DropTables { .. } if !admin => {
+                Err(ExecError::PermissionDenied("drop_tables needs the admin role".to_string()))
+            }
+            Command::Grant(_) | Command::Revoke(_) if !admin => {
+                Err(ExecError::PermissionDenied("grant and revoke need the admin role".to_string()))
+            }
+            // they read and write whatever path the client names
+            Command::Backup { .. } | Command::Restore { .. } | Command::Verify { .. } if !admin => Err(
+                ExecError::PermissionDenied("backup, restore and verify need the admin role".to_string()),
+            ),
+            Command::Create(CreateCommand::User { .. }) | Command::Delete(DeleteCommand::User { .. }) | Command::ListUsers
+                if !admin && db.has_admin() =>
+            {
+                Err(ExecError::PermissionDenied("managing users needs the admin role".to_string()))
+            }
+            // users may change their own password, nothing else
+            Command::Update(UpdateCommand::User { username, role, .. }) if !admin && db.has_admin() => {
+                match role.is_none() && self.user.as_deref() == Some(username) {
+                    true => Ok(()),
+                    false => Err(ExecError::PermissionDenied(
+                        "changing another user or a role needs the admin role".to_string(),
+                    )),
+                }
+            }
+            Command::Batch { commands, .. } => commands.iter().try_for_each(|cmd| self.check_allowed(db, cmd)),
+            cmd => db.check_privileges(self.user.as_deref(), self.role.as_deref(), cmd),
+        }
+    }
+
+    // preparing a name again replaces its read
+    fn prepare(&mut self, name: String, query: ReadCommand) -> Result<Output, ExecError> {
+        self.prepared.insert(name, PreparedRead::new(query));
+        Ok(Output::Done)
+    }
+
+    // an `execute` as the read it binds, any other command as it is
+    fn bind_prepared(&self, cmd: Command) -> Result<Command, ExecError> {
+        let Command::Execute { name, params } = cmd else {
+            return Ok(cmd);
+        };
+        let prepared = self
+            .prepared
+            .get(&name)
+            .ok_or_else(|| ExecError::InvalidQuery(format!("no read is prepared as '{}'", name)))?;
+        prepared.bind(&params).map(Command::Read)
+    }
+
+    fn max_rows(&self, db: &Database<B>) -> Option<usize> {
+        self.settings.max_rows.or(db.max_rows)
+    }
+
+    fn begin(&mut self, db: &Database<B>) -> Result<Output, ExecError> {
+        self.transaction_mut()?;
+        if self.transaction.is_some() {
+            return Err(ExecError::InvalidQuery("a transaction is already open".to_string()));
+        }
+        self.transaction = Some(Transaction {
+            base: db.tables.clone(),
+            base_views: db.views.clone(),
+            work: Database {
+                tables: db.tables.clone(),
+                views: db.views.clone(),
+                max_rows: self.max_rows(db),
+                buffered_events: Some(Vec::new()),
+                read_only: db.read_only,
+                idempotency: db.idempotency.clone(),
+                ..Database::with_backend(db.backend.clone())
+            },
+            log: Vec::new(),
+            savepoints: Vec::new(),
+            started: Instant::now(),
+        });
+        Ok(Output::Done)
+    }
+
+    fn take_transaction(&mut self) -> Result<Transaction<B>, ExecError> {
+        self.transaction_mut()?;
+        self.transaction
+            .take()
+            .ok_or_else(|| ExecError::InvalidQuery("no transaction is open".to_string()))
+    }
+
+    fn savepoint(&mut self, name: String) -> Result<Output, ExecError> {
+        self.open_transaction()?.savepoint(name);
+        Ok(Output::Done)
+    }
+
+    fn rollback_to(&mut self, name: &str) -> Result<Output, ExecError> {
+        self.open_transaction()?.rollback_to(name)?;
+        Ok(Output::Done)
+    }
+
+    fn open_transaction(&mut self) -> Result<&mut Transaction<B>, ExecError> {
+        self.transaction_mut()?
+            .ok_or_else(|| ExecError::InvalidQuery("no transaction is open".to_string()))
+    }
+
+    // the open transaction, rolled back once it outlived the session's timeout
+    fn transaction_mut(&mut self) -> Result<Option<&mut Transaction<B>>, ExecError> {
+        let expired = match (&self.transaction, self.settings.timeout) {
+            (Some(transaction), Some(timeout)) => transaction.started.elapsed() > timeout,
+            _ => false,
+        };
+        if expired {
+            self.transaction = None;
+            return Err(ExecError::InvalidQuery("the transaction timed out and was rolled back".to_string()));
+        }
+        Ok(self.transaction.as_mut())
+    }
+}
+
+impl<B: StorageBackend> Transaction<B> {
+    fn execute(&mut self, cmd: Command) -> Result<Output, ExecError> {
+        if !cmd.is_mutating() {
+            return self.work.query(cmd);
+        }
+        let cmd = self.work.resolve_defaults(users::hash_passwords(cmd)?)?;
+        let stamp = Stamp::now();
+        let output = stamp.run(|| self.work.execute(cmd.clone()))?;
+        self.log.push((stamp, cmd));
+        Ok(output)
+    }
+
+    // a savepoint may reuse a name; rolling back goes to the latest one
+    fn savepoint(&mut self, name: String) {
+        self.savepoints.push(Savepoint {
+            name,
+            tables: self.work.tables.clone(),
+            views: self.work.views.clone(),
+            idempotency: self.work.idempotency.clone(),
+            log_len: self.log.len(),
+            events_len: self.work.buffered_events.as_ref().map_or(0, Vec::len),
+        });
+    }
+
+    // undoes everything since the savepoint, which stays set; later ones are released
+    fn rollback_to(&mut self, name: &str) -> Result<(), ExecError> {
+        let Some(position) = self.savepoints.iter().rposition(|savepoint| savepoint.name == name) else {
+            return Err(ExecError::InvalidQuery(format!("no savepoint named '{}'", name)));
+        };
+        self.savepoints.truncate(position + 1);
+        let savepoint = &self.savepoints[position];
+        self.work.tables = savepoint.tables.clone();
+        self.work.views = savepoint.views.clone();
+        self.work.idempotency = savepoint.idempotency.clone();
+        self.log.truncate(savepoint.log_len);
+        if let Some(events) = &mut self.work.buffered_events {
+            events.truncate(savepoint.events_len);
+        }
+        Ok(())
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    // runs `cmd` for a session: begin, commit, rollback and savepoints manage its
+    // transaction, other commands run inside the transaction when one is open.
+    // `execute` itself trusts its caller and checks no role
+    pub fn execute_in(&mut self, session: &mut Session<B>, cmd: Command) -> Result<Output, ExecError> {
+        let cmd = session.bind_prepared(cmd)?;
+        session.check_allowed(self, &cmd)?;
+        match cmd {
+            Command::Begin => session.begin(self),
+            Command::Commit => {
+                self.commit(session.take_transaction()?)?;
+                Ok(Output::Done)
+            }
+            Command::Rollback => {
+                session.take_transaction()?;
+                Ok(Output::Done)
+            }
+            Command::Savepoint { name } => session.savepoint(name),
+            Command::RollbackTo { name } => session.rollback_to(&name),
+            Command::Prepare { name, query } => session.prepare(name, query),
+            cmd => match session.transaction_mut()? {
+                Some(transaction) => transaction.execute(cmd),
+                None if cmd.is_mutating() => self.execute(cmd),
+                None => {
+                    let timer = self.start_timer(&cmd);
+                    let result = self.query_capped(cmd, session.max_rows(self));
+                    self.finish_timer(timer, result)
+                }
+            },
+        }
+    }
+
+    // the first committer wins: a transaction that changed a table another
+    // writer changed since it began is rejected and changes nothing
+    fn commit(&mut self, transaction: Transaction<B>) -> Result<(), ExecError> {
+        let Transaction { base, base_views, work, log, .. } = transaction;
+        let names: BTreeSet<&String> = base.keys().chain(work.tables.keys()).collect();
+        let written: Vec<&String> = names
+            .into_iter()
+            .filter(|name| !same_table(base.get(*name), work.tables.get(*name)))
+            .collect();
+        for name in &written {
+            if !same_table(base.get(*name), self.tables.get(*name)) {
+                return Err(ExecError::Conflict { table: name.to_string() });
+            }
+        }
+        let created: Vec<(&String, &ReadCommand)> =
+            work.views.iter().filter(|(name, _)| !base_views.contains_key(*name)).collect();
+        for (name, _) in &created {
+            if self.views.contains_key(*name) || self.tables.contains_key(*name) {
+                return Err(ExecError::Conflict { table: name.to_string() });
+            }
+        }
+        // views a table rename pointed at the new name
+        let rewritten: Vec<(&String, &ReadCommand)> = work
+            .views
+            .iter()
+            .filter(|(name, view)| base_views.get(*name).is_some_and(|base| !same_view(base, view)))
+            .collect();
+        for (name, _) in &rewritten {
+            if !self.views.get(*name).is_some_and(|current| same_view(current, &base_views[*name])) {
+                return Err(ExecError::Conflict { table: name.to_string() });
+            }
+        }
+
+        if let Some(wal) = &mut self.wal {
+            for (stamp, cmd) in &log {
+                wal.append(*stamp, cmd).map_err(|err| ExecError::Io(err.to_string()))?;
+            }
+        }
+        log.into_iter().for_each(|(_, cmd)| self.record(cmd));
+        for name in written {
+            match work.tables.get(name) {
+                Some(table) => self.tables.insert(name.clone(), Arc::clone(table)),
+                None => self.tables.remove(name),
+            };
+        }
+        for (name, view) in created.into_iter().chain(rewritten) {
+            self.views.insert(name.clone(), view.clone());
+        }
+        self.idempotency.extend(work.idempotency);
+        for ChangeEvent { kind, table, keys } in work.buffered_events.unwrap_or_default() {
+            self.notify(kind, &table, keys);
+        }
+        Ok(())
+    }
+}
+
+impl<B: StorageBackend> AsyncDatabase<B> {
+    // like `Database::execute_in`; an open transaction only takes the lock to
+    // begin and to commit
+    pub async fn execute_in(&self, session: &mut Session<B>, cmd: Command) -> Result<Output, ExecError> {
+        if let Some(limiter) = &self.limiter {
+            limiter.check(session)?;
+        }
+        let cmd = session.bind_prepared(cmd)?;
+        session.check_allowed(&*self.db.read().await, &cmd)?;
+        match cmd {
+            Command::Begin => session.begin(&*self.db.read().await),
+            Command::Commit => {
+                let transaction = session.take_transaction()?;
+                self.db.write().await.commit(transaction)?;
+                Ok(Output::Done)
+            }
+            Command::Rollback => {
+                session.take_transaction()?;
+                Ok(Output::Done)
+            }
+            Command::Savepoint { name } => session.savepoint(name),
+            Command::RollbackTo { name } => session.rollback_to(&name),
+            Command::Prepare { name, query } => session.prepare(name, query),
+            cmd => match session.transaction_mut()? {
+                Some(transaction) => transaction.execute(cmd),
+                None if cmd.is_mutating() => self.db.write().await.execute(cmd),
+                None => {
+                    let db = self.db.read().await;
+                    let timer = db.start_timer(&cmd);
+                    let result = db.query_capped(cmd, session.max_rows(&db));
+                    db.finish_timer(timer, result)
+                }
+            },
+        }
+    }
+}
+
+fn same_view(a: &ReadCommand, b: &ReadCommand) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+fn same_table<B: StorageBackend>(a: Option<&Arc<Table<B>>>, b: Option<&Arc<Table<B>>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,40 @@
+use crate::backend::{MemoryBackend, StorageBackend};
+use crate::database::{Database, ExecError, Output, Row};
+use crate::parser::{Command, ReadCommand};
+
+// a read-only view of the database as it was when the snapshot was taken.
+// taking one only clones table handles; the first later write to a table
+// copies that table, so writers never wait for a snapshot's readers and
+// snapshot readers never see later writes
+#[derive(Debug)]
+pub struct Snapshot<B = MemoryBackend> {
+    db: Database<B>,
+}
+
+impl<B: StorageBackend> Database<B> {
+    pub fn snapshot(&self) -> Snapshot<B> {
+        Snapshot {
+            db: Database {
+                tables: self.tables.clone(),
+                views: self.views.clone(),
+                max_rows: self.max_rows,
+                ..Database::with_backend(self.backend.clone())
+            },
+        }
+    }
+}
+
+impl<B: StorageBackend> Snapshot<B> {
+    // runs a command that doesn't change the database, see `Database::query`
+    pub fn query(&self, cmd: Command) -> Result<Output, ExecError> {
+        self.db.query(cmd)
+    }
+
+    pub fn read(&self, cmd: &ReadCommand) -> Result<Vec<Row>, ExecError> {
+        self.db.read(cmd)
+    }
+
+    pub fn table_names(&self) -> Vec<&str> {
+        self.db.table_names()
+    }
+}
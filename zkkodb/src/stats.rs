@@ -0,0 +1,54 @@
+use std::collections::BTreeMap;
+use serde::Serialize;
+
+use crate::backend::StorageBackend;
+use crate::database::{Database, ExecError, Table};
+
+// sizes for capacity planning. `bytes` approximates the rows' JSON size and
+// every table counts its primary key as an index besides its secondary ones
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub tables: usize,
+    pub rows: usize,
+    pub bytes: usize,
+    pub indexes: usize,
+    // keyed by column, or by "table.column" for database-wide stats
+    pub null_counts: BTreeMap<String, usize>,
+}
+
+impl<B: StorageBackend> Database<B> {
+    pub fn stats(&self, table: Option<&str>) -> Result<Stats, ExecError> {
+        if let Some(name) = table {
+            return Ok(table_stats(self.table(name)?, ""));
+        }
+        let mut total = Stats::default();
+        for name in self.table_names() {
+            let stats = table_stats(&self.tables[name], &format!("{}.", name));
+            total.tables += 1;
+            total.rows += stats.rows;
+            total.bytes += stats.bytes;
+            total.indexes += stats.indexes;
+            total.null_counts.extend(stats.null_counts);
+        }
+        Ok(total)
+    }
+}
+
+fn table_stats<B: StorageBackend>(table: &Table<B>, prefix: &str) -> Stats {
+    let mut stats = Stats {
+        tables: 1,
+        indexes: 1 + table.indexes.len(),
+        null_counts: table.columns.keys().map(|column| (format!("{}{}", prefix, column), 0)).collect(),
+        ..Stats::default()
+    };
+    for row in table.rows() {
+        stats.rows += 1;
+        stats.bytes += serde_json::to_vec(&row).map_or(0, |json| json.len());
+        for column in table.columns.keys() {
+            if row.get(column).is_none_or(|value| value.is_null()) {
+                *stats.null_counts.entry(format!("{}{}", prefix, column)).or_default() += 1;
+            }
+        }
+    }
+    stats
+}
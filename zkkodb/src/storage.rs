@@ -0,0 +1,744 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{MemoryBackend, StorageBackend};
+use crate::codec::{Codec, DecodeError};
+use crate::database::{Database, ExecError, Row, Table, CREATED_AT_FIELD, UPDATED_AT_FIELD};
+use crate::index::{Index, IndexDefinition};
+use crate::parser::{ColumnDefinition, Command, CreateCommand, PrimaryKey, ReadCommand, StorageLayout};
+use crate::wal::{self, Wal};
+
+const SCHEMA_SUFFIX: &str = ".schema.json";
+const DATA_SUFFIX: &str = ".data";
+const VIEWS_FILE: &str = "views.json";
+// CRC-32s of every table's files as the last save wrote them
+const CHECKSUMS_FILE: &str = "checksums.json";
+// bumped whenever the layout of a backup file changes
+const BACKUP_FORMAT_VERSION: u32 = 1;
+// what backups are encrypted under, so they still restore once renamed
+const BACKUP_NAME: &str = "backup";
+// rows of ttl tables are stored with their insertion time under this field
+pub(crate) const INSERTED_AT_FIELD: &str = "_inserted_at";
+// the manifest of a save whose files may not all be in place yet
+const SAVE_FILE: &str = "save.json";
+// a save writes every file under its name plus this until it commits
+const PENDING_SUFFIX: &str = ".pending";
+
+// compression of the snapshot files written by `save_compressed`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+// what `verify` found for each table, by name
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct VerifyReport {
+    pub tables: BTreeMap<String, Integrity>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Integrity {
+    // the files are as the last save wrote them
+    #[serde(rename = "ok")]
+    Ok,
+    // the files changed since the last save
+    #[serde(rename = "mismatch")]
+    Mismatch,
+    // a file of the table is gone
+    #[serde(rename = "missing")]
+    Missing,
+    // no save recorded a checksum for the table's files
+    #[serde(rename = "unchecked")]
+    Unchecked,
+}
+
+impl VerifyReport {
+    // the tables whose files are damaged or gone
+    pub fn corrupt_tables(&self) -> Vec<&str> {
+        self.tables
+            .iter()
+            .filter(|(_, integrity)| matches!(integrity, Integrity::Mismatch | Integrity::Missing))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    Corrupt { file: PathBuf, reason: String },
+    // the key is wrong or the encrypted file was tampered with
+    Decrypt { file: PathBuf },
+    // the command of the write-ahead log's `record`th record, from 1, failed
+    Replay { record: usize, error: ExecError },
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(err) => write!(f, "i/o error: {}", err),
+            StorageError::Corrupt { file, reason } => {
+                write!(f, "corrupt file {}: {}", file.display(), reason)
+            }
+            StorageError::Decrypt { file } => {
+                write!(f, "could not decrypt {}: wrong key or tampered file", file.display())
+            }
+            StorageError::Replay { record, error } => {
+                write!(f, "replaying record {} of the write-ahead log failed: {}", record, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        StorageError::Io(err)
+    }
+}
+
+// contents of `<table>.schema.json`
+#[derive(Serialize, Deserialize)]
+struct TableSchema {
+    primary_key: PrimaryKey,
+    columns: HashMap<String, ColumnDefinition>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl_seconds: Option<u64>,
+    #[serde(default)]
+    storage: StorageLayout,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    timestamps: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_rows: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    indexes: Vec<IndexDefinition>,
+}
+
+// contents of `save.json`: the files a save wrote as pending copies, the
+// files of tables dropped since the last save and whether the write-ahead log
+// in the same directory is covered by the new snapshot
+#[derive(Serialize, Deserialize)]
+pub(crate) struct PendingSave {
+    written: Vec<String>,
+    removed: Vec<String>,
+    covers_log: bool,
+}
+
+impl PendingSave {
+    fn read(dir: &Path) -> Result<Option<PendingSave>, StorageError> {
+        let path = dir.join(SAVE_FILE);
+        match path.exists() {
+            true => read_json(&path, &Codec::default()).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    // where the snapshot's file `file` is: its pending copy while the save commits
+    fn path(save: Option<&PendingSave>, dir: &Path, file: &str) -> PathBuf {
+        match save.is_some_and(|save| save.written.iter().any(|written| written == file)) {
+            true => dir.join(format!("{}{}", file, PENDING_SUFFIX)),
+            false => dir.join(file),
+        }
+    }
+}
+
+// contents of a backup file written by `backup`
+#[derive(Serialize, Deserialize)]
+struct Archive {
+    format_version: u32,
+    tables: BTreeMap<String, ArchivedTable>,
+    views: BTreeMap<String, ReadCommand>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchivedTable {
+    schema: TableSchema,
+    rows: Vec<Row>,
+}
+
+// the document read by `from_json_document` and written by `to_json_document`
+#[derive(Serialize, Deserialize)]
+struct Document {
+    tables: BTreeMap<String, ArchivedTable>,
+}
+
+impl Database {
+    // opens the database stored in `dir`: loads the last snapshot, replays the
+    // write-ahead log over it and keeps logging every mutation to that log.
+    // fails with `StorageError::Replay` when a logged command fails again
+    pub fn open(dir: impl AsRef<Path>) -> Result<Database, StorageError> {
+        Database::open_with_backend(dir, MemoryBackend::new(), None)
+    }
+
+    // like `open` for a database saved with `save_encrypted`: the snapshot,
+    // the write-ahead log and backups are all encrypted with `key`
+    pub fn open_encrypted(dir: impl AsRef<Path>, key: &[u8; 32]) -> Result<Database, StorageError> {
+        Database::open_with_backend(dir, MemoryBackend::new(), Some(key))
+    }
+
+    // opens the database in `dir` like `open`, but nothing is ever written
+    // there: mutating commands fail with `ExecError::ReadOnly` and saving or
+    // restoring fails too. for read replicas, or to look into a backup safely
+    pub fn open_read_only(dir: impl AsRef<Path>) -> Result<Database, StorageError> {
+        Database::open_read_only_with_backend(dir, MemoryBackend::new())
+    }
+
+    // loads the snapshot in `dir` without touching its write-ahead log
+    pub fn load(dir: impl AsRef<Path>) -> Result<Database, StorageError> {
+        Database::load_with_backend(dir, MemoryBackend::new(), None)
+    }
+
+    // loads a snapshot written by `save_encrypted` with the same key
+    pub fn load_encrypted(dir: impl AsRef<Path>, key: &[u8; 32]) -> Result<Database, StorageError> {
+        Database::load_with_backend(dir, MemoryBackend::new(), Some(key))
+    }
+
+    // builds a database from a document of `{ "tables": { name: { "schema", "rows" } } }`,
+    // the schema laid out like a `.schema.json` file. tables are created and
+    // filled one at a time, after the tables they reference, through the same
+    // checks as `create` and `insert`, so a bad schema or row fails the load
+    pub fn from_json_document(json: &str) -> Result<Database, ExecError> {
+        let document: Document = serde_json::from_str(json)
+            .map_err(|err| ExecError::InvalidQuery(format!("invalid database document: {}", err)))?;
+        let mut db = Database::new();
+        let mut pending: Vec<(String, ArchivedTable)> = document.tables.into_iter().collect();
+        while !pending.is_empty() {
+            // a reference nothing pending can satisfy fails in `create`
+            let ready = pending.iter().position(|(name, table)| {
+                table
+                    .schema
+                    .columns
+                    .values()
+                    .filter_map(|def| def.references.as_ref())
+                    .all(|fk| fk.table == *name || !pending.iter().any(|(other, _)| *other == fk.table))
+            });
+            let (name, table) = pending.remove(ready.unwrap_or(0));
+            db.seed_table(name, table)?;
+        }
+        Ok(db)
+    }
+
+    // recomputes the checksums of the snapshot files in `dir`, of `table` or of
+    // every table, and compares them with the ones the last save recorded.
+    // files are checked as stored, so encrypted snapshots need no key
+    pub fn verify(dir: impl AsRef<Path>, table: Option<&str>) -> Result<VerifyReport, StorageError> {
+        let dir = dir.as_ref();
+        let recorded_file = dir.join(CHECKSUMS_FILE);
+        let recorded: BTreeMap<String, u32> = match recorded_file.exists() {
+            true => read_json(&recorded_file, &Codec::default())?,
+            false => BTreeMap::new(),
+        };
+        let mut names: BTreeSet<String> = BTreeSet::new();
+        match table {
+            Some(table) => {
+                names.insert(table.to_string());
+            }
+            None => {
+                names.extend(recorded.keys().cloned());
+                for entry in fs::read_dir(dir)? {
+                    let file_name = entry?.file_name().to_string_lossy().into_owned();
+                    if let Some(name) = file_name.strip_suffix(SCHEMA_SUFFIX) {
+                        names.insert(name.to_string());
+                    }
+                }
+            }
+        }
+
+        let mut report = VerifyReport::default();
+        for name in names {
+            let files = [SCHEMA_SUFFIX, DATA_SUFFIX].map(|suffix| dir.join(format!("{}{}", name, suffix)));
+            let integrity = match (recorded.get(&name), files.iter().all(|file| file.exists())) {
+                (_, false) => Integrity::Missing,
+                (None, true) => Integrity::Unchecked,
+                (Some(&sum), true) if checksum(&fs::read(&files[0])?, &fs::read(&files[1])?) == sum => Integrity::Ok,
+                (Some(_), true) => Integrity::Mismatch,
+            };
+            report.tables.insert(name, integrity);
+        }
+        Ok(report)
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    // like `open`, or `open_encrypted` with a key, keeping every table's rows
+    // in stores made by `backend`
+    pub fn open_with_backend(
+        dir: impl AsRef<Path>,
+        backend: B,
+        key: Option<&[u8; 32]>,
+    ) -> Result<Database<B>, StorageError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let mut db = Database::load_with_backend(dir, backend, key)?;
+        let save = PendingSave::read(dir)?;
+        replay_log(&mut db, dir, save.as_ref())?;
+        // a save that crashed after its manifest is finished
+        if let Some(save) = save {
+            commit_save(dir, &save)?;
+        }
+        db.wal = Some(Wal::open(dir, db.encryption.clone())?);
+        Ok(db)
+    }
+
+    // like `open_read_only`, keeping every table's rows in stores made by `backend`
+    pub fn open_read_only_with_backend(dir: impl AsRef<Path>, backend: B) -> Result<Database<B>, StorageError> {
+        let dir = dir.as_ref();
+        let mut db = Database::load_with_backend(dir, backend, None)?;
+        replay_log(&mut db, dir, PendingSave::read(dir)?.as_ref())?;
+        db.read_only = true;
+        Ok(db)
+    }
+
+    // like `load`, or `load_encrypted` with a key, keeping every table's rows
+    // in stores made by `backend`
+    pub fn load_with_backend(
+        dir: impl AsRef<Path>,
+        backend: B,
+        key: Option<&[u8; 32]>,
+    ) -> Result<Database<B>, StorageError> {
+        let dir = dir.as_ref();
+        let mut db = Database::with_backend(backend);
+        if let Some(key) = key {
+            db.encryption = Codec::encrypted(key);
+        }
+        if !dir.exists() {
+            return Ok(db);
+        }
+
+        // a save cut short after its manifest is read as if it had finished
+        let save = PendingSave::read(dir)?;
+        let save = save.as_ref();
+        let mut names = BTreeSet::new();
+        for entry in fs::read_dir(dir)? {
+            let file_name = entry?.file_name().to_string_lossy().into_owned();
+            let (file, pending) = match file_name.strip_suffix(PENDING_SUFFIX) {
+                Some(file) => (file, true),
+                None => (file_name.as_str(), false),
+            };
+            let listed = |files: &[String]| files.iter().any(|listed| listed == file);
+            let current = match save {
+                Some(save) if pending => listed(&save.written),
+                Some(save) => !listed(&save.removed),
+                None => !pending,
+            };
+            if let Some(name) = file.strip_suffix(SCHEMA_SUFFIX).filter(|_| current) {
+                names.insert(name.to_string());
+            }
+        }
+        for name in names {
+            let table = load_table(dir, &name, save, &db.encryption, &db.backend)?;
+            db.tables.insert(name, Arc::new(table));
+        }
+
+        let views = PendingSave::path(save, dir, VIEWS_FILE);
+        if views.exists() {
+            db.views = read_json(&views, &db.encryption)?;
+        }
+        Ok(db)
+    }
+
+    // writes a snapshot of every table and view to `dir`; when the database
+    // logs to `dir` the log is truncated since the snapshot now covers it
+    pub fn save(&mut self, dir: impl AsRef<Path>) -> Result<(), StorageError> {
+        self.save_with(dir.as_ref(), &Codec::default())
+    }
+
+    // like `save`, but every table and view file is encrypted with `key` using
+    // ChaCha20-Poly1305 and a fresh nonce per file. from then on backups are
+    // too, and so is the write-ahead log when the database logs to `dir`
+    pub fn save_encrypted(&mut self, dir: impl AsRef<Path>, key: &[u8; 32]) -> Result<(), StorageError> {
+        self.save_with(dir.as_ref(), &Codec::encrypted(key))?;
+        self.encryption = Codec::encrypted(key);
+        Ok(())
+    }
+
+    // like `save`, with every table and view file compressed. `load` detects
+    // the compression of each file on its own
+    pub fn save_compressed(&mut self, dir: impl AsRef<Path>, compression: Compression) -> Result<(), StorageError> {
+        self.save_with(dir.as_ref(), &Codec::compressed(compression))
+    }
+
+    // the snapshot commits in one step, the rename of its manifest, so a crash
+    // leaves either the last snapshot and the whole log or the new snapshot
+    fn save_with(&mut self, dir: &Path, codec: &Codec) -> Result<(), StorageError> {
+        let save = self.prepare_save(dir, codec)?;
+        commit_save(dir, &save)?;
+        // the log is encrypted like the snapshot it starts from
+        if let Some(wal) = self.wal.as_mut().filter(|_| save.covers_log) {
+            wal.truncate()?;
+            wal.set_codec(codec.encryption());
+        }
+        Ok(())
+    }
+
+    // writes every table and view as a pending copy next to the current
+    // snapshot, then the manifest naming them, from which on the save is done
+    pub(crate) fn prepare_save(&self, dir: &Path, codec: &Codec) -> Result<PendingSave, StorageError> {
+        self.check_writable()?;
+        fs::create_dir_all(dir)?;
+        let mut written = Vec::new();
+        let mut write_pending = |file: String, codec: &Codec, write: &dyn Fn(&mut Vec<u8>) -> io::Result<()>| {
+            let encoded = write_atomic_as(&dir.join(format!("{}{}", file, PENDING_SUFFIX)), &file, codec, write)?;
+            written.push(file);
+            io::Result::Ok(encoded)
+        };
+
+        let mut checksums = BTreeMap::new();
+        for (name, table) in &self.tables {
+            let schema = write_pending(format!("{}{}", name, SCHEMA_SUFFIX), codec, &|out| {
+                serde_json::to_writer_pretty(&mut *out, &schema_of(table)).map_err(io::Error::from)
+            })?;
+            let data = write_pending(format!("{}{}", name, DATA_SUFFIX), codec, &|out| {
+                for row in stored_rows(table) {
+                    serde_json::to_writer(&mut *out, &row)?;
+                    out.write_all(b"\n")?;
+                }
+                Ok(())
+            })?;
+            checksums.insert(name, checksum(&schema, &data));
+        }
+        write_pending(CHECKSUMS_FILE.to_string(), &Codec::default(), &|out| {
+            serde_json::to_writer_pretty(&mut *out, &checksums).map_err(io::Error::from)
+        })?;
+        let views: BTreeMap<&String, &ReadCommand> = self.views.iter().collect();
+        write_pending(VIEWS_FILE.to_string(), codec, &|out| {
+            serde_json::to_writer_pretty(&mut *out, &views).map_err(io::Error::from)
+        })?;
+
+        // files of dropped tables would otherwise come back on the next load.
+        // pending copies of an earlier save that failed are left over
+        let mut removed = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let file_name = entry?.file_name().to_string_lossy().into_owned();
+            if let Some(file) = file_name.strip_suffix(PENDING_SUFFIX) {
+                if !written.iter().any(|written| written == file) {
+                    fs::remove_file(dir.join(&file_name))?;
+                }
+                continue;
+            }
+            let table = file_name
+                .strip_suffix(SCHEMA_SUFFIX)
+                .or_else(|| file_name.strip_suffix(DATA_SUFFIX));
+            if table.is_some_and(|table| !self.tables.contains_key(table)) {
+                removed.push(file_name);
+            }
+        }
+
+        let save = PendingSave {
+            written,
+            removed,
+            covers_log: self.wal.as_ref().is_some_and(|wal| wal.dir() == dir),
+        };
+        write_atomic(&dir.join(SAVE_FILE), &Codec::default(), |out| {
+            serde_json::to_writer_pretty(&mut *out, &save).map_err(io::Error::from)
+        })?;
+        Ok(save)
+    }
+
+    // writes every table (schema, index definitions and rows) and view into
+    // the single file `path`
+    pub fn backup(&self, path: impl AsRef<Path>) -> Result<(), StorageError> {
+        let archive = Archive {
+            format_version: BACKUP_FORMAT_VERSION,
+            tables: self
+                .tables
+                .iter()
+                .map(|(name, table)| {
+                    let archived = ArchivedTable { schema: schema_of(table), rows: stored_rows(table).collect() };
+                    (name.clone(), archived)
+                })
+                .collect(),
+            views: self.views.iter().map(|(name, view)| (name.clone(), view.clone())).collect(),
+        };
+        write_atomic_as(path.as_ref(), BACKUP_NAME, &self.encryption, |out| {
+            serde_json::to_writer(&mut *out, &archive).map_err(io::Error::from)
+        })?;
+        Ok(())
+    }
+
+    // replaces every table and view with the contents of a backup. the whole
+    // archive is read and checked first, so a bad file leaves the database as is
+    pub fn restore(&mut self, path: impl AsRef<Path>) -> Result<(), StorageError> {
+        self.check_writable()?;
+        let path = path.as_ref();
+        let archive = self.read_backup(path)?;
+        self.restore_from(path, archive)
+    }
+
+    // the decoded contents of the backup file `path`
+    pub(crate) fn read_backup(&self, path: &Path) -> Result<serde_json::Value, StorageError> {
+        let archive = decode(path, BACKUP_NAME, &self.encryption, fs::read(path)?)?;
+        serde_json::from_slice(&archive).map_err(|err| corrupt(path, err))
+    }
+
+    // replaces the database with `archive`, the contents of the backup `path`
+    pub(crate) fn restore_from(&mut self, path: &Path, archive: serde_json::Value) -> Result<(), StorageError> {
+        self.check_writable()?;
+        let version = archive.get("format_version").and_then(serde_json::Value::as_u64);
+        if version != Some(BACKUP_FORMAT_VERSION.into()) {
+            let found = version.map_or("none".to_string(), |version| version.to_string());
+            return Err(corrupt(path, format!("unsupported backup format version {}", found)));
+        }
+        let archive: Archive = serde_json::from_value(archive).map_err(|err| corrupt(path, err))?;
+
+        let mut tables = HashMap::new();
+        for (name, archived) in archive.tables {
+            let table = restore_table(&name, archived.schema, archived.rows, path, self.backend())?;
+            tables.insert(name, Arc::new(table));
+        }
+        self.tables = tables;
+        self.views = archive.views.into_iter().collect();
+        Ok(())
+    }
+
+    // every table's schema and rows as a document for `from_json_document`.
+    // generated columns are left out of the rows and ttl tables' insertion
+    // times aren't kept, so their rows start a new ttl when loaded
+    pub fn to_json_document(&self) -> String {
+        let tables = self
+            .tables
+            .iter()
+            .map(|(name, table)| {
+                let rows = table
+                    .rows()
+                    .map(|row| {
+                        let mut row = row.into_owned();
+                        row.retain(|column, _| table.columns.get(column).is_none_or(|def| def.generated.is_none()));
+                        row
+                    })
+                    .collect();
+                (name.clone(), ArchivedTable { schema: schema_of(table), rows })
+            })
+            .collect();
+        serde_json::to_string_pretty(&Document { tables }).expect("documents hold only json values")
+    }
+
+    fn seed_table(&mut self, name: String, table: ArchivedTable) -> Result<(), ExecError> {
+        let ArchivedTable { schema, rows } = table;
+        let mut columns = schema.columns;
+        if schema.timestamps {
+            columns.remove(CREATED_AT_FIELD);
+            columns.remove(UPDATED_AT_FIELD);
+        }
+        self.execute(Command::Create(CreateCommand::Table {
+            table: name.clone(),
+            primary_key: schema.primary_key,
+            rows: columns,
+            ttl_seconds: schema.ttl_seconds,
+            storage: schema.storage,
+            implicit_default: false,
+            timestamps: schema.timestamps,
+            max_rows: schema.max_rows,
+        }))?;
+        for definition in schema.indexes {
+            self.execute(Command::CreateIndex {
+                table: name.clone(),
+                name: definition.name,
+                columns: definition.columns,
+                predicate: definition.predicate,
+                unique: definition.unique,
+            })?;
+        }
+        self.insert_many(&name, rows)?;
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<(), StorageError> {
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, ExecError::ReadOnly.to_string()).into());
+        }
+        Ok(())
+    }
+}
+
+// runs the records of `dir`'s write-ahead log on `db`, each with its stamp so
+// it sees the clock and draws the random values it did the first time. only
+// commands that applied are logged, so one failing means the log no longer
+// fits the snapshot. a log the pending `save` covers is already in it
+fn replay_log<B: StorageBackend>(db: &mut Database<B>, dir: &Path, save: Option<&PendingSave>) -> Result<(), StorageError> {
+    if save.is_some_and(|save| save.covers_log) {
+        return Ok(());
+    }
+    for (position, record) in wal::read_log(dir, &db.encryption)?.into_iter().enumerate() {
+        record
+            .stamp
+            .run(|| db.execute(record.command))
+            .map_err(|error| StorageError::Replay { record: position + 1, error })?;
+    }
+    Ok(())
+}
+
+// moves a prepared save's pending copies into place, removes the files of
+// dropped tables and empties the log the snapshot covers, then the manifest.
+// each step can run again, so the next open finishes a commit a crash cut short
+fn commit_save(dir: &Path, save: &PendingSave) -> io::Result<()> {
+    for file in &save.written {
+        let pending = dir.join(format!("{}{}", file, PENDING_SUFFIX));
+        if pending.exists() {
+            fs::rename(pending, dir.join(file))?;
+        }
+    }
+    for file in &save.removed {
+        match fs::remove_file(dir.join(file)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    if save.covers_log {
+        wal::clear_log(dir)?;
+    }
+    fs::remove_file(dir.join(SAVE_FILE))
+}
+
+fn load_table<B: StorageBackend>(
+    dir: &Path,
+    name: &str,
+    save: Option<&PendingSave>,
+    codec: &Codec,
+    backend: &B,
+) -> Result<Table<B>, StorageError> {
+    let schema: TableSchema = read_json(&PendingSave::path(save, dir, &format!("{}{}", name, SCHEMA_SUFFIX)), codec)?;
+    let mut rows = Vec::new();
+
+    let data = PendingSave::path(save, dir, &format!("{}{}", name, DATA_SUFFIX));
+    if data.exists() {
+        for line in read_file(&data, codec)?.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            rows.push(serde_json::from_str(&line).map_err(|err| corrupt(&data, err))?);
+        }
+    }
+    restore_table(name, schema, rows, &data, backend)
+}
+
+fn schema_of<B: StorageBackend>(table: &Table<B>) -> TableSchema {
+    TableSchema {
+        primary_key: table.primary_key.clone(),
+        columns: table.columns.clone(),
+        ttl_seconds: table.ttl_seconds,
+        storage: table.storage(),
+        timestamps: table.timestamps,
+        max_rows: table.max_rows,
+        indexes: table.indexes.iter().map(|index| index.definition.clone()).collect(),
+    }
+}
+
+// the live rows of a table as they are stored, with the insertion time of rows
+// of ttl tables. expired rows are left out, so writing them also purges them
+fn stored_rows<B: StorageBackend>(table: &Table<B>) -> impl Iterator<Item = Row> + '_ {
+    table.entries().map(|(key, row)| {
+        let mut row = row.into_owned();
+        if let Some(at) = table.inserted_at.get(key) {
+            row.insert(INSERTED_AT_FIELD.to_string(), (*at).into());
+        }
+        row
+    })
+}
+
+// builds a table from its schema and stored rows; `file` names the source in errors
+fn restore_table<B: StorageBackend>(
+    name: &str,
+    schema: TableSchema,
+    rows: Vec<Row>,
+    file: &Path,
+    backend: &B,
+) -> Result<Table<B>, StorageError> {
+    let mut table = Table::new(schema.primary_key, schema.columns, schema.ttl_seconds, schema.storage, backend);
+    table.timestamps = schema.timestamps;
+    table.max_rows = schema.max_rows;
+    for definition in schema.indexes {
+        let index = Index::new(name, &table, definition).map_err(|err| corrupt(file, err))?;
+        table.add_index(index);
+    }
+    for mut row in rows {
+        let inserted_at = match table.ttl_seconds {
+            Some(_) => row.remove(INSERTED_AT_FIELD).and_then(|at| at.as_u64()),
+            None => None,
+        };
+        if !table.primary_key.columns().iter().all(|column| row.contains_key(column)) {
+            return Err(corrupt(file, "row without primary key"));
+        }
+        table.insert_row(table.key_of(&row), row, inserted_at);
+    }
+    Ok(table)
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path, codec: &Codec) -> Result<T, StorageError> {
+    serde_json::from_slice(&read_file(path, codec)?).map_err(|err| corrupt(path, err))
+}
+
+// the decoded contents of a snapshot file. a pending copy is encoded under
+// the name it is saved as
+fn read_file(path: &Path, codec: &Codec) -> Result<Vec<u8>, StorageError> {
+    let name = file_name(path);
+    decode(path, name.strip_suffix(PENDING_SUFFIX).unwrap_or(&name), codec, fs::read(path)?)
+}
+
+// `bytes` read from `path` and encoded under `name`, decoded
+pub(crate) fn decode(path: &Path, name: &str, codec: &Codec, bytes: Vec<u8>) -> Result<Vec<u8>, StorageError> {
+    codec.decode(name, bytes).map_err(|err| match err {
+        DecodeError::Decrypt => StorageError::Decrypt { file: path.to_path_buf() },
+        DecodeError::NeedsKey => corrupt(path, "file is encrypted, load it with its key"),
+        DecodeError::NotEncrypted => corrupt(path, "file is not encrypted"),
+        DecodeError::Decompress(err) => corrupt(path, err),
+    })
+}
+
+// writes through a temporary file so a crash never leaves a half-written file
+// behind, and returns the bytes as written
+fn write_atomic(
+    path: &Path,
+    codec: &Codec,
+    write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>,
+) -> io::Result<Vec<u8>> {
+    write_atomic_as(path, &file_name(path), codec, write)
+}
+
+// like `write_atomic`, encoded under `name` rather than the file's name
+fn write_atomic_as(
+    path: &Path,
+    name: &str,
+    codec: &Codec,
+    write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>,
+) -> io::Result<Vec<u8>> {
+    let mut plain = Vec::new();
+    write(&mut plain)?;
+    let encoded = codec.encode(name, plain)?;
+    let tmp = path.with_extension("tmp");
+    let mut out = fs::File::create(&tmp)?;
+    out.write_all(&encoded)?;
+    out.sync_all()?;
+    fs::rename(tmp, path)?;
+    Ok(encoded)
+}
+
+// the CRC-32 of a table's files, one after the other
+fn checksum(schema: &[u8], data: &[u8]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    crc.update(schema);
+    crc.update(data);
+    crc.sum()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+pub(crate) fn corrupt(path: &Path, reason: impl fmt::Display) -> StorageError {
+    StorageError::Corrupt {
+        file: path.to_path_buf(),
+        reason: reason.to_string(),
+    }
+}
@@ -0,0 +1,164 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use serde_json::Value;
+
+use crate::backend::{MemoryBackend, StorageBackend};
+use crate::database::{Key, Row};
+use crate::parser::{ColumnDefinition, StorageLayout};
+
+// the rows of a table, kept in primary key order
+#[derive(Debug, Clone)]
+pub(crate) enum RowStore<B = MemoryBackend> {
+    Rows(B),
+    Columnar(ColumnStore),
+}
+
+// one contiguous vector per column, all of the same length. `keys[i]` is the
+// primary key of the row at position i and `positions` maps it back
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ColumnStore {
+    positions: BTreeMap<Key, usize>,
+    keys: Vec<Key>,
+    columns: HashMap<String, Vec<Value>>,
+}
+
+impl<B: StorageBackend> RowStore<B> {
+    pub(crate) fn new(layout: StorageLayout, columns: &HashMap<String, ColumnDefinition>, backend: &B) -> RowStore<B> {
+        match layout {
+            StorageLayout::Rows => RowStore::Rows(backend.empty()),
+            StorageLayout::Columnar => RowStore::Columnar(ColumnStore {
+                columns: columns.keys().map(|column| (column.clone(), Vec::new())).collect(),
+                ..ColumnStore::default()
+            }),
+        }
+    }
+
+    pub(crate) fn layout(&self) -> StorageLayout {
+        match self {
+            RowStore::Rows(_) => StorageLayout::Rows,
+            RowStore::Columnar(_) => StorageLayout::Columnar,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            RowStore::Rows(rows) => rows.len(),
+            RowStore::Columnar(store) => store.keys.len(),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &Key) -> Option<Cow<'_, Row>> {
+        match self {
+            RowStore::Rows(rows) => rows.get(key),
+            RowStore::Columnar(store) => store.positions.get(key).map(|&i| Cow::Owned(store.row(i, None))),
+        }
+    }
+
+    pub(crate) fn keys(&self) -> Box<dyn Iterator<Item = &Key> + '_> {
+        match self {
+            RowStore::Rows(rows) => Box::new(rows.scan(None).map(|(key, _)| key)),
+            RowStore::Columnar(store) => Box::new(store.positions.keys()),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = (&Key, Cow<'_, Row>)> + '_> {
+        match self {
+            RowStore::Rows(rows) => rows.scan(None),
+            RowStore::Columnar(store) => Box::new(
+                store.positions.iter().map(|(key, &i)| (key, Cow::Owned(store.row(i, None)))),
+            ),
+        }
+    }
+
+    // the rows whose key sorts after `after`
+    pub(crate) fn iter_after<'a>(&'a self, after: &Key) -> Box<dyn Iterator<Item = (&'a Key, Cow<'a, Row>)> + 'a> {
+        match self {
+            RowStore::Rows(rows) => rows.scan(Some(after)),
+            RowStore::Columnar(store) => Box::new(
+                store
+                    .positions
+                    .range::<Key, _>((Bound::Excluded(after), Bound::Unbounded))
+                    .map(|(key, &i)| (key, Cow::Owned(store.row(i, None)))),
+            ),
+        }
+    }
+
+    // rows holding only `columns`; a columnar store reads just those vectors
+    pub(crate) fn project<'a>(
+        &'a self,
+        columns: &'a [String],
+    ) -> Box<dyn Iterator<Item = (&'a Key, Row)> + 'a> {
+        match self {
+            RowStore::Rows(rows) => Box::new(rows.scan(None).map(move |(key, row)| {
+                let projected = columns
+                    .iter()
+                    .filter_map(|column| row.get(column).map(|value| (column.clone(), value.clone())))
+                    .collect();
+                (key, projected)
+            })),
+            RowStore::Columnar(store) => Box::new(
+                store.positions.iter().map(move |(key, &i)| (key, store.row(i, Some(columns)))),
+            ),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: Key, row: Row) {
+        match self {
+            RowStore::Rows(rows) => rows.put(key, row),
+            RowStore::Columnar(store) => store.insert(key, row),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &Key) {
+        match self {
+            RowStore::Rows(rows) => rows.delete(key),
+            RowStore::Columnar(store) => store.remove(key),
+        }
+    }
+}
+
+impl ColumnStore {
+    fn row(&self, i: usize, only: Option<&[String]>) -> Row {
+        let mut row = Row::new();
+        for (column, values) in &self.columns {
+            if only.is_none_or(|only| only.contains(column)) {
+                row.insert(column.clone(), values[i].clone());
+            }
+        }
+        row
+    }
+
+    // writes go to every column vector in lockstep; replacing a key
+    // overwrites its position in place
+    fn insert(&mut self, key: Key, mut row: Row) {
+        match self.positions.get(&key) {
+            Some(&i) => {
+                for (column, values) in &mut self.columns {
+                    values[i] = row.remove(column).unwrap_or(Value::Null);
+                }
+            }
+            None => {
+                for (column, values) in &mut self.columns {
+                    values.push(row.remove(column).unwrap_or(Value::Null));
+                }
+                self.positions.insert(key.clone(), self.keys.len());
+                self.keys.push(key);
+            }
+        }
+    }
+
+    // the last row moves into the freed position so the vectors stay dense
+    fn remove(&mut self, key: &Key) {
+        let Some(i) = self.positions.remove(key) else {
+            return;
+        };
+        self.keys.swap_remove(i);
+        for values in self.columns.values_mut() {
+            values.swap_remove(i);
+        }
+        if let Some(moved) = self.keys.get(i) {
+            self.positions.insert(moved.clone(), i);
+        }
+    }
+}
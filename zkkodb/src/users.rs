@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use serde_json::json;
+
+use crate::backend::StorageBackend;
+use crate::database::{Database, ExecError, Key, Row};
+use crate::events::ChangeKind;
+use crate::migrations::command;
+use crate::parser::{Command, CreateCommand, UpdateCommand};
+
+// users live in an ordinary table like grants, one row per user with the
+// argon2 hash of their password, so they are saved, loaded and logged like any
+// other data. only the admin role may touch it directly
+pub const USERS_TABLE: &str = "_users";
+
+impl<B: StorageBackend> Database<B> {
+    pub(crate) fn create_user(&mut self, username: String, password_hash: String, role: String) -> Result<(), ExecError> {
+        if username.is_empty() || role.is_empty() {
+            return Err(ExecError::InvalidQuery("a user needs a username and a role".to_string()));
+        }
+        check_hash(&username, &password_hash)?;
+        if !self.tables.contains_key(USERS_TABLE) {
+            self.apply(command(json!({
+                "command": "create",
+                "type": "table",
+                "table": USERS_TABLE,
+                "primary_key": "username",
+                "rows": {
+                    "username": { "type": "string" },
+                    "password_hash": { "type": "string" },
+                    "role": { "type": "string" }
+                }
+            })))?;
+        }
+        if self.user(&username).is_some() {
+            return Err(ExecError::InvalidQuery(format!("user '{}' already exists", username)));
+        }
+        let row = HashMap::from([
+            ("username".to_string(), json!(username)),
+            ("password_hash".to_string(), json!(password_hash)),
+            ("role".to_string(), json!(role)),
+        ]);
+        self.insert(USERS_TABLE, row)
+    }
+
+    // a new password hash, role or both for an existing user. the last admin
+    // keeps the admin role so users can still be managed
+    pub(crate) fn alter_user(&mut self, username: &str, password_hash: Option<String>, role: Option<String>) -> Result<(), ExecError> {
+        let user = self
+            .user(username)
+            .ok_or_else(|| ExecError::InvalidQuery(format!("user '{}' does not exist", username)))?;
+        if password_hash.is_none() && role.is_none() {
+            return Err(ExecError::InvalidQuery(format!("altering user '{}' needs a password or a role", username)));
+        }
+        if role.as_ref().is_some_and(|role| role.is_empty()) {
+            return Err(ExecError::InvalidQuery(format!("user '{}' needs a role", username)));
+        }
+        let demoted = user["role"] == "admin" && role.as_ref().is_some_and(|role| role != "admin");
+        if demoted && self.admins() == 1 {
+            return Err(ExecError::InvalidQuery(format!("user '{}' is the last admin", username)));
+        }
+        let mut row = HashMap::from([("username".to_string(), json!(username))]);
+        if let Some(password_hash) = password_hash {
+            check_hash(username, &password_hash)?;
+            row.insert("password_hash".to_string(), json!(password_hash));
+        }
+        if let Some(role) = role {
+            row.insert("role".to_string(), json!(role));
+        }
+        self.upsert(USERS_TABLE, row, &[])
+    }
+
+    // the user and the grants made to them. the last admin stays
+    pub(crate) fn delete_user(&mut self, username: &str) -> Result<(), ExecError> {
+        let user = self
+            .user(username)
+            .ok_or_else(|| ExecError::InvalidQuery(format!("user '{}' does not exist", username)))?;
+        if user["role"] == "admin" && self.admins() == 1 {
+            return Err(ExecError::InvalidQuery(format!("user '{}' is the last admin", username)));
+        }
+        let key = Key(json!(username));
+        let users = self.tables.get_mut(USERS_TABLE).expect("the user was found in it");
+        Arc::make_mut(users).remove_row(&key);
+        self.notify(ChangeKind::Delete, USERS_TABLE, vec![key.0]);
+        self.forget_user_grants(username);
+        Ok(())
+    }
+
+    // every user and their role by username, never their password hash
+    pub(crate) fn list_users(&self) -> Vec<Row> {
+        let Some(users) = self.tables.get(USERS_TABLE) else {
+            return Vec::new();
+        };
+        users
+            .rows()
+            .map(|row| {
+                let mut row = row.into_owned();
+                row.remove("password_hash");
+                row
+            })
+            .collect()
+    }
+
+    // the role of `username` when `password` is theirs
+    pub fn authenticate(&self, username: &str, password: &str) -> Result<String, ExecError> {
+        let denied = || ExecError::PermissionDenied("unknown user or wrong password".to_string());
+        let user = self.user(username).ok_or_else(denied)?;
+        let hash = user["password_hash"].as_str().and_then(|hash| PasswordHash::new(hash).ok()).ok_or_else(denied)?;
+        Argon2::default().verify_password(password.as_bytes(), &hash).map_err(|_| denied())?;
+        Ok(user["role"].as_str().unwrap_or_default().to_string())
+    }
+
+    // whether a user has the admin role. until one does, any session may
+    // manage users so the first admin can be created
+    pub(crate) fn has_admin(&self) -> bool {
+        self.admins() > 0
+    }
+
+    fn admins(&self) -> usize {
+        let Some(users) = self.tables.get(USERS_TABLE) else {
+            return 0;
+        };
+        users.rows().filter(|row| row["role"] == "admin").count()
+    }
+
+    fn user(&self, username: &str) -> Option<Row> {
+        let users = self.tables.get(USERS_TABLE)?;
+        users.get(&Key(json!(username))).map(|row| row.into_owned())
+    }
+}
+
+// `cmd` with the password of every user it creates or alters replaced by its
+// hash, so the write-ahead log and the command log never hold a password
+pub(crate) fn hash_passwords(cmd: Command) -> Result<Command, ExecError> {
+    match cmd {
+        Command::Create(CreateCommand::User { username, password: Some(password), password_hash: None, role }) => {
+            Ok(Command::Create(CreateCommand::User {
+                username,
+                password: None,
+                password_hash: Some(hash_password(&password)?),
+                role,
+            }))
+        }
+        Command::Update(UpdateCommand::User { username, password: Some(password), password_hash: None, role }) => {
+            Ok(Command::Update(UpdateCommand::User {
+                username,
+                password: None,
+                password_hash: Some(hash_password(&password)?),
+                role,
+            }))
+        }
+        Command::Batch { commands, atomic } => Ok(Command::Batch {
+            commands: commands.into_iter().map(hash_passwords).collect::<Result<_, _>>()?,
+            atomic,
+        }),
+        cmd => Ok(cmd),
+    }
+}
+
+// the hash a command gives a user: its `password` hashed, or its
+// `password_hash` as logged, None for neither
+pub(crate) fn password_hash(password: Option<String>, password_hash: Option<String>) -> Result<Option<String>, ExecError> {
+    match (password, password_hash) {
+        (Some(password), None) => hash_password(&password).map(Some),
+        (None, password_hash) => Ok(password_hash),
+        (Some(_), Some(_)) => Err(ExecError::InvalidQuery("give either a password or a password_hash".to_string())),
+    }
+}
+
+fn hash_password(password: &str) -> Result<String, ExecError> {
+    if password.is_empty() {
+        return Err(ExecError::InvalidQuery("a password must not be empty".to_string()));
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| ExecError::InvalidQuery(format!("couldn't hash the password: {}", err)))
+}
+
+fn check_hash(username: &str, password_hash: &str) -> Result<(), ExecError> {
+    PasswordHash::new(password_hash)
+        .map(|_| ())
+        .map_err(|err| ExecError::InvalidQuery(format!("invalid password hash for user '{}': {}", username, err)))
+}
@@ -0,0 +1,336 @@
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{self, AtomicU64};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+thread_local! {
+    // the clock in microseconds and the generator state of the stamp this
+    // thread runs a command with, see `Stamp::run`
+    static PINNED: Cell<Option<(u64, u64)>> = const { Cell::new(None) };
+}
+
+// total order over json values: null < bool < number < string < array < object
+pub fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (Value::Number(x), Value::Number(y)) => {
+            match (x.as_i64(), y.as_i64()) {
+                (Some(x), Some(y)) => x.cmp(&y),
+                _ => {
+                    let x = x.as_f64().unwrap_or(f64::NAN);
+                    let y = y.as_f64().unwrap_or(f64::NAN);
+                    x.partial_cmp(&y).unwrap_or(Ordering::Equal)
+                }
+            }
+        }
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Array(x), Value::Array(y)) => {
+            for (x, y) in x.iter().zip(y.iter()) {
+                let ord = compare_values(x, y);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            x.len().cmp(&y.len())
+        }
+        (Value::Object(_), Value::Object(_)) => a.to_string().cmp(&b.to_string()),
+        _ => type_rank(a).cmp(&type_rank(b)),
+    }
+}
+
+// equality that treats 1 and 1.0 as the same number
+pub fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(_), Value::Number(_)) => compare_values(a, b) == Ordering::Equal,
+        _ => a == b,
+    }
+}
+
+// ordering between two values of the same kind, None when they can't be compared
+pub fn compare_same_type(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(_), Value::Number(_))
+        | (Value::String(_), Value::String(_))
+        | (Value::Bool(_), Value::Bool(_)) => Some(compare_values(a, b)),
+        _ => None,
+    }
+}
+
+// 64-bit FNV-1a, used to detect torn or corrupted records on disk
+pub fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// milliseconds since the unix epoch
+pub fn now_millis() -> u64 {
+    now_micros() / 1_000
+}
+
+// microseconds since the unix epoch, or the stamp's time inside `Stamp::run`
+pub fn now_micros() -> u64 {
+    PINNED.get().map_or_else(
+        || SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_micros() as u64),
+        |(at, _)| at,
+    )
+}
+
+// the time and random seed a mutating command runs with. running it again
+// inside `run` with the same stamp reads the same clock and draws the same
+// random values, so a replayed command does exactly what it did the first time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    // microseconds since the unix epoch
+    pub at: u64,
+    pub seed: u64,
+}
+
+impl Stamp {
+    // the current time and a fresh seed; inside `run` the stamp being run
+    pub fn now() -> Stamp {
+        match PINNED.get() {
+            Some((at, seed)) => Stamp { at, seed },
+            None => Stamp { at: now_micros(), seed: Rng::from_entropy().next_u64() },
+        }
+    }
+
+    // runs `f` on the stamp's clock and randomness. inside another stamp's
+    // `run` that one stays, so nested commands draw from the same sequence
+    pub fn run<T>(self, f: impl FnOnce() -> T) -> T {
+        struct Unpin;
+        impl Drop for Unpin {
+            fn drop(&mut self) {
+                PINNED.set(None);
+            }
+        }
+        if PINNED.get().is_some() {
+            return f();
+        }
+        PINNED.set(Some((self.at, self.seed)));
+        let _unpin = Unpin;
+        f()
+    }
+}
+
+// whether `name` matches a glob in which `*` stands for any run of characters
+// and `?` for exactly one
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // the last `*` seen and where in `name` its match currently ends
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // let the `*` swallow one more character and retry
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// orders strings with runs of digits compared by their value, "item2" before
+// "item10". runs of equal value with more leading zeros come later, so only
+// identical strings are equal
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(ca), Some(cb)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            let (run_a, run_b) = (&a[..digits(a)], &b[..digits(b)]);
+            let (value_a, value_b) = (run_a.trim_start_matches('0'), run_b.trim_start_matches('0'));
+            let ord = value_a
+                .len()
+                .cmp(&value_b.len())
+                .then_with(|| value_a.cmp(value_b))
+                .then_with(|| run_a.len().cmp(&run_b.len()));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            (a, b) = (&a[run_a.len()..], &b[run_b.len()..]);
+        } else {
+            if ca != cb {
+                return ca.cmp(&cb);
+            }
+            (a, b) = (&a[ca.len_utf8()..], &b[cb.len_utf8()..]);
+        }
+    }
+}
+
+// the Levenshtein distance between `a` and `b` in characters: the fewest
+// insertions, deletions and substitutions turning one into the other
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // distances from the prefix of `a` seen so far to every prefix of `b`
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitute.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+// microseconds since the unix epoch of an RFC 3339 timestamp such as
+// "2024-05-01T12:30:00.5+02:00", None when it isn't one. digits past
+// microseconds are dropped
+pub fn parse_rfc3339(text: &str) -> Option<i64> {
+    let number = |from: usize, to: usize| -> Option<i64> {
+        let part = text.get(from..to)?;
+        part.bytes().all(|byte| byte.is_ascii_digit()).then(|| part.parse().ok())?
+    };
+    let bytes = text.as_bytes();
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if separators.iter().any(|&(i, c)| bytes.get(i) != Some(&c)) || !matches!(bytes.get(10), Some(b'T' | b't' | b' ')) {
+        return None;
+    }
+    let (year, month, day) = (number(0, 4)?, number(5, 7)?, number(8, 10)?);
+    let (hour, minute, second) = (number(11, 13)?, number(14, 16)?, number(17, 19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut rest = text.get(19..)?;
+    let mut micros = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return None;
+        }
+        let digits = &fraction[..len.min(6)];
+        micros = digits.parse::<i64>().ok()? * 10_i64.pow(6 - digits.len() as u32);
+        rest = &fraction[len..];
+    }
+    let offset_minutes = match rest.as_bytes() {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let minutes = rest.get(1..3)?.parse::<i64>().ok()? * 60 + rest.get(4..6)?.parse::<i64>().ok()?;
+            if *sign == b'-' { -minutes } else { minutes }
+        }
+        _ => return None,
+    };
+
+    // the day count of a civil date, Howard Hinnant's days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let days = era * 146_097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719_468;
+    let seconds = days * 86_400 + hour * 3_600 + minute * 60 + second - offset_minutes * 60;
+    Some(seconds * 1_000_000 + micros)
+}
+
+// the current UTC time in RFC 3339 with microseconds, e.g.
+// "2024-05-01T12:30:00.123456Z". the fixed width makes them sort as strings
+pub fn now_rfc3339() -> String {
+    let elapsed = Duration::from_micros(now_micros());
+    let (days, secs) = ((elapsed.as_secs() / 86_400) as i64, elapsed.as_secs() % 86_400);
+    // the civil date of a day count, Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60,
+        elapsed.subsec_micros()
+    )
+}
+
+// a random version 4 UUID, hyphenated
+pub fn uuid_v4() -> String {
+    let (mut high, mut low) = (Rng::from_entropy(), Rng::from_entropy());
+    let bits = (high.next_u64() as u128) << 64 | low.next_u64() as u128;
+    // version 4 in the high nibble of byte 6, variant 0b10 at the top of byte 8
+    let bits = bits & !(0xf << 76) | 0x4 << 76;
+    let bits = bits & !(0x3 << 62) | 0x2 << 62;
+    let hex = format!("{:032x}", bits);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+// splitmix64, a small fast generator for ids and sampling; not for secrets
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn seeded(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    // a different seed on every call, taken from std's randomly keyed hasher.
+    // inside `Stamp::run` the seeds follow from the stamp's instead
+    pub fn from_entropy() -> Rng {
+        if let Some((at, state)) = PINNED.get() {
+            let mut pinned = Rng(state);
+            let seed = pinned.next_u64();
+            PINNED.set(Some((at, pinned.0)));
+            return Rng(seed);
+        }
+        static CALLS: AtomicU64 = AtomicU64::new(0);
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(CALLS.fetch_add(1, atomic::Ordering::Relaxed));
+        hasher.write_u64(now_millis());
+        Rng(hasher.finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let z = self.0;
+        let z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in 0..bound, bound above 0
+    pub fn below(&mut self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
+
+fn type_rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use serde_json::Value;
+
+use crate::backend::StorageBackend;
+use crate::database::{ExecError, Row, SchemaError, Table, CREATED_AT_FIELD, UPDATED_AT_FIELD};
+use crate::expr::{EvalError, Expr};
+use crate::parser::{ColumnDefinition, CreateCommand, PrimaryKey};
+use crate::result::ColumnType;
+use crate::utils::{now_rfc3339, uuid_v4};
+
+// checks a table definition on its own, before anything about the database
+// matters: key columns exist once each and can't end up null, column names
+// aren't empty and defaults are JSON of their column's type or call a
+// function returning it. primary key columns
+// are unique and not null by definition, whatever their flags say
+pub fn validate_schema(create: &CreateCommand) -> Result<(), SchemaError> {
+    let CreateCommand::Table { primary_key, rows: columns, .. } = create else {
+        return Ok(());
+    };
+    let key_columns = primary_key.columns();
+    if key_columns.is_empty() {
+        return Err(SchemaError::NoPrimaryKey);
+    }
+    for (i, column) in key_columns.iter().enumerate() {
+        if !columns.contains_key(column) {
+            return Err(SchemaError::PrimaryKeyColumnNotFound { column: column.clone() });
+        }
+        if key_columns[..i].contains(column) {
+            return Err(SchemaError::DuplicatePrimaryKeyColumn { column: column.clone() });
+        }
+    }
+    if columns.keys().any(|name| name.trim().is_empty()) {
+        return Err(SchemaError::EmptyColumnName);
+    }
+    for (name, def) in columns {
+        let Some(default) = &def.default else {
+            continue;
+        };
+        if let Some(function) = default.as_str().and_then(default_function) {
+            match call_default(function) {
+                None => {
+                    return Err(SchemaError::UnknownDefaultFunction {
+                        column: name.clone(),
+                        function: format!("{}()", function),
+                    })
+                }
+                Some((returns, _)) if def.col_type != returns => {
+                    return Err(SchemaError::InvalidDefault {
+                        column: name.clone(),
+                        default: default.clone(),
+                        col_type: def.col_type,
+                    })
+                }
+                Some(_) => continue,
+            }
+        }
+        if !type_accepts(def.col_type, default) {
+            return Err(SchemaError::InvalidDefault {
+                column: name.clone(),
+                default: default.clone(),
+                col_type: def.col_type,
+            });
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_create_table(
+    table: &str,
+    primary_key: &PrimaryKey,
+    columns: &HashMap<String, ColumnDefinition>,
+) -> Result<(), ExecError> {
+    for (name, def) in columns {
+        if !def.collation.is_binary() && !def.col_type.is_text() {
+            return Err(ExecError::InvalidQuery(format!(
+                "column '{}' of type {} can't have a collation, only string and char columns can",
+                name, def.col_type
+            )));
+        }
+    }
+    for (name, def) in columns {
+        if let Some(expression) = &def.generated {
+            validate_generated(table, primary_key, columns, name, def, expression)?;
+        }
+    }
+    Ok(())
+}
+
+// a generated column is numeric and computed from numeric, non-generated columns
+fn validate_generated(
+    table: &str,
+    primary_key: &PrimaryKey,
+    columns: &HashMap<String, ColumnDefinition>,
+    name: &str,
+    def: &ColumnDefinition,
+    expression: &str,
+) -> Result<(), ExecError> {
+    let invalid = |reason: String| {
+        ExecError::InvalidQuery(format!("generated column '{}': {}", name, reason))
+    };
+    if !def.col_type.is_numeric() {
+        return Err(invalid(format!("type '{}' is not numeric", def.col_type)));
+    }
+    if primary_key.contains(name) || def.default.is_some() {
+        return Err(invalid("can't be part of the primary key or have a default".to_string()));
+    }
+    let expr = Expr::parse(expression).map_err(invalid)?;
+    for column in expr.columns() {
+        let source = columns.get(column).ok_or_else(|| ExecError::ColumnNotFound {
+            table: table.to_string(),
+            column: column.to_string(),
+        })?;
+        if source.generated.is_some() || !source.col_type.is_numeric() {
+            return Err(invalid(format!("column '{}' is generated or not numeric", column)));
+        }
+    }
+    Ok(())
+}
+
+// checks an insert against the table schema and fills in defaults
+pub fn validate_insert<B: StorageBackend>(table_name: &str, table: &Table<B>, mut row: Row) -> Result<Row, ExecError> {
+    if table.timestamps {
+        // kept when given, so imported rows keep their history
+        let now = now_rfc3339();
+        for column in [CREATED_AT_FIELD, UPDATED_AT_FIELD] {
+            if row.get(column).is_none_or(Value::is_null) {
+                row.insert(column.to_string(), Value::String(now.clone()));
+            }
+        }
+    }
+    for column in row.keys() {
+        match table.columns.get(column) {
+            None => {
+                return Err(ExecError::ColumnNotFound {
+                    table: table_name.to_string(),
+                    column: column.clone(),
+                })
+            }
+            Some(def) if def.generated.is_some() => return Err(generated_write(column)),
+            Some(_) => {}
+        }
+    }
+
+    for (name, def) in &table.columns {
+        if def.generated.is_some() {
+            continue;
+        }
+        let value = match row.remove(name) {
+            Some(value) if !value.is_null() => value,
+            _ => column_default(name, def)?,
+        };
+
+        if value.is_null() {
+            if def.not_null || table.primary_key.contains(name) {
+                return Err(ExecError::NotNull { column: name.clone() });
+            }
+        } else if !type_accepts(def.col_type, &value) {
+            return Err(ExecError::TypeMismatch {
+                column: name.clone(),
+                expected: def.col_type.to_string(),
+            });
+        }
+        row.insert(name.clone(), value);
+    }
+    fill_generated(table, &mut row)?;
+    Ok(row)
+}
+
+// computes every generated column of a complete row
+pub(crate) fn fill_generated<B: StorageBackend>(table: &Table<B>, row: &mut Row) -> Result<(), ExecError> {
+    for (name, def) in &table.columns {
+        let Some(expression) = &def.generated else {
+            continue;
+        };
+        let expr = Expr::parse(expression)
+            .map_err(|reason| ExecError::InvalidQuery(format!("generated column '{}': {}", name, reason)))?;
+        let value = expr.eval(row).map_err(|err| eval_error(name, "generated column", err))?;
+        // an int column only takes integral results
+        let value = match value {
+            Value::Null if def.not_null => return Err(ExecError::NotNull { column: name.clone() }),
+            Value::Null => Value::Null,
+            value => coerce_to_type(def.col_type, &value).ok_or_else(|| ExecError::TypeMismatch {
+                column: name.clone(),
+                expected: def.col_type.to_string(),
+            })?,
+        };
+        row.insert(name.clone(), value);
+    }
+    Ok(())
+}
+
+// the new value of a column in an update: a literal, or {"$expr": "price * 1.1"}
+// computed from the matched row's current values
+#[derive(Debug)]
+pub(crate) enum Assignment {
+    Value(Value),
+    Expr(Expr),
+}
+
+// checks the new column values of an update against the table schema
+pub fn validate_update<B: StorageBackend>(
+    table_name: &str,
+    table: &Table<B>,
+    updates: Row,
+) -> Result<Vec<(String, Assignment)>, ExecError> {
+    let mut assignments = Vec::with_capacity(updates.len());
+    for (column, value) in updates {
+        let def = table.columns.get(&column).ok_or_else(|| ExecError::ColumnNotFound {
+            table: table_name.to_string(),
+            column: column.clone(),
+        })?;
+        if def.generated.is_some() {
+            return Err(generated_write(&column));
+        }
+        if let Some(expression) = expression_of(&value) {
+            let invalid = |reason: String| ExecError::InvalidQuery(format!("$expr for column '{}': {}", column, reason));
+            if !def.col_type.is_numeric() {
+                return Err(invalid(format!("type '{}' is not numeric", def.col_type)));
+            }
+            let expr = Expr::parse(expression).map_err(invalid)?;
+            for source in expr.columns() {
+                if !table.columns.contains_key(source) {
+                    return Err(ExecError::ColumnNotFound {
+                        table: table_name.to_string(),
+                        column: source.to_string(),
+                    });
+                }
+            }
+            assignments.push((column, Assignment::Expr(expr)));
+            continue;
+        }
+        if value.is_null() {
+            if def.not_null || table.primary_key.contains(&column) {
+                return Err(ExecError::NotNull { column: column.clone() });
+            }
+        } else if !type_accepts(def.col_type, &value) {
+            return Err(ExecError::TypeMismatch {
+                column: column.clone(),
+                expected: def.col_type.to_string(),
+            });
+        }
+        assignments.push((column, Assignment::Value(value)));
+    }
+    Ok(assignments)
+}
+
+// the updated row; expressions all see the values from before the update
+pub(crate) fn apply_update<B: StorageBackend>(table: &Table<B>, old: &Row, assignments: &[(String, Assignment)]) -> Result<Row, ExecError> {
+    let mut row = old.clone();
+    for (column, assignment) in assignments {
+        let value = match assignment {
+            Assignment::Value(value) => value.clone(),
+            Assignment::Expr(expr) => {
+                let value = expr.eval(old).map_err(|err| eval_error(column, "$expr for column", err))?;
+                let def = &table.columns[column];
+                match value {
+                    Value::Null if def.not_null || table.primary_key.contains(column) => {
+                        return Err(ExecError::NotNull { column: column.clone() })
+                    }
+                    Value::Null => Value::Null,
+                    value => coerce_to_type(def.col_type, &value).ok_or_else(|| ExecError::TypeMismatch {
+                        column: column.clone(),
+                        expected: def.col_type.to_string(),
+                    })?,
+                }
+            }
+        };
+        row.insert(column.clone(), value);
+    }
+    if table.timestamps && !assignments.iter().any(|(column, _)| column == UPDATED_AT_FIELD) {
+        row.insert(UPDATED_AT_FIELD.to_string(), Value::String(now_rfc3339()));
+    }
+    fill_generated(table, &mut row)?;
+    Ok(row)
+}
+
+// a non-finite result would break sorting and comparisons, so it is never stored
+fn eval_error(column: &str, what: &str, err: EvalError) -> ExecError {
+    match err {
+        EvalError::Invalid(reason) => ExecError::InvalidQuery(format!("{} '{}': {}", what, column, reason)),
+        EvalError::NotFinite(value) => ExecError::InvalidValue {
+            column: column.to_string(),
+            reason: format!("{} is not a finite number", value),
+        },
+    }
+}
+
+fn expression_of(value: &Value) -> Option<&str> {
+    match value {
+        Value::Object(ops) if ops.len() == 1 => ops.get("$expr")?.as_str(),
+        _ => None,
+    }
+}
+
+fn generated_write(column: &str) -> ExecError {
+    ExecError::InvalidQuery(format!("column '{}' is generated and can't be written", column))
+}
+
+pub fn type_accepts(col_type: ColumnType, value: &Value) -> bool {
+    match col_type {
+        ColumnType::Int => value.is_i64() || value.is_u64(),
+        ColumnType::Float => value.is_number(),
+        ColumnType::String | ColumnType::Char => value.is_string(),
+        ColumnType::Bool => value.is_boolean(),
+        ColumnType::Datetime => value.as_str().is_some_and(is_rfc3339),
+        ColumnType::Uuid => value.as_str().is_some_and(is_uuid),
+        // any JSON value: arrays, objects and scalars alike
+        ColumnType::Json => true,
+    }
+}
+
+// converts a value to the column type where that is lossless, e.g. "10" for an int column
+pub fn coerce_to_type(col_type: ColumnType, value: &Value) -> Option<Value> {
+    if type_accepts(col_type, value) {
+        return Some(value.clone());
+    }
+    match (col_type, value) {
+        (ColumnType::Int, Value::String(s)) => s.trim().parse::<i64>().ok().map(Value::from),
+        (ColumnType::Int, Value::Number(n)) => n
+            .as_f64()
+            .filter(|f| f.fract() == 0.0 && f.abs() < i64::MAX as f64)
+            .map(|f| Value::from(f as i64)),
+        (ColumnType::Float, Value::String(s)) => s
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number),
+        (ColumnType::String | ColumnType::Char, Value::Number(n)) => Some(Value::String(n.to_string())),
+        (ColumnType::Bool, Value::String(s)) => s.parse::<bool>().ok().map(Value::Bool),
+        _ => None,
+    }
+}
+
+// sets the columns `row` leaves out or null whose default calls a function,
+// such as "uuid()", to a value from a call
+pub(crate) fn fill_function_defaults<B: StorageBackend>(table: &Table<B>, row: &mut Row) {
+    for (column, def) in &table.columns {
+        if row.get(column).is_some_and(|value| !value.is_null()) || def.generated.is_some() {
+            continue;
+        }
+        let function = def.default.as_ref().and_then(Value::as_str).and_then(default_function);
+        if let Some((_, call)) = function.and_then(call_default) {
+            row.insert(column.clone(), Value::String(call()));
+        }
+    }
+}
+
+// the value a row gets for a column it doesn't set: its default, else the
+// type's zero value with `implicit_default`, else null
+pub(crate) fn column_default(column: &str, def: &ColumnDefinition) -> Result<Value, ExecError> {
+    match &def.default {
+        Some(default) => parse_default(column, def, default),
+        None if def.implicit_default => Ok(zero_value(def.col_type)),
+        None => Ok(Value::Null),
+    }
+}
+
+// datetime, uuid and json have no zero value and stay null
+fn zero_value(col_type: ColumnType) -> Value {
+    match col_type {
+        ColumnType::Int => Value::from(0),
+        ColumnType::Float => Value::from(0.0),
+        ColumnType::String | ColumnType::Char => Value::String(String::new()),
+        ColumnType::Bool => Value::Bool(false),
+        ColumnType::Datetime | ColumnType::Uuid | ColumnType::Json => Value::Null,
+    }
+}
+
+// a function default is called anew for every row. schemas saved while
+// defaults were strings, like "1" for an int column, still convert
+fn parse_default(column: &str, def: &ColumnDefinition, default: &Value) -> Result<Value, ExecError> {
+    let value = match default.as_str().and_then(default_function).and_then(call_default) {
+        Some((_, call)) => Value::String(call()),
+        None => default.clone(),
+    };
+    coerce_to_type(def.col_type, &value).ok_or_else(|| ExecError::TypeMismatch {
+        column: column.to_string(),
+        expected: def.col_type.to_string(),
+    })
+}
+
+// the name of the function a default like "now()" calls
+fn default_function(default: &str) -> Option<&str> {
+    let name = default.trim().strip_suffix("()")?;
+    let is_name = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    is_name.then_some(name)
+}
+
+// the column type a default function returns and the function itself
+type DefaultFunction = (ColumnType, fn() -> String);
+
+fn call_default(function: &str) -> Option<DefaultFunction> {
+    match function.to_ascii_lowercase().as_str() {
+        "now" => Some((ColumnType::Datetime, now_rfc3339)),
+        "uuid" => Some((ColumnType::Uuid, uuid_v4)),
+        _ => None,
+    }
+}
+
+// an RFC 3339 timestamp such as "2024-05-01T12:30:00Z" or
+// "2024-05-01T14:30:00.5+02:00"; only the shape is checked, not the ranges
+fn is_rfc3339(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    let digits = |from: usize, to: usize| bytes.get(from..to).is_some_and(|part| part.iter().all(u8::is_ascii_digit));
+    let at = |i: usize, expected: &[u8]| bytes.get(i).is_some_and(|byte| expected.contains(byte));
+    let shaped = digits(0, 4)
+        && at(4, b"-")
+        && digits(5, 7)
+        && at(7, b"-")
+        && digits(8, 10)
+        && at(10, b"Tt ")
+        && digits(11, 13)
+        && at(13, b":")
+        && digits(14, 16)
+        && at(16, b":")
+        && digits(17, 19);
+    if !shaped {
+        return false;
+    }
+    let mut rest = &text[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return false;
+        }
+        rest = &fraction[len..];
+    }
+    match rest.as_bytes() {
+        [b'Z' | b'z'] => true,
+        [b'+' | b'-', h1, h2, b':', m1, m2] => [h1, h2, m1, m2].iter().all(|d| d.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+// a hyphenated UUID such as "123e4567-e89b-12d3-a456-426614174000"
+fn is_uuid(text: &str) -> bool {
+    text.len() == 36
+        && text.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
@@ -0,0 +1,178 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use serde_json::Number;
+
+use crate::backend::StorageBackend;
+use crate::database::{ExecError, Row, Table};
+use crate::result::ColumnType;
+use crate::utils::compare_values;
+
+// a value read through its column's type, so an int column yields ints and a
+// float column floats even where the JSON looks the same ("22" vs "22.0").
+// rows are stored and sent as JSON; this is the engine's view of them
+#[derive(Debug, Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    // string, char, datetime and uuid columns
+    Text(String),
+    // json columns, and values whose column type isn't known
+    Json(serde_json::Value),
+}
+
+// a row of typed values by column name
+pub type TypedRow = HashMap<String, Value>;
+
+impl Value {
+    // the value of a column of type `col_type`, None when the JSON doesn't fit
+    // the type. ints are accepted by float columns and become floats
+    pub fn from_json(col_type: ColumnType, json: &serde_json::Value) -> Option<Value> {
+        if json.is_null() {
+            return Some(Value::Null);
+        }
+        let typed = match col_type {
+            ColumnType::Int => Value::Int(json.as_i64()?),
+            ColumnType::Float => Value::Float(json.as_f64()?),
+            ColumnType::Bool => Value::Bool(json.as_bool()?),
+            ColumnType::String | ColumnType::Char | ColumnType::Datetime | ColumnType::Uuid => {
+                Value::Text(json.as_str()?.to_string())
+            }
+            ColumnType::Json => Value::Json(json.clone()),
+        };
+        Some(typed)
+    }
+
+    // the value of JSON without a schema, typed by its shape
+    pub fn infer(json: &serde_json::Value) -> Value {
+        match json {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(*b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => Value::Int(i),
+                None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => Value::Text(s.clone()),
+            json => Value::Json(json.clone()),
+        }
+    }
+
+    // the value of a column of type `col_type` that may not hold it, e.g. a
+    // filter operand: typed by its shape when there is no type, the column is
+    // json or the JSON doesn't fit, so 1.5 compared with an int column stays 1.5
+    pub fn of(col_type: Option<ColumnType>, json: &serde_json::Value) -> Value {
+        match col_type {
+            Some(ColumnType::Json) | None => Value::infer(json),
+            Some(col_type) => Value::from_json(col_type, json).unwrap_or_else(|| Value::infer(json)),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    // the value as JSON, how it is stored and sent. a non-finite float has no
+    // JSON form and becomes null
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(b) => serde_json::Value::Bool(*b),
+            Value::Int(i) => serde_json::Value::from(*i),
+            Value::Float(f) => Number::from_f64(*f).map_or(serde_json::Value::Null, serde_json::Value::Number),
+            Value::Text(s) => serde_json::Value::String(s.clone()),
+            Value::Json(json) => json.clone(),
+        }
+    }
+
+    // the order of two bools, numbers or texts, ints and floats counting as
+    // one kind. None for values of different kinds, nulls and JSON
+    pub fn compare_same_kind(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Bool(_), Value::Bool(_))
+            | (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_))
+            | (Value::Text(_), Value::Text(_)) => Some(self.cmp(other)),
+            _ => None,
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Int(_) | Value::Float(_) => 2,
+            Value::Text(_) => 3,
+            Value::Json(_) => 4,
+        }
+    }
+}
+
+// null first, then bools, numbers, text and JSON. ints and floats compare by
+// value with each other, floats by `total_cmp` so NaN has a place too
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+            (Value::Int(x), Value::Int(y)) => x.cmp(y),
+            (Value::Int(x), Value::Float(y)) => (*x as f64).total_cmp(y),
+            (Value::Float(x), Value::Int(y)) => x.total_cmp(&(*y as f64)),
+            (Value::Float(x), Value::Float(y)) => x.total_cmp(y),
+            (Value::Text(x), Value::Text(y)) => x.cmp(y),
+            (Value::Json(x), Value::Json(y)) => compare_values(x, y),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// equal when `cmp` says so, so 1 and 1.0 are equal and NaN equals itself
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl From<Value> for serde_json::Value {
+    fn from(value: Value) -> Self {
+        value.to_json()
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_json())
+    }
+}
+
+impl<B: StorageBackend> Table<B> {
+    // a stored row typed by the table's schema. a value that doesn't fit its
+    // column's type is a TypeMismatch, a column the schema lacks ColumnNotFound
+    pub fn typed_row(&self, table_name: &str, row: &Row) -> Result<TypedRow, ExecError> {
+        row.iter()
+            .map(|(column, json)| {
+                let def = self.columns.get(column).ok_or_else(|| ExecError::ColumnNotFound {
+                    table: table_name.to_string(),
+                    column: column.clone(),
+                })?;
+                let value = Value::from_json(def.col_type, json).ok_or_else(|| ExecError::TypeMismatch {
+                    column: column.clone(),
+                    expected: def.col_type.to_string(),
+                })?;
+                Ok((column.clone(), value))
+            })
+            .collect()
+    }
+}
+
+// the JSON form of a typed row, as it would be stored
+pub fn json_row(row: &TypedRow) -> Row {
+    row.iter().map(|(column, value)| (column.clone(), value.to_json())).collect()
+}
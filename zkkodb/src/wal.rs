@@ -0,0 +1,251 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::backend::StorageBackend;
+use crate::codec::Codec;
+use crate::database::{Database, ExecError};
+use crate::parser::Command;
+use crate::storage::{self, StorageError};
+use crate::utils::{checksum, Stamp};
+use crate::validator;
+
+const WAL_FILE: &str = "wal.log";
+
+// append-only log of mutating commands, one `<checksum> <record json>` line each.
+// a record is synced to disk before its command is applied and taken back out
+// when the command fails, so every record replays. with a key the json is
+// encrypted and written in hex
+#[derive(Debug)]
+pub struct Wal {
+    dir: PathBuf,
+    file: File,
+    // the length of the file before the last record
+    last: u64,
+    codec: Codec,
+}
+
+// a logged command and the stamp it ran with
+#[derive(Debug, Clone, Deserialize)]
+pub struct Record {
+    #[serde(flatten)]
+    pub stamp: Stamp,
+    pub command: Command,
+}
+
+impl Wal {
+    pub(crate) fn open(dir: &Path, codec: Codec) -> io::Result<Wal> {
+        let path = dir.join(WAL_FILE);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        // cut a torn last record off so records appended after it replay
+        if let Some(start) = torn_tail(&fs::read(&path)?) {
+            file.set_len(start as u64)?;
+            file.sync_data()?;
+        }
+        let last = file.metadata()?.len();
+        Ok(Wal {
+            dir: dir.to_path_buf(),
+            file,
+            last,
+            codec,
+        })
+    }
+
+    // encrypts the records appended from now on like `codec`
+    pub(crate) fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn append(&mut self, stamp: Stamp, cmd: &Command) -> io::Result<()> {
+        let mut payload = serde_json::to_string(&json!({ "at": stamp.at, "seed": stamp.seed, "command": cmd }))?;
+        if self.codec.is_encrypted() {
+            payload = to_hex(&self.codec.encode(WAL_FILE, payload.into_bytes())?);
+        }
+        self.last = self.file.metadata()?.len();
+        writeln!(self.file, "{:016x} {}", checksum(payload.as_bytes()), payload)?;
+        self.file.sync_data()
+    }
+
+    // removes the record `append` wrote last
+    pub fn discard_last(&mut self) -> io::Result<()> {
+        self.file.set_len(self.last)?;
+        self.file.sync_data()
+    }
+
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.last = 0;
+        self.file.sync_all()
+    }
+}
+
+// the mutating commands a database ran successfully, in order, in memory. the
+// same commands the WAL records, but only once they applied, so replaying them
+// into an empty database rebuilds it
+#[derive(Debug, Default)]
+pub struct CommandLog {
+    commands: Vec<Command>,
+}
+
+impl CommandLog {
+    pub(crate) fn push(&mut self, cmd: Command) {
+        self.commands.push(cmd);
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    // the commands from position `since` on, the first one being 0
+    pub fn since(&self, since: usize) -> &[Command] {
+        self.commands.get(since..).unwrap_or_default()
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    // starts recording mutating commands; enabling twice keeps the log
+    pub fn enable_command_log(&mut self) {
+        self.command_log.get_or_insert_with(CommandLog::default);
+    }
+
+    pub fn command_log(&self) -> Option<&CommandLog> {
+        self.command_log.as_ref()
+    }
+
+}
+
+impl Database {
+    // a new database built by running `log` from empty, recording it again.
+    // stops at the first command that fails, which a log exported from a
+    // database only does when a command depended on the clock (ttl)
+    pub fn replay(log: impl IntoIterator<Item = Command>) -> Result<Database, ExecError> {
+        let mut db = Database::new();
+        db.enable_command_log();
+        for cmd in log {
+            db.execute(cmd)?;
+        }
+        Ok(db)
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    // the recorded commands from position `since` on, as JSON
+    pub(crate) fn export_log(&self, since: usize) -> Result<Vec<serde_json::Value>, ExecError> {
+        let log = self.command_log.as_ref().ok_or_else(|| {
+            ExecError::InvalidQuery("the command log is not enabled, see `enable_command_log`".to_string())
+        })?;
+        log.since(since)
+            .iter()
+            .map(|cmd| serde_json::to_value(cmd).map_err(|err| ExecError::Io(err.to_string())))
+            .collect()
+    }
+
+    // `cmd` with the `uuid()` and `now()` defaults of the rows it inserts
+    // filled in and the backup it restores read in, so the logs hold the
+    // values the rows got and replay without the backup file
+    pub(crate) fn resolve_defaults(&self, cmd: Command) -> Result<Command, ExecError> {
+        Ok(match cmd {
+            Command::Insert(mut insert) => {
+                if let Some(table) = self.tables.get(&insert.table) {
+                    validator::fill_function_defaults(table, &mut insert.rows);
+                }
+                Command::Insert(insert)
+            }
+            Command::Restore { path, archive: None } => {
+                let archive = self.read_backup(Path::new(&path)).map_err(|err| ExecError::Io(err.to_string()))?;
+                Command::Restore { path, archive: Some(archive) }
+            }
+            Command::Batch { commands, atomic } => Command::Batch {
+                commands: commands.into_iter().map(|cmd| self.resolve_defaults(cmd)).collect::<Result<_, _>>()?,
+                atomic,
+            },
+            cmd => cmd,
+        })
+    }
+
+    pub(crate) fn record(&mut self, cmd: Command) {
+        if let Some(log) = &mut self.command_log {
+            log.push(cmd);
+        }
+    }
+}
+
+// records in `dir`'s log, in order, decrypted like `codec`. only the last
+// record may be torn (from a crash mid-write) and is left out; one before it
+// that fails its checksum or doesn't parse makes the log corrupt, and one that
+// doesn't decrypt fails like a snapshot file would
+pub(crate) fn read_log(dir: &Path, codec: &Codec) -> Result<Vec<Record>, StorageError> {
+    let path = dir.join(WAL_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read(&path)?;
+    let whole = &content[..torn_tail(&content).unwrap_or(content.len())];
+    let mut records = Vec::new();
+    let Some(lines) = whole.strip_suffix(b"\n") else {
+        return Ok(records);
+    };
+    for (i, line) in lines.split(|&byte| byte == b'\n').enumerate() {
+        let corrupt = |reason: &dyn std::fmt::Display| storage::corrupt(&path, format!("record {}: {}", i + 1, reason));
+        let payload = checked_payload(line).ok_or_else(|| corrupt(&"fails its checksum"))?;
+        let json = match codec.is_encrypted() {
+            true => {
+                let sealed = from_hex(payload).ok_or_else(|| storage::corrupt(&path, "record is not encrypted"))?;
+                storage::decode(&path, WAL_FILE, codec, sealed)?
+            }
+            false => payload.as_bytes().to_vec(),
+        };
+        records.push(serde_json::from_slice(&json).map_err(|err| corrupt(&err))?);
+    }
+    Ok(records)
+}
+
+// where a torn last record starts: one cut short before its newline, or one
+// failing its checksum, as a crash mid-write leaves them
+fn torn_tail(content: &[u8]) -> Option<usize> {
+    let terminated = content.strip_suffix(b"\n");
+    let body = terminated.unwrap_or(content);
+    let start = body.iter().rposition(|&byte| byte == b'\n').map_or(0, |newline| newline + 1);
+    let torn = terminated.is_none() || checked_payload(&body[start..]).is_none();
+    (start < content.len() && torn).then_some(start)
+}
+
+// empties the log in `dir`, if there is one
+pub(crate) fn clear_log(dir: &Path) -> io::Result<()> {
+    let path = dir.join(WAL_FILE);
+    if !path.exists() {
+        return Ok(());
+    }
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(0)?;
+    file.sync_all()
+}
+
+fn checked_payload(line: &[u8]) -> Option<&str> {
+    let line = std::str::from_utf8(line).ok()?;
+    let (sum, payload) = line.split_once(' ')?;
+    (u64::from_str_radix(sum, 16).ok()? == checksum(payload.as_bytes())).then_some(payload)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Serialize;
+
+use crate::parser::{parse_command, Command};
+
+// encoding of commands and results on the wire. the command model is the same
+// for every format, only the codec differs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    Decode(String),
+    Encode(String),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::Decode(reason) => write!(f, "could not decode command: {}", reason),
+            WireError::Encode(reason) => write!(f, "could not encode result: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+impl WireFormat {
+    // the format for a content type such as "application/msgpack", None if unknown
+    pub fn from_content_type(content_type: &str) -> Option<WireFormat> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        match mime.to_ascii_lowercase().as_str() {
+            "application/json" => Some(WireFormat::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(WireFormat::MessagePack)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            WireFormat::Json => "application/json",
+            WireFormat::MessagePack => "application/msgpack",
+        }
+    }
+
+    pub fn decode_command(&self, bytes: &[u8]) -> Result<Command, WireError> {
+        let decoded = match self {
+            WireFormat::Json => std::str::from_utf8(bytes)
+                .map_err(|err| err.to_string())
+                .and_then(|input| parse_command(input).map_err(|err| err.to_string())),
+            // json values would quietly turn a NaN or infinite float into null
+            WireFormat::MessagePack => rmp_serde::from_slice::<Finite>(bytes)
+                .and_then(|_| rmp_serde::from_slice(bytes))
+                .map_err(|err| err.to_string()),
+        };
+        decoded.map_err(WireError::Decode)
+    }
+
+    // any value, e.g. the body of an http request, checked like a command
+    pub fn decode_value(&self, bytes: &[u8]) -> Result<serde_json::Value, WireError> {
+        let decoded = match self {
+            WireFormat::Json => serde_json::from_slice(bytes).map_err(|err| err.to_string()),
+            WireFormat::MessagePack => rmp_serde::from_slice::<Finite>(bytes)
+                .and_then(|_| rmp_serde::from_slice(bytes))
+                .map_err(|err| err.to_string()),
+        };
+        decoded.map_err(WireError::Decode)
+    }
+
+    // structs are encoded as maps with named fields so every format carries the
+    // same shape, e.g. an `Output` or an error message
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, WireError> {
+        let encoded = match self {
+            WireFormat::Json => serde_json::to_vec(value).map_err(|err| err.to_string()),
+            WireFormat::MessagePack => rmp_serde::to_vec_named(value).map_err(|err| err.to_string()),
+        };
+        encoded.map_err(WireError::Encode)
+    }
+}
+
+// any value without a NaN or infinite float anywhere in it
+struct Finite;
+
+impl<'de> Deserialize<'de> for Finite {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Finite, D::Error> {
+        deserializer.deserialize_any(FiniteVisitor)
+    }
+}
+
+struct FiniteVisitor;
+
+impl<'de> Visitor<'de> for FiniteVisitor {
+    type Value = Finite;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("any value with finite numbers")
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Finite, E> {
+        match value.is_finite() {
+            true => Ok(Finite),
+            false => Err(E::custom(format!("{} is not a finite number", value))),
+        }
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<Finite, E> {
+        Ok(Finite)
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<Finite, E> {
+        Ok(Finite)
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<Finite, E> {
+        Ok(Finite)
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<Finite, E> {
+        Ok(Finite)
+    }
+
+    fn visit_bytes<E: de::Error>(self, _: &[u8]) -> Result<Finite, E> {
+        Ok(Finite)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Finite, E> {
+        Ok(Finite)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Finite, E> {
+        Ok(Finite)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Finite, D::Error> {
+        Finite::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Finite, A::Error> {
+        while seq.next_element::<Finite>()?.is_some() {}
+        Ok(Finite)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Finite, A::Error> {
+        while map.next_entry::<Finite, Finite>()?.is_some() {}
+        Ok(Finite)
+    }
+}
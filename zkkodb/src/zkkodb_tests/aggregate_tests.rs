@@ -0,0 +1,191 @@
+use serde_json::json;
+
+use super::{rows, run};
+use crate::database::*;
+
+fn grocery() -> Database {
+    grocery_stored_as("rows")
+}
+
+fn grocery_stored_as(storage: &str) -> Database {
+    let mut db = Database::new();
+    let create = r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "products",
+      "primary_key": "id",
+      "storage": "STORAGE",
+      "rows": {
+        "id": { "type": "int", "not_null": true },
+        "category": { "type": "string" },
+        "price": { "type": "float" }
+      }
+    }
+    "#;
+    run(&mut db, &create.replace("STORAGE", storage)).unwrap();
+
+    for input in [
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "category": "fruit", "price": 2.5 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "category": "drinks", "price": 4.0 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 3, "category": "fruit", "price": 1.25 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 4, "category": "drinks", "price": 3.5 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 5, "category": "fruit" } }"#,
+    ] {
+        run(&mut db, input).unwrap();
+    }
+    db
+}
+
+#[test]
+fn test_sum_grouped_by_category() {
+    let mut db = grocery();
+    let input = r#"
+    {
+      "command": "read",
+      "table": "products",
+      "group_by": ["category"],
+      "aggregates": [
+        { "function": "sum", "column": "price" },
+        { "function": "count" }
+      ]
+    }
+    "#;
+
+    let result = rows(run(&mut db, input).unwrap());
+    assert_eq!(result.len(), 2);
+
+    // groups are ordered by their grouping values
+    assert_eq!(result[0]["category"], json!("drinks"));
+    assert_eq!(result[0]["sum(price)"], json!(7.5));
+    assert_eq!(result[0]["count(*)"], json!(2));
+
+    assert_eq!(result[1]["category"], json!("fruit"));
+    assert_eq!(result[1]["sum(price)"], json!(3.75));
+    assert_eq!(result[1]["count(*)"], json!(3));
+}
+
+#[test]
+fn test_aggregates_without_group_by() {
+    let mut db = grocery();
+    let input = r#"
+    {
+      "command": "read",
+      "table": "products",
+      "filter": { "category": "fruit" },
+      "aggregates": [
+        { "function": "count", "column": "price" },
+        { "function": "avg", "column": "price", "as": "average" },
+        { "function": "min", "column": "price" },
+        { "function": "max", "column": "price" }
+      ]
+    }
+    "#;
+
+    let result = rows(run(&mut db, input).unwrap());
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0]["count(price)"], json!(2));
+    assert_eq!(result[0]["average"], json!(1.875));
+    assert_eq!(result[0]["min(price)"], json!(1.25));
+    assert_eq!(result[0]["max(price)"], json!(2.5));
+}
+
+#[test]
+fn test_aggregate_requires_column() {
+    let mut db = grocery();
+    let input = r#"
+    {
+      "command": "read",
+      "table": "products",
+      "aggregates": [{ "function": "sum" }]
+    }
+    "#;
+
+    assert!(matches!(run(&mut db, input), Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_columnar_storage_matches_row_storage() {
+    let mut by_rows = grocery();
+    let mut by_columns = grocery_stored_as("columnar");
+    assert_eq!(by_columns.table("products").unwrap().storage(), crate::parser::StorageLayout::Columnar);
+
+    let changes = [
+        r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 2" }"#,
+        r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 5", "rows": { "id": 6, "price": 0.75 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "category": "bakery", "price": 3.0 } }"#,
+    ];
+    let reads = [
+        r#"{ "command": "read", "table": "products" }"#,
+        r#"{ "command": "read", "table": "products", "filter": { "price": { "$gt": 1 } }, "limit": 2 }"#,
+        r#"{ "command": "read", "table": "products", "group_by": ["category"], "aggregates": [{ "function": "avg", "column": "price" }, { "function": "count" }] }"#,
+        r#"{ "command": "read", "table": "products", "filter": { "category": "fruit" }, "aggregates": [{ "function": "max", "column": "price" }] }"#,
+    ];
+    for change in changes {
+        assert_eq!(run(&mut by_rows, change), run(&mut by_columns, change));
+        for read in reads {
+            let expected = rows(run(&mut by_rows, read).unwrap());
+            assert_eq!(rows(run(&mut by_columns, read).unwrap()), expected);
+        }
+    }
+    assert_eq!(by_columns.table("products").unwrap().len(), 5);
+}
+
+#[test]
+fn test_distinct_categories() {
+    let mut db = grocery();
+    let input = r#"{ "command": "read", "table": "products", "columns": ["category"], "distinct": true }"#;
+    let result = rows(run(&mut db, input).unwrap());
+    let categories: Vec<_> = result.iter().map(|row| row["category"].clone()).collect();
+    // first occurrence order follows the primary key
+    assert_eq!(categories, vec![json!("fruit"), json!("drinks")]);
+    assert_eq!(result[0].len(), 1);
+
+    // distinct compares whole projected rows
+    let input = r#"{ "command": "read", "table": "products", "columns": ["category", "price"], "distinct": true }"#;
+    assert_eq!(rows(run(&mut db, input).unwrap()).len(), 5);
+
+    let input = r#"{ "command": "read", "table": "products", "columns": ["colour"] }"#;
+    assert!(matches!(run(&mut db, input), Err(ExecError::ColumnNotFound { .. })));
+}
+
+#[test]
+fn test_having_filters_groups() {
+    let mut db = grocery();
+    let read = |db: &mut Database, having: &str| {
+        let input = format!(
+            r#"{{ "command": "read", "table": "products", "group_by": ["category"], "aggregates": [{{ "function": "count" }}, {{ "function": "sum", "column": "price", "as": "total" }}], "having": {} }}"#,
+            having
+        );
+        run(db, &input).map(|output| rows(output).iter().map(|row| row["category"].clone()).collect::<Vec<_>>())
+    };
+
+    assert_eq!(read(&mut db, r#"{ "count(*)": { "$gt": 2 } }"#), Ok(vec![json!("fruit")]));
+    assert_eq!(read(&mut db, r#"{ "total": { "$gt": 5 } }"#), Ok(vec![json!("drinks")]));
+    let either = r#"{ "$or": [{ "category": "fruit" }, { "count(*)": 5 }] }"#;
+    assert_eq!(read(&mut db, either), Ok(vec![json!("fruit")]));
+    assert_eq!(read(&mut db, r#"{ "total": { "$lt": 0 } }"#), Ok(vec![]));
+
+    let unknown = read(&mut db, r#"{ "price": { "$gt": 1 } }"#);
+    assert!(matches!(unknown, Err(ExecError::ColumnNotFound { column, .. }) if column == "price"));
+    let ungrouped = run(&mut db, r#"{ "command": "read", "table": "products", "having": { "id": 1 } }"#);
+    assert!(matches!(ungrouped, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_aggregate_command() {
+    let mut db = grocery();
+    let aggregate = r#"{ "command": "aggregate", "table": "products", "filter": { "category": "fruit" },
+        "aggregates": [{ "function": "count" }, { "function": "sum", "column": "price" }, { "function": "max", "column": "price", "as": "top" }] }"#;
+    let result = rows(run(&mut db, aggregate).unwrap());
+    assert_eq!(result.len(), 1);
+    assert_eq!((&result[0]["count(*)"], &result[0]["sum(price)"], &result[0]["top"]), (&json!(3), &json!(3.75), &json!(2.5)));
+
+    let grouped = r#"{ "command": "aggregate", "table": "products", "group_by": ["category"],
+        "aggregates": [{ "function": "avg", "column": "price" }], "having": { "avg(price)": { "$gt": 2 } } }"#;
+    let read = r#"{ "command": "read", "table": "products", "group_by": ["category"],
+        "aggregates": [{ "function": "avg", "column": "price" }], "having": { "avg(price)": { "$gt": 2 } } }"#;
+    assert_eq!(db.query(serde_json::from_str(grouped).unwrap()), run(&mut db, read));
+    let none = run(&mut db, r#"{ "command": "aggregate", "table": "products", "aggregates": [] }"#);
+    assert!(matches!(none, Err(ExecError::InvalidQuery(_))));
+}
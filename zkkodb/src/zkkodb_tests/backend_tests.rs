@@ -0,0 +1,86 @@
+use super::{run, VecBackend};
+use crate::database::*;
+use crate::parser::Command;
+use crate::session::Session;
+
+const SCRIPT: &[&str] = &[
+    r#"{ "command": "create", "type": "table", "table": "products", "primary_key": "id", "rows": {
+        "id": { "type": "int", "not_null": true },
+        "name": { "type": "string", "unique": true },
+        "category": { "type": "string" },
+        "price": { "type": "float" }
+    } }"#,
+    r#"{ "command": "create", "type": "table", "table": "orders", "primary_key": ["product_id", "line"], "rows": {
+        "product_id": { "type": "int", "references": { "table": "products", "column": "id" } },
+        "line": { "type": "int" },
+        "quantity": { "type": "int", "default": 1 }
+    } }"#,
+    r#"{ "command": "insert", "table": "products", "rows": { "id": 3, "name": "Mango", "category": "fruit", "price": 1.75 } }"#,
+    r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Coconut Water", "category": "drinks", "price": 2.5 } }"#,
+    r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Banana", "category": "fruit", "price": 0.5 } }"#,
+    r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Again" } }"#,
+    r#"{ "command": "insert", "table": "products", "rows": { "id": 4, "name": "Banana" } }"#,
+    r#"{ "command": "insert", "table": "orders", "rows": { "product_id": 2, "line": 1, "quantity": 6 } }"#,
+    r#"{ "command": "insert", "table": "orders", "rows": { "product_id": 1, "line": 2 } }"#,
+    r#"{ "command": "insert", "table": "orders", "rows": { "product_id": 2, "line": 0 } }"#,
+    r#"{ "command": "insert", "table": "orders", "rows": { "product_id": 99, "line": 0 } }"#,
+    r#"{ "command": "create_index", "table": "products", "name": "by_category", "column": "category" }"#,
+    r#"{ "command": "read", "table": "products" }"#,
+    r#"{ "command": "read", "table": "orders" }"#,
+    r#"{ "command": "get", "table": "products", "key": 3 }"#,
+    r#"{ "command": "read", "table": "products", "filter": { "category": "fruit" } }"#,
+    r#"{ "command": "read", "table": "products", "filter": { "price": { "$gt": 1 } }, "columns": ["name"] }"#,
+    r#"{ "command": "read", "table": "products", "limit": 2, "paginate": true }"#,
+    r#"{ "command": "read", "table": "products", "group_by": ["category"], "aggregates": [{ "function": "sum", "column": "price" }, { "function": "count" }] }"#,
+    r#"{ "command": "read", "table": "orders", "join": { "table": "products", "on": { "left": "product_id", "right": "id" } } }"#,
+    r#"{ "command": "explain", "query": { "table": "products", "filter": { "category": "fruit" } } }"#,
+    r#"{ "command": "update", "type": "content", "table": "products", "filter": "category = 'fruit'", "rows": { "price": { "$expr": "price * 2" } } }"#,
+    r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "rows": { "name": "Mango" } }"#,
+    r#"{ "command": "update", "type": "rows", "table": "products", "add": { "stock": { "type": "int", "default": 5 } } }"#,
+    r#"{ "command": "copy_table", "from": "products", "to": "archive", "include_data": true }"#,
+    r#"{ "command": "delete", "type": "content", "table": "archive", "filter": "id = 3" }"#,
+    r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 2" }"#,
+    r#"{ "command": "delete", "type": "content", "table": "orders", "filter": "product_id = 2", "limit": 1 }"#,
+    r#"{ "command": "read", "table": "products" }"#,
+    r#"{ "command": "read", "table": "archive" }"#,
+    r#"{ "command": "read", "table": "orders" }"#,
+    r#"{ "command": "stats" }"#,
+];
+
+#[test]
+fn test_engine_runs_the_same_on_another_backend() {
+    let mut builtin = Database::new();
+    let mut custom = Database::with_backend(VecBackend::default());
+    for input in SCRIPT {
+        assert_eq!(run(&mut custom, input), run(&mut builtin, input), "{}", input);
+    }
+    let page = r#"{ "command": "read", "table": "products", "limit": 1, "paginate": true }"#;
+    let Ok(Output::Page { next_cursor: Some(cursor), .. }) = run(&mut custom, page) else {
+        panic!("Expected a first page");
+    };
+    let next = format!(r#"{{ "command": "read", "table": "products", "limit": 1, "paginate": true, "after": "{}" }}"#, cursor);
+    let second = run(&mut custom, &next);
+    assert!(matches!(&second, Ok(Output::Page { rows, .. }) if rows[0]["id"] == 2), "{:?}", second);
+    assert_eq!(second, run(&mut builtin, &next));
+    for name in ["products", "orders", "archive"] {
+        let rows = format!("{:?}", custom.table(name).unwrap().rows);
+        assert!(rows.contains("VecBackend"), "{}: {}", name, rows);
+    }
+
+    // transactions and snapshots work on copies of the backend's stores
+    let snapshot = custom.snapshot();
+    let mut session = Session::default();
+    let insert = r#"{ "command": "insert", "table": "products", "rows": { "id": 7, "name": "Fig" } }"#;
+    custom.execute_in(&mut session, Command::Begin).unwrap();
+    custom.execute_in(&mut session, serde_json::from_str(insert).unwrap()).unwrap();
+    let read: Command = serde_json::from_str(r#"{ "command": "read", "table": "products" }"#).unwrap();
+    let Output::Rows(before) = custom.query(read.clone()).unwrap() else {
+        panic!("Expected Output::Rows");
+    };
+    custom.execute_in(&mut session, Command::Commit).unwrap();
+    let Output::Rows(after) = custom.query(read.clone()).unwrap() else {
+        panic!("Expected Output::Rows");
+    };
+    assert_eq!((before.len(), after.len()), (3, 4));
+    assert_eq!(snapshot.query(read), Ok(Output::Rows(before)));
+}
@@ -0,0 +1,74 @@
+use serde_json::json;
+
+use super::{rows, run};
+use crate::database::*;
+use crate::parser::{parse_command, Command};
+use crate::session::Session;
+
+const CREATE: &str = r#"{ "command": "create", "type": "table", "table": "products", "primary_key": "id", "rows": { "id": { "type": "int" }, "name": { "type": "string" } } }"#;
+
+fn insert(id: i64) -> String {
+    format!(r#"{{ "command": "insert", "table": "products", "rows": {{ "id": {}, "name": "item {}" }} }}"#, id, id)
+}
+
+fn count(db: &mut Database) -> usize {
+    rows(run(db, r#"{ "command": "read", "table": "products" }"#).unwrap()).len()
+}
+
+#[test]
+fn test_batch_runs_every_command() {
+    let mut db = Database::new();
+    run(&mut db, CREATE).unwrap();
+    // a top-level array is a batch
+    let batch = parse_command(&format!("[{}, {}, {}, {}]", insert(1), insert(2), insert(1), insert(3))).unwrap();
+    assert!(matches!(&batch, Command::Batch { commands, atomic: false } if commands.len() == 4));
+    let Output::Batch(results) = db.execute(batch).unwrap() else {
+        panic!("Expected Output::Batch");
+    };
+    assert!(matches!(results.as_slice(), [Ok(Output::Done), Ok(Output::Done), Err(ExecError::DuplicateKey { .. }), Ok(Output::Done)]));
+    assert_eq!(count(&mut db), 3);
+    let serialized = serde_json::to_value(Output::Batch(results)).unwrap();
+    assert_eq!(serialized[1], json!(null));
+    assert!(serialized[2]["error"].as_str().is_some_and(|error| error.contains("duplicate")), "{}", serialized);
+
+    // reads only, through a shared reference
+    let reads = r#"{ "command": "batch", "commands": [{ "command": "get", "table": "products", "key": 2 }, { "command": "stats", "table": "products" }] }"#;
+    let Output::Batch(results) = db.query(serde_json::from_str(reads).unwrap()).unwrap() else {
+        panic!("Expected Output::Batch");
+    };
+    assert!(matches!(&results[0], Ok(Output::Row(Some(row))) if row["name"] == "item 2"));
+}
+
+#[test]
+fn test_atomic_batch_applies_all_or_nothing() {
+    let mut db = Database::new();
+    let events = db.subscribe("products");
+    let batch = |commands: &[String]| {
+        format!(r#"{{ "command": "batch", "atomic": true, "commands": [{}] }}"#, commands.join(", "))
+    };
+
+    let failing = run(&mut db, &batch(&[CREATE.to_string(), insert(1), insert(1)]));
+    assert!(matches!(&failing, Err(ExecError::Batch { index: 2, error }) if matches!(**error, ExecError::DuplicateKey { .. })));
+    assert!(db.table("products").is_err());
+    assert!(events.try_recv().is_err());
+
+    let applied = run(&mut db, &batch(&[CREATE.to_string(), insert(1), insert(2)])).unwrap();
+    assert!(matches!(applied, Output::Batch(results) if results.len() == 3));
+    assert_eq!(count(&mut db), 2);
+    assert_eq!(events.try_iter().count(), 2);
+
+    // in a transaction a failed atomic batch leaves the earlier work alone
+    let mut session = Session::new();
+    db.execute_in(&mut session, Command::Begin).unwrap();
+    db.execute_in(&mut session, serde_json::from_str(&insert(3)).unwrap()).unwrap();
+    let failing = db.execute_in(&mut session, serde_json::from_str(&batch(&[insert(4), insert(1)])).unwrap());
+    assert!(matches!(failing, Err(ExecError::Batch { index: 1, .. })));
+    db.execute_in(&mut session, Command::Commit).unwrap();
+    assert_eq!(count(&mut db), 3);
+    assert_eq!(events.try_iter().count(), 1);
+
+    // every command of a batch is checked against the session's role
+    let drop = r#"{ "command": "batch", "commands": [{ "command": "drop_tables", "pattern": "*", "confirm": true }] }"#;
+    let denied = db.execute_in(&mut Session::new(), serde_json::from_str(drop).unwrap());
+    assert!(matches!(denied, Err(ExecError::PermissionDenied(_))));
+}
@@ -0,0 +1,135 @@
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+
+use super::{rows, run, temp_dir};
+use crate::cancel::CancelToken;
+use crate::database::*;
+use crate::parser::Command;
+
+const ROWS: usize = 49_000;
+
+fn large() -> Database {
+    let mut db = Database::new();
+    fill(&mut db);
+    db
+}
+
+fn fill(db: &mut Database) {
+    run(db, r#"{ "command": "create", "type": "table", "table": "events", "primary_key": "id", "rows": {
+        "id": { "type": "int" }, "kind": { "type": "string" }, "seen": { "type": "bool" }
+    } }"#).unwrap();
+    let events = (0..ROWS)
+        .map(|id| Row::from([("id".to_string(), json!(id)), ("kind".to_string(), json!(format!("kind-{}", id % 7)))]))
+        .collect();
+    db.insert_many("events", events).unwrap();
+}
+
+fn command(input: &str) -> Command {
+    serde_json::from_str(input).unwrap()
+}
+
+// cancels `token` from another thread once a scan has started checking it
+fn cancel_mid_scan(token: &CancelToken) -> thread::JoinHandle<()> {
+    let token = token.clone();
+    thread::spawn(move || {
+        while token.checks() < 1000 {
+            thread::yield_now();
+        }
+        token.cancel();
+    })
+}
+
+#[test]
+fn test_cancelling_mid_scan() {
+    let mut db = large();
+    let read = r#"{ "command": "read", "table": "events", "filter": { "kind": "kind-3" } }"#;
+
+    let token = CancelToken::new();
+    let canceller = cancel_mid_scan(&token);
+    assert_eq!(db.query_cancellable(command(read), &token), Err(ExecError::Cancelled));
+    canceller.join().unwrap();
+    assert!(token.checks() < ROWS);
+    // a cancelled token stops the next command before it starts
+    assert_eq!(db.query_cancellable(command(read), &token), Err(ExecError::Cancelled));
+    assert_eq!(rows(db.query_cancellable(command(read), &CancelToken::new()).unwrap()).len(), ROWS / 7);
+
+    // an update cut off mid-scan changes no row
+    let update = r#"{ "command": "update", "type": "content", "table": "events", "filter": "kind = 'kind-3'", "rows": { "seen": true } }"#;
+    let token = CancelToken::new();
+    let canceller = cancel_mid_scan(&token);
+    assert_eq!(db.execute_cancellable(command(update), &token), Err(ExecError::Cancelled));
+    canceller.join().unwrap();
+    let seen = rows(run(&mut db, r#"{ "command": "read", "table": "events", "filter": { "seen": true } }"#).unwrap());
+    assert!(seen.is_empty());
+    assert_eq!(db.execute_cancellable(command(update), &CancelToken::new()), Ok(Output::Affected(ROWS / 7)));
+}
+
+#[test]
+fn test_cancelled_batch_leaves_no_events_or_keys() {
+    let mut db = large();
+    let events = db.subscribe("events");
+    let insert = format!(
+        r#"{{ "command": "insert", "table": "events", "idempotency_key": "k1", "rows": {{ "id": {}, "kind": "late" }} }}"#,
+        ROWS
+    );
+    let update = r#"{ "command": "update", "type": "content", "table": "events", "filter": "kind = 'kind-3'", "rows": { "seen": true } }"#;
+    let batch = format!(r#"{{ "command": "batch", "commands": [{}, {}] }}"#, insert, update);
+
+    let token = CancelToken::new();
+    let canceller = cancel_mid_scan(&token);
+    assert_eq!(db.execute_cancellable(command(&batch), &token), Err(ExecError::Cancelled));
+    canceller.join().unwrap();
+    assert!(events.try_recv().is_err());
+    assert_eq!(db.table("events").unwrap().len(), ROWS);
+
+    // the key was forgotten with the row, so a retry inserts it
+    assert_eq!(db.execute_cancellable(command(&insert), &CancelToken::new()), Ok(Output::Done));
+    assert_eq!(db.table("events").unwrap().len(), ROWS + 1);
+    assert!(events.try_recv().is_ok());
+}
+
+#[test]
+fn test_cancel_token_timeout() {
+    let mut db = large();
+    let token = CancelToken::with_timeout(Duration::ZERO);
+    let delete = r#"{ "command": "delete", "type": "content", "table": "events", "filter": "kind = 'kind-1'" }"#;
+    assert_eq!(db.execute_cancellable(command(delete), &token), Err(ExecError::Cancelled));
+    assert_eq!(db.table("events").unwrap().len(), ROWS);
+
+    let token = CancelToken::with_timeout(Duration::from_secs(60));
+    assert!(!token.is_cancelled());
+    let deleted = db.execute_cancellable(command(delete), &token).unwrap();
+    assert!(matches!(deleted, Output::Affected(n) if n > 0));
+    assert_eq!(ExecError::Cancelled.code(), "cancelled");
+}
+
+#[test]
+fn test_a_cancelled_or_failed_command_leaves_no_log_record() {
+    let dir = temp_dir("cancel-wal");
+    let wal_len = || std::fs::metadata(dir.join("wal.log")).unwrap().len();
+    let mut db = Database::open(&dir).unwrap();
+    fill(&mut db);
+    let filled = wal_len();
+
+    let update = r#"{ "command": "update", "type": "content", "table": "events", "filter": "kind = 'kind-3'", "rows": { "seen": true } }"#;
+    let token = CancelToken::new();
+    let canceller = cancel_mid_scan(&token);
+    assert_eq!(db.execute_cancellable(command(update), &token), Err(ExecError::Cancelled));
+    canceller.join().unwrap();
+    assert_eq!(wal_len(), filled);
+    let duplicate = r#"{ "command": "insert", "table": "events", "rows": { "id": 0 } }"#;
+    assert!(db.execute_cancellable(command(duplicate), &CancelToken::new()).is_err());
+    assert_eq!(wal_len(), filled);
+
+    // the record after them is logged as usual
+    let delete = r#"{ "command": "delete", "type": "content", "table": "events", "filter": "kind = 'kind-1'" }"#;
+    db.execute_cancellable(command(delete), &CancelToken::new()).unwrap();
+    assert!(wal_len() > filled);
+    drop(db);
+    let mut reopened = Database::open(&dir).unwrap();
+    assert_eq!(reopened.table("events").unwrap().len(), ROWS - ROWS / 7);
+    let seen = rows(run(&mut reopened, r#"{ "command": "read", "table": "events", "filter": { "seen": true } }"#).unwrap());
+    assert!(seen.is_empty());
+}
@@ -0,0 +1,153 @@
+use serde_json::json;
+
+use super::run;
+use crate::database::*;
+
+fn customers_and_orders(on_delete: &str) -> Database {
+    let mut db = Database::new();
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "customers",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int", "not_null": true },
+        "name": { "type": "string" }
+      }
+    }
+    "#).unwrap();
+    let orders = r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "orders",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int", "not_null": true },
+        "customer_id": {
+          "type": "int",
+          "references": { "table": "customers", "column": "id", "on_delete": "ON_DELETE" }
+        }
+      }
+    }
+    "#;
+    run(&mut db, &orders.replace("ON_DELETE", on_delete)).unwrap();
+
+    for input in [
+        r#"{ "command": "insert", "table": "customers", "rows": { "id": 1, "name": "Ada" } }"#,
+        r#"{ "command": "insert", "table": "customers", "rows": { "id": 2, "name": "Linus" } }"#,
+        r#"{ "command": "insert", "table": "orders", "rows": { "id": 10, "customer_id": 1 } }"#,
+        r#"{ "command": "insert", "table": "orders", "rows": { "id": 11, "customer_id": 1 } }"#,
+        r#"{ "command": "insert", "table": "orders", "rows": { "id": 12, "customer_id": 2 } }"#,
+        r#"{ "command": "insert", "table": "orders", "rows": { "id": 13 } }"#,
+    ] {
+        run(&mut db, input).unwrap();
+    }
+    db
+}
+
+#[test]
+fn test_insert_with_missing_parent_is_rejected() {
+    let mut db = customers_and_orders("restrict");
+    let result = run(&mut db, r#"{ "command": "insert", "table": "orders", "rows": { "id": 14, "customer_id": 3 } }"#);
+    assert_eq!(
+        result,
+        Err(ExecError::ForeignKeyViolation {
+            table: "orders".to_string(),
+            column: "customer_id".to_string(),
+            value: json!(3),
+        })
+    );
+    assert_eq!(db.table("orders").unwrap().len(), 4);
+
+    let update = run(&mut db, r#"{ "command": "update", "type": "content", "table": "orders", "filter": "id = 10", "rows": { "customer_id": 9 } }"#);
+    assert!(matches!(update, Err(ExecError::ForeignKeyViolation { .. })));
+}
+
+#[test]
+fn test_restrict_blocks_delete_of_referenced_row() {
+    let mut db = customers_and_orders("restrict");
+    let result = run(&mut db, r#"{ "command": "delete", "type": "content", "table": "customers", "filter": "id = 1" }"#);
+    assert_eq!(
+        result,
+        Err(ExecError::ForeignKeyViolation {
+            table: "orders".to_string(),
+            column: "customer_id".to_string(),
+            value: json!(1),
+        })
+    );
+    assert_eq!(db.table("customers").unwrap().len(), 2);
+
+    let drop = run(&mut db, r#"{ "command": "delete", "type": "table", "table": "customers" }"#);
+    assert!(matches!(drop, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_cascade_delete_removes_children() {
+    let mut db = customers_and_orders("cascade");
+    let result = run(&mut db, r#"{ "command": "delete", "type": "content", "table": "customers", "filter": "id = 1" }"#);
+    assert_eq!(result, Ok(Output::Affected(1)));
+
+    let remaining: Vec<_> = db.table("orders").unwrap().rows().map(|row| row["id"].clone()).collect();
+    assert_eq!(remaining, vec![json!(12), json!(13)]);
+}
+
+#[test]
+fn test_reference_must_target_key_column() {
+    let mut db = customers_and_orders("restrict");
+    let result = run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "notes",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int" },
+        "author": { "type": "string", "references": { "table": "customers", "column": "name" } }
+      }
+    }
+    "#);
+    assert!(matches!(result, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_unique_column_rejects_duplicates() {
+    let mut db = Database::new();
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "users",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int", "not_null": true },
+        "email": { "type": "string", "unique": true },
+        "handle": { "type": "string", "unique": true, "not_null": true }
+      }
+    }
+    "#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 1, "email": "ada@example.com", "handle": "ada" } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 2, "handle": "linus" } }"#).unwrap();
+
+    let duplicate = run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 3, "email": "ada@example.com", "handle": "grace" } }"#);
+    assert_eq!(duplicate, Err(ExecError::UniqueViolation {
+        table: "users".to_string(),
+        column: "email".to_string(),
+        value: json!("ada@example.com"),
+    }));
+    // nulls may repeat in a nullable unique column
+    run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 3, "handle": "grace" } }"#).unwrap();
+
+    let update = r#"{ "command": "update", "type": "content", "table": "users", "filter": "id = 2", "rows": { "email": "ada@example.com" } }"#;
+    assert!(matches!(run(&mut db, update), Err(ExecError::UniqueViolation { .. })));
+    // a row may keep its own value, and updating many rows to one value collides
+    let same = r#"{ "command": "update", "type": "content", "table": "users", "filter": "id = 1", "rows": { "email": "ada@example.com" } }"#;
+    assert_eq!(run(&mut db, same), Ok(Output::Affected(1)));
+    let many = r#"{ "command": "update", "type": "content", "table": "users", "filter": "id >= 2", "rows": { "handle": "same" } }"#;
+    assert!(matches!(run(&mut db, many), Err(ExecError::UniqueViolation { .. })));
+
+    // deleting the holder frees its value
+    run(&mut db, r#"{ "command": "delete", "type": "content", "table": "users", "filter": "id = 1" }"#).unwrap();
+    run(&mut db, update).unwrap();
+}
@@ -0,0 +1,1261 @@
+use serde_json::{json, Value};
+
+use super::{rows, run};
+use crate::database::*;
+use crate::result::ColumnType;
+
+fn shop() -> Database {
+    let mut db = Database::new();
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "products",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int", "not_null": true },
+        "name": { "type": "string" },
+        "price": { "type": "float" }
+      }
+    }
+    "#).unwrap();
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "orders",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int", "not_null": true },
+        "product_id": { "type": "int" },
+        "quantity": { "type": "int", "default": 1 }
+      }
+    }
+    "#).unwrap();
+
+    for input in [
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Coconut Water", "price": 2.5 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Banana", "price": 0.5 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 3, "name": "Mango", "price": 1.75 } }"#,
+        r#"{ "command": "insert", "table": "orders", "rows": { "id": 10, "product_id": 2, "quantity": 6 } }"#,
+        r#"{ "command": "insert", "table": "orders", "rows": { "id": 11, "product_id": 1 } }"#,
+        r#"{ "command": "insert", "table": "orders", "rows": { "id": 12, "product_id": 2, "quantity": 3 } }"#,
+        r#"{ "command": "insert", "table": "orders", "rows": { "id": 13, "product_id": 99 } }"#,
+    ] {
+        run(&mut db, input).unwrap();
+    }
+    db
+}
+
+#[test]
+fn test_insert_fills_defaults() {
+    let mut db = shop();
+    let result = rows(run(&mut db, r#"{ "command": "read", "table": "orders", "filter": { "id": 11 } }"#).unwrap());
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0]["quantity"], json!(1));
+}
+
+#[test]
+fn test_insert_rejects_duplicate_key_and_bad_type() {
+    let mut db = shop();
+    let dup = run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Again" } }"#);
+    assert_eq!(dup, Err(ExecError::DuplicateKey { table: "products".to_string(), key: json!(1) }));
+
+    let bad = run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 4, "price": "cheap" } }"#);
+    assert!(matches!(bad, Err(ExecError::TypeMismatch { .. })));
+}
+
+#[test]
+fn test_inner_join_orders_products() {
+    let mut db = shop();
+    let input = r#"
+    {
+      "command": "read",
+      "table": "orders",
+      "join": {
+        "table": "products",
+        "on": { "left": "product_id", "right": "id" }
+      }
+    }
+    "#;
+
+    let result = rows(run(&mut db, input).unwrap());
+    // order 13 references a missing product and is dropped by the inner join
+    assert_eq!(result.len(), 3);
+
+    let ids: Vec<_> = result.iter().map(|row| row["orders.id"].clone()).collect();
+    assert_eq!(ids, vec![json!(10), json!(11), json!(12)]);
+
+    assert_eq!(result[0]["products.name"], json!("Banana"));
+    assert_eq!(result[0]["orders.quantity"], json!(6));
+    assert_eq!(result[0]["products.id"], json!(2));
+    assert_eq!(result[1]["products.name"], json!("Coconut Water"));
+    assert_eq!(result[1]["orders.quantity"], json!(1));
+}
+
+#[test]
+fn test_left_join_keeps_unmatched_rows() {
+    let mut db = shop();
+    let join = |db: &mut Database, table: &str, left: &str, right: &str, joined: &str| {
+        let input = format!(
+            r#"{{ "command": "read", "table": "{}", "join": {{ "table": "{}", "type": "left", "on": {{ "left": "{}", "right": "{}" }} }} }}"#,
+            table, joined, left, right
+        );
+        rows(run(db, &input).unwrap())
+    };
+
+    // probing products by key: order 13's product is missing, its columns are null
+    let result = join(&mut db, "orders", "product_id", "id", "products");
+    let ids: Vec<_> = result.iter().map(|row| row["orders.id"].clone()).collect();
+    assert_eq!(ids, vec![json!(10), json!(11), json!(12), json!(13)]);
+    assert_eq!(result[3]["products.id"], Value::Null);
+    assert_eq!(result[3]["products.name"], Value::Null);
+
+    // the read table drives a left join even when it joins on its own key
+    let result = join(&mut db, "products", "id", "product_id", "orders");
+    let pairs: Vec<_> = result.iter().map(|row| (row["products.id"].clone(), row["orders.id"].clone())).collect();
+    assert_eq!(pairs, vec![
+        (json!(1), json!(11)),
+        (json!(2), json!(10)),
+        (json!(2), json!(12)),
+        (json!(3), Value::Null),
+    ]);
+
+    let unmatched = r#"{ "command": "read", "table": "products", "join": { "table": "orders", "type": "left", "on": { "left": "id", "right": "product_id" } }, "filter": { "orders.id": { "$is_null": true } }, "columns": ["products.name"] }"#;
+    assert_eq!(rows(run(&mut db, unmatched).unwrap()), vec![Row::from([("products.name".to_string(), json!("Mango"))])]);
+    let plan = run(&mut db, r#"{ "command": "explain", "query": { "table": "products", "join": { "table": "orders", "type": "left", "on": { "left": "id", "right": "product_id" } } } }"#);
+    assert!(matches!(plan, Ok(Output::Plan(plan)) if plan.join.as_deref() == Some("nested_loop") && plan.steps.contains(&"left_join".to_string())));
+}
+
+#[test]
+fn test_join_through_secondary_index_and_missing_values() {
+    let mut db = shop();
+    run(&mut db, r#"{ "command": "create_index", "table": "orders", "name": "by_product", "column": "product_id" }"#).unwrap();
+    // a row stored without the join column joins like one holding null
+    let orders = db.table_mut("orders").unwrap();
+    orders.insert_row(Key(json!(14)), Row::from([("id".to_string(), json!(14))]), None);
+
+    let join = |db: &mut Database, kind: &str| {
+        let query = format!(
+            r#"{{ "table": "products", "join": {{ "table": "orders", "type": "{}", "on": {{ "left": "id", "right": "product_id" }} }} }}"#,
+            kind
+        );
+        let plan = run(db, &format!(r#"{{ "command": "explain", "query": {} }}"#, query));
+        let pairs: Vec<_> = rows(run(db, &format!(r#"{{ "command": "read", {}"#, &query[1..])).unwrap())
+            .iter()
+            .map(|row| (row["products.id"].clone(), row["orders.id"].clone()))
+            .collect();
+        (plan, pairs)
+    };
+    let (plan, pairs) = join(&mut db, "left");
+    assert!(matches!(&plan, Ok(Output::Plan(plan)) if plan.join.as_deref() == Some("index_lookup on orders.product_id (by_product)")), "{:?}", plan);
+    assert_eq!(pairs, vec![
+        (json!(1), json!(11)),
+        (json!(2), json!(10)),
+        (json!(2), json!(12)),
+        (json!(3), Value::Null),
+    ]);
+
+    // without the index the same rows come from a nested loop
+    run(&mut db, r#"{ "command": "delete", "type": "index", "table": "orders", "name": "by_product" }"#).unwrap();
+    assert_eq!(join(&mut db, "left").1, pairs);
+    // an inner join probes products by key instead, driven by the orders
+    assert_eq!(join(&mut db, "inner").1, vec![(json!(2), json!(10)), (json!(1), json!(11)), (json!(2), json!(12))]);
+    let by_key = r#"{ "command": "read", "table": "orders", "join": { "table": "products", "type": "left", "on": { "left": "product_id", "right": "id" } } }"#;
+    let joined = rows(run(&mut db, by_key).unwrap());
+    assert_eq!((joined.len(), &joined[4]["orders.id"], &joined[4]["products.id"]), (5, &json!(14), &Value::Null));
+}
+
+#[test]
+fn test_inner_join_with_filter() {
+    let mut db = shop();
+    let input = r#"
+    {
+      "command": "read",
+      "table": "products",
+      "join": {
+        "table": "orders",
+        "on": { "left": "id", "right": "product_id" }
+      },
+      "filter": { "products.name": "Banana" }
+    }
+    "#;
+
+    let result = rows(run(&mut db, input).unwrap());
+    assert_eq!(result.len(), 2);
+    for row in &result {
+        assert_eq!(row["products.price"], json!(0.5));
+        assert_eq!(row["orders.product_id"], json!(2));
+    }
+}
+
+#[test]
+fn test_inner_join_unknown_column() {
+    let mut db = shop();
+    let input = r#"
+    {
+      "command": "read",
+      "table": "orders",
+      "join": {
+        "table": "products",
+        "on": { "left": "product", "right": "id" }
+      }
+    }
+    "#;
+
+    assert_eq!(
+        run(&mut db, input),
+        Err(ExecError::ColumnNotFound { table: "orders".to_string(), column: "product".to_string() })
+    );
+}
+
+#[test]
+fn test_read_with_comparison_operators() {
+    let mut db = shop();
+    let input = r#"
+    {
+      "command": "read",
+      "table": "products",
+      "filter": { "price": { "$gt": 0.5, "$lte": 2.5 } }
+    }
+    "#;
+
+    let result = rows(run(&mut db, input).unwrap());
+    let names: Vec<_> = result.iter().map(|row| row["name"].clone()).collect();
+    assert_eq!(names, vec![json!("Coconut Water"), json!("Mango")]);
+
+    let unknown = run(&mut db, r#"{ "command": "read", "table": "products", "filter": { "price": { "$near": 1 } } }"#);
+    assert!(matches!(unknown, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_create_and_read_view() {
+    let mut db = shop();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 4, "name": "Truffle", "price": 40.0 } }"#).unwrap();
+    run(&mut db, r#"
+    {
+      "command": "create_view",
+      "name": "cheap_products",
+      "query": {
+        "table": "products",
+        "filter": { "price": { "$lt": 10 } }
+      }
+    }
+    "#).unwrap();
+
+    let result = rows(run(&mut db, r#"{ "command": "read", "table": "cheap_products" }"#).unwrap());
+    let ids: Vec<_> = result.iter().map(|row| row["id"].clone()).collect();
+    assert_eq!(ids, vec![json!(1), json!(2), json!(3)]);
+
+    // an extra filter is ANDed with the view's own
+    let input = r#"{ "command": "read", "table": "cheap_products", "filter": { "price": { "$gt": 1 } } }"#;
+    let result = rows(run(&mut db, input).unwrap());
+    let ids: Vec<_> = result.iter().map(|row| row["id"].clone()).collect();
+    assert_eq!(ids, vec![json!(1), json!(3)]);
+
+    assert_eq!(db.view_names(), vec!["cheap_products"]);
+    assert_eq!(db.table_names(), vec!["orders", "products"]);
+}
+
+#[test]
+fn test_view_name_conflicts() {
+    let mut db = shop();
+    let clash = run(&mut db, r#"{ "command": "create_view", "name": "orders", "query": { "table": "products" } }"#);
+    assert_eq!(clash, Err(ExecError::TableExists("orders".to_string())));
+
+    run(&mut db, r#"{ "command": "create_view", "name": "all_products", "query": { "table": "products" } }"#).unwrap();
+    let table = run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "all_products",
+      "primary_key": "id",
+      "rows": { "id": { "type": "int" } }
+    }
+    "#);
+    assert_eq!(table, Err(ExecError::ViewExists("all_products".to_string())));
+
+    let missing = run(&mut db, r#"{ "command": "create_view", "name": "ghosts", "query": { "table": "ghost" } }"#);
+    assert_eq!(missing, Err(ExecError::TableNotFound("ghost".to_string())));
+    let column = run(&mut db, r#"{ "command": "create_view", "name": "ghosts", "query": { "table": "products", "columns": ["ghost"] } }"#);
+    assert!(matches!(column, Err(ExecError::ColumnNotFound { .. })));
+    let nested = r#"{ "command": "create_view", "name": "ghosts", "query": { "table": "all_products", "order_by": [{ "column": "ghost" }] } }"#;
+    assert!(matches!(run(&mut db, nested), Err(ExecError::ColumnNotFound { .. })));
+    run(&mut db, r#"{ "command": "create_view", "name": "named", "query": { "table": "all_products", "where": "name != ''" } }"#).unwrap();
+    assert_eq!(db.view_names(), vec!["all_products", "named"]);
+}
+
+#[test]
+fn test_update_content() {
+    let mut db = shop();
+    let input = r#"
+    {
+      "command": "update",
+      "type": "content",
+      "table": "orders",
+      "filter": "product_id = 2 AND quantity >= 4",
+      "rows": { "quantity": 5 }
+    }
+    "#;
+    assert_eq!(run(&mut db, input), Ok(Output::Affected(1)));
+
+    let result = rows(run(&mut db, r#"{ "command": "read", "table": "orders", "filter": { "quantity": 5 } }"#).unwrap());
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0]["id"], json!(10));
+
+    let bad = run(&mut db, r#"{ "command": "update", "type": "content", "table": "orders", "filter": "id = 10", "rows": { "quantity": "many" } }"#);
+    assert!(matches!(bad, Err(ExecError::TypeMismatch { .. })));
+}
+
+#[test]
+fn test_delete_content_and_table() {
+    let mut db = shop();
+    let deleted = run(&mut db, r#"{ "command": "delete", "type": "content", "table": "orders", "filter": "product_id = 2" }"#);
+    assert_eq!(deleted, Ok(Output::Affected(2)));
+    assert_eq!(db.table("orders").unwrap().len(), 2);
+
+    run(&mut db, r#"{ "command": "delete", "type": "table", "table": "orders" }"#).unwrap();
+    assert_eq!(db.table_names(), vec!["products"]);
+}
+
+#[test]
+fn test_string_filters_with_or_and_groups() {
+    let mut db = shop();
+    // `where` and the map filter both holding an $or keep both
+    let read = r#"{ "command": "read", "table": "orders", "where": "id = 10 OR id = 12",
+      "filter": { "$or": [{ "quantity": 1 }, { "quantity": 6 }] } }"#;
+    let ids: Vec<_> = rows(run(&mut db, read).unwrap()).iter().map(|row| row["id"].clone()).collect();
+    assert_eq!(ids, vec![json!(10)]);
+
+    let delete = r#"{ "command": "delete", "type": "content", "table": "orders",
+      "filter": "product_id = 99 OR (product_id = 2 AND NOT quantity > 5)" }"#;
+    assert_eq!(run(&mut db, delete), Ok(Output::Affected(2)));
+    let left: Vec<_> = db.table("orders").unwrap().entries().map(|(_, row)| row["id"].clone()).collect();
+    assert_eq!(left, vec![json!(10), json!(11)]);
+}
+
+fn order_lines() -> Database {
+    let mut db = Database::new();
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "order_lines",
+      "primary_key": ["order_id", "line_no"],
+      "rows": {
+        "order_id": { "type": "int", "not_null": true },
+        "line_no": { "type": "int", "not_null": true },
+        "sku": { "type": "string" }
+      }
+    }
+    "#).unwrap();
+    db
+}
+
+#[test]
+fn test_composite_primary_key() {
+    let mut db = order_lines();
+    for input in [
+        r#"{ "command": "insert", "table": "order_lines", "rows": { "order_id": 2, "line_no": 1, "sku": "A" } }"#,
+        r#"{ "command": "insert", "table": "order_lines", "rows": { "order_id": 1, "line_no": 2, "sku": "B" } }"#,
+        r#"{ "command": "insert", "table": "order_lines", "rows": { "order_id": 1, "line_no": 1, "sku": "C" } }"#,
+    ] {
+        run(&mut db, input).unwrap();
+    }
+
+    let dup = run(&mut db, r#"{ "command": "insert", "table": "order_lines", "rows": { "order_id": 1, "line_no": 2, "sku": "D" } }"#);
+    assert_eq!(dup, Err(ExecError::DuplicateKey { table: "order_lines".to_string(), key: json!([1, 2]) }));
+
+    let missing = run(&mut db, r#"{ "command": "insert", "table": "order_lines", "rows": { "order_id": 3, "sku": "E" } }"#);
+    assert_eq!(missing, Err(ExecError::NotNull { column: "line_no".to_string() }));
+
+    // rows are ordered by the key tuple
+    let result = rows(run(&mut db, r#"{ "command": "read", "table": "order_lines" }"#).unwrap());
+    let skus: Vec<_> = result.iter().map(|row| row["sku"].clone()).collect();
+    assert_eq!(skus, vec![json!("C"), json!("B"), json!("A")]);
+
+    // moving a line onto an existing key collides as well
+    let moved = run(&mut db, r#"{ "command": "update", "type": "content", "table": "order_lines", "filter": "sku = 'A'", "rows": { "order_id": 1 } }"#);
+    assert_eq!(moved, Err(ExecError::DuplicateKey { table: "order_lines".to_string(), key: json!([1, 1]) }));
+}
+
+#[test]
+fn test_composite_primary_key_columns_must_exist() {
+    let mut db = Database::new();
+    let result = run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "order_lines",
+      "primary_key": ["order_id", "line"],
+      "rows": {
+        "order_id": { "type": "int", "not_null": true },
+        "line_no": { "type": "int", "not_null": true }
+      }
+    }
+    "#);
+    assert_eq!(
+        result,
+        Err(ExecError::InvalidSchema {
+            table: "order_lines".to_string(),
+            error: SchemaError::PrimaryKeyColumnNotFound { column: "line".to_string() },
+        })
+    );
+}
+
+#[test]
+fn test_create_validates_schema() {
+    let mut db = Database::new();
+    let create = |db: &mut Database, primary_key: &str, columns: &str| {
+        let input = format!(
+            r#"{{ "command": "create", "type": "table", "table": "t", "primary_key": "{}", "rows": {} }}"#,
+            primary_key, columns
+        );
+        match run(db, &input) {
+            Err(ExecError::InvalidSchema { error, .. }) => Some(error),
+            other => {
+                assert_eq!(other, Ok(Output::Done));
+                None
+            }
+        }
+    };
+
+    let missing = create(&mut db, "id", r#"{ "code": { "type": "int" } }"#);
+    assert_eq!(missing, Some(SchemaError::PrimaryKeyColumnNotFound { column: "id".to_string() }));
+    let bad_default = create(&mut db, "id", r#"{ "id": { "type": "int" }, "qty": { "type": "int", "default": "many" } }"#);
+    assert_eq!(
+        bad_default,
+        Some(SchemaError::InvalidDefault { column: "qty".to_string(), default: json!("many"), col_type: ColumnType::Int })
+    );
+    let float_for_int = create(&mut db, "id", r#"{ "id": { "type": "int" }, "qty": { "type": "int", "default": 1.5 } }"#);
+    assert!(matches!(float_for_int, Some(SchemaError::InvalidDefault { .. })));
+    // defaults are typed JSON, a quoted number doesn't do for an int
+    let quoted = create(&mut db, "id", r#"{ "id": { "type": "int" }, "qty": { "type": "int", "default": "1" } }"#);
+    assert_eq!(
+        quoted,
+        Some(SchemaError::InvalidDefault { column: "qty".to_string(), default: json!("1"), col_type: ColumnType::Int })
+    );
+    let object = create(&mut db, "id", r#"{ "id": { "type": "int" }, "flag": { "type": "bool", "default": { "on": true } } }"#);
+    assert!(matches!(object, Some(SchemaError::InvalidDefault { .. })));
+    let empty_name = create(&mut db, "id", r#"{ "id": { "type": "int" }, " ": { "type": "int" } }"#);
+    assert_eq!(empty_name, Some(SchemaError::EmptyColumnName));
+    assert!(db.table_names().is_empty());
+
+    let typed = r#"{ "id": { "type": "int" }, "qty": { "type": "float", "default": 1.5 }, "tags": { "type": "json", "default": ["new"] } }"#;
+    assert_eq!(create(&mut db, "id", typed), None);
+    run(&mut db, r#"{ "command": "insert", "table": "t", "rows": { "id": 1 } }"#).unwrap();
+    let row = &rows(run(&mut db, r#"{ "command": "read", "table": "t" }"#).unwrap())[0];
+    assert_eq!((&row["qty"], &row["tags"]), (&json!(1.5), &json!(["new"])));
+}
+
+#[test]
+fn test_function_defaults_run_per_insert() {
+    let mut db = Database::new();
+    let unknown = run(&mut db, r#"{ "command": "create", "type": "table", "table": "t", "primary_key": "id",
+        "rows": { "id": { "type": "int" }, "at": { "type": "datetime", "default": "today()" } } }"#);
+    assert!(matches!(unknown, Err(ExecError::InvalidSchema { error: SchemaError::UnknownDefaultFunction { .. }, .. })));
+    let wrong_type = run(&mut db, r#"{ "command": "create", "type": "table", "table": "t", "primary_key": "id",
+        "rows": { "id": { "type": "int" }, "at": { "type": "int", "default": "now()" } } }"#);
+    assert!(matches!(wrong_type, Err(ExecError::InvalidSchema { error: SchemaError::InvalidDefault { .. }, .. })));
+
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "events", "primary_key": "id", "rows": {
+        "id": { "type": "int" },
+        "token": { "type": "uuid", "default": "uuid()" },
+        "at": { "type": "datetime", "not_null": true, "default": "now()" }
+    } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "events", "rows": { "id": 1 } }"#).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    run(&mut db, r#"{ "command": "insert", "table": "events", "rows": { "id": 2 } }"#).unwrap();
+    let events = rows(run(&mut db, r#"{ "command": "read", "table": "events" }"#).unwrap());
+    assert!(crate::validator::type_accepts(ColumnType::Datetime, &events[0]["at"]));
+    assert!(crate::validator::type_accepts(ColumnType::Uuid, &events[0]["token"]));
+    assert!(events[0]["at"].as_str() < events[1]["at"].as_str());
+    assert_ne!(events[0]["token"], events[1]["token"]);
+
+    // literals still work and must have the column's shape
+    run(&mut db, r#"{ "command": "insert", "table": "events", "rows": { "id": 3, "at": "2024-05-01T12:30:00+02:00" } }"#).unwrap();
+    let bad = run(&mut db, r#"{ "command": "insert", "table": "events", "rows": { "id": 4, "token": "not-a-uuid" } }"#);
+    assert!(matches!(bad, Err(ExecError::TypeMismatch { .. })));
+}
+
+#[test]
+fn test_implicit_defaults_fill_zero_values() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "plain", "primary_key": "id", "rows": {
+        "id": { "type": "int" }, "count": { "type": "int" }, "name": { "type": "string", "not_null": true }
+    } }"#).unwrap();
+    // off by default: omitted columns are null and not_null ones an error
+    let missing = run(&mut db, r#"{ "command": "insert", "table": "plain", "rows": { "id": 1 } }"#);
+    assert!(matches!(missing, Err(ExecError::NotNull { column }) if column == "name"));
+    run(&mut db, r#"{ "command": "insert", "table": "plain", "rows": { "id": 1, "name": "a" } }"#).unwrap();
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "plain" }"#).unwrap())[0]["count"], json!(null));
+
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "zeros", "primary_key": "id", "implicit_default": true, "rows": {
+        "id": { "type": "int" }, "count": { "type": "int" }, "ratio": { "type": "float" }, "active": { "type": "bool" },
+        "name": { "type": "string", "not_null": true }, "level": { "type": "int", "default": 3 }, "at": { "type": "datetime" }
+    } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "zeros", "rows": { "id": 1 } }"#).unwrap();
+    let row = &rows(run(&mut db, r#"{ "command": "read", "table": "zeros" }"#).unwrap())[0];
+    assert_eq!((&row["count"], &row["ratio"], &row["active"], &row["name"]), (&json!(0), &json!(0.0), &json!(false), &json!("")));
+    // a real default still wins, and a type without a zero stays null
+    assert_eq!((&row["level"], &row["at"]), (&json!(3), &json!(null)));
+
+    // or per column
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "mixed", "primary_key": "id", "rows": {
+        "id": { "type": "int" }, "count": { "type": "int", "implicit_default": true }, "note": { "type": "string" }
+    } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "mixed", "rows": { "id": 1 } }"#).unwrap();
+    let row = &rows(run(&mut db, r#"{ "command": "read", "table": "mixed" }"#).unwrap())[0];
+    assert_eq!((&row["count"], &row["note"]), (&json!(0), &json!(null)));
+}
+
+fn invoice_lines() -> Database {
+    let mut db = Database::new();
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "lines",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int" },
+        "price": { "type": "float" },
+        "quantity": { "type": "int" },
+        "total": { "type": "float", "generated": "price * quantity" },
+        "doubled": { "type": "int", "generated": "(quantity + 1) * 2 - 2" }
+      }
+    }
+    "#).unwrap();
+    db
+}
+
+#[test]
+fn test_generated_column() {
+    let mut db = invoice_lines();
+    run(&mut db, r#"{ "command": "insert", "table": "lines", "rows": { "id": 1, "price": 2.5, "quantity": 4 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "lines", "rows": { "id": 2, "price": 1.5 } }"#).unwrap();
+
+    let result = rows(run(&mut db, r#"{ "command": "read", "table": "lines" }"#).unwrap());
+    assert_eq!(result[0]["total"], json!(10.0));
+    assert_eq!(result[0]["doubled"], json!(8));
+    // a null operand makes the result null
+    assert_eq!(result[1]["total"], json!(null));
+
+    // updates recompute it, and it can be filtered like any column
+    run(&mut db, r#"{ "command": "update", "type": "content", "table": "lines", "filter": "id = 2", "rows": { "quantity": 2 } }"#).unwrap();
+    let result = rows(run(&mut db, r#"{ "command": "read", "table": "lines", "filter": { "total": { "$gte": 3 } } }"#).unwrap());
+    assert_eq!(result.len(), 2);
+    assert_eq!(result[1]["total"], json!(3.0));
+}
+
+#[test]
+fn test_generated_column_rejects_writes() {
+    let mut db = invoice_lines();
+    let insert = run(&mut db, r#"{ "command": "insert", "table": "lines", "rows": { "id": 1, "total": 3 } }"#);
+    assert!(matches!(insert, Err(ExecError::InvalidQuery(_))));
+    run(&mut db, r#"{ "command": "insert", "table": "lines", "rows": { "id": 1 } }"#).unwrap();
+    let update = run(&mut db, r#"{ "command": "update", "type": "content", "table": "lines", "filter": "id = 1", "rows": { "doubled": 3 } }"#);
+    assert!(matches!(update, Err(ExecError::InvalidQuery(_))));
+
+    // expressions may only read existing numeric columns
+    let create = |expression: &str| {
+        let cmd = json!({
+            "command": "create", "type": "table", "table": "bad", "primary_key": "id",
+            "rows": {
+                "id": { "type": "int" },
+                "name": { "type": "string" },
+                "out": { "type": "int", "generated": expression }
+            }
+        });
+        Database::new().execute(serde_json::from_value(cmd).unwrap())
+    };
+    assert!(create("id * 2").is_ok());
+    assert!(matches!(create("name + 1"), Err(ExecError::InvalidQuery(_))));
+    assert!(matches!(create("id *"), Err(ExecError::InvalidQuery(_))));
+    assert!(matches!(create("missing + 1"), Err(ExecError::ColumnNotFound { .. })));
+}
+
+#[test]
+fn test_stats() {
+    let mut db = shop();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 4 } }"#).unwrap();
+
+    let Output::Stats(stats) = run(&mut db, r#"{ "command": "stats", "table": "products" }"#).unwrap() else {
+        panic!("Expected Output::Stats");
+    };
+    assert_eq!((stats.tables, stats.rows, stats.indexes), (1, 4, 1));
+    assert_eq!(stats.null_counts["id"], 0);
+    assert_eq!(stats.null_counts["name"], 1);
+    assert_eq!(stats.null_counts["price"], 1);
+    assert!(stats.bytes > 0);
+
+    let Output::Stats(total) = run(&mut db, r#"{ "command": "stats" }"#).unwrap() else {
+        panic!("Expected Output::Stats");
+    };
+    assert_eq!((total.tables, total.rows), (2, 8));
+    assert_eq!(total.null_counts["orders.quantity"], 0);
+    assert!(total.bytes > stats.bytes);
+
+    let missing = run(&mut db, r#"{ "command": "stats", "table": "nope" }"#);
+    assert_eq!(missing, Err(ExecError::TableNotFound("nope".to_string())));
+}
+
+#[test]
+fn test_max_rows_cap() {
+    let mut db = shop();
+    db.set_max_rows(Some(2));
+
+    let capped = run(&mut db, r#"{ "command": "read", "table": "orders" }"#).unwrap();
+    let Output::Truncated { rows: result, truncated } = capped else {
+        panic!("Expected Output::Truncated");
+    };
+    assert!(truncated);
+    let ids: Vec<_> = result.iter().map(|row| row["id"].clone()).collect();
+    assert_eq!(ids, vec![json!(10), json!(11)]);
+
+    // reads within the cap are untouched
+    let within = run(&mut db, r#"{ "command": "read", "table": "orders", "filter": { "product_id": 2 } }"#);
+    assert_eq!(rows(within.unwrap()).len(), 2);
+    let limited = run(&mut db, r#"{ "command": "read", "table": "orders", "limit": 1 }"#);
+    assert_eq!(rows(limited.unwrap()).len(), 1);
+
+    let too_many = run(&mut db, r#"{ "command": "read", "table": "orders", "limit": 3 }"#);
+    assert!(matches!(too_many, Err(ExecError::InvalidQuery(_))));
+
+    db.set_max_rows(None);
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "orders", "limit": 3 }"#).unwrap()).len(), 3);
+}
+
+#[test]
+fn test_explain_reports_index_or_full_scan() {
+    let mut db = shop();
+    let explain = |db: &mut Database, query: &str| {
+        let input = format!(r#"{{ "command": "explain", "query": {} }}"#, query);
+        match run(db, &input).unwrap() {
+            Output::Plan(plan) => plan,
+            other => panic!("Expected Output::Plan, got {:?}", other),
+        }
+    };
+
+    let plan = explain(&mut db, r#"{ "table": "products", "filter": { "id": 2, "price": { "$lt": 1 } } }"#);
+    assert_eq!(plan.access, "index_lookup");
+    assert_eq!(plan.index.as_deref(), Some("primary key (id)"));
+    assert_eq!(plan.estimated_rows, 1);
+    assert_eq!(plan.steps, vec!["index_lookup", "filter"]);
+
+    let plan = explain(&mut db, r#"{ "table": "products", "filter": { "name": "Mango" }, "limit": 1 }"#);
+    assert_eq!(plan.access, "full_scan");
+    assert_eq!(plan.index, None);
+    assert_eq!(plan.estimated_rows, 3);
+    assert_eq!(plan.steps, vec!["full_scan", "filter", "limit"]);
+
+    let plan = explain(&mut db, r#"{ "table": "orders", "join": { "table": "products", "on": { "left": "product_id", "right": "id" } } }"#);
+    assert_eq!(plan.join.as_deref(), Some("index_lookup on products.id"));
+
+    // the lookup still applies the rest of the filter
+    let result = run(&mut db, r#"{ "command": "read", "table": "products", "filter": { "id": 2, "price": { "$gt": 1 } } }"#);
+    assert!(rows(result.unwrap()).is_empty());
+    let result = run(&mut db, r#"{ "command": "read", "table": "products", "filter": { "id": { "$eq": 2 } } }"#);
+    assert_eq!(rows(result.unwrap())[0]["name"], json!("Banana"));
+
+    let missing = run(&mut db, r#"{ "command": "explain", "query": { "table": "products", "filter": { "colour": 1 } } }"#);
+    assert!(matches!(missing, Err(ExecError::ColumnNotFound { .. })));
+}
+
+#[test]
+fn test_explain_plans_writes_without_running_them() {
+    let mut db = shop();
+    run(&mut db, r#"{ "command": "create_index", "table": "orders", "name": "by_product", "column": "product_id" }"#).unwrap();
+    let explain = |db: &mut Database, inner: &str| {
+        let input = format!(r#"{{ "command": "explain", "inner": {} }}"#, inner);
+        match run(db, &input).unwrap() {
+            Output::Plan(plan) => plan,
+            other => panic!("Expected Output::Plan, got {:?}", other),
+        }
+    };
+
+    // `inner` takes any command, reads included
+    let read = explain(&mut db, r#"{ "command": "read", "table": "products", "filter": { "id": 2 } }"#);
+    assert_eq!((read.access.as_str(), read.estimated_rows), ("index_lookup", 1));
+
+    let update = explain(&mut db, r#"{ "command": "update", "type": "content", "table": "orders", "filter": "product_id = 2", "rows": { "quantity": 0 } }"#);
+    assert_eq!(update.index.as_deref(), Some("by_product (product_id)"));
+    assert_eq!(update.estimated_rows, 2);
+    assert_eq!(update.steps, vec!["index_lookup", "filter", "update"]);
+
+    let delete = explain(&mut db, r#"{ "command": "delete", "type": "content", "table": "orders", "filter": "id = 10 OR quantity > 5", "limit": 1 }"#);
+    assert_eq!((delete.access.as_str(), delete.estimated_rows), ("full_scan", 4));
+    assert_eq!(delete.steps, vec!["full_scan", "filter", "limit", "delete"]);
+    let by_key = explain(&mut db, r#"{ "command": "delete", "type": "content", "table": "orders", "filter": "id = 10" }"#);
+    assert_eq!(by_key.index.as_deref(), Some("primary key (id)"));
+
+    let insert = explain(&mut db, r#"{ "command": "insert", "table": "orders", "rows": { "id": 20 } }"#);
+    assert_eq!((insert.access.as_str(), insert.steps), ("none", vec!["none".to_string(), "validate".to_string(), "insert".to_string()]));
+    let upsert = explain(&mut db, r#"{ "command": "upsert", "table": "orders", "rows": { "id": 10, "quantity": 1 } }"#);
+    assert_eq!(upsert.index.as_deref(), Some("primary key (id)"));
+
+    // nothing ran
+    assert_eq!(db.table("orders").unwrap().len(), 4);
+    let quantities: Vec<_> = db.table("orders").unwrap().rows().map(|row| row["quantity"].clone()).collect();
+    assert_eq!(quantities, vec![json!(6), json!(1), json!(3), json!(1)]);
+
+    // and the writes find the rows the plan says they would
+    let updated = run(&mut db, r#"{ "command": "update", "type": "content", "table": "orders", "filter": "product_id = 2 AND quantity > 4", "rows": { "quantity": 0 } }"#);
+    assert_eq!(updated, Ok(Output::Affected(1)));
+    let deleted = run(&mut db, r#"{ "command": "delete", "type": "content", "table": "orders", "filter": "product_id = 2", "limit": 1 }"#);
+    assert_eq!(deleted, Ok(Output::Affected(1)));
+    let left: Vec<_> = db.table("orders").unwrap().rows().map(|row| row["id"].clone()).collect();
+    assert_eq!(left, vec![json!(11), json!(12), json!(13)]);
+
+    let create = r#"{ "command": "explain", "inner": { "command": "describe", "table": "orders" } }"#;
+    assert!(matches!(run(&mut db, create), Err(ExecError::InvalidQuery(_))));
+    let missing = r#"{ "command": "explain", "inner": { "command": "delete", "type": "content", "table": "orders", "filter": "colour = 1" } }"#;
+    assert!(matches!(run(&mut db, missing), Err(ExecError::ColumnNotFound { .. })));
+}
+
+#[test]
+fn test_get_by_primary_key() {
+    let mut db = shop();
+    let found = run(&mut db, r#"{ "command": "get", "table": "products", "key": 2 }"#).unwrap();
+    let Output::Row(Some(row)) = found else {
+        panic!("Expected a row, got {:?}", found);
+    };
+    assert_eq!(row["name"], json!("Banana"));
+    assert_eq!(run(&mut db, r#"{ "command": "get", "table": "products", "key": 42 }"#), Ok(Output::Row(None)));
+    assert_eq!(serde_json::to_value(Output::Row(None)).unwrap(), json!(null));
+
+    let mut db = order_lines();
+    run(&mut db, r#"{ "command": "insert", "table": "order_lines", "rows": { "order_id": 1, "line_no": 2, "sku": "B" } }"#).unwrap();
+    let found = run(&mut db, r#"{ "command": "get", "table": "order_lines", "key": [1, 2] }"#).unwrap();
+    assert!(matches!(found, Output::Row(Some(row)) if row["sku"] == json!("B")));
+    assert_eq!(run(&mut db, r#"{ "command": "get", "table": "order_lines", "key": [2, 1] }"#), Ok(Output::Row(None)));
+    let partial = run(&mut db, r#"{ "command": "get", "table": "order_lines", "key": 1 }"#);
+    assert!(matches!(partial, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_cursor_pagination_is_stable_under_inserts() {
+    let mut db = shop();
+    for id in [5, 7, 9] {
+        let insert = format!(r#"{{ "command": "insert", "table": "products", "rows": {{ "id": {}, "name": "Fig", "price": 1.0 }} }}"#, id);
+        run(&mut db, &insert).unwrap();
+    }
+    let page = |db: &mut Database, after: Option<&str>| {
+        let after = after.map_or(String::new(), |cursor| format!(r#", "after": "{}""#, cursor));
+        let input = format!(r#"{{ "command": "read", "table": "products", "columns": ["name"], "limit": 2, "paginate": true{} }}"#, after);
+        match run(db, &input).unwrap() {
+            Output::Page { rows, next_cursor } => (rows, next_cursor),
+            other => panic!("Expected Output::Page, got {:?}", other),
+        }
+    };
+
+    let mut seen = Vec::new();
+    let (first, mut cursor) = page(&mut db, None);
+    assert_eq!(first.len(), 2);
+    seen.extend(first);
+    // a row before the cursor is not picked up, one after it is
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 0, "name": "Early" } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 6, "name": "Late" } }"#).unwrap();
+    while let Some(after) = cursor {
+        let (rows, next) = page(&mut db, Some(&after));
+        assert!(!rows.is_empty() && rows.len() <= 2);
+        seen.extend(rows);
+        cursor = next;
+    }
+    let names: Vec<_> = seen.iter().map(|row| row["name"].as_str().unwrap()).collect();
+    assert_eq!(names, vec!["Coconut Water", "Banana", "Mango", "Fig", "Late", "Fig", "Fig"]);
+
+    let no_limit = run(&mut db, r#"{ "command": "read", "table": "products", "paginate": true }"#);
+    assert!(matches!(no_limit, Err(ExecError::InvalidQuery(_))));
+    let bad = run(&mut db, r#"{ "command": "read", "table": "products", "limit": 2, "after": "zz" }"#);
+    assert!(matches!(bad, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_changed_since_returns_updated_rows_in_change_order() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "notes", "primary_key": "id", "timestamps": true,
+        "rows": { "id": { "type": "int" }, "text": { "type": "string" } } }"#).unwrap();
+    for id in 1..=3 {
+        run(&mut db, &format!(r#"{{ "command": "insert", "table": "notes", "rows": {{ "id": {}, "text": "draft" }} }}"#, id)).unwrap();
+    }
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    let since = crate::utils::now_rfc3339();
+    for id in [3, 1] {
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let update = format!(r#"{{ "command": "update", "type": "content", "table": "notes", "filter": "id = {}", "rows": {{ "text": "final" }} }}"#, id);
+        run(&mut db, &update).unwrap();
+    }
+
+    let changed = rows(run(&mut db, &format!(r#"{{ "command": "read", "table": "notes", "changed_since": "{}" }}"#, since)).unwrap());
+    assert_eq!(changed.iter().map(|row| row["id"].clone()).collect::<Vec<_>>(), vec![json!(3), json!(1)]);
+    assert!(changed[0]["created_at"].as_str() < changed[0]["updated_at"].as_str());
+    let all = rows(run(&mut db, r#"{ "command": "read", "table": "notes", "changed_since": "2000-01-01T00:00:00+01:00" }"#).unwrap());
+    assert_eq!(all.len(), 3);
+
+    let bad_time = run(&mut db, r#"{ "command": "read", "table": "notes", "changed_since": "yesterday" }"#);
+    assert!(matches!(bad_time, Err(ExecError::InvalidQuery(_))));
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "plain", "primary_key": "id", "rows": { "id": { "type": "int" } } }"#).unwrap();
+    let untimed = run(&mut db, r#"{ "command": "read", "table": "plain", "changed_since": "2000-01-01T00:00:00Z" }"#);
+    assert!(matches!(untimed, Err(ExecError::InvalidQuery(reason)) if reason.contains("timestamps")));
+}
+
+#[test]
+fn test_sampled_read() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "numbers", "primary_key": "id", "rows": { "id": { "type": "int" } } }"#).unwrap();
+    for id in 0..100 {
+        run(&mut db, &format!(r#"{{ "command": "insert", "table": "numbers", "rows": {{ "id": {} }} }}"#, id)).unwrap();
+    }
+    let ids = |db: &mut Database, input: &str| -> Vec<i64> {
+        rows(run(db, input).unwrap()).iter().map(|row| row["id"].as_i64().unwrap()).collect()
+    };
+
+    let sampled = ids(&mut db, r#"{ "command": "read", "table": "numbers", "filter": { "id": { "$gte": 50 } }, "sample": 10 }"#);
+    assert_eq!(sampled.len(), 10);
+    assert!(sampled.iter().all(|id| *id >= 50));
+    assert!(sampled.windows(2).all(|pair| pair[0] < pair[1]));
+
+    // a seed picks the same rows every time
+    let seeded = r#"{ "command": "read", "table": "numbers", "sample": 5, "seed": 42 }"#;
+    assert_eq!(ids(&mut db, seeded), ids(&mut db, seeded));
+    let other_seed = ids(&mut db, r#"{ "command": "read", "table": "numbers", "sample": 5, "seed": 7 }"#);
+    assert_ne!(ids(&mut db, seeded), other_seed);
+
+    let fewer = ids(&mut db, r#"{ "command": "read", "table": "numbers", "filter": { "id": { "$lt": 3 } }, "sample": 10 }"#);
+    assert_eq!(fewer, vec![0, 1, 2]);
+    let with_limit = run(&mut db, r#"{ "command": "read", "table": "numbers", "sample": 5, "limit": 5 }"#);
+    assert!(matches!(with_limit, Err(ExecError::InvalidQuery(_))));
+
+    db.set_max_rows(Some(20));
+    assert_eq!(ids(&mut db, r#"{ "command": "read", "table": "numbers", "sample": 20 }"#).len(), 20);
+    assert!(run(&mut db, r#"{ "command": "read", "table": "numbers", "sample": 21 }"#).is_err());
+}
+
+#[test]
+fn test_insert_with_idempotency_key() {
+    let mut db = shop();
+    let count = |db: &mut Database| rows(run(db, r#"{ "command": "read", "table": "products" }"#).unwrap()).len();
+    let before = count(&mut db);
+    let insert = r#"{ "command": "insert", "table": "products", "idempotency_key": "req-1", "rows": { "id": 10, "name": "Kiwi" } }"#;
+
+    let first = run(&mut db, insert);
+    let retry = run(&mut db, insert);
+    assert_eq!(first, Ok(Output::Done));
+    assert_eq!(retry, first);
+    assert_eq!(count(&mut db), before + 1);
+
+    // only inserts that succeeded are remembered, so a failed one can be retried
+    let failing = r#"{ "command": "insert", "table": "products", "idempotency_key": "req-2", "rows": { "id": 10, "name": "Again" } }"#;
+    assert!(matches!(run(&mut db, failing), Err(ExecError::DuplicateKey { .. })));
+    assert!(matches!(run(&mut db, failing), Err(ExecError::DuplicateKey { .. })));
+
+    // once the window passed, the repeat is a new insert
+    db.set_idempotency_window(std::time::Duration::ZERO);
+    assert!(matches!(run(&mut db, insert), Err(ExecError::DuplicateKey { .. })));
+}
+
+#[test]
+fn test_delete_in_chunks_with_limit() {
+    let mut db = shop();
+    for id in 4..=8 {
+        let insert = format!(r#"{{ "command": "insert", "table": "products", "rows": {{ "id": {}, "name": "Fig", "price": 3.0 }} }}"#, id);
+        run(&mut db, &insert).unwrap();
+    }
+    let chunk = r#"{ "command": "delete", "type": "content", "table": "products", "filter": "price > 1", "limit": 2 }"#;
+    let mut counts = Vec::new();
+    loop {
+        let Output::Affected(count) = run(&mut db, chunk).unwrap() else {
+            panic!("Expected Output::Affected");
+        };
+        counts.push(count);
+        if count == 0 {
+            break;
+        }
+    }
+    // 1, 3 and 4..=8 match; chunks go in key order
+    assert_eq!(counts, vec![2, 2, 2, 1, 0]);
+    let left = rows(run(&mut db, r#"{ "command": "read", "table": "products" }"#).unwrap());
+    assert_eq!(left.iter().map(|row| row["id"].clone()).collect::<Vec<_>>(), vec![json!(2)]);
+}
+
+#[test]
+fn test_update_with_expressions() {
+    let mut db = shop();
+    let raise = r#"{ "command": "update", "type": "content", "table": "products", "filter": "price > 1", "rows": { "price": { "$expr": "price * 1.1" } } }"#;
+    assert_eq!(run(&mut db, raise), Ok(Output::Affected(2)));
+    let prices: Vec<f64> = rows(run(&mut db, r#"{ "command": "read", "table": "products" }"#).unwrap())
+        .iter()
+        .map(|row| row["price"].as_f64().unwrap())
+        .collect();
+    for (price, expected) in prices.iter().zip([2.75, 0.5, 1.925]) {
+        assert!((price - expected).abs() < 1e-9, "{} != {}", price, expected);
+    }
+
+    // 12 / quantity fails for the order with quantity 0
+    run(&mut db, r#"{ "command": "insert", "table": "orders", "rows": { "id": 14, "product_id": 3, "quantity": 0 } }"#).unwrap();
+    let split = r#"{ "command": "update", "type": "content", "table": "orders", "filter": "id >= 10", "rows": { "quantity": { "$expr": "12 / quantity" } }, "on_error": "ON_ERROR" }"#;
+    let aborted = run(&mut db, &split.replace("ON_ERROR", "abort"));
+    assert!(matches!(aborted, Err(ExecError::InvalidQuery(reason)) if reason.contains("division by zero")));
+    let quantities = |db: &mut Database| -> Vec<serde_json::Value> {
+        rows(run(db, r#"{ "command": "read", "table": "orders" }"#).unwrap()).iter().map(|row| row["quantity"].clone()).collect()
+    };
+    assert_eq!(quantities(&mut db), vec![json!(6), json!(1), json!(3), json!(1), json!(0)]);
+
+    assert_eq!(run(&mut db, &split.replace("ON_ERROR", "skip")), Ok(Output::Affected(4)));
+    assert_eq!(quantities(&mut db), vec![json!(2), json!(12), json!(4), json!(12), json!(0)]);
+
+    let unknown = run(&mut db, r#"{ "command": "update", "type": "content", "table": "orders", "filter": "id = 10", "rows": { "quantity": { "$expr": "weight * 2" } } }"#);
+    assert!(matches!(unknown, Err(ExecError::ColumnNotFound { .. })));
+}
+
+#[test]
+fn test_copy_table_with_and_without_data() {
+    let mut db = shop();
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_name", "column": "name" }"#).unwrap();
+
+    run(&mut db, r#"{ "command": "copy_table", "from": "products", "to": "backup", "include_data": true }"#).unwrap();
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "backup" }"#).unwrap()).len(), 3);
+    let dup = run(&mut db, r#"{ "command": "insert", "table": "backup", "rows": { "id": 1, "name": "Again" } }"#);
+    assert!(matches!(dup, Err(ExecError::DuplicateKey { .. })));
+    run(&mut db, r#"{ "command": "delete", "type": "content", "table": "backup", "filter": "id = 1" }"#).unwrap();
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "backup" }"#).unwrap()).len(), 2);
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "products" }"#).unwrap()).len(), 3);
+
+    run(&mut db, r#"{ "command": "copy_table", "from": "products", "to": "empty", "include_data": false }"#).unwrap();
+    assert!(rows(run(&mut db, r#"{ "command": "read", "table": "empty" }"#).unwrap()).is_empty());
+    let columns = |db: &Database, table: &str| serde_json::to_value(&db.table(table).unwrap().columns).unwrap();
+    assert_eq!(columns(&db, "empty"), columns(&db, "products"));
+    run(&mut db, r#"{ "command": "insert", "table": "empty", "rows": { "id": 1, "name": "Fig" } }"#).unwrap();
+    let plan = run(&mut db, r#"{ "command": "explain", "query": { "table": "empty", "filter": { "name": "Fig" } } }"#).unwrap();
+    assert!(matches!(plan, Output::Plan(plan) if plan.index.as_deref() == Some("by_name (name)")));
+
+    let taken = run(&mut db, r#"{ "command": "copy_table", "from": "products", "to": "orders", "include_data": true }"#);
+    assert!(matches!(taken, Err(ExecError::TableExists(_))));
+    let missing = run(&mut db, r#"{ "command": "copy_table", "from": "nothing", "to": "other", "include_data": true }"#);
+    assert!(matches!(missing, Err(ExecError::TableNotFound(_))));
+}
+
+#[test]
+fn test_rename_table() {
+    let mut db = shop();
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_name", "column": "name" }"#).unwrap();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "reviews", "primary_key": "id", "rows": {
+        "id": { "type": "int" }, "product_id": { "type": "int", "references": { "table": "products", "column": "id" } } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "reviews", "rows": { "id": 1, "product_id": 3 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create_view", "name": "cheap", "query": { "table": "products", "filter": { "price": { "$lt": 1 } } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create_view", "name": "pricey_reviews", "query": { "table": "reviews", "filter": { "$or": [
+        { "product_id": { "$in_query": { "table": "products", "columns": ["id"], "filter": { "price": { "$gt": 1 } } } } }
+    ] } } }"#).unwrap();
+    let pricey_reviews = rows(run(&mut db, r#"{ "command": "read", "table": "pricey_reviews" }"#).unwrap());
+    assert_eq!(pricey_reviews.len(), 1);
+
+    let rename = |db: &mut Database, to: &str| {
+        run(db, &format!(r#"{{ "command": "update", "type": "rename", "table": "products", "to": "{}" }}"#, to))
+    };
+    let views = db.views.clone();
+    assert!(matches!(rename(&mut db, "orders"), Err(ExecError::TableExists(_))));
+    assert!(matches!(rename(&mut db, "cheap"), Err(ExecError::ViewExists(_))));
+    // a failed rename changes nothing
+    assert_eq!(db.table("reviews").unwrap().columns["product_id"].references.as_ref().unwrap().table, "products");
+    assert_eq!(format!("{:?}", db.views), format!("{:?}", views));
+    rename(&mut db, "items").unwrap();
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "pricey_reviews" }"#).unwrap()), pricey_reviews);
+    assert!(matches!(run(&mut db, r#"{ "command": "read", "table": "products" }"#), Err(ExecError::TableNotFound(_))));
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "items" }"#).unwrap()).len(), 3);
+    let plan = run(&mut db, r#"{ "command": "explain", "query": { "table": "items", "filter": { "name": "Mango" } } }"#).unwrap();
+    assert!(matches!(plan, Output::Plan(plan) if plan.index.as_deref() == Some("by_name (name)")));
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "cheap" }"#).unwrap())[0]["name"], json!("Banana"));
+    assert_eq!(db.table("reviews").unwrap().columns["product_id"].references.as_ref().unwrap().table, "items");
+    let orphan = run(&mut db, r#"{ "command": "insert", "table": "reviews", "rows": { "id": 2, "product_id": 99 } }"#);
+    assert!(matches!(orphan, Err(ExecError::ForeignKeyViolation { .. })), "{:?}", orphan);
+    let referenced = run(&mut db, r#"{ "command": "delete", "type": "table", "table": "items" }"#);
+    assert!(referenced.is_err());
+}
+
+#[test]
+fn test_truncate_keeps_schema_and_indexes() {
+    let mut db = shop();
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_name", "column": "name" }"#).unwrap();
+    let truncate = |db: &mut Database, table: &str| {
+        run(db, &format!(r#"{{ "command": "delete", "type": "truncate", "table": "{}" }}"#, table))
+    };
+    assert_eq!(truncate(&mut db, "products"), Ok(Output::Affected(3)));
+    assert!(rows(run(&mut db, r#"{ "command": "read", "table": "products" }"#).unwrap()).is_empty());
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Fig" } }"#).unwrap();
+    let plan = run(&mut db, r#"{ "command": "explain", "query": { "table": "products", "filter": { "name": "Fig" } } }"#).unwrap();
+    assert!(matches!(plan, Output::Plan(plan) if plan.index.as_deref() == Some("by_name (name)")));
+
+    // rows pointing at the table block it or go with it, like a delete
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "reviews", "primary_key": "id", "rows": {
+        "id": { "type": "int" }, "product_id": { "type": "int", "references": { "table": "products", "column": "id" } } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "tags", "primary_key": "id", "rows": {
+        "id": { "type": "int" },
+        "product_id": { "type": "int", "references": { "table": "products", "column": "id", "on_delete": "cascade" } } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "tags", "rows": { "id": 1, "product_id": 1 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "reviews", "rows": { "id": 1, "product_id": 1 } }"#).unwrap();
+    assert!(matches!(truncate(&mut db, "products"), Err(ExecError::ForeignKeyViolation { .. })));
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "products" }"#).unwrap()).len(), 1);
+    assert_eq!(truncate(&mut db, "reviews"), Ok(Output::Affected(1)));
+    assert_eq!(truncate(&mut db, "products"), Ok(Output::Affected(1)));
+    assert!(rows(run(&mut db, r#"{ "command": "read", "table": "tags" }"#).unwrap()).is_empty());
+    assert!(matches!(truncate(&mut db, "nothing"), Err(ExecError::TableNotFound(_))));
+}
+
+#[test]
+fn test_conditional_update() {
+    let mut db = shop();
+    let set_price = |db: &mut Database, filter: &str, condition: &str, price: f64| {
+        let input = format!(
+            r#"{{ "command": "update", "type": "content", "table": "products", "filter": "{}", "if": "{}", "rows": {{ "price": {} }} }}"#,
+            filter, condition, price
+        );
+        run(db, &input).unwrap()
+    };
+    let price = |db: &mut Database| {
+        rows(run(db, r#"{ "command": "read", "table": "products", "filter": { "id": 1 } }"#).unwrap())[0]["price"].clone()
+    };
+
+    assert_eq!(set_price(&mut db, "id = 1", "price = 2.5", 3.5), Output::Applied { matched: 1, applied: 1 });
+    assert_eq!(price(&mut db), json!(3.5));
+    // the row exists but no longer has the expected price
+    assert_eq!(set_price(&mut db, "id = 1", "price = 2.5", 9.5), Output::Applied { matched: 1, applied: 0 });
+    assert_eq!(price(&mut db), json!(3.5));
+    assert_eq!(set_price(&mut db, "id = 42", "price = 2.5", 9.5), Output::Applied { matched: 0, applied: 0 });
+    assert_eq!(serde_json::to_value(Output::Applied { matched: 1, applied: 0 }).unwrap(), json!({ "matched": 1, "applied": 0 }));
+
+    assert_eq!(set_price(&mut db, "price > 0", "price < 2", 1.0), Output::Applied { matched: 3, applied: 2 });
+    let bad = run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "if": "colour = 'red'", "rows": { "price": 1 } }"#);
+    assert!(matches!(bad, Err(ExecError::ColumnNotFound { .. })));
+}
+
+#[test]
+fn test_row_quota() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "t", "primary_key": "id", "max_rows": 2, "rows": { "id": { "type": "int" } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "t", "rows": { "id": 1 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "t", "rows": { "id": 2 } }"#).unwrap();
+    let over = run(&mut db, r#"{ "command": "insert", "table": "t", "rows": { "id": 3 } }"#);
+    assert_eq!(over, Err(ExecError::QuotaExceeded { table: "t".to_string(), limit: 2, count: 2 }));
+    assert_eq!(over.unwrap_err().code(), "quota_exceeded");
+
+    // a batch that doesn't fit inserts nothing
+    run(&mut db, r#"{ "command": "delete", "type": "content", "table": "t", "filter": "id = 2" }"#).unwrap();
+    let batch: Vec<Row> = (10..12).map(|id| Row::from([("id".to_string(), json!(id))])).collect();
+    assert!(matches!(db.insert_many("t", batch), Err(ExecError::QuotaExceeded { count: 1, .. })));
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "t" }"#).unwrap()).len(), 1);
+    let batch = vec![Row::from([("id".to_string(), json!(10))])];
+    assert_eq!(db.insert_many("t", batch), Ok(1));
+
+    run(&mut db, r#"{ "command": "update", "type": "rows", "table": "t", "max_rows": 3 }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "t", "rows": { "id": 4 } }"#).unwrap();
+    assert!(run(&mut db, r#"{ "command": "insert", "table": "t", "rows": { "id": 5 } }"#).is_err());
+    run(&mut db, r#"{ "command": "update", "type": "rows", "table": "t", "max_rows": null }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "t", "rows": { "id": 5 } }"#).unwrap();
+    assert_eq!(db.table("t").unwrap().max_rows, None);
+}
+
+#[test]
+fn test_typed_read_reports_columns_in_schema_order() {
+    use crate::result::QueryResult;
+
+    let mut db = shop();
+    let result = |db: &mut Database, read: &str| match run(db, read).unwrap() {
+        Output::Result(result) => result,
+        other => panic!("Expected Output::Result, got {:?}", other),
+    };
+    fn columns(result: &QueryResult) -> Vec<(&str, ColumnType)> {
+        result.columns.iter().map(|column| (column.name.as_str(), column.col_type)).collect()
+    }
+
+    let all = result(&mut db, r#"{ "command": "read", "table": "products", "filter": { "id": 1 }, "with_types": true }"#);
+    assert_eq!(columns(&all), vec![("id", ColumnType::Int), ("name", ColumnType::String), ("price", ColumnType::Float)]);
+    let table = db.table("products").unwrap();
+    for column in &all.columns {
+        assert_eq!(table.columns[&column.name].col_type, column.col_type);
+    }
+    assert_eq!(all.rows.len(), 1);
+    assert_eq!(
+        serde_json::to_value(&all).unwrap()["columns"][2],
+        json!({ "name": "price", "type": "float" })
+    );
+
+    let projected = result(&mut db, r#"{ "command": "read", "table": "products", "columns": ["price", "id"], "with_types": true }"#);
+    assert_eq!(columns(&projected), vec![("price", ColumnType::Float), ("id", ColumnType::Int)]);
+    let grouped = result(&mut db, r#"{ "command": "read", "table": "orders", "group_by": ["product_id"], "with_types": true,
+        "aggregates": [{ "function": "count" }, { "function": "sum", "column": "quantity" }, { "function": "avg", "column": "quantity" }] }"#);
+    assert_eq!(
+        columns(&grouped),
+        vec![("product_id", ColumnType::Int), ("count(*)", ColumnType::Int), ("sum(quantity)", ColumnType::Int), ("avg(quantity)", ColumnType::Float)]
+    );
+    let joined = result(&mut db, r#"{ "command": "read", "table": "orders", "join": { "table": "products", "on": { "left": "product_id", "right": "id" } }, "with_types": true }"#);
+    assert_eq!(joined.columns[0].name, "orders.id");
+    assert_eq!(joined.columns[3].name, "products.id");
+    assert_eq!(joined.columns.len(), 6);
+
+    db.set_max_rows(Some(1));
+    let capped = result(&mut db, r#"{ "command": "read", "table": "products", "with_types": true }"#);
+    assert!(capped.truncated);
+    let paged = run(&mut db, r#"{ "command": "read", "table": "products", "limit": 1, "paginate": true, "with_types": true }"#);
+    assert!(matches!(paged, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_update_can_add_new_columns() {
+    let mut db = shop();
+    let strict = run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "rows": { "stock": 4 } }"#);
+    assert!(matches!(strict, Err(ExecError::ColumnNotFound { column, .. }) if column == "stock"));
+    assert!(!db.table("products").unwrap().columns.contains_key("stock"));
+
+    let update = r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "allow_new_columns": true,
+        "rows": { "stock": 4, "rating": 4.5, "organic": true, "origin": "Brazil", "tags": ["drink"], "price": 3.0 } }"#;
+    assert_eq!(run(&mut db, update), Ok(Output::Affected(1)));
+    let columns = &db.table("products").unwrap().columns;
+    for (column, col_type) in [("stock", "int"), ("rating", "float"), ("organic", "bool"), ("origin", "string"), ("tags", "json")] {
+        assert_eq!(columns[column].col_type.as_str(), col_type, "{}", column);
+        assert!(!columns[column].not_null);
+    }
+    let read = rows(run(&mut db, r#"{ "command": "read", "table": "products", "filter": { "id": { "$lt": 3 } } }"#).unwrap());
+    assert_eq!((read[0]["stock"].clone(), read[0]["price"].clone()), (json!(4), json!(3.0)));
+    assert_eq!(read[1]["stock"], json!(null));
+
+    // a failing update takes its new columns back out
+    let failing = r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "allow_new_columns": true,
+        "rows": { "colour": "red", "price": "free" } }"#;
+    assert!(matches!(run(&mut db, failing), Err(ExecError::TypeMismatch { .. })));
+    assert!(!db.table("products").unwrap().columns.contains_key("colour"));
+    let null = r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "allow_new_columns": true, "rows": { "colour": null } }"#;
+    assert!(matches!(run(&mut db, null), Err(ExecError::InvalidQuery(reason)) if reason.contains("colour")));
+}
+
+#[test]
+fn test_non_finite_results_are_rejected() {
+    let mut db = shop();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 4, "name": "Saffron", "price": 1e308 } }"#).unwrap();
+    let update = |expression: &str| {
+        format!(
+            r#"{{ "command": "update", "type": "content", "table": "products", "filter": "id = 4", "rows": {{ "price": {{ "$expr": "{}" }} }} }}"#,
+            expression
+        )
+    };
+    for expression in ["price * 10", "price * 10 - price * 10"] {
+        let result = run(&mut db, &update(expression));
+        assert!(matches!(&result, Err(ExecError::InvalidValue { column, .. }) if column == "price"), "{:?}", result);
+        assert_eq!(result.unwrap_err().code(), "invalid_value");
+    }
+    let price = |db: &mut Database| rows(run(db, r#"{ "command": "read", "table": "products", "filter": { "id": 4 } }"#).unwrap())[0]["price"].clone();
+    assert_eq!(price(&mut db), json!(1e308));
+    assert_eq!(run(&mut db, &update("price / 2")), Ok(Output::Affected(1)));
+    assert_eq!(price(&mut db), json!(5e307));
+
+    // generated columns are held to the same rule
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "lines", "primary_key": "id", "rows": {
+        "id": { "type": "int" }, "price": { "type": "float" }, "total": { "type": "float", "generated": "price * 100" }
+    } }"#).unwrap();
+    let overflow = run(&mut db, r#"{ "command": "insert", "table": "lines", "rows": { "id": 1, "price": 1e307 } }"#);
+    assert!(matches!(overflow, Err(ExecError::InvalidValue { column, .. }) if column == "total"));
+    run(&mut db, r#"{ "command": "insert", "table": "lines", "rows": { "id": 1, "price": 2.5 } }"#).unwrap();
+    // a string that parses to infinity isn't a float either
+    let text = run(&mut db, r#"{ "command": "insert", "table": "lines", "rows": { "id": 2, "price": "inf" } }"#);
+    assert!(matches!(text, Err(ExecError::TypeMismatch { .. })));
+}
+
+#[test]
+fn test_read_order_by() {
+    let mut db = shop();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 4, "name": "Apple", "price": 1.75 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 5, "name": "Fig" } }"#).unwrap();
+    let read = |db: &mut Database, extra: &str| {
+        let input = format!(r#"{{ "command": "read", "table": "products", {} }}"#, extra);
+        run(db, &input).map(|output| rows(output).iter().map(|row| row["id"].clone()).collect::<Vec<_>>())
+    };
+
+    // nulls first, ties broken by the next column
+    let both = r#""order_by": [{ "column": "price" }, { "column": "name", "direction": "asc" }]"#;
+    assert_eq!(read(&mut db, both), Ok(vec![json!(5), json!(2), json!(4), json!(3), json!(1)]));
+    // ties keep key order either way
+    let desc = r#""order_by": [{ "column": "price", "direction": "desc" }]"#;
+    assert_eq!(read(&mut db, desc), Ok(vec![json!(1), json!(3), json!(4), json!(2), json!(5)]));
+    // sorting comes before projection and limit
+    let top = r#"{ "command": "read", "table": "products", "columns": ["name"], "limit": 2, "order_by": [{ "column": "price", "direction": "desc" }] }"#;
+    assert_eq!(rows(run(&mut db, top).unwrap()), vec![
+        Row::from([("name".to_string(), json!("Coconut Water"))]),
+        Row::from([("name".to_string(), json!("Mango"))]),
+    ]);
+
+    let joined = r#"{ "command": "read", "table": "orders", "join": { "table": "products", "on": { "left": "product_id", "right": "id" } }, "order_by": [{ "column": "products.price", "direction": "desc" }, { "column": "orders.id", "direction": "desc" }] }"#;
+    let ids: Vec<_> = rows(run(&mut db, joined).unwrap()).iter().map(|row| row["orders.id"].clone()).collect();
+    assert_eq!(ids, vec![json!(11), json!(12), json!(10)]);
+    let grouped = r#"{ "command": "read", "table": "orders", "group_by": ["product_id"], "aggregates": [{ "function": "sum", "column": "quantity" }], "order_by": [{ "column": "sum(quantity)", "direction": "desc" }] }"#;
+    let groups: Vec<_> = rows(run(&mut db, grouped).unwrap()).iter().map(|row| row["product_id"].clone()).collect();
+    assert_eq!(groups, vec![json!(2), json!(1), json!(99)]);
+
+    let unknown = read(&mut db, r#""order_by": [{ "column": "weight" }]"#);
+    assert!(matches!(unknown, Err(ExecError::ColumnNotFound { column, .. }) if column == "weight"));
+    let paged = read(&mut db, r#""limit": 2, "paginate": true, "order_by": [{ "column": "price" }]"#);
+    assert!(matches!(paged, Err(ExecError::InvalidQuery(_))));
+    let direction = r#"{ "table": "products", "order_by": [{ "column": "price", "direction": "up" }] }"#;
+    assert!(serde_json::from_str::<crate::parser::ReadCommand>(direction).is_err());
+}
+
+#[test]
+fn test_read_offset_and_cursor() {
+    let mut db = shop();
+    let read = |db: &mut Database, extra: &str| {
+        let input = format!(r#"{{ "command": "read", "table": "products", {} }}"#, extra);
+        run(db, &input).map(|output| rows(output).iter().map(|row| row["id"].clone()).collect::<Vec<_>>())
+    };
+
+    assert_eq!(read(&mut db, r#""offset": 1"#), Ok(vec![json!(2), json!(3)]));
+    assert_eq!(read(&mut db, r#""offset": 1, "limit": 1"#), Ok(vec![json!(2)]));
+    let sorted = r#""offset": 1, "order_by": [{ "column": "price", "direction": "desc" }]"#;
+    assert_eq!(read(&mut db, sorted), Ok(vec![json!(3), json!(2)]));
+    assert_eq!(read(&mut db, r#""offset": 5"#), Ok(vec![]));
+
+    // `cursor` is another name for `after`
+    let Ok(Output::Page { next_cursor: Some(cursor), .. }) = run(&mut db, r#"{ "command": "read", "table": "products", "limit": 1, "paginate": true }"#) else {
+        panic!("Expected a first page");
+    };
+    let next = format!(r#"{{ "command": "read", "table": "products", "limit": 1, "cursor": "{}" }}"#, cursor);
+    assert!(matches!(run(&mut db, &next), Ok(Output::Page { rows, .. }) if rows[0]["id"] == 2));
+
+    for invalid in [r#""offset": 1, "limit": 1, "paginate": true"#, r#""offset": 1, "sample": 2"#] {
+        assert!(matches!(read(&mut db, invalid), Err(ExecError::InvalidQuery(_))), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_upsert_inserts_or_updates_on_conflict() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "users", "primary_key": "id", "rows": {
+        "id": { "type": "int", "not_null": true },
+        "email": { "type": "string", "unique": true },
+        "name": { "type": "string" },
+        "visits": { "type": "int", "default": 0 }
+    } }"#).unwrap();
+    let upsert = |db: &mut Database, body: &str| run(db, &format!(r#"{{ "command": "upsert", "table": "users", {} }}"#, body));
+    let all = |db: &mut Database| rows(run(db, r#"{ "command": "read", "table": "users" }"#).unwrap());
+
+    upsert(&mut db, r#""rows": { "id": 1, "email": "ada@example.com", "name": "Ada" }"#).unwrap();
+    upsert(&mut db, r#""rows": { "id": 1, "name": "Ada L.", "visits": 3 }"#).unwrap();
+    let users = all(&mut db);
+    assert_eq!(users.len(), 1);
+    assert_eq!((&users[0]["name"], &users[0]["email"], &users[0]["visits"]), (&json!("Ada L."), &json!("ada@example.com"), &json!(3)));
+
+    // by a unique column; the row found keeps its key
+    upsert(&mut db, r#""rows": { "email": "ada@example.com", "visits": 4 }, "on_conflict": "email""#).unwrap();
+    upsert(&mut db, r#""rows": { "id": 2, "email": "bob@example.com", "name": "Bob" }, "on_conflict": ["email"]"#).unwrap();
+    let users = all(&mut db);
+    assert_eq!(users.len(), 2);
+    assert_eq!((&users[0]["id"], &users[0]["visits"]), (&json!(1), &json!(4)));
+    assert_eq!((&users[1]["name"], &users[1]["visits"]), (&json!("Bob"), &json!(0)));
+
+    // the update is checked like any other
+    let taken = upsert(&mut db, r#""rows": { "id": 2, "email": "ada@example.com" }"#);
+    assert!(matches!(taken, Err(ExecError::UniqueViolation { .. })), "{:?}", taken);
+    let not_unique = upsert(&mut db, r#""rows": { "id": 3, "name": "Bob" }, "on_conflict": "name""#);
+    assert!(matches!(not_unique, Err(ExecError::InvalidQuery(_))));
+    let missing = upsert(&mut db, r#""rows": { "name": "Cy" }, "on_conflict": "email""#);
+    assert!(matches!(missing, Err(ExecError::InvalidQuery(_))));
+    assert_eq!(all(&mut db).len(), 2);
+}
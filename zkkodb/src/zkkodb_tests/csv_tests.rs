@@ -0,0 +1,40 @@
+use super::{rows, run};
+use crate::database::*;
+
+fn users() -> Database {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "users", "primary_key": "id", "rows": {
+        "id": { "type": "int", "not_null": true },
+        "email": { "type": "string", "unique": true },
+        "age": { "type": "int" }
+    } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 1, "email": "a@example.com" } }"#).unwrap();
+    db
+}
+
+#[test]
+fn test_validate_csv_reports_failing_lines() {
+    let db = users();
+    let csv = "id,email,age\n\
+               2,b@example.com,30\n\
+               3,c@example.com,old\n\
+               4,\"d,\"\"quoted\"\"@example.com\",\n\
+               5,\"e@example.com\",41\n\
+               2,f@example.com,50\n";
+    let errors = db.validate_csv("users", csv.as_bytes()).unwrap();
+    assert_eq!(errors.iter().map(|error| error.line).collect::<Vec<_>>(), vec![3, 6]);
+    assert!(matches!(errors[0].error, ExecError::TypeMismatch { .. }));
+    assert_eq!(errors[1].error, ExecError::DuplicateKey { table: "users".to_string(), key: serde_json::json!(2) });
+
+    // lines 2 and 5 of the file: a taken unique email and a missing field
+    let csv = "id,email,age\n9,a@example.com,1\n10,x@example.com,2\n11,y@example.com,3\n12,z@example.com\n";
+    let errors = db.validate_csv("users", csv.as_bytes()).unwrap();
+    assert_eq!(errors.iter().map(|error| error.line).collect::<Vec<_>>(), vec![2, 5]);
+    assert!(matches!(errors[0].error, ExecError::UniqueViolation { .. }));
+    assert!(matches!(errors[1].error, ExecError::InvalidQuery(_)));
+
+    // nothing was inserted
+    assert_eq!(rows(db.query(serde_json::from_str(r#"{ "command": "read", "table": "users" }"#).unwrap()).unwrap()).len(), 1);
+    assert_eq!(db.validate_csv("users", "id,email\n2,b@example.com\n".as_bytes()).unwrap(), vec![]);
+    assert!(matches!(db.validate_csv("users", "id,name\n".as_bytes()), Err(ExecError::ColumnNotFound { .. })));
+}
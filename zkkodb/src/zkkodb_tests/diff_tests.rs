@@ -0,0 +1,107 @@
+use serde_json::json;
+
+use super::{rows, run};
+use crate::database::*;
+use crate::diff::ConflictPolicy;
+
+fn shop() -> Database {
+    let mut db = Database::new();
+    for input in [
+        r#"{ "command": "create", "type": "table", "table": "products", "primary_key": "id", "rows": { "id": { "type": "int" }, "name": { "type": "string", "unique": true }, "price": { "type": "float" } } }"#,
+        r#"{ "command": "create", "type": "table", "table": "notes", "primary_key": "id", "rows": { "id": { "type": "int" } } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Coconut Water", "price": 2.5 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Banana", "price": 0.5 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 3, "name": "Mango", "price": 1.75 } }"#,
+    ] {
+        run(&mut db, input).unwrap();
+    }
+    db
+}
+
+// the shop with one product removed, one repriced, one added, `notes` dropped
+// and a new `tags` table
+fn changed_shop() -> Database {
+    let mut db = shop();
+    for input in [
+        r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 2" }"#,
+        r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 3", "rows": { "price": 2.0 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 4, "name": "Banana Bread" } }"#,
+        r#"{ "command": "delete", "type": "table", "table": "notes" }"#,
+        r#"{ "command": "create", "type": "table", "table": "tags", "primary_key": "tag", "rows": { "tag": { "type": "string" } } }"#,
+        r#"{ "command": "create_index", "table": "tags", "name": "by_tag", "column": "tag" }"#,
+        r#"{ "command": "insert", "table": "tags", "rows": { "tag": "fruit" } }"#,
+    ] {
+        run(&mut db, input).unwrap();
+    }
+    db
+}
+
+fn read(db: &mut Database, table: &str) -> Vec<Row> {
+    rows(run(db, &format!(r#"{{ "command": "read", "table": "{}" }}"#, table)).unwrap())
+}
+
+#[test]
+fn test_diff_reports_tables_and_rows() {
+    let (ours, theirs) = (shop(), changed_shop());
+    let diff = ours.diff(&theirs);
+    assert_eq!(diff.added_tables, vec!["tags"]);
+    assert_eq!(diff.removed_tables, vec!["notes"]);
+    assert!(diff.schema_changed.is_empty());
+
+    let products = &diff.tables["products"];
+    let ids = |rows: &[Row]| rows.iter().map(|row| row["id"].clone()).collect::<Vec<_>>();
+    assert_eq!(ids(&products.added), vec![json!(4)]);
+    assert_eq!(ids(&products.removed), vec![json!(2)]);
+    assert_eq!(products.changed.len(), 1);
+    assert_eq!(products.changed[0].before["price"], json!(1.75));
+    assert_eq!(products.changed[0].after["price"], json!(2.0));
+    assert!(ours.diff(&shop()).is_empty());
+
+    let mut wider = shop();
+    run(&mut wider, r#"{ "command": "update", "type": "rows", "table": "products", "add": { "stock": { "type": "int" } } }"#).unwrap();
+    let diff = ours.diff(&wider);
+    assert_eq!(diff.schema_changed, vec!["products"]);
+    assert!(matches!(shop().apply_diff(&diff, ConflictPolicy::Theirs), Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_apply_diff_converges_the_databases() {
+    let (mut ours, mut theirs) = (shop(), changed_shop());
+    let diff = ours.diff(&theirs);
+    // one product each inserted, updated and deleted, plus the tag
+    assert_eq!(ours.apply_diff(&diff, ConflictPolicy::Fail), Ok(4));
+    assert!(ours.diff(&theirs).is_empty());
+    assert_eq!(ours.table_names(), vec!["products", "tags"]);
+    assert_eq!(read(&mut ours, "products"), read(&mut theirs, "products"));
+    assert_eq!(read(&mut ours, "tags"), read(&mut theirs, "tags"));
+    let plan = run(&mut ours, r#"{ "command": "explain", "query": { "table": "tags", "filter": { "tag": "fruit" } } }"#).unwrap();
+    assert!(matches!(plan, Output::Plan(plan) if plan.access == "index_lookup"));
+    assert_eq!(ours.apply_diff(&diff, ConflictPolicy::Fail), Ok(0));
+}
+
+#[test]
+fn test_apply_diff_conflict_policies() {
+    let diff = shop().diff(&changed_shop());
+    // meanwhile the product the diff reprices was repriced here too
+    let diverged = || {
+        let mut db = shop();
+        run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 3", "rows": { "price": 9.0 } }"#).unwrap();
+        db
+    };
+    let price = |db: &mut Database| read(db, "products").into_iter().find(|row| row["id"] == json!(3)).unwrap()["price"].clone();
+
+    let mut db = diverged();
+    assert_eq!(db.apply_diff(&diff, ConflictPolicy::Fail), Err(ExecError::Conflict { table: "products".to_string() }));
+    assert_eq!(read(&mut db, "products").len(), 3);
+    assert_eq!(db.table_names(), vec!["notes", "products"]);
+
+    let mut db = diverged();
+    db.apply_diff(&diff, ConflictPolicy::Ours).unwrap();
+    assert_eq!(price(&mut db), json!(9.0));
+    assert_eq!(read(&mut db, "products").len(), 3);
+
+    let mut db = diverged();
+    db.apply_diff(&diff, ConflictPolicy::Theirs).unwrap();
+    assert_eq!(price(&mut db), json!(2.0));
+    assert!(db.diff(&changed_shop()).is_empty());
+}
@@ -0,0 +1,67 @@
+use serde_json::json;
+
+use super::run;
+use crate::database::*;
+use crate::events::*;
+
+fn products() -> Database {
+    let mut db = Database::new();
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "products",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int", "not_null": true },
+        "price": { "type": "float" }
+      }
+    }
+    "#).unwrap();
+    db
+}
+
+#[test]
+fn test_insert_delivers_one_event() {
+    let mut db = products();
+    let events = db.subscribe("products");
+
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "price": 2.5 } }"#).unwrap();
+
+    let received: Vec<_> = events.try_iter().collect();
+    assert_eq!(
+        received,
+        vec![ChangeEvent { kind: ChangeKind::Insert, table: "products".to_string(), keys: vec![json!(1)] }]
+    );
+}
+
+#[test]
+fn test_update_and_delete_events() {
+    let mut db = products();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "price": 2.5 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "price": 12.0 } }"#).unwrap();
+    let events = db.subscribe("products");
+
+    run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "price > 10", "rows": { "price": 9.5 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 1" }"#).unwrap();
+    // nothing matches, so nothing is emitted
+    run(&mut db, r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 7" }"#).unwrap();
+
+    let received: Vec<_> = events.try_iter().map(|event| (event.kind, event.keys)).collect();
+    assert_eq!(
+        received,
+        vec![(ChangeKind::Update, vec![json!(2)]), (ChangeKind::Delete, vec![json!(1)])]
+    );
+}
+
+#[test]
+fn test_dropped_subscriber_does_not_block_writes() {
+    let mut db = products();
+    let other = db.subscribe("other_table");
+    drop(db.subscribe("products"));
+
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2 } }"#).unwrap();
+    assert_eq!(db.table("products").unwrap().len(), 2);
+    assert!(other.try_recv().is_err());
+}
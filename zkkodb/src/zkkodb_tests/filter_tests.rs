@@ -0,0 +1,364 @@
+use serde_json::json;
+
+use super::{rows, run};
+use crate::database::*;
+
+fn products() -> Database {
+    let mut db = Database::new();
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "products",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int", "not_null": true },
+        "name": { "type": "string" },
+        "price": { "type": "float" }
+      }
+    }
+    "#).unwrap();
+
+    for input in [
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Coconut Water", "price": 2.5 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Banana", "price": 0.5 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 3, "name": "Cocoa Powder", "price": 6.0 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 4, "name": "Hot Cocoa", "price": 3.0 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 5, "price": 1.0 } }"#,
+    ] {
+        run(&mut db, input).unwrap();
+    }
+    db
+}
+
+fn ids(result: Vec<Row>) -> Vec<serde_json::Value> {
+    result.iter().map(|row| row["id"].clone()).collect()
+}
+
+#[test]
+fn test_regex_filter() {
+    let mut db = products();
+    let input = r#"{ "command": "read", "table": "products", "filter": { "name": { "$regex": "^Coco" } } }"#;
+    assert_eq!(ids(rows(run(&mut db, input).unwrap())), vec![json!(1), json!(3)]);
+
+    // non-string values never match
+    let input = r#"{ "command": "read", "table": "products", "filter": { "price": { "$regex": "2" } } }"#;
+    assert!(ids(rows(run(&mut db, input).unwrap())).is_empty());
+}
+
+#[test]
+fn test_matches_keyword_in_string_filter() {
+    let mut db = products();
+    let input = r#"{ "command": "delete", "type": "content", "table": "products", "filter": "name MATCHES '^Coco' AND price < 5" }"#;
+    assert_eq!(run(&mut db, input), Ok(Output::Affected(1)));
+
+    let remaining = ids(rows(run(&mut db, r#"{ "command": "read", "table": "products" }"#).unwrap()));
+    assert_eq!(remaining, vec![json!(2), json!(3), json!(4), json!(5)]);
+}
+
+#[test]
+fn test_invalid_regex_is_rejected() {
+    let mut db = products();
+    let read = run(&mut db, r#"{ "command": "read", "table": "products", "filter": { "name": { "$regex": "(Coco" } } }"#);
+    assert!(matches!(read, Err(ExecError::InvalidQuery(_))));
+
+    let delete = run(&mut db, r#"{ "command": "delete", "type": "content", "table": "products", "filter": "name matches '[a-'" }"#);
+    assert!(matches!(delete, Err(ExecError::InvalidQuery(_))));
+    assert_eq!(db.table("products").unwrap().len(), 5);
+}
+
+#[test]
+fn test_between_operator() {
+    let mut db = products();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 6, "name": "Saffron", "price": 10.0 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 7, "name": "Vanilla", "price": 50.0 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 8, "name": "Caviar", "price": 50.5 } }"#).unwrap();
+
+    // bounds are inclusive and coerced to the column type
+    let input = r#"{ "command": "read", "table": "products", "filter": { "price": { "$between": ["10", 50] } } }"#;
+    assert_eq!(ids(rows(run(&mut db, input).unwrap())), vec![json!(6), json!(7)]);
+
+    let input = r#"{ "command": "delete", "type": "content", "table": "products", "filter": "price BETWEEN 10 AND 50 AND id > 6" }"#;
+    assert_eq!(run(&mut db, input), Ok(Output::Affected(1)));
+}
+
+#[test]
+fn test_between_rejects_reversed_bounds() {
+    let mut db = products();
+    let input = r#"{ "command": "read", "table": "products", "filter": { "price": { "$between": [50, 10] } } }"#;
+    assert!(matches!(run(&mut db, input), Err(ExecError::InvalidQuery(_))));
+
+    let input = r#"{ "command": "read", "table": "products", "filter": { "price": { "$between": [10] } } }"#;
+    assert!(matches!(run(&mut db, input), Err(ExecError::InvalidQuery(_))));
+
+    let input = r#"{ "command": "delete", "type": "content", "table": "products", "filter": "price BETWEEN 5 AND 1" }"#;
+    assert!(matches!(run(&mut db, input), Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_case_insensitive_equality() {
+    let mut db = products();
+    let result = run(&mut db, r#"{ "command": "read", "table": "products", "filter": { "name": { "$ieq": "coconut water" } } }"#);
+    assert_eq!(ids(rows(result.unwrap())), vec![json!(1)]);
+
+    // plain equality stays case-sensitive and non-strings compare as usual
+    let result = run(&mut db, r#"{ "command": "read", "table": "products", "filter": { "name": "coconut water" } }"#);
+    assert!(rows(result.unwrap()).is_empty());
+    let result = run(&mut db, r#"{ "command": "read", "table": "products", "filter": { "price": { "$ieq": 6 } } }"#);
+    assert_eq!(ids(rows(result.unwrap())), vec![json!(3)]);
+}
+
+#[test]
+fn test_prepared_read_binds_parameters() {
+    use crate::prepared::PreparedRead;
+    use std::collections::HashMap;
+
+    let db = products();
+    let read = serde_json::from_str(r#"{ "table": "products", "filter": { "price": { "$gt": "?price" } } }"#).unwrap();
+    let prep = PreparedRead::new(read);
+    assert_eq!(prep.params().collect::<Vec<_>>(), vec!["price"]);
+
+    let bind = |price: f64| HashMap::from([("price".to_string(), json!(price))]);
+    assert_eq!(ids(rows(db.execute_prepared(&prep, bind(2.0)).unwrap())), vec![json!(1), json!(3), json!(4)]);
+    assert_eq!(ids(rows(db.execute_prepared(&prep, bind(5.0)).unwrap())), vec![json!(3)]);
+
+    // bound strings stay values, they are never parsed as filter syntax
+    let read = serde_json::from_str(r#"{ "table": "products", "filter": { "name": "?name" } }"#).unwrap();
+    let by_name = PreparedRead::new(read);
+    let injected = HashMap::from([("name".to_string(), json!("x' OR id > '0"))]);
+    assert!(rows(db.execute_prepared(&by_name, injected).unwrap()).is_empty());
+
+    let missing = db.execute_prepared(&prep, HashMap::new());
+    assert!(matches!(missing, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_null_comparisons_are_unknown() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "items", "primary_key": "id", "rows": { "id": { "type": "int" }, "price": { "type": "int" } } }"#).unwrap();
+    for insert in [
+        r#"{ "command": "insert", "table": "items", "rows": { "id": 1, "price": 5 } }"#,
+        r#"{ "command": "insert", "table": "items", "rows": { "id": 2, "price": 20 } }"#,
+        r#"{ "command": "insert", "table": "items", "rows": { "id": 3, "price": null } }"#,
+        r#"{ "command": "insert", "table": "items", "rows": { "id": 4 } }"#,
+    ] {
+        run(&mut db, insert).unwrap();
+    }
+    let read = |db: &mut Database, filter: &str| {
+        let input = format!(r#"{{ "command": "read", "table": "items", "filter": {} }}"#, filter);
+        ids(rows(run(db, &input).unwrap()))
+    };
+
+    assert_eq!(read(&mut db, r#"{ "price": { "$gt": 10 } }"#), vec![json!(2)]);
+    assert_eq!(read(&mut db, r#"{ "price": { "$not": { "$gt": 10 } } }"#), vec![json!(1)]);
+    assert_eq!(read(&mut db, r#"{ "price": { "$ne": 5 } }"#), vec![json!(2)]);
+    // equality with null never matches, $is_null does
+    assert!(read(&mut db, r#"{ "price": null }"#).is_empty());
+    assert!(read(&mut db, r#"{ "price": { "$not": null } }"#).is_empty());
+    assert_eq!(read(&mut db, r#"{ "price": { "$is_null": true } }"#), vec![json!(3), json!(4)]);
+    assert_eq!(read(&mut db, r#"{ "price": { "$is_null": false } }"#), vec![json!(1), json!(2)]);
+    let bad = run(&mut db, r#"{ "command": "read", "table": "items", "filter": { "price": { "$is_null": 1 } } }"#);
+    assert!(matches!(bad, Err(ExecError::InvalidQuery(_))));
+
+    // the string form of update and delete filters
+    let update = |db: &mut Database, filter: &str| {
+        let input = format!(r#"{{ "command": "update", "type": "content", "table": "items", "filter": "{}", "rows": {{ "price": 1 }} }}"#, filter);
+        run(db, &input).unwrap()
+    };
+    assert!(matches!(update(&mut db, "NOT price > 10"), Output::Affected(1)));
+    assert!(matches!(update(&mut db, "NOT price > 10 AND NOT price < 1"), Output::Affected(1)));
+    assert!(matches!(update(&mut db, "NOT price > 10 AND NOT price < 2"), Output::Affected(0)));
+    let deleted = run(&mut db, r#"{ "command": "delete", "type": "content", "table": "items", "filter": "price IS NULL AND id > 3" }"#).unwrap();
+    assert!(matches!(deleted, Output::Affected(1)));
+    assert_eq!(read(&mut db, r#"{ "price": { "$is_null": true } }"#), vec![json!(3)]);
+    let deleted = run(&mut db, r#"{ "command": "delete", "type": "content", "table": "items", "filter": "price IS NOT NULL" }"#).unwrap();
+    assert!(matches!(deleted, Output::Affected(2)));
+}
+
+#[test]
+fn test_in_query_filters_by_another_tables_values() {
+    let mut db = products();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "order_lines", "primary_key": "id", "rows": { "id": { "type": "int" }, "product_id": { "type": "int" } } }"#).unwrap();
+    for (id, product_id) in [(1, 3), (2, 1), (3, 3), (4, 42)] {
+        let insert = format!(r#"{{ "command": "insert", "table": "order_lines", "rows": {{ "id": {}, "product_id": {} }} }}"#, id, product_id);
+        run(&mut db, &insert).unwrap();
+    }
+    let ordered = r#"{ "command": "read", "table": "products", "filter": { "id": { "$in_query": { "table": "order_lines", "columns": ["product_id"] } } } }"#;
+    assert_eq!(ids(rows(run(&mut db, ordered).unwrap())), vec![json!(1), json!(3)]);
+
+    let narrowed = r#"{ "command": "read", "table": "products", "filter": { "id": { "$in_query": { "table": "order_lines", "columns": ["product_id"], "filter": { "id": { "$gt": 2 } } } } } }"#;
+    assert_eq!(ids(rows(run(&mut db, narrowed).unwrap())), vec![json!(3)]);
+    let listed = r#"{ "command": "read", "table": "products", "filter": { "id": { "$in": [2, 4, 9] } } }"#;
+    assert_eq!(ids(rows(run(&mut db, listed).unwrap())), vec![json!(2), json!(4)]);
+
+    let two_columns = r#"{ "command": "read", "table": "products", "filter": { "id": { "$in_query": { "table": "order_lines", "columns": ["id", "product_id"] } } } }"#;
+    assert!(matches!(run(&mut db, two_columns), Err(ExecError::InvalidQuery(_))));
+    let missing = r#"{ "command": "read", "table": "products", "filter": { "id": { "$in_query": { "table": "nothing", "columns": ["id"] } } } }"#;
+    assert!(matches!(run(&mut db, missing), Err(ExecError::TableNotFound(_))));
+
+    // the sub-read is held to the row cap instead of being truncated
+    db.set_max_rows(Some(3));
+    assert!(matches!(run(&mut db, ordered), Err(ExecError::InvalidQuery(_))));
+    assert_eq!(ids(rows(run(&mut db, narrowed).unwrap())), vec![json!(3)]);
+}
+
+#[test]
+fn test_array_contains_and_size() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "items", "primary_key": "id", "rows": { "id": { "type": "int" }, "tags": { "type": "json" } } }"#).unwrap();
+    for (id, tags) in [(1, r#"["sale", "new"]"#), (2, r#"["sale"]"#), (3, r#"["new", "eco", "local"]"#), (4, r#""sale""#), (5, "null")] {
+        let insert = format!(r#"{{ "command": "insert", "table": "items", "rows": {{ "id": {}, "tags": {} }} }}"#, id, tags);
+        run(&mut db, &insert).unwrap();
+    }
+    let read = |db: &mut Database, filter: &str| {
+        let input = format!(r#"{{ "command": "read", "table": "items", "filter": {} }}"#, filter);
+        ids(rows(run(db, &input).unwrap()))
+    };
+
+    // a plain string isn't an array, so it neither contains nor has a size
+    assert_eq!(read(&mut db, r#"{ "tags": { "$contains": "sale" } }"#), vec![json!(1), json!(2)]);
+    assert_eq!(read(&mut db, r#"{ "tags": { "$contains": "sale", "$size": { "$gte": 2 } } }"#), vec![json!(1)]);
+    assert_eq!(read(&mut db, r#"{ "tags": { "$size": 1 } }"#), vec![json!(2)]);
+    assert_eq!(read(&mut db, r#"{ "tags": { "$not": { "$contains": "sale" } } }"#), vec![json!(3), json!(4)]);
+}
+
+#[test]
+fn test_map_filter_and_where_both_apply() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "produce", "primary_key": "id",
+        "rows": { "id": { "type": "int" }, "category": { "type": "string" }, "price": { "type": "float" } } }"#).unwrap();
+    for (id, category, price) in [(1, "fruit", 12.0), (2, "fruit", 25.0), (3, "vegetable", 5.0), (4, "fruit", 19.5)] {
+        let insert = format!(r#"{{ "command": "insert", "table": "produce", "rows": {{ "id": {}, "category": "{}", "price": {} }} }}"#, id, category, price);
+        run(&mut db, &insert).unwrap();
+    }
+    let read = |db: &mut Database, input: &str| -> Vec<serde_json::Value> {
+        rows(run(db, input).unwrap()).iter().map(|row| row["id"].clone()).collect()
+    };
+    let both = r#"{ "command": "read", "table": "produce", "filter": { "category": "fruit" }, "where": "price < 20" }"#;
+    assert_eq!(read(&mut db, both), vec![json!(1), json!(4)]);
+    // conditions on the same column are combined too
+    let same_column = r#"{ "command": "read", "table": "produce", "filter": { "price": { "$gt": 10 } }, "where": "price < 20" }"#;
+    assert_eq!(read(&mut db, same_column), vec![json!(1), json!(4)]);
+
+    let twice = run(&mut db, r#"{ "command": "read", "table": "produce", "filter": { "price": { "$lt": 30 } }, "where": "price < 20" }"#);
+    assert!(matches!(twice, Err(ExecError::InvalidQuery(_))));
+    let malformed = run(&mut db, r#"{ "command": "read", "table": "produce", "where": "price <" }"#);
+    assert!(matches!(malformed, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_fuzzy_matches_within_an_edit_distance() {
+    let mut db = products();
+    let fuzzy = |target: &str, distance: usize| {
+        format!(
+            r#"{{ "command": "read", "table": "products", "filter": {{ "name": {{ "$fuzzy": {{ "target": "{}", "distance": {} }} }} }} }}"#,
+            target, distance
+        )
+    };
+    // a typo in one word of the name
+    assert_eq!(ids(rows(run(&mut db, &fuzzy("Cocnut", 2)).unwrap())), vec![json!(1)]);
+    assert_eq!(ids(rows(run(&mut db, &fuzzy("Cocoa", 1)).unwrap())), vec![json!(3), json!(4)]);
+    assert!(rows(run(&mut db, &fuzzy("Cocnut", 0)).unwrap()).is_empty());
+    let negated = r#"{ "command": "read", "table": "products", "filter": { "name": { "$not": { "$fuzzy": { "target": "Banan", "distance": 1 } } } } }"#;
+    assert_eq!(ids(rows(run(&mut db, negated).unwrap())), vec![json!(1), json!(3), json!(4)]);
+
+    let numeric = run(&mut db, r#"{ "command": "read", "table": "products", "filter": { "price": { "$fuzzy": { "target": "2", "distance": 1 } } } }"#);
+    assert!(matches!(numeric, Err(ExecError::InvalidQuery(_))));
+    let no_distance = run(&mut db, r#"{ "command": "read", "table": "products", "filter": { "name": { "$fuzzy": { "target": "x" } } } }"#);
+    assert!(matches!(no_distance, Err(ExecError::InvalidQuery(_))));
+
+    // the scan is refused on tables larger than the read cap
+    db.set_max_rows(Some(4));
+    assert!(matches!(run(&mut db, &fuzzy("Cocnut", 2)), Err(ExecError::InvalidQuery(_))));
+    db.set_max_rows(Some(5));
+    assert_eq!(ids(rows(run(&mut db, &fuzzy("Cocnut", 2)).unwrap())), vec![json!(1)]);
+}
+
+#[test]
+fn test_collations_govern_matching_and_ordering() {
+    let mut db = Database::new();
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "items",
+      "primary_key": "code",
+      "rows": {
+        "code": { "type": "string", "collation": "nocase" },
+        "label": { "type": "string", "collation": "numeric" },
+        "plain": { "type": "string" }
+      }
+    }
+    "#).unwrap();
+    for (code, label) in [("A", "item2"), ("b", "item10"), ("C", "item9")] {
+        let input = format!(
+            r#"{{ "command": "insert", "table": "items", "rows": {{ "code": "{}", "label": "{}", "plain": "{}" }} }}"#,
+            code, label, label
+        );
+        run(&mut db, &input).unwrap();
+    }
+    run(&mut db, r#"{ "command": "create_index", "table": "items", "name": "by_label", "column": "label" }"#).unwrap();
+    let codes = |db: &mut Database, filter: &str| -> Vec<serde_json::Value> {
+        let input = format!(r#"{{ "command": "read", "table": "items", "filter": {} }}"#, filter);
+        rows(run(db, &input).unwrap()).iter().map(|row| row["code"].clone()).collect()
+    };
+
+    // nocase: "a" is "A", through the key too
+    assert_eq!(codes(&mut db, r#"{ "code": "a" }"#), vec![json!("A")]);
+    assert_eq!(codes(&mut db, r#"{ "code": { "$in": ["B", "c"] } }"#), vec![json!("C"), json!("b")]);
+    assert_eq!(codes(&mut db, r#"{ "code": { "$ne": "a" } }"#), vec![json!("C"), json!("b")]);
+    let update = run(&mut db, r#"{ "command": "update", "type": "content", "table": "items", "filter": "code = 'a'", "rows": { "plain": "item2" } }"#);
+    assert_eq!(update, Ok(Output::Affected(1)));
+
+    // numeric: "item2" < "item9" < "item10", bytes would put "item10" first
+    assert_eq!(codes(&mut db, r#"{ "label": { "$lt": "item10" } }"#), vec![json!("A"), json!("C")]);
+    assert_eq!(codes(&mut db, r#"{ "plain": { "$lt": "item10" } }"#), Vec::<serde_json::Value>::new());
+    assert_eq!(codes(&mut db, r#"{ "label": { "$between": ["item3", "item10"] } }"#), vec![json!("C"), json!("b")]);
+    assert_eq!(codes(&mut db, r#"{ "label": "item9" }"#), vec![json!("C")]);
+    let extremes = r#"{ "command": "read", "table": "items", "aggregates": [
+        { "function": "min", "column": "label" }, { "function": "max", "column": "label" }, { "function": "max", "column": "plain" } ] }"#;
+    let result = rows(run(&mut db, extremes).unwrap()).remove(0);
+    assert_eq!(result["min(label)"], json!("item2"));
+    assert_eq!(result["max(label)"], json!("item10"));
+    assert_eq!(result["max(plain)"], json!("item9"));
+
+    // the index on the collated column isn't used
+    let plan = run(&mut db, r#"{ "command": "explain", "query": { "table": "items", "filter": { "label": "item9" } } }"#).unwrap();
+    assert!(matches!(plan, Output::Plan(plan) if plan.access == "full_scan"));
+
+    let numeric = run(&mut db, r#"{ "command": "create", "type": "table", "table": "t", "primary_key": "id", "rows": { "id": { "type": "int", "collation": "nocase" } } }"#);
+    assert!(matches!(numeric, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_and_or_not_combine_nested_filters() {
+    let mut db = products();
+    let read = |db: &mut Database, filter: &str| {
+        let input = format!(r#"{{ "command": "read", "table": "products", "filter": {} }}"#, filter);
+        run(db, &input).map(|output| ids(rows(output)))
+    };
+
+    let either = r#"{ "$or": [{ "price": { "$lt": 1 } }, { "name": { "$regex": "^Coco" } }] }"#;
+    assert_eq!(read(&mut db, either), Ok(vec![json!(1), json!(2), json!(3)]));
+    // next to column conditions, which all still have to hold
+    let narrowed = r#"{ "price": { "$lt": 3 }, "$or": [{ "id": 1 }, { "id": 2 }, { "id": 3 }] }"#;
+    assert_eq!(read(&mut db, narrowed), Ok(vec![json!(1), json!(2)]));
+    // nesting to any depth; $not of an unknown stays unknown, so id 5 without
+    // a name is left out
+    let nested = r#"{ "$and": [
+        { "$or": [{ "price": { "$gt": 2 } }, { "id": 5 }] },
+        { "$not": { "name": "Hot Cocoa" } }
+    ] }"#;
+    assert_eq!(read(&mut db, nested), Ok(vec![json!(1), json!(3)]));
+    let sub_read = r#"{ "$or": [{ "id": 2 }, { "id": { "$in_query": { "table": "products", "columns": ["id"], "filter": { "price": { "$gt": 5 } } } } }] }"#;
+    assert_eq!(read(&mut db, sub_read), Ok(vec![json!(2), json!(3)]));
+
+    let count = r#"{ "command": "read", "table": "products", "filter": { "$or": [{ "price": { "$lt": 1 } }, { "price": { "$gt": 5 } }] }, "aggregates": [{ "function": "count" }] }"#;
+    assert_eq!(rows(run(&mut db, count).unwrap())[0]["count(*)"], json!(2));
+
+    let unknown = read(&mut db, r#"{ "$or": [{ "id": 1 }, { "colour": "red" }] }"#);
+    assert!(matches!(unknown, Err(ExecError::ColumnNotFound { column, .. }) if column == "colour"));
+    for invalid in [r#"{ "$or": [] }"#, r#"{ "$and": { "id": 1 } }"#, r#"{ "$not": [{ "id": 1 }] }"#] {
+        assert!(matches!(read(&mut db, invalid), Err(ExecError::InvalidQuery(_))), "{}", invalid);
+    }
+}
@@ -0,0 +1,104 @@
+use super::{rows, run, temp_dir};
+use crate::database::*;
+use crate::grants::GRANTS_TABLE;
+use crate::parser::parse_command;
+use crate::session::Session;
+
+fn shop() -> Database {
+    let mut db = Database::new();
+    for input in [
+        r#"{ "command": "create", "type": "table", "table": "products", "primary_key": "id", "rows": { "id": { "type": "int" }, "name": { "type": "string" } } }"#,
+        r#"{ "command": "create", "type": "table", "table": "orders", "primary_key": "id", "rows": { "id": { "type": "int" }, "product_id": { "type": "int" } } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Mango" } }"#,
+        r#"{ "command": "insert", "table": "orders", "rows": { "id": 10, "product_id": 1 } }"#,
+        r#"{ "command": "create_view", "name": "mangos", "query": { "table": "products", "filter": { "name": "Mango" } } }"#,
+    ] {
+        run(&mut db, input).unwrap();
+    }
+    db
+}
+
+fn exec(db: &mut Database, session: &mut Session, input: &str) -> Result<Output, ExecError> {
+    db.execute_in(session, parse_command(input).unwrap())
+}
+
+fn denied(result: Result<Output, ExecError>, reason: &str) -> bool {
+    matches!(&result, Err(ExecError::PermissionDenied(got)) if got.contains(reason))
+}
+
+const READ_PRODUCTS: &str = r#"{ "command": "read", "table": "products" }"#;
+const INSERT_PRODUCT: &str = r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Fig" } }"#;
+
+#[test]
+fn test_grants_decide_what_sessions_may_run() {
+    let mut db = shop();
+    let (mut admin, mut analyst, mut bo) =
+        (Session::authenticated("ana", "admin"), Session::authenticated("al", "analyst"), Session::authenticated("bo", "staff"));
+    // without grants everything stays open
+    assert!(exec(&mut db, &mut bo, READ_PRODUCTS).is_ok());
+
+    let grant = r#"{ "command": "grant", "table": "products", "privileges": ["read"], "role": "analyst" }"#;
+    assert!(denied(exec(&mut db, &mut analyst, grant), "admin role"));
+    assert_eq!(exec(&mut db, &mut admin, grant), Ok(Output::Affected(1)));
+    assert_eq!(exec(&mut db, &mut admin, grant), Ok(Output::Affected(0)));
+    let to_bo = r#"{ "command": "grant", "table": "products", "privileges": "insert", "user": "bo" }"#;
+    exec(&mut db, &mut admin, to_bo).unwrap();
+
+    assert_eq!(rows(exec(&mut db, &mut analyst, READ_PRODUCTS).unwrap()).len(), 1);
+    assert!(denied(exec(&mut db, &mut analyst, INSERT_PRODUCT), "insert on table 'products' isn't granted"));
+    let explain_insert = format!(r#"{{ "command": "explain", "inner": {} }}"#, INSERT_PRODUCT);
+    assert!(denied(exec(&mut db, &mut analyst, &explain_insert), "insert on table 'products' isn't granted"));
+    assert!(denied(exec(&mut db, &mut bo, READ_PRODUCTS), "read on table 'products'"));
+    exec(&mut db, &mut bo, INSERT_PRODUCT).unwrap();
+    // tables nobody was granted are closed, and views and subqueries check what they read
+    assert!(denied(exec(&mut db, &mut analyst, r#"{ "command": "read", "table": "orders" }"#), "table 'orders'"));
+    assert!(exec(&mut db, &mut analyst, r#"{ "command": "read", "table": "mangos" }"#).is_ok());
+    assert!(denied(exec(&mut db, &mut bo, r#"{ "command": "read", "table": "mangos" }"#), "table 'products'"));
+    let subquery = r#"{ "command": "read", "table": "products", "filter": { "id": { "$in_query": { "table": "orders", "columns": ["product_id"] } } } }"#;
+    assert!(denied(exec(&mut db, &mut analyst, subquery), "table 'orders'"));
+    let having = r#"{ "command": "read", "table": "products", "group_by": ["name"], "aggregates": [{ "function": "count" }],
+        "having": { "count(*)": { "$in_query": { "table": "orders", "columns": ["id"], "filter": { "id": { "$gt": 5 } } } } } }"#;
+    assert!(denied(exec(&mut db, &mut analyst, having), "table 'orders'"));
+    // so are new tables and views, whatever they are made from
+    let copy = r#"{ "command": "copy_table", "from": "products", "to": "mine", "include_data": true }"#;
+    assert!(denied(exec(&mut db, &mut analyst, copy), "table 'mine' needs the admin role"));
+    let view = r#"{ "command": "create_view", "name": "cheap", "query": { "table": "products" } }"#;
+    assert!(denied(exec(&mut db, &mut analyst, view), "table 'cheap' needs the admin role"));
+    let batch = format!(r#"[{}, {}]"#, READ_PRODUCTS, INSERT_PRODUCT);
+    assert!(denied(exec(&mut db, &mut analyst, &batch), "insert"));
+    // schema changes and the catalog itself are the admin's
+    let index = r#"{ "command": "create_index", "table": "products", "name": "by_name", "column": "name" }"#;
+    assert!(denied(exec(&mut db, &mut analyst, index), "needs the admin role"));
+    let catalog = format!(r#"{{ "command": "read", "table": "{}" }}"#, GRANTS_TABLE);
+    assert!(denied(exec(&mut db, &mut analyst, &catalog), "_grants"));
+    exec(&mut db, &mut admin, index).unwrap();
+
+    let revoke = r#"{ "command": "revoke", "table": "products", "privileges": ["read", "delete"], "role": "analyst" }"#;
+    assert_eq!(exec(&mut db, &mut admin, revoke), Ok(Output::Affected(1)));
+    assert!(denied(exec(&mut db, &mut analyst, READ_PRODUCTS), "read"));
+    // run outside a session, commands aren't checked
+    assert!(run(&mut db, READ_PRODUCTS).is_ok());
+}
+
+#[test]
+fn test_grants_are_saved_and_follow_their_table() {
+    let mut db = shop();
+    let dir = temp_dir("grants");
+    let mut analyst = Session::authenticated("al", "analyst");
+    run(&mut db, r#"{ "command": "grant", "table": "products", "privileges": ["read"], "role": "analyst" }"#).unwrap();
+    let neither = run(&mut db, r#"{ "command": "grant", "table": "products", "privileges": ["read"] }"#);
+    assert!(matches!(neither, Err(ExecError::InvalidQuery(_))));
+    let missing = run(&mut db, r#"{ "command": "grant", "table": "nothing", "privileges": ["read"], "role": "analyst" }"#);
+    assert!(matches!(missing, Err(ExecError::TableNotFound(_))));
+
+    run(&mut db, r#"{ "command": "update", "type": "rename", "table": "products", "to": "items" }"#).unwrap();
+    db.save(&dir).unwrap();
+    let mut loaded = Database::load(&dir).unwrap();
+    assert!(exec(&mut loaded, &mut analyst, r#"{ "command": "read", "table": "items" }"#).is_ok());
+
+    run(&mut loaded, r#"{ "command": "delete", "type": "table", "table": "items" }"#).unwrap();
+    let grants = rows(run(&mut loaded, &format!(r#"{{ "command": "read", "table": "{}" }}"#, GRANTS_TABLE)).unwrap());
+    assert_eq!(grants, Vec::<Row>::new());
+    let orders = exec(&mut loaded, &mut analyst, r#"{ "command": "read", "table": "orders" }"#);
+    assert!(matches!(orders, Err(ExecError::PermissionDenied(_))), "{:?}", orders);
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::database::Database;
+use crate::http::{serve_http, Credentials};
+use crate::server::AsyncDatabase;
+use crate::wire::WireFormat;
+
+async fn start() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let credentials = Credentials { user: "ana".to_string(), role: "admin".to_string() };
+    let tokens = Arc::new(HashMap::from([("secret".to_string(), credentials)]));
+    tokio::spawn(serve_http(listener, Arc::new(AsyncDatabase::new(Database::new())), tokens));
+    addr
+}
+
+// sends one request and returns the status and the JSON body
+async fn request(addr: SocketAddr, method: &str, target: &str, token: Option<&str>, body: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let auth = token.map(|token| format!("Authorization: Bearer {}\r\n", token)).unwrap_or_default();
+    let head = format!("{} {} HTTP/1.1\r\nHost: test\r\n{}Content-Length: {}\r\n\r\n", method, target, auth, body.len());
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+// like `request` with extra headers and raw bytes, returning the response's
+// content type and raw body
+async fn raw_request(addr: SocketAddr, method: &str, target: &str, headers: &str, body: &[u8]) -> (u16, String, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: test\r\nAuthorization: Bearer secret\r\n{}Content-Length: {}\r\n\r\n",
+        method, target, headers, body.len()
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let split = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8(response[..split].to_vec()).unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    let content_type = head.lines().find_map(|line| line.strip_prefix("Content-Type: ")).unwrap().to_string();
+    (status, content_type, response[split + 4..].to_vec())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_http_endpoints_map_to_commands() {
+    let addr = start().await;
+    let send = |method: &'static str, target: &'static str, body: &'static str| request(addr, method, target, Some("secret"), body);
+
+    let create = r#"{ "table": "products", "primary_key": "id", "rows": { "id": { "type": "int" }, "name": { "type": "string" }, "price": { "type": "int" } } }"#;
+    assert_eq!(send("POST", "/tables", create).await, (200, Value::Null));
+    for row in [r#"{ "id": 1, "name": "Fig", "price": 3 }"#, r#"{ "id": 2, "name": "Kiwi", "price": 8 }"#, r#"{ "id": 3, "name": "Lime", "price": 12 }"#] {
+        assert_eq!(send("POST", "/tables/products/rows", row).await, (200, Value::Null));
+    }
+
+    let (status, rows) = send("GET", "/tables/products?filter=%7B%22price%22%3A%7B%22%24gt%22%3A5%7D%7D&limit=1", "").await;
+    assert_eq!((status, rows), (200, json!([{ "id": 2, "name": "Kiwi", "price": 8 }])));
+    let (_, rows) = send("GET", "/tables/products?limit=1&offset=2", "").await;
+    assert_eq!(rows, json!([{ "id": 3, "name": "Lime", "price": 12 }]));
+    assert_eq!(send("PATCH", "/tables/products/rows?filter=id+%3D+1", r#"{ "price": 4 }"#).await, (200, json!(1)));
+    assert_eq!(send("DELETE", "/tables/products/rows?filter=price+>+5&limit=1", "").await, (200, json!(1)));
+    let (_, rows) = send("GET", "/tables/products", "").await;
+    assert_eq!(rows, json!([{ "id": 1, "name": "Fig", "price": 4 }, { "id": 3, "name": "Lime", "price": 12 }]));
+
+    assert_eq!(send("DELETE", "/tables/products", "").await, (200, Value::Null));
+    let (status, error) = send("GET", "/tables/products", "").await;
+    assert_eq!(status, 404);
+    assert_eq!(error, json!({ "error": "table 'products' does not exist" }));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_http_rejects_bad_requests() {
+    let addr = start().await;
+    assert_eq!(request(addr, "GET", "/tables/products", None, "").await.0, 401);
+    assert_eq!(request(addr, "GET", "/tables/products", Some("guess"), "").await.0, 401);
+
+    let send = |method: &'static str, target: &'static str, body: &'static str| request(addr, method, target, Some("secret"), body);
+    assert_eq!(send("GET", "/nowhere", "").await.0, 404);
+    assert_eq!(send("PUT", "/tables/products", "").await.0, 405);
+    assert_eq!(send("POST", "/tables", "{ not json").await.0, 400);
+    assert_eq!(send("PATCH", "/tables/products/rows", r#"{ "price": 1 }"#).await.0, 400);
+    assert_eq!(send("GET", "/tables/products?limit=many", "").await.0, 400);
+
+    let create = r#"{ "table": "products", "primary_key": "id", "rows": { "id": { "type": "int" } } }"#;
+    assert_eq!(send("POST", "/tables", create).await.0, 200);
+    assert_eq!(send("POST", "/tables", create).await.0, 409);
+    assert_eq!(send("POST", "/tables/products/rows", r#"{ "id": 1 }"#).await.0, 200);
+    assert_eq!(send("POST", "/tables/products/rows", r#"{ "id": 1 }"#).await.0, 409);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_http_speaks_messagepack() {
+    let addr = start().await;
+    let msgpack = |value: Value| WireFormat::MessagePack.encode(&value).unwrap();
+    let decode = |body: &[u8]| -> Value { rmp_serde::from_slice(body).unwrap() };
+    const MSGPACK: &str = "Content-Type: application/msgpack\r\n";
+
+    let create = json!({ "table": "products", "primary_key": "id", "rows": { "id": { "type": "int" }, "price": { "type": "float" } } });
+    let (status, content_type, body) = raw_request(addr, "POST", "/tables", MSGPACK, &msgpack(create)).await;
+    assert_eq!((status, content_type.as_str(), decode(&body)), (200, "application/msgpack", Value::Null));
+    let row = msgpack(json!({ "id": 1, "price": 2.5 }));
+    assert_eq!(raw_request(addr, "POST", "/tables/products/rows", MSGPACK, &row).await.0, 200);
+
+    // Accept picks the response format, whatever the request used
+    let (_, content_type, body) = raw_request(addr, "GET", "/tables/products", "Accept: application/msgpack\r\n", b"").await;
+    assert_eq!((content_type.as_str(), decode(&body)), ("application/msgpack", json!([{ "id": 1, "price": 2.5 }])));
+    let (_, content_type, body) = raw_request(addr, "GET", "/tables/products", "Accept: application/json\r\n", b"").await;
+    assert_eq!(content_type, "application/json");
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!([{ "id": 1, "price": 2.5 }]));
+
+    let (status, content_type, body) = raw_request(addr, "POST", "/tables/products/rows", MSGPACK, &row).await;
+    assert_eq!((status, content_type.as_str()), (409, "application/msgpack"));
+    assert_eq!(decode(&body)["error"], json!("duplicate primary key 1 in table 'products'"));
+    let (status, _, _) = raw_request(addr, "POST", "/tables/products/rows", MSGPACK, b"\xc1").await;
+    assert_eq!(status, 400);
+    let (status, content_type, _) = raw_request(addr, "POST", "/tables/products/rows", "Content-Type: text/csv\r\n", b"id\n2").await;
+    assert_eq!((status, content_type.as_str()), (415, "application/json"));
+}
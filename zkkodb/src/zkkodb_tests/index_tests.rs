@@ -0,0 +1,246 @@
+use serde_json::{json, Value};
+
+use super::{rows, run, temp_dir};
+use crate::database::*;
+use crate::explain::Plan;
+
+fn catalog() -> Database {
+    let mut db = Database::new();
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "products",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int", "not_null": true },
+        "name": { "type": "string" },
+        "price": { "type": "int" }
+      }
+    }
+    "#).unwrap();
+    for (id, price) in [(1, 20), (2, 120), (3, 80), (4, 300), (5, 150)] {
+        let insert = format!(
+            r#"{{ "command": "insert", "table": "products", "rows": {{ "id": {}, "name": "item {}", "price": {} }} }}"#,
+            id, id, price
+        );
+        run(&mut db, &insert).unwrap();
+    }
+    db
+}
+
+fn explain(db: &mut Database, filter: &str) -> Plan {
+    let input = format!(r#"{{ "command": "explain", "query": {{ "table": "products", "filter": {} }} }}"#, filter);
+    match run(db, &input).unwrap() {
+        Output::Plan(plan) => plan,
+        other => panic!("Expected Output::Plan, got {:?}", other),
+    }
+}
+
+fn ids(db: &mut Database, filter: &str) -> Vec<Value> {
+    let input = format!(r#"{{ "command": "read", "table": "products", "filter": {} }}"#, filter);
+    rows(run(db, &input).unwrap()).into_iter().map(|row| row["id"].clone()).collect()
+}
+
+#[test]
+fn test_partial_index_serves_only_implying_filters() {
+    let mut db = catalog();
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "expensive", "column": "price", "where": { "price": { "$gt": 100 } } }"#).unwrap();
+
+    for filter in [r#"{ "price": { "$gt": 140 } }"#, r#"{ "price": { "$gte": 101 } }"#, r#"{ "price": 300 }"#] {
+        let plan = explain(&mut db, filter);
+        assert_eq!(plan.access, "index_lookup", "{}", filter);
+        assert_eq!(plan.index.as_deref(), Some("expensive (price)"));
+    }
+    assert_eq!(explain(&mut db, r#"{ "price": { "$gt": 140 } }"#).estimated_rows, 3);
+    assert_eq!(explain(&mut db, r#"{ "price": 300 }"#).estimated_rows, 1);
+    assert_eq!(ids(&mut db, r#"{ "price": { "$gt": 140 } }"#), vec![json!(4), json!(5)]);
+
+    // these can match rows the index leaves out
+    for filter in [r#"{ "price": { "$gt": 50 } }"#, r#"{ "price": { "$gte": 100 } }"#, r#"{ "name": "item 2" }"#] {
+        assert_eq!(explain(&mut db, filter).access, "full_scan", "{}", filter);
+    }
+    assert_eq!(ids(&mut db, r#"{ "price": { "$gt": 50 } }"#), vec![json!(2), json!(3), json!(4), json!(5)]);
+}
+
+#[test]
+fn test_partial_index_follows_writes() {
+    let mut db = catalog();
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "expensive", "column": "price", "where": { "price": { "$gt": 100 } } }"#).unwrap();
+    let indexed = |db: &mut Database| explain(db, r#"{ "price": { "$gt": 100 } }"#).estimated_rows;
+    assert_eq!(indexed(&mut db), 3);
+
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 6, "price": 5 } }"#).unwrap();
+    assert_eq!(indexed(&mut db), 3);
+    run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 3", "rows": { "price": 180 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 4", "rows": { "price": 90 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 5" }"#).unwrap();
+    assert_eq!(indexed(&mut db), 2);
+    assert_eq!(ids(&mut db, r#"{ "price": { "$gt": 100 } }"#), vec![json!(2), json!(3)]);
+
+    let dir = temp_dir("partial-index");
+    db.save(&dir).unwrap();
+    let mut loaded = Database::load(&dir).unwrap();
+    assert_eq!(indexed(&mut loaded), 2);
+    assert_eq!(ids(&mut loaded, r#"{ "price": { "$gt": 100 } }"#), vec![json!(2), json!(3)]);
+}
+
+#[test]
+fn test_create_type_index_builds_a_named_index() {
+    let mut db = catalog();
+    run(&mut db, r#"{ "command": "create", "type": "index", "table": "products", "columns": ["price"], "unique": true }"#).unwrap();
+    let plan = explain(&mut db, r#"{ "price": 120 }"#);
+    assert_eq!(plan.access, "index_lookup");
+    assert!(plan.index.as_deref().is_some_and(|index| index.contains("products_price_idx")), "{:?}", plan);
+    assert_eq!(ids(&mut db, r#"{ "price": 120 }"#), vec![json!(2)]);
+    let dup = run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 6, "price": 120 } }"#);
+    assert!(matches!(dup, Err(ExecError::UniqueViolation { .. })), "{:?}", dup);
+    let again = run(&mut db, r#"{ "command": "create", "type": "index", "table": "products", "name": "products_price_idx", "column": "name" }"#);
+    assert!(again.is_err());
+}
+
+#[test]
+fn test_create_index_errors() {
+    let mut db = catalog();
+    let unknown = run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_colour", "column": "colour" }"#);
+    assert!(matches!(unknown, Err(ExecError::ColumnNotFound { .. })));
+    let bad_predicate = run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "odd", "column": "price", "where": { "price": { "$near": 1 } } }"#);
+    assert!(matches!(bad_predicate, Err(ExecError::InvalidQuery(_))));
+
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_price", "column": "price" }"#).unwrap();
+    let twice = run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_price", "column": "name" }"#);
+    assert!(matches!(twice, Err(ExecError::InvalidQuery(_))));
+    // a full index only serves equality
+    assert_eq!(explain(&mut db, r#"{ "price": 80 }"#).index.as_deref(), Some("by_price (price)"));
+    assert_eq!(explain(&mut db, r#"{ "price": { "$gt": 80 } }"#).access, "full_scan");
+}
+
+#[test]
+fn test_composite_index_serves_column_prefixes() {
+    let mut db = Database::new();
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "products",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int", "not_null": true },
+        "category": { "type": "string" },
+        "price": { "type": "int" }
+      }
+    }
+    "#).unwrap();
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_category", "column": ["category", "price"] }"#).unwrap();
+    for (id, category, price) in [(1, "fruit", 2), (2, "drink", 2), (3, "fruit", 5), (4, "fruit", 2), (5, "drink", 9)] {
+        let insert = format!(
+            r#"{{ "command": "insert", "table": "products", "rows": {{ "id": {}, "category": "{}", "price": {} }} }}"#,
+            id, category, price
+        );
+        run(&mut db, &insert).unwrap();
+    }
+
+    let both = r#"{ "category": "fruit", "price": 2 }"#;
+    let plan = explain(&mut db, both);
+    assert_eq!(plan.index.as_deref(), Some("by_category (category, price)"));
+    assert_eq!(plan.estimated_rows, 2);
+    assert_eq!(ids(&mut db, both), vec![json!(1), json!(4)]);
+
+    // a prefix still narrows the rows, the remaining conditions filter them
+    let prefix = r#"{ "category": "drink", "price": { "$gt": 5 } }"#;
+    let plan = explain(&mut db, prefix);
+    assert_eq!(plan.access, "index_lookup");
+    assert_eq!(plan.estimated_rows, 2);
+    assert_eq!(ids(&mut db, prefix), vec![json!(5)]);
+
+    // the second column alone is not a prefix
+    assert_eq!(explain(&mut db, r#"{ "price": 2 }"#).access, "full_scan");
+
+    run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 3", "rows": { "price": 2 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 1" }"#).unwrap();
+    assert_eq!(explain(&mut db, both).estimated_rows, 2);
+    assert_eq!(ids(&mut db, both), vec![json!(3), json!(4)]);
+}
+
+#[test]
+fn test_unique_index_rejects_repeated_values() {
+    let mut db = catalog();
+    let create = |db: &mut Database, name: &str, columns: &str| {
+        let input = format!(
+            r#"{{ "command": "create_index", "table": "products", "name": "{}", "columns": {}, "unique": true }}"#,
+            name, columns
+        );
+        run(db, &input)
+    };
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 6, "name": "item 1", "price": 99 } }"#).unwrap();
+    let existing = create(&mut db, "by_name", r#"["name"]"#);
+    assert_eq!(existing, Err(ExecError::UniqueViolation {
+        table: "products".to_string(),
+        column: "name".to_string(),
+        value: json!("item 1"),
+    }));
+    assert!(db.describe("products").unwrap().indexes.is_empty());
+
+    create(&mut db, "by_name_price", r#"["name", "price"]"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 7, "name": "item 1", "price": 5 } }"#).unwrap();
+    let repeated = run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 8, "name": "item 1", "price": 5 } }"#);
+    assert!(matches!(&repeated, Err(ExecError::UniqueViolation { column, value, .. })
+        if column == "name, price" && *value == json!(["item 1", 5])), "{:?}", repeated);
+    let updated = run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 7", "rows": { "price": 99 } }"#);
+    assert!(matches!(updated, Err(ExecError::UniqueViolation { .. })));
+
+    // nulls never collide, and a row may keep its own values
+    for id in [8, 9] {
+        let input = format!(r#"{{ "command": "insert", "table": "products", "rows": {{ "id": {}, "name": "item 1" }} }}"#, id);
+        run(&mut db, &input).unwrap();
+    }
+    run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 7", "rows": { "price": 5 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 7" }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 10, "name": "item 1", "price": 5 } }"#).unwrap();
+
+    let dir = temp_dir("unique-index");
+    db.save(&dir).unwrap();
+    let mut loaded = Database::load(&dir).unwrap();
+    let again = run(&mut loaded, r#"{ "command": "insert", "table": "products", "rows": { "id": 11, "name": "item 1", "price": 5 } }"#);
+    assert!(matches!(again, Err(ExecError::UniqueViolation { .. })));
+}
+
+#[test]
+fn test_describe_lists_indexes() {
+    let mut db = catalog();
+    assert!(db.describe("products").unwrap().indexes.is_empty());
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_price", "column": "price", "unique": true }"#).unwrap();
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "expensive", "column": "price", "where": { "price": { "$gt": 100 } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_name_price", "columns": ["name", "price"] }"#).unwrap();
+
+    let described = serde_json::to_value(db.describe("products").unwrap().indexes).unwrap();
+    assert_eq!(
+        described,
+        json!([
+            { "name": "by_price", "columns": ["price"], "unique": true },
+            { "name": "expensive", "columns": ["price"], "where": { "price": { "$gt": 100 } }, "unique": false },
+            { "name": "by_name_price", "columns": ["name", "price"], "unique": false }
+        ])
+    );
+    let output = serde_json::to_value(run(&mut db, r#"{ "command": "describe", "table": "products" }"#).unwrap()).unwrap();
+    assert_eq!(output["indexes"], described);
+}
+
+#[test]
+fn test_drop_index() {
+    let mut db = catalog();
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_price", "column": "price", "unique": true }"#).unwrap();
+    assert_eq!(explain(&mut db, r#"{ "price": 80 }"#).access, "index_lookup");
+
+    let drop = r#"{ "command": "delete", "type": "index", "table": "products", "name": "by_price" }"#;
+    run(&mut db, drop).unwrap();
+    assert_eq!(explain(&mut db, r#"{ "price": 80 }"#).access, "full_scan");
+    assert!(db.describe("products").unwrap().indexes.is_empty());
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 6, "price": 80 } }"#).unwrap();
+    assert_eq!(ids(&mut db, r#"{ "price": 80 }"#), vec![json!(3), json!(6)]);
+
+    let missing = run(&mut db, drop);
+    assert!(matches!(missing, Err(ExecError::InvalidQuery(reason)) if reason.contains("'by_price' doesn't exist")));
+    // the column is free to go once no index uses it
+    run(&mut db, r#"{ "command": "update", "type": "rows", "table": "products", "drop": ["price"] }"#).unwrap();
+}
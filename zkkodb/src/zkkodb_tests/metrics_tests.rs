@@ -0,0 +1,42 @@
+use serde_json::json;
+
+use super::run;
+use crate::database::*;
+
+#[test]
+fn test_metrics_count_commands_and_errors() {
+    let mut db = Database::new();
+    assert_eq!(db.metrics(), None);
+    assert!(matches!(run(&mut db, r#"{ "command": "metrics" }"#), Err(ExecError::InvalidQuery(_))));
+
+    db.enable_metrics();
+    for input in [
+        r#"{ "command": "create", "type": "table", "table": "products", "primary_key": "id", "rows": { "id": { "type": "int" } } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#,
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 2 } }"#,
+        r#"{ "command": "read", "table": "products" }"#,
+        r#"{ "command": "get", "table": "products", "key": 1 }"#,
+        r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "rows": { "id": 1 } }"#,
+        r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 2" }"#,
+        r#"{ "command": "stats" }"#,
+    ] {
+        run(&mut db, input).unwrap();
+    }
+    assert!(run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).is_err());
+    assert!(run(&mut db, r#"{ "command": "read", "table": "missing" }"#).is_err());
+    db.query(serde_json::from_str(r#"{ "command": "read", "table": "missing" }"#).unwrap()).unwrap_err();
+
+    let metrics = db.metrics().unwrap();
+    let expected = json!({ "create": 1, "read": 4, "insert": 3, "update": 1, "delete": 1, "other": 1 });
+    assert_eq!(serde_json::to_value(&metrics.commands).unwrap(), expected);
+    assert_eq!(serde_json::to_value(&metrics.errors).unwrap(), json!({ "duplicate_key": 1, "table_not_found": 2 }));
+    assert_eq!(metrics.latency.iter().map(|bucket| bucket.count).sum::<u64>(), 11);
+    assert_eq!(metrics.latency.last().unwrap().le_micros, None);
+
+    // the metrics command reports the counts before itself
+    let Output::Metrics(reported) = run(&mut db, r#"{ "command": "metrics" }"#).unwrap() else {
+        panic!("Expected Output::Metrics");
+    };
+    assert_eq!(reported, metrics);
+    assert_eq!(db.metrics().unwrap().commands["other"], 2);
+}
@@ -0,0 +1,152 @@
+use serde_json::json;
+
+use super::{rows, run, temp_dir};
+use crate::database::*;
+use crate::parser::Command;
+
+fn commands(inputs: &[&str]) -> Vec<Command> {
+    inputs.iter().map(|input| serde_json::from_str(input).unwrap()).collect()
+}
+
+const CREATE_ITEMS: &str = r#"{ "command": "create", "type": "table", "table": "items", "primary_key": "id", "rows": { "id": { "type": "int" } } }"#;
+const SEED_ITEMS: &str = r#"{ "command": "insert", "table": "items", "rows": { "id": 1 } }"#;
+
+#[test]
+fn test_migration_runs_once() {
+    let mut db = Database::new();
+    assert_eq!(db.apply_migration("001_items", commands(&[CREATE_ITEMS, SEED_ITEMS])), Ok(true));
+    assert_eq!(db.apply_migration("001_items", commands(&[CREATE_ITEMS, SEED_ITEMS])), Ok(false));
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "items" }"#).unwrap()).len(), 1);
+
+    let seed = r#"{ "command": "insert", "table": "items", "rows": { "id": 2 } }"#;
+    assert_eq!(db.apply_migration("002_more_items", commands(&[seed])), Ok(true));
+    assert_eq!(db.applied_migrations(), vec!["001_items", "002_more_items"]);
+    assert_eq!(rows(run(&mut db, r#"{ "command": "read", "table": "items" }"#).unwrap()).len(), 2);
+}
+
+#[test]
+fn test_failed_migration_is_not_recorded() {
+    let mut db = Database::new();
+    let bad = r#"{ "command": "insert", "table": "missing", "rows": { "id": 1 } }"#;
+    let result = db.apply_migration("001_bad", commands(&[bad]));
+    assert_eq!(result, Err(ExecError::TableNotFound("missing".to_string())));
+    assert!(!db.migration_applied("001_bad"));
+}
+
+#[test]
+fn test_applied_migrations_survive_reopen() {
+    let dir = temp_dir("migrations");
+    {
+        let mut db = Database::open(&dir).unwrap();
+        db.apply_migration("001_items", commands(&[CREATE_ITEMS])).unwrap();
+    }
+    let mut db = Database::open(&dir).unwrap();
+    assert!(db.migration_applied("001_items"));
+    assert_eq!(db.apply_migration("001_items", commands(&[CREATE_ITEMS])), Ok(false));
+    assert_eq!(db.table_names(), vec!["_migrations", "items"]);
+}
+
+#[test]
+fn test_add_column_then_backfill() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "products", "primary_key": "id", "rows": { "id": { "type": "int" }, "name": { "type": "string" } } }"#).unwrap();
+    for (id, name) in [(1, "Coconut Water"), (2, "Hot Cocoa"), (3, "Banana")] {
+        let insert = format!(r#"{{ "command": "insert", "table": "products", "rows": {{ "id": {}, "name": "{}" }} }}"#, id, name);
+        run(&mut db, &insert).unwrap();
+    }
+    let required = run(&mut db, r#"{ "command": "update", "type": "rows", "table": "products", "add": { "sku": { "type": "string", "not_null": true } } }"#);
+    assert_eq!(required, Err(ExecError::NotNull { column: "sku".to_string() }));
+    run(&mut db, r#"{ "command": "update", "type": "rows", "table": "products", "add": {
+        "slug": { "type": "string", "unique": true },
+        "stock": { "type": "int", "default": 0 }
+    } }"#).unwrap();
+
+    let slug = |row: &Row| serde_json::json!(row["name"].as_str().unwrap().to_lowercase().replace(' ', "-"));
+    assert_eq!(db.backfill("products", "slug", slug), Ok(3));
+    let read = rows(run(&mut db, r#"{ "command": "read", "table": "products", "columns": ["slug", "stock"] }"#).unwrap());
+    let slugs: Vec<_> = read.iter().map(|row| row["slug"].as_str().unwrap()).collect();
+    assert_eq!(slugs, vec!["coconut-water", "hot-cocoa", "banana"]);
+    assert!(read.iter().all(|row| row["stock"] == serde_json::json!(0)));
+
+    // a value the column can't take stops the backfill at that row, with nothing changed
+    let failed = db.backfill("products", "slug", |row| if row["id"] == 2 { serde_json::json!(2) } else { serde_json::json!("x") });
+    let Err(ExecError::Backfill { key, error }) = failed else {
+        panic!("Expected ExecError::Backfill, got {:?}", failed);
+    };
+    assert_eq!(key, serde_json::json!(2));
+    assert!(matches!(*error, ExecError::TypeMismatch { .. }));
+    let duplicate = db.backfill("products", "slug", |_| serde_json::json!("same"));
+    assert!(matches!(duplicate, Err(ExecError::UniqueViolation { .. })));
+    let unchanged = rows(run(&mut db, r#"{ "command": "read", "table": "products", "filter": { "slug": "banana" } }"#).unwrap());
+    assert_eq!(unchanged.len(), 1);
+}
+
+#[test]
+fn test_alter_column_constraints() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "users", "primary_key": "id",
+        "rows": { "id": { "type": "int" }, "email": { "type": "string" }, "plan": { "type": "string" } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 1, "email": "a@x.io" } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 2, "email": "b@x.io" } }"#).unwrap();
+
+    run(&mut db, r#"{ "command": "update", "type": "rows", "table": "users",
+        "alter": { "email": { "not_null": true, "unique": true }, "plan": { "default": "free" } } }"#).unwrap();
+    let missing = run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 3 } }"#);
+    assert_eq!(missing, Err(ExecError::NotNull { column: "email".to_string() }));
+    let taken = run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 3, "email": "a@x.io" } }"#);
+    assert!(matches!(taken, Err(ExecError::UniqueViolation { .. })));
+    run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 3, "email": "c@x.io" } }"#).unwrap();
+    let read = rows(run(&mut db, r#"{ "command": "read", "table": "users", "filter": { "id": 3 } }"#).unwrap());
+    assert_eq!(read[0]["plan"], serde_json::json!("free"));
+
+    // plan is null in the first two rows, so nothing in the alter applies
+    let blocked = run(&mut db, r#"{ "command": "update", "type": "rows", "table": "users",
+        "alter": { "plan": { "not_null": true, "default": null }, "email": { "unique": false } } }"#);
+    assert_eq!(blocked, Err(ExecError::NullsPresent { column: "plan".to_string(), rows: 2 }));
+    let schema = db.describe("users").unwrap();
+    assert!(!schema.columns["plan"].not_null && schema.columns["email"].unique);
+    assert_eq!(schema.columns["plan"].default, Some(json!("free")));
+
+    run(&mut db, r#"{ "command": "update", "type": "rows", "table": "users", "alter": { "plan": { "default": null } } }"#).unwrap();
+    assert_eq!(db.describe("users").unwrap().columns["plan"].default, None);
+}
+
+#[test]
+fn test_drop_and_rename_columns() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "users", "primary_key": "id", "rows": {
+        "id": { "type": "int" }, "email": { "type": "string", "unique": true }, "plan": { "type": "string" },
+        "legacy": { "type": "int" } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "invites", "primary_key": "code", "rows": {
+        "code": { "type": "string" }, "sent_to": { "type": "string", "references": { "table": "users", "column": "email" } } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create_index", "table": "users", "name": "by_plan", "column": "plan" }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 1, "email": "a@x.io", "plan": "pro", "legacy": 7 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "invites", "rows": { "code": "c1", "sent_to": "a@x.io" } }"#).unwrap();
+
+    for (change, error) in [
+        (r#""drop": ["id"]"#, "key column 'id' can't be dropped"),
+        (r#""drop": ["plan"]"#, "column 'plan' is used by index 'by_plan'"),
+        (r#""drop": ["email"]"#, "column 'email' is referenced by invites.sent_to"),
+        (r#""rename": { "plan": "email" }"#, "column 'email' already exists in table 'users'"),
+        // the drop goes again with the failed rename
+        (r#""drop": ["legacy"], "rename": { "missing": "x" }"#, "missing"),
+    ] {
+        let update = format!(r#"{{ "command": "update", "type": "rows", "table": "users", {} }}"#, change);
+        let result = run(&mut db, &update);
+        assert!(result.as_ref().is_err_and(|err| err.to_string().contains(error)), "{}: {:?}", change, result);
+    }
+    assert!(db.describe("users").unwrap().columns.contains_key("legacy"));
+
+    run(&mut db, r#"{ "command": "update", "type": "rows", "table": "users", "drop": ["legacy"],
+        "rename": { "email": "address", "plan": "tier" }, "alter": { "tier": { "default": "free" } } }"#).unwrap();
+    let read = rows(run(&mut db, r#"{ "command": "read", "table": "users", "filter": { "tier": "pro" } }"#).unwrap());
+    assert_eq!(read, vec![serde_json::from_value(serde_json::json!({ "id": 1, "address": "a@x.io", "tier": "pro" })).unwrap()]);
+    let schema = db.describe("users").unwrap();
+    assert_eq!(schema.columns["tier"].default, Some(json!("free")));
+    assert!(schema.columns["address"].unique && !schema.columns.contains_key("legacy"));
+    assert_eq!(db.describe("invites").unwrap().columns["sent_to"].references.as_ref().unwrap().column, "address");
+    let orphan = run(&mut db, r#"{ "command": "insert", "table": "invites", "rows": { "code": "c2", "sent_to": "b@x.io" } }"#);
+    assert!(orphan.is_err(), "{:?}", orphan);
+    run(&mut db, r#"{ "command": "insert", "table": "users", "rows": { "id": 2, "address": "b@x.io" } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "invites", "rows": { "code": "c2", "sent_to": "b@x.io" } }"#).unwrap();
+}
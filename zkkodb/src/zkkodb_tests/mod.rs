@@ -1 +1,92 @@
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+use crate::backend::StorageBackend;
+use crate::database::{Database, ExecError, Key, Output, Row};
+use crate::parser::Command;
+
 pub mod parser_tests;
+pub mod crud_tests;
+pub mod aggregate_tests;
+pub mod events_tests;
+pub mod constraints_tests;
+pub mod filter_tests;
+pub mod storage_tests;
+pub mod ttl_tests;
+pub mod migrations_tests;
+pub mod wire_tests;
+pub mod server_tests;
+pub mod index_tests;
+pub mod snapshot_tests;
+pub mod session_tests;
+pub mod metrics_tests;
+pub mod csv_tests;
+pub mod value_tests;
+pub mod diff_tests;
+pub mod backend_tests;
+pub mod cancel_tests;
+pub mod batch_tests;
+pub mod grants_tests;
+pub mod users_tests;
+#[cfg(feature = "http")]
+pub mod http_tests;
+
+pub fn run<B: StorageBackend>(db: &mut Database<B>, input: &str) -> Result<Output, ExecError> {
+    let cmd: Command = serde_json::from_str(input).unwrap();
+    db.execute(cmd)
+}
+
+pub fn rows(output: Output) -> Vec<Row> {
+    match output {
+        Output::Rows(rows) => rows,
+        _ => panic!("Expected Output::Rows"),
+    }
+}
+
+// a fresh, empty directory under the system temp dir
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("zkkodb-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// a second store to run tests on besides the built-in one: rows in a vector
+// sorted by key, found by binary search
+#[derive(Debug, Clone, Default)]
+pub struct VecBackend {
+    rows: Vec<(Key, Row)>,
+}
+
+impl StorageBackend for VecBackend {
+    fn get(&self, key: &Key) -> Option<Cow<'_, Row>> {
+        let i = self.rows.binary_search_by(|(k, _)| k.cmp(key)).ok()?;
+        Some(Cow::Borrowed(&self.rows[i].1))
+    }
+
+    fn put(&mut self, key: Key, row: Row) {
+        match self.rows.binary_search_by(|(k, _)| k.cmp(&key)) {
+            Ok(i) => self.rows[i].1 = row,
+            Err(i) => self.rows.insert(i, (key, row)),
+        }
+    }
+
+    fn delete(&mut self, key: &Key) {
+        if let Ok(i) = self.rows.binary_search_by(|(k, _)| k.cmp(key)) {
+            self.rows.remove(i);
+        }
+    }
+
+    fn scan<'a>(&'a self, after: Option<&Key>) -> Box<dyn Iterator<Item = (&'a Key, Cow<'a, Row>)> + 'a> {
+        let start = after.map_or(0, |after| self.rows.partition_point(|(k, _)| k <= after));
+        Box::new(self.rows[start..].iter().map(|(key, row)| (key, Cow::Borrowed(row))))
+    }
+
+    fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn empty(&self) -> VecBackend {
+        VecBackend::default()
+    }
+}
@@ -1,5 +1,5 @@
-use super::*;
 use crate::parser::*;
+use crate::result::ColumnType;
 
 #[test]
 fn test_parse_create_table() {
@@ -46,6 +46,30 @@ fn test_parse_create_user() {
     println!("{:?}", parsed); 
 }
 
+#[test]
+fn test_parse_create_index() {
+    let input = r#"
+    {
+      "command": "create",
+      "type": "index",
+      "table": "products",
+      "columns": ["category", "price"],
+      "unique": true
+    }
+    "#;
+
+    let parsed: Command = serde_json::from_str(input).unwrap();
+    let Command::Create(CreateCommand::Index { table, name, columns, predicate, unique }) = parsed else {
+        panic!("Expected CreateCommand::Index, got {:?}", parsed);
+    };
+    assert_eq!((table.as_str(), name, predicate, unique), ("products", None, None, true));
+    assert_eq!(columns, vec!["category", "price"]);
+
+    let one = r#"{ "command": "create", "type": "index", "table": "products", "name": "by_price", "column": "price" }"#;
+    let parsed: Command = serde_json::from_str(one).unwrap();
+    assert!(matches!(parsed, Command::Create(CreateCommand::Index { name: Some(_), unique: false, .. })), "{:?}", parsed);
+}
+
 #[test]
 fn test_parse_read_table() {
     let input = r#"
@@ -108,9 +132,9 @@ fn test_parse_update_rows() {
     let parsed: Command = serde_json::from_str(input).unwrap();
 
     match parsed {
-        Command::Update(UpdateCommand::Rows { table, add }) => {
+        Command::Update(UpdateCommand::Rows { table, add, .. }) => {
             assert_eq!(table, "products");
-            assert_eq!(add.get("category").unwrap().col_type, "string");
+            assert_eq!(add.get("category").unwrap().col_type, ColumnType::String);
         }
         _ => panic!("Expected Command::Update::Rows"),
     }
@@ -133,7 +157,7 @@ fn test_parse_update_content() {
     let parsed: Command = serde_json::from_str(input).unwrap();
 
     match parsed {
-        Command::Update(UpdateCommand::Content { table, filter, rows }) => {
+        Command::Update(UpdateCommand::Content { table, filter, rows, .. }) => {
             assert_eq!(table, "products");
             assert_eq!(filter, "id = 1");
 
@@ -160,7 +184,7 @@ fn test_parse_insert() {
 
   let parsed: Command = serde_json::from_str(input).unwrap();
   match parsed {
-      Command::Insert(InsertCommand { table, rows }) => {
+      Command::Insert(InsertCommand { table, rows, .. }) => {
           assert_eq!(table, "products");
 
           assert_eq!(rows.get("id").unwrap().as_i64().unwrap(), 1);
@@ -203,10 +227,180 @@ fn test_parse_delete_content() {
 
   let parsed: Command = serde_json::from_str(input).unwrap();
   match parsed {
-      Command::Delete(DeleteCommand::Content { table, filter }) => {
+      Command::Delete(DeleteCommand::Content { table, filter, limit }) => {
           assert_eq!(table, "products");
           assert_eq!(filter, "price > 10");
+          assert_eq!(limit, None);
       }
       _ => panic!("Expected Command::Delete::Content"),
   }
-}
\ No newline at end of file
+}
+#[test]
+fn test_parse_filter_string() {
+  let filter = parse_filter("price > 10 AND name != 'Coconut Water' and id <= 5").unwrap();
+  assert_eq!(filter.get("price").unwrap(), &serde_json::json!({ "$gt": 10 }));
+  assert_eq!(filter.get("name").unwrap(), &serde_json::json!({ "$ne": "Coconut Water" }));
+  assert_eq!(filter.get("id").unwrap(), &serde_json::json!({ "$lte": 5 }));
+
+  let range = parse_filter("price >= 1 AND price < 3").unwrap();
+  assert_eq!(range.get("price").unwrap(), &serde_json::json!({ "$gte": 1, "$lt": 3 }));
+
+  assert_eq!(parse_filter("id = 1").unwrap().get("id").unwrap(), &serde_json::json!(1));
+  assert!(parse_filter("id =").is_err());
+  assert!(parse_filter("id = 1 OR").is_err());
+  let negated = parse_filter("NOT price > 10 AND note IS NULL AND name is not null").unwrap();
+  assert_eq!(negated.get("price").unwrap(), &serde_json::json!({ "$not": { "$gt": 10 } }));
+  assert_eq!(negated.get("note").unwrap(), &serde_json::json!({ "$is_null": true }));
+  assert_eq!(negated.get("name").unwrap(), &serde_json::json!({ "$is_null": false }));
+  assert!(parse_filter("note IS 5").is_err());
+}
+
+#[test]
+fn test_parse_filter_repeated_not_on_a_column() {
+  let both = parse_filter("NOT price > 10 AND NOT price < 5").unwrap();
+  assert_eq!(
+    serde_json::to_value(&both).unwrap(),
+    serde_json::json!({ "price": { "$not": { "$gt": 10 } }, "$and": [{ "price": { "$not": { "$lt": 5 } } }] })
+  );
+  let three = parse_filter("NOT id = 1 AND NOT id = 2 AND id < 9 AND NOT id = 3").unwrap();
+  assert_eq!(
+    serde_json::to_value(&three).unwrap(),
+    serde_json::json!({
+      "id": { "$not": { "$eq": 1 }, "$lt": 9 },
+      "$and": [{ "id": { "$not": { "$eq": 2 } } }, { "id": { "$not": { "$eq": 3 } } }]
+    })
+  );
+  assert!(parse_filter("price > 1 AND price > 5").is_err());
+}
+
+#[test]
+fn test_parse_filter_precedence() {
+  // AND binds tighter than OR
+  let filter = parse_filter("id = 1 OR price > 10 AND name = 'x'").unwrap();
+  assert_eq!(
+    serde_json::to_value(&filter).unwrap(),
+    serde_json::json!({ "$or": [{ "id": 1 }, { "price": { "$gt": 10 }, "name": "x" }] })
+  );
+  // parentheses group first
+  let grouped = parse_filter("(id = 1 OR price > 10) AND name = 'x'").unwrap();
+  assert_eq!(
+    serde_json::to_value(&grouped).unwrap(),
+    serde_json::json!({ "$or": [{ "id": 1 }, { "price": { "$gt": 10 } }], "name": "x" })
+  );
+  // NOT binds tighter than AND, and negates a whole group
+  let negated = parse_filter("NOT (id = 1 OR id = 2) AND NOT price > 10 OR id = 3").unwrap();
+  assert_eq!(
+    serde_json::to_value(&negated).unwrap(),
+    serde_json::json!({ "$or": [
+      { "$not": { "$or": [{ "id": 1 }, { "id": 2 }] }, "price": { "$not": { "$gt": 10 } } },
+      { "id": 3 }
+    ] })
+  );
+  // two groups joined by AND are both kept
+  let both = parse_filter("(id = 1 OR id = 2) AND (price < 1 OR price > 10)").unwrap();
+  assert_eq!(
+    serde_json::to_value(&both).unwrap(),
+    serde_json::json!({ "$and": [
+      { "$or": [{ "id": 1 }, { "id": 2 }] },
+      { "$or": [{ "price": { "$lt": 1 } }, { "price": { "$gt": 10 } }] }
+    ] })
+  );
+  assert_eq!(parse_filter("((id = 1))").unwrap(), parse_filter("id = 1").unwrap());
+  assert!(parse_filter("(id = 1").is_err());
+  assert!(parse_filter("id = 1)").is_err());
+  assert!(parse_filter("()").is_err());
+}
+
+#[test]
+fn test_parse_filter_between() {
+  let filter = parse_filter("price BETWEEN 10 AND 50 AND name = 'x'").unwrap();
+  assert_eq!(filter.get("price").unwrap(), &serde_json::json!({ "$between": [10, 50] }));
+  assert_eq!(filter.get("name").unwrap(), &serde_json::json!("x"));
+  assert!(parse_filter("price BETWEEN 10 50").is_err());
+}
+
+#[test]
+fn test_parse_command_errors() {
+    let parsed = parse_command(r#"{ "command": "read", "table": "products" }"#).unwrap();
+    assert!(matches!(parsed, Command::Read(ReadCommand { ref table, .. }) if table == "products"));
+
+    assert_eq!(
+        parse_command("{\n  \"command\": \"read\",\n  \"table\": }").unwrap_err(),
+        ParseError::InvalidJson { line: 3, col: 12 }
+    );
+    assert_eq!(
+        parse_command(r#"{ "command": "select", "table": "products" }"#).unwrap_err(),
+        ParseError::UnknownCommand { command: "select".to_string() }
+    );
+    assert_eq!(
+        parse_command(r#"{ "command": "delete", "type": "everything", "table": "products" }"#).unwrap_err(),
+        ParseError::UnknownCommand { command: "delete everything".to_string() }
+    );
+    assert_eq!(
+        parse_command(r#"{ "command": "create", "table": "products" }"#).unwrap_err(),
+        ParseError::MissingField { field: "type".to_string() }
+    );
+    assert_eq!(
+        parse_command(r#"{ "table": "products" }"#).unwrap_err(),
+        ParseError::MissingField { field: "command".to_string() }
+    );
+    assert_eq!(
+        parse_command(r#"{ "command": "insert", "rows": {} }"#).unwrap_err(),
+        ParseError::MissingField { field: "table".to_string() }
+    );
+    let create = r#"{ "command": "create", "type": "table", "table": "t", "primary_key": "id", "rows": { "id": { "type": "blob" } } }"#;
+    assert_eq!(parse_command(create).unwrap_err(), ParseError::UnknownColumnType { got: "blob".to_string() });
+    let add = r#"{ "command": "update", "type": "rows", "table": "t", "add": { "stock": { "type": "intt" } } }"#;
+    assert_eq!(parse_command(add).unwrap_err(), ParseError::UnknownColumnType { got: "intt".to_string() });
+}
+
+#[test]
+fn test_column_types_parse_in_any_case() {
+    let def: ColumnDefinition = serde_json::from_str(r#"{ "type": "DateTime" }"#).unwrap();
+    assert_eq!(def.col_type, ColumnType::Datetime);
+    assert_eq!(serde_json::to_value(&def).unwrap()["type"], "datetime");
+    let unknown = serde_json::from_str::<ColumnDefinition>(r#"{ "type": "intt" }"#).unwrap_err();
+    assert!(unknown.to_string().contains("unknown column type 'intt'"), "{}", unknown);
+}
+
+#[test]
+fn test_parse_command_limits() {
+    let read = r#"{ "command": "read", "table": "products" }"#;
+    assert!(parse_command_limited(read, read.len()).is_ok());
+    let err = parse_command_limited(read, read.len() - 1).unwrap_err();
+    assert_eq!(err, ParseError::TooLarge { max: read.len() - 1 });
+    assert_eq!(err.to_string(), format!("input exceeds the maximum of {} bytes", read.len() - 1));
+
+    let nested = format!(
+        r#"{{ "command": "read", "table": "products", "filter": {{ "id": {}1{} }} }}"#,
+        "[".repeat(10_000),
+        "]".repeat(10_000)
+    );
+    assert_eq!(parse_command(&nested).unwrap_err(), ParseError::TooDeep { max: MAX_NESTING });
+    // brackets inside strings don't count
+    let quoted = format!(r#"{{ "command": "read", "table": "{}" }}"#, "[{".repeat(100));
+    assert!(parse_command(&quoted).is_ok());
+}
+
+#[test]
+fn test_strict_parsing_rejects_duplicate_fields() {
+    let insert = r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "price": 1, "price": 2 } }"#;
+    // the lenient default keeps the last value
+    match parse_command(insert).unwrap() {
+        Command::Insert(insert) => assert_eq!(insert.rows["price"], serde_json::json!(2)),
+        _ => panic!("Expected Command::Insert"),
+    }
+    let err = parse_command_strict(insert).unwrap_err();
+    assert_eq!(err, ParseError::DuplicateField { field: "rows.price".to_string() });
+    assert_eq!(err.to_string(), "duplicate field 'rows.price'");
+
+    let filter = r#"{ "command": "read", "table": "products", "filter": { "price": { "$gt": 1, "$gt": 5 } } }"#;
+    assert_eq!(parse_command_strict(filter).unwrap_err(), ParseError::DuplicateField { field: "filter.price.$gt".to_string() });
+    let joined = r#"{ "command": "read", "table": "products", "aggregates": [{ "function": "sum", "column": "a", "column": "b" }] }"#;
+    assert_eq!(parse_command_strict(joined).unwrap_err(), ParseError::DuplicateField { field: "aggregates[0].column".to_string() });
+
+    // the same key in sibling objects is fine, and other errors are reported as before
+    let read = r#"{ "command": "read", "table": "products", "filter": { "price": { "$gt": 1 }, "stock": { "$gt": 1 } } }"#;
+    assert!(parse_command_strict(read).is_ok());
+    assert!(matches!(parse_command_strict("{ \"command\": "), Err(ParseError::InvalidJson { .. })));
+}
@@ -0,0 +1,169 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::database::*;
+use crate::parser::parse_command;
+use crate::server::{serve, AsyncDatabase, RateLimiter, MAX_LINE_BYTES};
+use crate::session::Session;
+use crate::wire::WireFormat;
+
+fn create(table: &str) -> String {
+    format!(
+        r#"{{ "command": "create", "type": "table", "table": "{}", "primary_key": "id", "rows": {{ "id": {{ "type": "int" }} }} }}"#,
+        table
+    )
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_tasks() {
+    let db = Arc::new(AsyncDatabase::new(Database::new()));
+    let mut tasks = Vec::new();
+    for task in 0..8 {
+        let db = Arc::clone(&db);
+        tasks.push(tokio::spawn(async move {
+            let table = format!("t{}", task);
+            db.execute(parse_command(&create(&table)).unwrap()).await.unwrap();
+            for id in 0..10 {
+                let insert = format!(r#"{{ "command": "insert", "table": "{}", "rows": {{ "id": {} }} }}"#, table, id);
+                db.execute(parse_command(&insert).unwrap()).await.unwrap();
+            }
+            let read = format!(r#"{{ "command": "read", "table": "{}" }}"#, table);
+            db.execute(parse_command(&read).unwrap()).await.unwrap()
+        }));
+    }
+    for task in tasks {
+        match task.await.unwrap() {
+            Output::Rows(rows) => assert_eq!(rows.len(), 10),
+            other => panic!("Expected Output::Rows, got {:?}", other),
+        }
+    }
+
+    let db = Arc::try_unwrap(db).unwrap().into_inner();
+    assert_eq!(db.table_names().len(), 8);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_tcp_server_speaks_json_lines() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, Arc::new(AsyncDatabase::new(Database::new()))));
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut send = async |input: &str| -> Value {
+        writer.write_all(format!("{}\n", input).as_bytes()).await.unwrap();
+        serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap()
+    };
+
+    assert_eq!(send(&create("products")).await, Value::Null);
+    assert_eq!(send(r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).await, Value::Null);
+    assert_eq!(send(r#"{ "command": "read", "table": "products" }"#).await, json!([{ "id": 1 }]));
+
+    let error = send(r#"{ "command": "read", "table": "missing" }"#).await;
+    assert_eq!(error, json!({ "error": "table 'missing' does not exist" }));
+    let error = send("{ not json").await;
+    assert!(error["error"].as_str().unwrap().starts_with("invalid JSON"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_tcp_server_speaks_messagepack_frames() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, Arc::new(AsyncDatabase::new(Database::new()))));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"Content-Type: application/msgpack\n").await.unwrap();
+    let mut send = async |frame: Vec<u8>| -> Value {
+        stream.write_all(&(frame.len() as u32).to_be_bytes()).await.unwrap();
+        stream.write_all(&frame).await.unwrap();
+        let mut length = [0; 4];
+        stream.read_exact(&mut length).await.unwrap();
+        let mut response = vec![0; u32::from_be_bytes(length) as usize];
+        stream.read_exact(&mut response).await.unwrap();
+        rmp_serde::from_slice(&response).unwrap()
+    };
+    let command = |input: &str| WireFormat::MessagePack.encode(&parse_command(input).unwrap()).unwrap();
+
+    assert_eq!(send(command(&create("products"))).await, Value::Null);
+    assert_eq!(send(command(r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#)).await, Value::Null);
+    assert_eq!(send(command(r#"{ "command": "read", "table": "products" }"#)).await, json!([{ "id": 1 }]));
+    let error = send(command(r#"{ "command": "read", "table": "missing" }"#)).await;
+    assert_eq!(error, json!({ "error": "table 'missing' does not exist" }));
+    let error = send(b"\xc1".to_vec()).await;
+    assert!(error["error"].as_str().unwrap().starts_with("could not decode command"));
+
+    // an unknown content type is an error and the connection stays on JSON lines
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer.write_all(b"content-type: text/csv\n{ \"command\": \"list_tables\" }\n").await.unwrap();
+    let error: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+    assert_eq!(error, json!({ "error": "unknown content type 'text/csv'" }));
+    assert_eq!(lines.next_line().await.unwrap().unwrap(), r#"["products"]"#);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_tcp_server_rejects_oversized_lines() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, Arc::new(AsyncDatabase::new(Database::new()))));
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let oversized = format!(r#"{{ "command": "read", "table": "{}" }}"#, "x".repeat(MAX_LINE_BYTES));
+    writer.write_all(format!("{}\n", oversized).as_bytes()).await.unwrap();
+    let error: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+    assert_eq!(error, json!({ "error": format!("input exceeds the maximum of {} bytes", MAX_LINE_BYTES) }));
+
+    // the connection stays usable
+    writer.write_all(format!("{}\n", create("products")).as_bytes()).await.unwrap();
+    assert_eq!(lines.next_line().await.unwrap().unwrap(), "null");
+}
+
+#[tokio::test]
+async fn test_rate_limit_per_session_and_user() {
+    let db = AsyncDatabase::new(Database::new()).with_rate_limiter(RateLimiter::new(1.0, 3));
+    let (mut first, mut second) = (Session::new(), Session::new());
+    db.execute_in(&mut first, parse_command(&create("t")).unwrap()).await.unwrap();
+    let read = || parse_command(r#"{ "command": "read", "table": "t" }"#).unwrap();
+    db.execute_in(&mut first, read()).await.unwrap();
+    db.execute_in(&mut first, read()).await.unwrap();
+    match db.execute_in(&mut first, read()).await {
+        Err(ExecError::RateLimited { .. }) => {}
+        other => panic!("Expected ExecError::RateLimited, got {:?}", other),
+    }
+    // other sessions have their own bucket
+    db.execute_in(&mut second, read()).await.unwrap();
+
+    // a user's sessions share one, and admins aren't limited
+    let (mut ana, mut bo) = (Session::authenticated("ana", "writer"), Session::authenticated("ana", "writer"));
+    for _ in 0..3 {
+        db.execute_in(&mut ana, read()).await.unwrap();
+    }
+    assert!(matches!(db.execute_in(&mut bo, read()).await, Err(ExecError::RateLimited { .. })));
+    let mut admin = Session::authenticated("root", "admin");
+    for _ in 0..10 {
+        db.execute_in(&mut admin, read()).await.unwrap();
+    }
+}
+
+#[test]
+fn test_rate_limiter_refills() {
+    let limiter = RateLimiter::new(10.0, 2);
+    let start = Instant::now();
+    assert!(limiter.take("a", start));
+    assert!(limiter.take("a", start));
+    assert!(!limiter.take("a", start));
+    assert!(!limiter.take("a", start + Duration::from_millis(50)));
+    assert!(limiter.take("a", start + Duration::from_millis(100)));
+    assert!(!limiter.take("a", start + Duration::from_millis(100)));
+    // refilling stops at the burst
+    let later = start + Duration::from_secs(60);
+    assert!(limiter.take("a", later) && limiter.take("a", later));
+    assert!(!limiter.take("a", later));
+}
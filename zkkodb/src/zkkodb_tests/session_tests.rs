@@ -0,0 +1,242 @@
+use serde_json::json;
+
+use super::{rows, run, temp_dir};
+use crate::database::*;
+use crate::parser::parse_command;
+use crate::server::AsyncDatabase;
+use crate::session::Session;
+
+fn products() -> Database {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "products", "primary_key": "id", "rows": { "id": { "type": "int" }, "name": { "type": "string" } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Mango" } }"#).unwrap();
+    db
+}
+
+fn exec(db: &mut Database, session: &mut Session, input: &str) -> Result<Output, ExecError> {
+    db.execute_in(session, parse_command(input).unwrap())
+}
+
+fn names(db: &mut Database, session: &mut Session) -> Vec<serde_json::Value> {
+    let output = exec(db, session, r#"{ "command": "read", "table": "products" }"#).unwrap();
+    rows(output).into_iter().map(|row| row["name"].clone()).collect()
+}
+
+const BEGIN: &str = r#"{ "command": "begin" }"#;
+const COMMIT: &str = r#"{ "command": "commit" }"#;
+const ROLLBACK: &str = r#"{ "command": "rollback" }"#;
+
+#[test]
+fn test_uncommitted_writes_are_isolated_between_sessions() {
+    let mut db = products();
+    let (mut writer, mut reader) = (Session::new(), Session::new());
+
+    exec(&mut db, &mut writer, BEGIN).unwrap();
+    exec(&mut db, &mut writer, r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Fig" } }"#).unwrap();
+    exec(&mut db, &mut writer, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "rows": { "name": "Lime" } }"#).unwrap();
+    assert_eq!(names(&mut db, &mut writer), vec![json!("Lime"), json!("Fig")]);
+    assert_eq!(names(&mut db, &mut reader), vec![json!("Mango")]);
+
+    exec(&mut db, &mut writer, COMMIT).unwrap();
+    assert!(!writer.in_transaction());
+    assert_eq!(names(&mut db, &mut reader), vec![json!("Lime"), json!("Fig")]);
+
+    exec(&mut db, &mut writer, BEGIN).unwrap();
+    exec(&mut db, &mut writer, r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 2" }"#).unwrap();
+    exec(&mut db, &mut writer, ROLLBACK).unwrap();
+    assert_eq!(names(&mut db, &mut writer), vec![json!("Lime"), json!("Fig")]);
+}
+
+#[test]
+fn test_rollback_to_savepoint_keeps_earlier_work() {
+    let mut db = products();
+    let mut session = Session::new();
+    let savepoint = r#"{ "command": "savepoint", "name": "before_kiwi" }"#;
+    let rollback_to = r#"{ "command": "rollback_to", "name": "before_kiwi" }"#;
+    assert!(matches!(exec(&mut db, &mut session, savepoint), Err(ExecError::InvalidQuery(_))));
+
+    exec(&mut db, &mut session, BEGIN).unwrap();
+    exec(&mut db, &mut session, r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Fig" } }"#).unwrap();
+    exec(&mut db, &mut session, savepoint).unwrap();
+    exec(&mut db, &mut session, r#"{ "command": "insert", "table": "products", "rows": { "id": 3, "name": "Kiwi" } }"#).unwrap();
+    exec(&mut db, &mut session, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "rows": { "name": "Lime" } }"#).unwrap();
+    exec(&mut db, &mut session, rollback_to).unwrap();
+    assert!(session.in_transaction());
+    assert_eq!(names(&mut db, &mut session), vec![json!("Mango"), json!("Fig")]);
+
+    // the savepoint stays set and can be returned to again
+    exec(&mut db, &mut session, r#"{ "command": "insert", "table": "products", "rows": { "id": 4, "name": "Pear" } }"#).unwrap();
+    exec(&mut db, &mut session, rollback_to).unwrap();
+    let unknown = exec(&mut db, &mut session, r#"{ "command": "rollback_to", "name": "nowhere" }"#);
+    assert!(matches!(unknown, Err(ExecError::InvalidQuery(_))));
+
+    exec(&mut db, &mut session, COMMIT).unwrap();
+    assert_eq!(names(&mut db, &mut Session::new()), vec![json!("Mango"), json!("Fig")]);
+    // savepoints end with their transaction
+    exec(&mut db, &mut session, BEGIN).unwrap();
+    assert!(matches!(exec(&mut db, &mut session, rollback_to), Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_conflicting_commit_is_rejected() {
+    let mut db = products();
+    let (mut first, mut second) = (Session::new(), Session::new());
+    exec(&mut db, &mut first, BEGIN).unwrap();
+    exec(&mut db, &mut second, BEGIN).unwrap();
+    exec(&mut db, &mut first, r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Fig" } }"#).unwrap();
+    exec(&mut db, &mut second, r#"{ "command": "insert", "table": "products", "rows": { "id": 3, "name": "Kiwi" } }"#).unwrap();
+
+    exec(&mut db, &mut first, COMMIT).unwrap();
+    let conflict = exec(&mut db, &mut second, COMMIT);
+    assert!(matches!(conflict, Err(ExecError::Conflict { table }) if table == "products"));
+    assert!(!second.in_transaction());
+    assert_eq!(names(&mut db, &mut second), vec![json!("Mango"), json!("Fig")]);
+}
+
+#[test]
+fn test_session_errors_and_settings() {
+    let mut db = products();
+    let mut session = Session::authenticated("ana", "admin");
+    assert_eq!(session.user.as_deref(), Some("ana"));
+    assert!(matches!(exec(&mut db, &mut session, COMMIT), Err(ExecError::InvalidQuery(_))));
+    exec(&mut db, &mut session, BEGIN).unwrap();
+    assert!(matches!(exec(&mut db, &mut session, BEGIN), Err(ExecError::InvalidQuery(_))));
+    exec(&mut db, &mut session, ROLLBACK).unwrap();
+    // without a session there is no transaction to begin
+    assert!(matches!(run(&mut db, BEGIN), Err(ExecError::InvalidQuery(_))));
+
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Fig" } }"#).unwrap();
+    session.settings.max_rows = Some(1);
+    let read = exec(&mut db, &mut session, r#"{ "command": "read", "table": "products" }"#).unwrap();
+    assert!(matches!(read, Output::Truncated { rows, .. } if rows.len() == 1));
+
+    session.settings.timeout = Some(std::time::Duration::ZERO);
+    exec(&mut db, &mut session, BEGIN).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    let expired = exec(&mut db, &mut session, r#"{ "command": "insert", "table": "products", "rows": { "id": 3 } }"#);
+    assert!(matches!(expired, Err(ExecError::InvalidQuery(_))));
+    assert!(!session.in_transaction());
+}
+
+#[test]
+fn test_list_and_drop_tables_by_pattern() {
+    let mut db = products();
+    for table in ["log_a", "log_b", "logins"] {
+        let create = format!(r#"{{ "command": "create", "type": "table", "table": "{}", "primary_key": "id", "rows": {{ "id": {{ "type": "int" }} }} }}"#, table);
+        run(&mut db, &create).unwrap();
+    }
+    let listed = run(&mut db, r#"{ "command": "list_tables", "pattern": "log_*" }"#).unwrap();
+    assert_eq!(listed, Output::Tables(vec!["log_a".to_string(), "log_b".to_string()]));
+    let all = run(&mut db, r#"{ "command": "list_tables" }"#).unwrap();
+    assert!(matches!(all, Output::Tables(names) if names.len() == 4));
+
+    let drop = r#"{ "command": "drop_tables", "pattern": "log_*", "confirm": true }"#;
+    let unconfirmed = run(&mut db, r#"{ "command": "drop_tables", "pattern": "log_*" }"#);
+    assert!(matches!(unconfirmed, Err(ExecError::InvalidQuery(_))));
+    let mut user = Session::authenticated("bo", "reader");
+    assert!(matches!(exec(&mut db, &mut user, drop), Err(ExecError::PermissionDenied(_))));
+
+    let mut admin = Session::authenticated("ana", "admin");
+    assert_eq!(exec(&mut db, &mut admin, drop).unwrap(), Output::Affected(2));
+    let left = run(&mut db, r#"{ "command": "list_tables" }"#).unwrap();
+    assert_eq!(left, Output::Tables(vec!["logins".to_string(), "products".to_string()]));
+    let nothing = r#"{ "command": "drop_tables", "pattern": "nothing_*", "confirm": true }"#;
+    assert_eq!(exec(&mut db, &mut admin, nothing).unwrap(), Output::Affected(0));
+}
+
+#[test]
+fn test_list_type_tables_shows_row_counts_and_primary_keys() {
+    let mut db = products();
+    let create = r#"{ "command": "create", "type": "table", "table": "logs", "primary_key": "at", "rows": { "at": { "type": "int" } } }"#;
+    run(&mut db, create).unwrap();
+    let listed = run(&mut db, r#"{ "command": "list", "type": "tables" }"#).unwrap();
+    assert_eq!(
+        serde_json::to_value(listed).unwrap(),
+        json!([
+            { "name": "logs", "rows": 0, "primary_key": "at" },
+            { "name": "products", "rows": 1, "primary_key": "id" }
+        ])
+    );
+    let matching = run(&mut db, r#"{ "command": "list", "type": "tables", "pattern": "pro*" }"#).unwrap();
+    let detailed = run(&mut db, r#"{ "command": "list_tables", "pattern": "pro*", "details": true }"#).unwrap();
+    assert_eq!(matching, detailed);
+    assert_eq!(serde_json::to_value(matching).unwrap(), json!([{ "name": "products", "rows": 1, "primary_key": "id" }]));
+}
+
+#[test]
+fn test_commit_writes_the_log_and_sends_events() {
+    let dir = temp_dir("session-wal");
+    let mut db = Database::open(&dir).unwrap();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "products", "primary_key": "id", "rows": { "id": { "type": "int" }, "name": { "type": "string" } } }"#).unwrap();
+    let events = db.subscribe("products");
+    let mut session = Session::new();
+
+    exec(&mut db, &mut session, BEGIN).unwrap();
+    exec(&mut db, &mut session, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Fig" } }"#).unwrap();
+    assert!(events.try_recv().is_err());
+    exec(&mut db, &mut session, COMMIT).unwrap();
+    assert_eq!(events.try_recv().unwrap().keys, vec![json!(1)]);
+
+    drop(db);
+    let mut reopened = Database::open(&dir).unwrap();
+    assert_eq!(rows(run(&mut reopened, r#"{ "command": "read", "table": "products" }"#).unwrap()).len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_async_sessions_are_isolated() {
+    let db = AsyncDatabase::new(products());
+    let (mut writer, mut reader) = (Session::new(), Session::new());
+    let cmd = |input: &str| parse_command(input).unwrap();
+    let read = r#"{ "command": "read", "table": "products" }"#;
+
+    db.execute_in(&mut writer, cmd(BEGIN)).await.unwrap();
+    db.execute_in(&mut writer, cmd(r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Fig" } }"#)).await.unwrap();
+    assert_eq!(rows(db.execute_in(&mut writer, cmd(read)).await.unwrap()).len(), 2);
+    assert_eq!(rows(db.execute_in(&mut reader, cmd(read)).await.unwrap()).len(), 1);
+    db.execute_in(&mut writer, cmd(COMMIT)).await.unwrap();
+    assert_eq!(rows(db.execute_in(&mut reader, cmd(read)).await.unwrap()).len(), 2);
+}
+
+#[test]
+fn test_committed_rename_carries_views_along() {
+    let mut db = products();
+    run(&mut db, r#"{ "command": "create_view", "name": "mangos", "query": { "table": "products", "filter": { "name": "Mango" } } }"#).unwrap();
+    let mut session = Session::new();
+    exec(&mut db, &mut session, BEGIN).unwrap();
+    exec(&mut db, &mut session, r#"{ "command": "update", "type": "rename", "table": "products", "to": "items" }"#).unwrap();
+    assert!(run(&mut db, r#"{ "command": "read", "table": "mangos" }"#).is_ok());
+    exec(&mut db, &mut session, COMMIT).unwrap();
+
+    assert_eq!(db.table_names(), vec!["items"]);
+    let mangos = rows(run(&mut db, r#"{ "command": "read", "table": "mangos" }"#).unwrap());
+    assert_eq!(mangos[0]["id"], json!(1));
+}
+
+#[test]
+fn test_prepared_reads_are_kept_by_the_session() {
+    let mut db = products();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Fig" } }"#).unwrap();
+    let mut session = Session::new();
+    let prepare = r#"{ "command": "prepare", "name": "by_name", "query": { "table": "products", "filter": { "name": "?name" } } }"#;
+    exec(&mut db, &mut session, prepare).unwrap();
+
+    let by_name = |db: &mut Database, session: &mut Session, name: &str| {
+        let input = format!(r#"{{ "command": "execute", "name": "by_name", "params": {{ "name": "{}" }} }}"#, name);
+        exec(db, session, &input).map(rows)
+    };
+    assert_eq!(by_name(&mut db, &mut session, "Fig").unwrap()[0]["id"], json!(2));
+    // a bound value is only ever compared, never read as a filter
+    assert!(by_name(&mut db, &mut session, "Fig' OR '1' = '1").unwrap().is_empty());
+
+    // reads in a transaction see its writes
+    exec(&mut db, &mut session, BEGIN).unwrap();
+    exec(&mut db, &mut session, r#"{ "command": "insert", "table": "products", "rows": { "id": 3, "name": "Lime" } }"#).unwrap();
+    assert_eq!(by_name(&mut db, &mut session, "Lime").unwrap().len(), 1);
+    exec(&mut db, &mut session, ROLLBACK).unwrap();
+
+    let unbound = exec(&mut db, &mut session, r#"{ "command": "execute", "name": "by_name" }"#);
+    assert!(matches!(unbound, Err(ExecError::InvalidQuery(reason)) if reason.contains("'name' is not bound")));
+    let elsewhere = by_name(&mut db, &mut Session::new(), "Fig");
+    assert!(matches!(elsewhere, Err(ExecError::InvalidQuery(reason)) if reason.contains("no read is prepared as 'by_name'")));
+    assert!(matches!(run(&mut db, prepare), Err(ExecError::InvalidQuery(_))));
+}
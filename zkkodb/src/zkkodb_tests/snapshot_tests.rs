@@ -0,0 +1,57 @@
+use serde_json::json;
+
+use super::run;
+use crate::database::*;
+use crate::parser::{parse_command, ReadCommand};
+use crate::server::AsyncDatabase;
+
+fn products() -> Database {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "products", "primary_key": "id", "rows": { "id": { "type": "int" }, "name": { "type": "string" } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Mango" } }"#).unwrap();
+    db
+}
+
+fn all() -> ReadCommand {
+    serde_json::from_str(r#"{ "table": "products" }"#).unwrap()
+}
+
+#[test]
+fn test_snapshot_does_not_see_later_writes() {
+    let mut db = products();
+    let before = db.snapshot();
+
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Fig" } }"#).unwrap();
+    run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "rows": { "name": "Lime" } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "orders", "primary_key": "id", "rows": { "id": { "type": "int" } } }"#).unwrap();
+
+    let seen = before.read(&all()).unwrap();
+    assert_eq!(seen.len(), 1);
+    assert_eq!(seen[0]["name"], json!("Mango"));
+    assert_eq!(before.table_names(), vec!["products"]);
+
+    // re-reading through a new snapshot sees the committed writes
+    let after = db.snapshot();
+    let seen = after.read(&all()).unwrap();
+    assert_eq!(seen.len(), 2);
+    assert_eq!(seen[0]["name"], json!("Lime"));
+    assert_eq!(before.read(&all()).unwrap()[0]["name"], json!("Mango"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_async_snapshot_does_not_block_writers() {
+    let db = AsyncDatabase::new(products());
+    let snapshot = db.snapshot().await;
+
+    let insert = r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Fig" } }"#;
+    db.execute(parse_command(insert).unwrap()).await.unwrap();
+    let read = || parse_command(r#"{ "command": "read", "table": "products" }"#).unwrap();
+    let Output::Rows(live) = db.execute(read()).await.unwrap() else {
+        panic!("Expected Output::Rows");
+    };
+    assert_eq!(live.len(), 2);
+    let Output::Rows(old) = snapshot.query(read()).unwrap() else {
+        panic!("Expected Output::Rows");
+    };
+    assert_eq!(old.len(), 1);
+}
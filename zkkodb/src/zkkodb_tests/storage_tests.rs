@@ -0,0 +1,738 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use serde_json::json;
+
+use super::{rows, run, temp_dir, VecBackend};
+use crate::backend::{MemoryBackend, StorageBackend};
+use crate::database::*;
+use crate::storage::{Integrity, StorageError};
+
+const CREATE: &str = r#"
+{
+  "command": "create",
+  "type": "table",
+  "table": "products",
+  "primary_key": "id",
+  "rows": {
+    "id": { "type": "int", "not_null": true },
+    "name": { "type": "string", "default": "Unnamed" },
+    "price": { "type": "float" }
+  }
+}
+"#;
+
+fn run_in<B: StorageBackend>(db: &mut Database<B>, session: &mut crate::session::Session<B>, input: &str) {
+    db.execute_in(session, crate::parser::parse_command(input).unwrap()).unwrap();
+}
+
+fn ids<B: StorageBackend>(db: &mut Database<B>) -> Vec<serde_json::Value> {
+    rows(run(db, r#"{ "command": "read", "table": "products" }"#).unwrap())
+        .iter()
+        .map(|row| row["id"].clone())
+        .collect()
+}
+
+#[test]
+fn test_save_and_load_snapshot() {
+    save_and_load_snapshot(MemoryBackend::new());
+    save_and_load_snapshot(VecBackend::default());
+}
+
+fn save_and_load_snapshot<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("snapshot");
+    let mut db = Database::with_backend(backend.empty());
+    run(&mut db, CREATE).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "price": 1.5 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Mango" } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create_view", "name": "cheap", "query": { "table": "products", "filter": { "price": { "$lt": 2 } } } }"#).unwrap();
+    db.save(&dir).unwrap();
+
+    let mut loaded = Database::load_with_backend(&dir, backend.empty(), None).unwrap();
+    assert_eq!(ids(&mut loaded), vec![json!(1), json!(2)]);
+    // loaded into a store from `backend`
+    let store = std::any::type_name::<B>().rsplit("::").next().unwrap();
+    assert!(format!("{:?}", loaded.table("products").unwrap().rows).contains(store));
+    assert_eq!(loaded.view_names(), vec!["cheap"]);
+    let cheap = rows(run(&mut loaded, r#"{ "command": "read", "table": "cheap" }"#).unwrap());
+    assert_eq!(cheap[0]["name"], json!("Unnamed"));
+
+    // dropped tables disappear from the next snapshot
+    run(&mut db, r#"{ "command": "delete", "type": "table", "table": "products" }"#).unwrap();
+    db.save(&dir).unwrap();
+    assert!(Database::load_with_backend(&dir, backend.empty(), None).unwrap().table_names().is_empty());
+}
+
+#[test]
+fn test_wal_replays_over_snapshot() {
+    wal_replays_over_snapshot(MemoryBackend::new());
+    wal_replays_over_snapshot(VecBackend::default());
+}
+
+fn wal_replays_over_snapshot<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("wal-replay");
+    {
+        let mut db = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+        run(&mut db, CREATE).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).unwrap();
+        db.save(&dir).unwrap();
+        assert_eq!(std::fs::metadata(dir.join("wal.log")).unwrap().len(), 0);
+
+        // these only reach the log before the "crash"
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2 } }"#).unwrap();
+        run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "rows": { "price": 9.5 } }"#).unwrap();
+        run(&mut db, r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 7" }"#).unwrap();
+    }
+
+    assert_eq!(ids(&mut Database::load_with_backend(&dir, backend.empty(), None).unwrap()), vec![json!(1)]);
+
+    let mut recovered = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+    assert_eq!(ids(&mut recovered), vec![json!(1), json!(2)]);
+    let store = std::any::type_name::<B>().rsplit("::").next().unwrap();
+    assert!(format!("{:?}", recovered.table("products").unwrap().rows).contains(store));
+    let first = rows(run(&mut recovered, r#"{ "command": "read", "table": "products", "filter": { "id": 1 } }"#).unwrap());
+    assert_eq!(first[0]["price"], json!(9.5));
+}
+
+#[test]
+fn test_wal_replays_generated_values_and_expiry_exactly() {
+    wal_replays_generated_values_and_expiry_exactly(MemoryBackend::new());
+    wal_replays_generated_values_and_expiry_exactly(VecBackend::default());
+}
+
+fn wal_replays_generated_values_and_expiry_exactly<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("wal-stamps");
+    let read = |db: &mut Database<B>, table: &str| {
+        rows(run(db, &format!(r#"{{ "command": "read", "table": "{}", "order_by": [{{ "column": "n" }}] }}"#, table)).unwrap())
+    };
+    let before = {
+        let mut db = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+        run(&mut db, r#"{ "command": "create", "type": "table", "table": "events", "primary_key": "n", "timestamps": true, "rows": {
+            "n": { "type": "int" },
+            "id": { "type": "uuid", "default": "uuid()" },
+            "at": { "type": "datetime", "default": "now()" }
+        } }"#).unwrap();
+        run(&mut db, r#"{ "command": "create", "type": "table", "table": "sessions", "primary_key": "n", "ttl_seconds": 1, "rows": { "n": { "type": "int" } } }"#).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "events", "rows": { "n": 1 } }"#).unwrap();
+        run(&mut db, r#"{ "command": "upsert", "table": "events", "rows": { "n": 2 } }"#).unwrap();
+        db.insert_many("events", vec![HashMap::from([("n".to_string(), json!(3))])]).unwrap();
+        let mut session = crate::session::Session::default();
+        db.execute_in(&mut session, crate::parser::Command::Begin).unwrap();
+        run_in(&mut db, &mut session, r#"{ "command": "insert", "table": "events", "rows": { "n": 4 } }"#);
+        run_in(&mut db, &mut session, r#"{ "command": "batch", "commands": [{ "command": "upsert", "table": "events", "rows": { "n": 5 } }] }"#);
+        db.execute_in(&mut session, crate::parser::Command::Commit).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "sessions", "rows": { "n": 1 } }"#).unwrap();
+        read(&mut db, "events")
+    };
+    assert_eq!(before.len(), 5);
+
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    let mut recovered = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+    assert_eq!(read(&mut recovered, "events"), before);
+    // the row was inserted over a second ago however late the log is replayed
+    assert_eq!(read(&mut recovered, "sessions"), Vec::<Row>::new());
+
+    // inserts log the values their defaults got
+    let wal = std::fs::read_to_string(dir.join("wal.log")).unwrap();
+    assert!(wal.contains(before[0]["id"].as_str().unwrap()));
+}
+
+#[test]
+fn test_a_save_cut_short_reopens_to_one_snapshot() {
+    a_save_cut_short_reopens_to_one_snapshot(MemoryBackend::new());
+    a_save_cut_short_reopens_to_one_snapshot(VecBackend::default());
+}
+
+fn a_save_cut_short_reopens_to_one_snapshot<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("save-cut-short");
+    let mut db = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+    run(&mut db, CREATE).unwrap();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "tags", "primary_key": "id", "rows": { "id": { "type": "int" } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).unwrap();
+    db.save(&dir).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "delete", "type": "table", "table": "tags" }"#).unwrap();
+
+    // fails after every table's files are written, before the save commits
+    let blocker = dir.join("checksums.json.tmp");
+    std::fs::create_dir(&blocker).unwrap();
+    assert!(db.save(&dir).is_err());
+    std::fs::remove_dir(&blocker).unwrap();
+    let mut reopened = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+    assert_eq!(ids(&mut reopened), vec![json!(1), json!(2)]);
+    assert_eq!(reopened.table_names(), vec!["products"]);
+    drop(reopened);
+
+    // "crashes" right after the save committed, before any file moved into place
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 3 } }"#).unwrap();
+    db.prepare_save(&dir, &crate::codec::Codec::default()).unwrap();
+    drop(db);
+    let mut replica = Database::open_read_only_with_backend(&dir, backend.empty()).unwrap();
+    assert_eq!(ids(&mut replica), vec![json!(1), json!(2), json!(3)]);
+    assert!(dir.join("save.json").exists());
+
+    let mut recovered = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+    assert_eq!(ids(&mut recovered), vec![json!(1), json!(2), json!(3)]);
+    assert_eq!(recovered.table_names(), vec!["products"]);
+    assert!(!dir.join("save.json").exists() && !dir.join("tags.schema.json").exists());
+    assert_eq!(std::fs::metadata(dir.join("wal.log")).unwrap().len(), 0);
+    run(&mut recovered, r#"{ "command": "insert", "table": "products", "rows": { "id": 4 } }"#).unwrap();
+    drop(recovered);
+    assert_eq!(ids(&mut Database::open_with_backend(&dir, backend.empty(), None).unwrap()).len(), 4);
+}
+
+#[test]
+fn test_wal_replay_fails_on_a_command_that_no_longer_applies() {
+    wal_replay_fails_on_a_command_that_no_longer_applies(MemoryBackend::new());
+    wal_replay_fails_on_a_command_that_no_longer_applies(VecBackend::default());
+}
+
+fn wal_replay_fails_on_a_command_that_no_longer_applies<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("wal-replay-error");
+    {
+        let mut db = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+        run(&mut db, CREATE).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).unwrap();
+        let log_len = std::fs::metadata(dir.join("wal.log")).unwrap().len();
+        // a command that fails isn't logged
+        assert!(run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).is_err());
+        assert_eq!(std::fs::metadata(dir.join("wal.log")).unwrap().len(), log_len);
+    }
+    assert_eq!(ids(&mut Database::open_with_backend(&dir, backend.empty(), None).unwrap()), vec![json!(1)]);
+
+    // a well-formed record the snapshot doesn't fit, e.g. from another database
+    let record = r#"{"at":0,"seed":0,"command":{"command":"insert","table":"missing","rows":{"id":2}}}"#;
+    let mut wal = OpenOptions::new().append(true).open(dir.join("wal.log")).unwrap();
+    writeln!(wal, "{:016x} {}", crate::utils::checksum(record.as_bytes()), record).unwrap();
+    drop(wal);
+    let error = Database::open_with_backend(&dir, backend.empty(), None).unwrap_err();
+    assert!(matches!(error, StorageError::Replay { record: 3, error: ExecError::TableNotFound(_) }), "{}", error);
+    assert!(Database::open_read_only_with_backend(&dir, backend.empty()).is_err());
+}
+
+#[test]
+fn test_read_only_rejects_mutations() {
+    read_only_rejects_mutations(MemoryBackend::new());
+    read_only_rejects_mutations(VecBackend::default());
+}
+
+fn read_only_rejects_mutations<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("read-only");
+    {
+        let mut db = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+        run(&mut db, CREATE).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).unwrap();
+        db.save(&dir).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2 } }"#).unwrap();
+    }
+    let log_len = std::fs::metadata(dir.join("wal.log")).unwrap().len();
+
+    let mut db = Database::open_read_only_with_backend(&dir, backend.empty()).unwrap();
+    assert!(db.is_read_only());
+    assert!(!Database::with_backend(backend.empty()).is_read_only());
+    assert_eq!(ids(&mut db), vec![json!(1), json!(2)]);
+    for input in [
+        r#"{ "command": "insert", "table": "products", "rows": { "id": 3 } }"#,
+        r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 1", "rows": { "price": 9.5 } }"#,
+        r#"{ "command": "delete", "type": "table", "table": "products" }"#,
+        CREATE,
+    ] {
+        assert_eq!(run(&mut db, input), Err(ExecError::ReadOnly));
+    }
+    assert!(db.save(&dir).is_err());
+
+    // a transaction can't sneak writes in either
+    let mut session = crate::session::Session::default();
+    db.execute_in(&mut session, crate::parser::Command::Begin).unwrap();
+    let insert = serde_json::from_str(r#"{ "command": "insert", "table": "products", "rows": { "id": 3 } }"#).unwrap();
+    assert_eq!(db.execute_in(&mut session, insert), Err(ExecError::ReadOnly));
+
+    assert_eq!(ids(&mut db), vec![json!(1), json!(2)]);
+    assert_eq!(std::fs::metadata(dir.join("wal.log")).unwrap().len(), log_len);
+}
+
+#[test]
+fn test_column_comments_survive_save_and_load() {
+    column_comments_survive_save_and_load(MemoryBackend::new());
+    column_comments_survive_save_and_load(VecBackend::default());
+}
+
+fn column_comments_survive_save_and_load<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("comments");
+    let mut db = Database::with_backend(backend.empty());
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "products", "primary_key": "id", "rows": {
+        "id": { "type": "int" },
+        "price": { "type": "float", "comment": "in cents, before tax" }
+    } }"#).unwrap();
+    db.save(&dir).unwrap();
+
+    let mut loaded = Database::load_with_backend(&dir, backend.empty(), None).unwrap();
+    let Output::Description(description) = run(&mut loaded, r#"{ "command": "describe", "table": "products" }"#).unwrap() else {
+        panic!("Expected Output::Description");
+    };
+    assert_eq!(description.columns["price"].comment.as_deref(), Some("in cents, before tax"));
+    assert_eq!(description.columns["id"].comment, None);
+    assert_eq!(description, db.describe("products").unwrap());
+    let json = serde_json::to_value(&description).unwrap();
+    assert_eq!(json["columns"]["price"]["comment"], json!("in cents, before tax"));
+    assert!(matches!(loaded.describe("missing"), Err(ExecError::TableNotFound(_))));
+}
+
+#[test]
+fn test_verify_detects_a_flipped_byte() {
+    verify_detects_a_flipped_byte(MemoryBackend::new());
+    verify_detects_a_flipped_byte(VecBackend::default());
+}
+
+fn verify_detects_a_flipped_byte<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("verify");
+    let mut db = Database::with_backend(backend.empty());
+    run(&mut db, CREATE).unwrap();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "tags", "primary_key": "id", "rows": { "id": { "type": "int" } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Mango" } }"#).unwrap();
+    db.save(&dir).unwrap();
+    let report = Database::verify(&dir, None).unwrap();
+    assert_eq!(report.tables.values().collect::<Vec<_>>(), vec![&Integrity::Ok, &Integrity::Ok]);
+
+    let data = dir.join("products.data");
+    let mut bytes = std::fs::read(&data).unwrap();
+    let at = bytes.iter().position(|&byte| byte == b'M').unwrap();
+    bytes[at] ^= 0x20;
+    std::fs::write(&data, bytes).unwrap();
+    let report = Database::verify(&dir, None).unwrap();
+    assert_eq!(report.corrupt_tables(), vec!["products"]);
+    assert_eq!(report.tables["tags"], Integrity::Ok);
+
+    let verify = format!(r#"{{ "command": "verify", "dir": {:?}, "table": "tags" }}"#, dir);
+    let Output::Verified(report) = run(&mut db, &verify).unwrap() else { panic!("expected a verify report") };
+    assert_eq!(report.tables.len(), 1);
+    std::fs::remove_file(dir.join("tags.data")).unwrap();
+    assert_eq!(Database::verify(&dir, Some("tags")).unwrap().tables["tags"], Integrity::Missing);
+}
+
+#[test]
+fn test_wal_skips_corrupt_trailing_record() {
+    wal_skips_corrupt_trailing_record(MemoryBackend::new());
+    wal_skips_corrupt_trailing_record(VecBackend::default());
+}
+
+fn wal_skips_corrupt_trailing_record<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("wal-corrupt");
+    {
+        let mut db = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+        run(&mut db, CREATE).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).unwrap();
+    }
+
+    // a record torn by a crash mid-write
+    let mut wal = OpenOptions::new().append(true).open(dir.join("wal.log")).unwrap();
+    wal.write_all(br#"00000000deadbeef {"command":"insert","table":"prod"#).unwrap();
+    drop(wal);
+
+    {
+        let mut recovered = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+        assert_eq!(ids(&mut recovered), vec![json!(1)]);
+        run(&mut recovered, r#"{ "command": "insert", "table": "products", "rows": { "id": 2 } }"#).unwrap();
+    }
+
+    // records logged after recovery are not swallowed by the torn one
+    assert_eq!(ids(&mut Database::open_with_backend(&dir, backend.empty(), None).unwrap()), vec![json!(1), json!(2)]);
+}
+
+#[test]
+fn test_wal_rejects_a_damaged_record_before_the_last() {
+    wal_rejects_a_damaged_record_before_the_last(MemoryBackend::new());
+    wal_rejects_a_damaged_record_before_the_last(VecBackend::default());
+}
+
+fn wal_rejects_a_damaged_record_before_the_last<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("wal-damaged");
+    {
+        let mut db = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+        run(&mut db, CREATE).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2 } }"#).unwrap();
+    }
+    let log = std::fs::read_to_string(dir.join("wal.log")).unwrap();
+    let open = || Database::open_with_backend(&dir, backend.empty(), None);
+
+    // a flipped byte in the middle record
+    let damaged = log.replacen(r#""id":1"#, r#""id":7"#, 1);
+    std::fs::write(dir.join("wal.log"), &damaged).unwrap();
+    let error = open().unwrap_err();
+    assert!(matches!(&error, StorageError::Corrupt { reason, .. } if reason.contains("record 2")), "{}", error);
+    assert!(Database::open_read_only_with_backend(&dir, backend.empty()).is_err());
+
+    // a record whose checksum holds but that isn't one
+    let junk = r#"{"command":"insert"}"#;
+    let lines: Vec<&str> = log.lines().collect();
+    let forged = format!("{}\n{:016x} {}\n{}\n", lines[0], crate::utils::checksum(junk.as_bytes()), junk, lines[2]);
+    std::fs::write(dir.join("wal.log"), forged).unwrap();
+    assert!(matches!(open(), Err(StorageError::Corrupt { .. })));
+
+    // the same damage to the last record is a torn write and is dropped
+    std::fs::write(dir.join("wal.log"), log.replacen(r#""id":2"#, r#""id":7"#, 1)).unwrap();
+    assert_eq!(ids(&mut open().unwrap()), vec![json!(1)]);
+}
+
+#[test]
+fn test_composite_key_survives_save_and_load() {
+    composite_key_survives_save_and_load(MemoryBackend::new());
+    composite_key_survives_save_and_load(VecBackend::default());
+}
+
+fn composite_key_survives_save_and_load<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("composite");
+    let mut db = Database::with_backend(backend.empty());
+    run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "order_lines",
+      "primary_key": ["order_id", "line_no"],
+      "rows": {
+        "order_id": { "type": "int", "not_null": true },
+        "line_no": { "type": "int", "not_null": true }
+      }
+    }
+    "#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "order_lines", "rows": { "order_id": 1, "line_no": 1 } }"#).unwrap();
+    db.save(&dir).unwrap();
+
+    let mut loaded = Database::load_with_backend(&dir, backend.empty(), None).unwrap();
+    let dup = run(&mut loaded, r#"{ "command": "insert", "table": "order_lines", "rows": { "order_id": 1, "line_no": 1 } }"#);
+    assert!(matches!(dup, Err(ExecError::DuplicateKey { .. })));
+}
+
+#[test]
+fn test_encrypted_snapshot_round_trip() {
+    encrypted_snapshot_round_trip(MemoryBackend::new());
+    encrypted_snapshot_round_trip(VecBackend::default());
+}
+
+fn encrypted_snapshot_round_trip<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("encrypted");
+    let key = [7u8; 32];
+    let mut db = Database::with_backend(backend.empty());
+    run(&mut db, CREATE).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Secret Sauce" } }"#).unwrap();
+    db.save_encrypted(&dir, &key).unwrap();
+
+    let data = std::fs::read(dir.join("products.data")).unwrap();
+    assert!(!String::from_utf8_lossy(&data).contains("Secret Sauce"));
+
+    let mut loaded = Database::load_with_backend(&dir, backend.empty(), Some(&key)).unwrap();
+    assert_eq!(ids(&mut loaded), vec![json!(1)]);
+
+    let wrong = Database::load_with_backend(&dir, backend.empty(), Some(&[8u8; 32]));
+    assert!(matches!(wrong, Err(crate::storage::StorageError::Decrypt { .. })));
+    assert!(matches!(Database::load_with_backend(&dir, backend.empty(), None), Err(crate::storage::StorageError::Corrupt { .. })));
+}
+
+#[test]
+fn test_tampered_encrypted_file_is_rejected() {
+    tampered_encrypted_file_is_rejected(MemoryBackend::new());
+    tampered_encrypted_file_is_rejected(VecBackend::default());
+}
+
+fn tampered_encrypted_file_is_rejected<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("tampered");
+    let key = [7u8; 32];
+    let mut db = Database::with_backend(backend.empty());
+    run(&mut db, CREATE).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).unwrap();
+    db.save_encrypted(&dir, &key).unwrap();
+
+    let path = dir.join("products.data");
+    let mut data = std::fs::read(&path).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 1;
+    std::fs::write(&path, data).unwrap();
+
+    let result = Database::load_with_backend(&dir, backend.empty(), Some(&key));
+    assert!(matches!(result, Err(crate::storage::StorageError::Decrypt { file }) if file == path));
+}
+
+#[test]
+fn test_write_ahead_log_and_backups_are_encrypted_with_the_key() {
+    write_ahead_log_and_backups_are_encrypted_with_the_key(MemoryBackend::new());
+    write_ahead_log_and_backups_are_encrypted_with_the_key(VecBackend::default());
+}
+
+fn write_ahead_log_and_backups_are_encrypted_with_the_key<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("encrypted-wal");
+    let backup = temp_dir("encrypted-backup").join("products.backup");
+    let key = [7u8; 32];
+    let plaintext = |path: &std::path::Path| String::from_utf8_lossy(&std::fs::read(path).unwrap()).contains("Secret Sauce");
+    {
+        let mut db = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+        run(&mut db, CREATE).unwrap();
+        db.save_encrypted(&dir, &key).unwrap();
+        // logged from here on, encrypted like the snapshot
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Secret Sauce" } }"#).unwrap();
+        assert!(std::fs::metadata(dir.join("wal.log")).unwrap().len() > 0);
+        assert!(!plaintext(&dir.join("wal.log")));
+        db.backup(&backup).unwrap();
+        assert!(!plaintext(&backup));
+    }
+
+    let mut db = Database::open_with_backend(&dir, backend.empty(), Some(&key)).unwrap();
+    assert_eq!(ids(&mut db), vec![json!(1)]);
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "name": "Secret Sauce" } }"#).unwrap();
+    assert!(!plaintext(&dir.join("wal.log")));
+    assert!(matches!(Database::open_with_backend(&dir, backend.empty(), Some(&[8u8; 32])), Err(StorageError::Decrypt { .. })));
+
+    // a backup restores under any name, but only with the key
+    let renamed = backup.with_extension("old");
+    std::fs::rename(&backup, &renamed).unwrap();
+    run(&mut db, &json!({ "command": "restore", "path": renamed }).to_string()).unwrap();
+    assert_eq!(ids(&mut db), vec![json!(1)]);
+    assert!(matches!(Database::with_backend(backend.empty()).restore(&renamed), Err(StorageError::Corrupt { .. })));
+    drop(db);
+    assert_eq!(ids(&mut Database::open_with_backend(&dir, backend.empty(), Some(&key)).unwrap()), vec![json!(1)]);
+}
+
+#[test]
+fn test_compressed_snapshot_is_smaller_and_loads_identically() {
+    compressed_snapshot_is_smaller_and_loads_identically(MemoryBackend::new());
+    compressed_snapshot_is_smaller_and_loads_identically(VecBackend::default());
+}
+
+fn compressed_snapshot_is_smaller_and_loads_identically<B: StorageBackend>(backend: B) {
+    use crate::storage::Compression;
+
+    let mut db = Database::with_backend(backend.empty());
+    run(&mut db, CREATE).unwrap();
+    for id in 0..200 {
+        let insert = format!(r#"{{ "command": "insert", "table": "products", "rows": {{ "id": {}, "name": "Coconut Water {}" }} }}"#, id, id % 3);
+        run(&mut db, &insert).unwrap();
+    }
+    let plain = temp_dir("uncompressed");
+    db.save(&plain).unwrap();
+    let size = |dir: &std::path::Path| std::fs::metadata(dir.join("products.data")).unwrap().len();
+
+    for compression in [Compression::Gzip, Compression::Zstd] {
+        let dir = temp_dir(&format!("{:?}", compression).to_lowercase());
+        db.save_compressed(&dir, compression).unwrap();
+        assert!(size(&dir) * 4 < size(&plain));
+
+        let mut loaded = Database::load_with_backend(&dir, backend.empty(), None).unwrap();
+        assert_eq!(ids(&mut loaded), ids(&mut db));
+        let all = r#"{ "command": "read", "table": "products" }"#;
+        assert_eq!(run(&mut loaded, all), run(&mut db, all));
+    }
+}
+
+#[test]
+fn test_backup_and_restore_reproduce_the_database() {
+    backup_and_restore_reproduce_the_database(MemoryBackend::new());
+    backup_and_restore_reproduce_the_database(VecBackend::default());
+}
+
+fn backup_and_restore_reproduce_the_database<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("backup");
+    let archive = dir.join("backup.json");
+    let mut db = Database::with_backend(backend.empty());
+    run(&mut db, CREATE).unwrap();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "sessions", "primary_key": "token", "ttl_seconds": 3600, "rows": { "token": { "type": "string" } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "price": 1.5 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Mango", "price": 3.0 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "sessions", "rows": { "token": "abc" } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_name", "column": "name" }"#).unwrap();
+    run(&mut db, r#"{ "command": "create_view", "name": "cheap", "query": { "table": "products", "filter": { "price": { "$lt": 2 } } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create", "type": "user", "username": "ana", "password": "s3cret", "role": "admin" }"#).unwrap();
+    let backup = format!(r#"{{ "command": "backup", "path": {} }}"#, json!(archive));
+    assert_eq!(run(&mut db, &backup), Ok(Output::Done));
+    // users come along, as their password hashes
+    assert!(!std::fs::read_to_string(&archive).unwrap().contains("s3cret"));
+
+    let mut restored = Database::with_backend(backend.empty());
+    let restore = format!(r#"{{ "command": "restore", "path": {} }}"#, json!(archive));
+    run(&mut restored, &restore).unwrap();
+    assert_eq!(restored.table_names(), vec!["_users", "products", "sessions"]);
+    assert_eq!(restored.view_names(), vec!["cheap"]);
+    assert_eq!(restored.authenticate("ana", "s3cret"), Ok("admin".to_string()));
+    for read in [
+        r#"{ "command": "read", "table": "products" }"#,
+        r#"{ "command": "read", "table": "cheap" }"#,
+        r#"{ "command": "read", "table": "sessions" }"#,
+        r#"{ "command": "explain", "query": { "table": "products", "filter": { "name": "Mango" } } }"#,
+        r#"{ "command": "stats" }"#,
+    ] {
+        assert_eq!(run(&mut restored, read), run(&mut db, read), "{}", read);
+    }
+    let ttl = |db: &Database<B>| db.table("sessions").unwrap().inserted_at.values().copied().collect::<Vec<_>>();
+    assert_eq!(ttl(&restored), ttl(&db));
+}
+
+#[test]
+fn test_a_logged_restore_replays_without_its_backup() {
+    a_logged_restore_replays_without_its_backup(MemoryBackend::new());
+    a_logged_restore_replays_without_its_backup(VecBackend::default());
+}
+
+fn a_logged_restore_replays_without_its_backup<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("restore-logged");
+    let backup = temp_dir("restore-logged-backup").join("products.backup");
+    {
+        let mut db = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+        run(&mut db, CREATE).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).unwrap();
+        db.backup(&backup).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2 } }"#).unwrap();
+        run(&mut db, &json!({ "command": "restore", "path": backup }).to_string()).unwrap();
+        assert_eq!(ids(&mut db), vec![json!(1)]);
+        let missing = json!({ "command": "restore", "path": dir.join("missing.backup") });
+        assert!(matches!(run(&mut db, &missing.to_string()), Err(ExecError::Io(_))));
+    }
+
+    // the log holds what was restored, so the backup going away or changing doesn't matter
+    let mut other = Database::new();
+    run(&mut other, CREATE).unwrap();
+    run(&mut other, r#"{ "command": "insert", "table": "products", "rows": { "id": 9 } }"#).unwrap();
+    other.backup(&backup).unwrap();
+    assert_eq!(ids(&mut Database::open_with_backend(&dir, backend.empty(), None).unwrap()), vec![json!(1)]);
+    std::fs::remove_file(&backup).unwrap();
+    assert_eq!(ids(&mut Database::open_with_backend(&dir, backend.empty(), None).unwrap()), vec![json!(1)]);
+}
+
+#[test]
+fn test_restore_rejects_unknown_format_version() {
+    restore_rejects_unknown_format_version(MemoryBackend::new());
+    restore_rejects_unknown_format_version(VecBackend::default());
+}
+
+fn restore_rejects_unknown_format_version<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("backup-version");
+    let archive = dir.join("backup.json");
+    std::fs::write(&archive, r#"{ "format_version": 99, "tables": {}, "views": {} }"#).unwrap();
+
+    let mut db = Database::with_backend(backend.empty());
+    run(&mut db, CREATE).unwrap();
+    let restore = format!(r#"{{ "command": "restore", "path": {} }}"#, json!(archive));
+    let result = run(&mut db, &restore);
+    assert!(matches!(&result, Err(ExecError::Io(reason)) if reason.contains("format version 99")), "{:?}", result);
+    assert_eq!(db.table_names(), vec!["products"]);
+}
+
+#[test]
+fn test_replaying_the_exported_log_reproduces_the_database() {
+    let mut db = Database::new();
+    db.enable_command_log();
+    run(&mut db, CREATE).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Mango", "price": 3.0 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2, "price": 1.5 } }"#).unwrap();
+    // a failing command isn't recorded
+    assert!(run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2 } }"#).is_err());
+    run(&mut db, r#"{ "command": "update", "type": "content", "table": "products", "filter": "id = 2", "rows": { "price": 2.0 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create_view", "name": "cheap", "query": { "table": "products", "filter": { "price": { "$lt": 2.5 } } } }"#).unwrap();
+    db.insert_many("products", vec![Row::from([("id".to_string(), json!(3))])]).unwrap();
+    run(&mut db, r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 1" }"#).unwrap();
+    run(&mut db, r#"{ "command": "read", "table": "products" }"#).unwrap();
+
+    let Output::Commands(log) = run(&mut db, r#"{ "command": "export_log" }"#).unwrap() else {
+        panic!("Expected Output::Commands");
+    };
+    assert_eq!(log.len(), 7);
+    assert_eq!(log[1]["command"], json!("insert"));
+    let Output::Commands(tail) = run(&mut db, r#"{ "command": "export_log", "since": 5 }"#).unwrap() else {
+        panic!("Expected Output::Commands");
+    };
+    assert_eq!(tail, log[5..]);
+
+    let commands = log.into_iter().map(|cmd| serde_json::from_value(cmd).unwrap());
+    let mut replayed = Database::replay(commands).unwrap();
+    assert_eq!(replayed.view_names(), vec!["cheap"]);
+    for read in [
+        r#"{ "command": "read", "table": "products" }"#,
+        r#"{ "command": "read", "table": "cheap" }"#,
+        r#"{ "command": "describe", "table": "products" }"#,
+        r#"{ "command": "export_log" }"#,
+    ] {
+        assert_eq!(run(&mut replayed, read), run(&mut db, read), "{}", read);
+    }
+
+    let disabled = run(&mut Database::new(), r#"{ "command": "export_log" }"#);
+    assert!(matches!(disabled, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_json_document_round_trip() {
+    let mut db = Database::new();
+    run(&mut db, CREATE).unwrap();
+    // sorts before the table it references, so it has to be loaded after it
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "orders", "primary_key": "id", "timestamps": true, "rows": {
+        "id": { "type": "int" },
+        "product": { "type": "int", "references": { "table": "products", "column": "id" } },
+        "total": { "type": "float", "generated": "quantity * 2.5" },
+        "quantity": { "type": "int" }
+    } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "name": "Mango", "price": 3.0 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 2 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "orders", "rows": { "id": 7, "product": 1, "quantity": 4 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "create_index", "table": "products", "name": "by_name", "column": "name" }"#).unwrap();
+
+    let document = db.to_json_document();
+    let parsed: serde_json::Value = serde_json::from_str(&document).unwrap();
+    assert_eq!(parsed["tables"]["products"]["schema"]["primary_key"], json!("id"));
+    assert_eq!(parsed["tables"]["orders"]["rows"][0].get("total"), None);
+
+    let mut loaded = Database::from_json_document(&document).unwrap();
+    assert_eq!(loaded.table_names(), vec!["orders", "products"]);
+    for read in [
+        r#"{ "command": "read", "table": "products" }"#,
+        r#"{ "command": "read", "table": "orders" }"#,
+        r#"{ "command": "describe", "table": "orders" }"#,
+        r#"{ "command": "explain", "query": { "table": "products", "filter": { "name": "Mango" } } }"#,
+    ] {
+        assert_eq!(run(&mut loaded, read), run(&mut db, read), "{}", read);
+    }
+    let reloaded: serde_json::Value = serde_json::from_str(&loaded.to_json_document()).unwrap();
+    assert_eq!(reloaded, parsed);
+}
+
+#[test]
+fn test_json_document_rejects_malformed_fixtures() {
+    let fixture = |table: &str| format!(r#"{{ "tables": {{ "products": {} }} }}"#, table);
+    let schema = r#"{ "primary_key": "id", "columns": { "id": { "type": "int" }, "name": { "type": "string", "not_null": true } } }"#;
+
+    let loaded = Database::from_json_document(&fixture(&format!(r#"{{ "schema": {}, "rows": [{{ "id": 1, "name": "Mango" }}] }}"#, schema)));
+    assert_eq!(loaded.unwrap().table("products").unwrap().len(), 1);
+
+    let wrong_type = fixture(&format!(r#"{{ "schema": {}, "rows": [{{ "id": "one", "name": "Mango" }}] }}"#, schema));
+    assert!(matches!(Database::from_json_document(&wrong_type), Err(ExecError::TypeMismatch { column, .. }) if column == "id"));
+    let missing = fixture(&format!(r#"{{ "schema": {}, "rows": [{{ "id": 1 }}] }}"#, schema));
+    assert!(matches!(Database::from_json_document(&missing), Err(ExecError::NotNull { column }) if column == "name"));
+    let bad_schema = fixture(r#"{ "schema": { "primary_key": "sku", "columns": { "id": { "type": "int" } } }, "rows": [] }"#);
+    assert!(matches!(Database::from_json_document(&bad_schema), Err(ExecError::InvalidSchema { .. })));
+    let dangling = fixture(r#"{ "schema": { "primary_key": "id", "columns": { "id": { "type": "int", "references": { "table": "makers", "column": "id" } } } }, "rows": [] }"#);
+    assert!(matches!(Database::from_json_document(&dangling), Err(ExecError::TableNotFound(table)) if table == "makers"));
+    let no_rows = fixture(&format!(r#"{{ "schema": {} }}"#, schema));
+    assert!(matches!(Database::from_json_document(&no_rows), Err(ExecError::InvalidQuery(reason)) if reason.contains("rows")));
+}
+
+#[test]
+fn test_renamed_table_replaces_its_files() {
+    renamed_table_replaces_its_files(MemoryBackend::new());
+    renamed_table_replaces_its_files(VecBackend::default());
+}
+
+fn renamed_table_replaces_its_files<B: StorageBackend>(backend: B) {
+    let dir = temp_dir("rename");
+    {
+        let mut db = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+        run(&mut db, CREATE).unwrap();
+        run(&mut db, r#"{ "command": "insert", "table": "products", "rows": { "id": 1 } }"#).unwrap();
+        db.save(&dir).unwrap();
+        run(&mut db, r#"{ "command": "update", "type": "rename", "table": "products", "to": "items" }"#).unwrap();
+    }
+
+    // the rename is replayed from the log, then the next snapshot drops the old files
+    let mut db = Database::open_with_backend(&dir, backend.empty(), None).unwrap();
+    assert_eq!(db.table_names(), vec!["items"]);
+    db.save(&dir).unwrap();
+    assert!(!dir.join("products.schema.json").exists() && dir.join("items.schema.json").exists());
+    let items = rows(run(&mut db, r#"{ "command": "read", "table": "items" }"#).unwrap());
+    assert_eq!(items[0]["name"], json!("Unnamed"));
+}
@@ -0,0 +1,122 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use serde_json::json;
+
+use super::{rows, run, temp_dir};
+use crate::database::*;
+
+fn sessions(db: &mut Database) {
+    run(db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "sessions",
+      "primary_key": "id",
+      "ttl_seconds": 1,
+      "rows": {
+        "id": { "type": "int" },
+        "user": { "type": "string" }
+      }
+    }
+    "#).unwrap();
+}
+
+fn ids(db: &mut Database) -> Vec<serde_json::Value> {
+    rows(run(db, r#"{ "command": "read", "table": "sessions" }"#).unwrap())
+        .iter()
+        .map(|row| row["id"].clone())
+        .collect()
+}
+
+#[test]
+fn test_expired_rows_disappear_from_reads() {
+    let mut db = Database::new();
+    sessions(&mut db);
+    run(&mut db, r#"{ "command": "insert", "table": "sessions", "rows": { "id": 1, "user": "ann" } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "sessions", "rows": { "id": 2, "user": "bob" } }"#).unwrap();
+    assert_eq!(ids(&mut db), vec![json!(1), json!(2)]);
+
+    sleep(Duration::from_millis(1100));
+    run(&mut db, r#"{ "command": "insert", "table": "sessions", "rows": { "id": 3, "user": "cy" } }"#).unwrap();
+    assert_eq!(ids(&mut db), vec![json!(3)]);
+    assert_eq!(db.table("sessions").unwrap().len(), 1);
+
+    // expired rows are invisible to updates and deletes, and their keys can be reused
+    let updated = run(&mut db, r#"{ "command": "update", "type": "content", "table": "sessions", "filter": "user = 'ann'", "rows": { "user": "x" } }"#).unwrap();
+    assert_eq!(updated, Output::Affected(0));
+    run(&mut db, r#"{ "command": "insert", "table": "sessions", "rows": { "id": 1, "user": "dee" } }"#).unwrap();
+    assert_eq!(ids(&mut db), vec![json!(1), json!(3)]);
+}
+
+#[test]
+fn test_purge_expired_removes_rows() {
+    let mut db = Database::new();
+    sessions(&mut db);
+    run(&mut db, r#"{ "command": "insert", "table": "sessions", "rows": { "id": 1 } }"#).unwrap();
+    let events = db.subscribe("sessions");
+
+    let purged = run(&mut db, r#"{ "command": "purge_expired", "table": "sessions" }"#).unwrap();
+    assert_eq!(purged, Output::Affected(0));
+
+    sleep(Duration::from_millis(1100));
+    run(&mut db, r#"{ "command": "insert", "table": "sessions", "rows": { "id": 2 } }"#).unwrap();
+    let purged = run(&mut db, r#"{ "command": "purge_expired", "table": "sessions" }"#).unwrap();
+    assert_eq!(purged, Output::Affected(1));
+    assert_eq!(db.table("sessions").unwrap().rows.len(), 1);
+
+    let deleted: Vec<_> = events.try_iter().filter(|event| event.table == "sessions").collect();
+    assert_eq!(deleted.last().unwrap().keys, vec![json!(1)]);
+}
+
+#[test]
+fn test_insertion_time_survives_save_and_load() {
+    let dir = temp_dir("ttl");
+    let mut db = Database::new();
+    sessions(&mut db);
+    run(&mut db, r#"{ "command": "insert", "table": "sessions", "rows": { "id": 1 } }"#).unwrap();
+    db.save(&dir).unwrap();
+
+    let mut loaded = Database::load(&dir).unwrap();
+    assert_eq!(ids(&mut loaded), vec![json!(1)]);
+    assert_eq!(loaded.table("sessions").unwrap().ttl_seconds, Some(1));
+    assert!(!rows(run(&mut loaded, r#"{ "command": "read", "table": "sessions" }"#).unwrap())[0]
+        .contains_key("_inserted_at"));
+
+    sleep(Duration::from_millis(1100));
+    assert!(ids(&mut loaded).is_empty());
+}
+
+#[test]
+fn test_ttl_tables_cannot_be_referenced() {
+    let mut db = Database::new();
+    sessions(&mut db);
+    let result = run(&mut db, r#"
+    {
+      "command": "create",
+      "type": "table",
+      "table": "clicks",
+      "primary_key": "id",
+      "rows": {
+        "id": { "type": "int" },
+        "session": { "type": "int", "references": { "table": "sessions", "column": "id" } }
+      }
+    }
+    "#);
+    assert!(matches!(result, Err(ExecError::InvalidQuery(_))));
+}
+
+#[test]
+fn test_truncate_counts_live_rows() {
+    let mut db = Database::new();
+    sessions(&mut db);
+    run(&mut db, r#"{ "command": "insert", "table": "sessions", "rows": { "id": 1 } }"#).unwrap();
+    sleep(Duration::from_millis(1100));
+    run(&mut db, r#"{ "command": "insert", "table": "sessions", "rows": { "id": 2 } }"#).unwrap();
+    let events = db.subscribe("sessions");
+
+    let truncated = run(&mut db, r#"{ "command": "delete", "type": "truncate", "table": "sessions" }"#);
+    assert_eq!(truncated, Ok(Output::Affected(1)));
+    assert_eq!(events.try_recv().unwrap().keys, vec![json!(2)]);
+    assert_eq!(db.table("sessions").unwrap().rows.len(), 0);
+}
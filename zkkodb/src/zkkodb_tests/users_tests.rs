@@ -0,0 +1,181 @@
+use serde_json::json;
+
+use super::{rows, run, temp_dir};
+use crate::database::*;
+use crate::parser::parse_command;
+use crate::session::Session;
+use crate::users::USERS_TABLE;
+
+const CREATE_ANA: &str = r#"{ "command": "create", "type": "user", "username": "ana", "password": "s3cret", "role": "admin" }"#;
+const CREATE_BO: &str = r#"{ "command": "create", "type": "user", "username": "bo", "password": "hunter2", "role": "staff" }"#;
+const LIST_USERS: &str = r#"{ "command": "list_users" }"#;
+
+fn exec(db: &mut Database, session: &mut Session, input: &str) -> Result<Output, ExecError> {
+    db.execute_in(session, parse_command(input).unwrap())
+}
+
+fn denied(result: Result<Output, ExecError>, reason: &str) -> bool {
+    matches!(&result, Err(ExecError::PermissionDenied(got)) if got.contains(reason))
+}
+
+#[test]
+fn test_create_and_list_users() {
+    let mut db = Database::new();
+    assert_eq!(rows(run(&mut db, LIST_USERS).unwrap()), Vec::<Row>::new());
+    run(&mut db, CREATE_BO).unwrap();
+    run(&mut db, CREATE_ANA).unwrap();
+    assert!(matches!(run(&mut db, CREATE_BO), Err(ExecError::InvalidQuery(reason)) if reason.contains("'bo' already exists")));
+    let no_password = r#"{ "command": "create", "type": "user", "username": "cy", "role": "staff" }"#;
+    assert!(matches!(run(&mut db, no_password), Err(ExecError::InvalidQuery(_))));
+    let empty = r#"{ "command": "create", "type": "user", "username": "cy", "password": "", "role": "staff" }"#;
+    assert!(matches!(run(&mut db, empty), Err(ExecError::InvalidQuery(_))));
+
+    // by username, and never with the password or its hash
+    let users = rows(run(&mut db, LIST_USERS).unwrap());
+    assert_eq!(users, vec![
+        Row::from([("username".to_string(), json!("ana")), ("role".to_string(), json!("admin"))]),
+        Row::from([("username".to_string(), json!("bo")), ("role".to_string(), json!("staff"))]),
+    ]);
+    let stored = db.table(USERS_TABLE).unwrap().rows().next().unwrap().into_owned();
+    assert!(stored["password_hash"].as_str().unwrap().starts_with("$argon2"));
+
+    assert_eq!(db.authenticate("ana", "s3cret"), Ok("admin".to_string()));
+    assert!(matches!(db.authenticate("ana", "hunter2"), Err(ExecError::PermissionDenied(_))));
+    assert!(matches!(db.authenticate("nobody", "s3cret"), Err(ExecError::PermissionDenied(_))));
+    let session = Session::login(&db, "bo", "hunter2").unwrap();
+    assert_eq!((session.user.as_deref(), session.role.as_deref()), (Some("bo"), Some("staff")));
+}
+
+#[test]
+fn test_only_the_admin_manages_users() {
+    let mut db = Database::new();
+    // without an admin user any session may create one
+    let mut anonymous = Session::new();
+    exec(&mut db, &mut anonymous, CREATE_ANA).unwrap();
+    assert!(denied(exec(&mut db, &mut anonymous, CREATE_BO), "admin role"));
+    assert!(denied(exec(&mut db, &mut anonymous, LIST_USERS), "admin role"));
+
+    let mut admin = Session::login(&db, "ana", "s3cret").unwrap();
+    exec(&mut db, &mut admin, CREATE_BO).unwrap();
+    assert_eq!(rows(exec(&mut db, &mut admin, LIST_USERS).unwrap()).len(), 2);
+
+    // the table itself is closed to everyone else, grants or not
+    let mut bo = Session::login(&db, "bo", "hunter2").unwrap();
+    let read = r#"{ "command": "read", "table": "_users" }"#;
+    assert!(denied(exec(&mut db, &mut bo, read), "table '_users' needs the admin role"));
+    let grant = r#"{ "command": "grant", "table": "_users", "privileges": ["read"], "user": "bo" }"#;
+    assert!(matches!(exec(&mut db, &mut admin, grant), Err(ExecError::InvalidQuery(_))));
+    assert_eq!(rows(exec(&mut db, &mut admin, read).unwrap()).len(), 2);
+}
+
+#[test]
+fn test_logs_hold_no_passwords_and_users_survive_a_restart() {
+    let dir = temp_dir("users-wal");
+    {
+        let mut db = Database::open(&dir).unwrap();
+        db.enable_command_log();
+        run(&mut db, CREATE_ANA).unwrap();
+        let mut anonymous = Session::new();
+        let mut admin = Session::login(&db, "ana", "s3cret").unwrap();
+        assert!(denied(exec(&mut db, &mut anonymous, CREATE_BO), "admin role"));
+        exec(&mut db, &mut admin, "{ \"command\": \"begin\" }").unwrap();
+        exec(&mut db, &mut admin, CREATE_BO).unwrap();
+        exec(&mut db, &mut admin, "{ \"command\": \"commit\" }").unwrap();
+        let rotate = r#"{ "command": "update", "type": "user", "username": "ana", "password": "t0psecret" }"#;
+        exec(&mut db, &mut admin, rotate).unwrap();
+        let logged = serde_json::to_string(&db.export_log(0).unwrap()).unwrap();
+        assert!(logged.contains("password_hash") && !logged.contains("s3cret") && !logged.contains("hunter2"));
+        assert!(!logged.contains("t0psecret"));
+    }
+    let wal = std::fs::read_to_string(dir.join("wal.log")).unwrap();
+    assert!(wal.contains("$argon2") && !wal.contains("s3cret") && !wal.contains("hunter2") && !wal.contains("t0psecret"));
+
+    let db = Database::open(&dir).unwrap();
+    assert_eq!(db.authenticate("ana", "t0psecret"), Ok("admin".to_string()));
+    assert_eq!(db.authenticate("bo", "hunter2"), Ok("staff".to_string()));
+}
+
+#[test]
+fn test_alter_user_password_and_role() {
+    let mut db = Database::new();
+    run(&mut db, CREATE_ANA).unwrap();
+    run(&mut db, CREATE_BO).unwrap();
+    let (mut admin, mut bo) = (Session::login(&db, "ana", "s3cret").unwrap(), Session::login(&db, "bo", "hunter2").unwrap());
+
+    // users may change their own password, not their role or anyone else
+    let own_password = r#"{ "command": "update", "type": "user", "username": "bo", "password": "correct horse" }"#;
+    exec(&mut db, &mut bo, own_password).unwrap();
+    assert!(db.authenticate("bo", "hunter2").is_err());
+    assert_eq!(db.authenticate("bo", "correct horse"), Ok("staff".to_string()));
+    let promote_bo = r#"{ "command": "update", "type": "user", "username": "bo", "role": "admin" }"#;
+    assert!(denied(exec(&mut db, &mut bo, promote_bo), "admin role"));
+    let ana_password = r#"{ "command": "update", "type": "user", "username": "ana", "password": "mine now" }"#;
+    assert!(denied(exec(&mut db, &mut bo, ana_password), "admin role"));
+    assert_eq!(db.authenticate("ana", "s3cret"), Ok("admin".to_string()));
+
+    let nothing = r#"{ "command": "update", "type": "user", "username": "bo" }"#;
+    assert!(matches!(exec(&mut db, &mut admin, nothing), Err(ExecError::InvalidQuery(_))));
+    let unknown = r#"{ "command": "update", "type": "user", "username": "cy", "role": "staff" }"#;
+    assert!(matches!(exec(&mut db, &mut admin, unknown), Err(ExecError::InvalidQuery(reason)) if reason.contains("does not exist")));
+
+    // the last admin keeps the role
+    let demote_ana = r#"{ "command": "update", "type": "user", "username": "ana", "role": "staff" }"#;
+    assert!(matches!(exec(&mut db, &mut admin, demote_ana), Err(ExecError::InvalidQuery(reason)) if reason.contains("last admin")));
+    exec(&mut db, &mut admin, promote_bo).unwrap();
+    exec(&mut db, &mut admin, demote_ana).unwrap();
+    assert_eq!(db.authenticate("ana", "s3cret"), Ok("staff".to_string()));
+    assert_eq!(db.authenticate("bo", "correct horse"), Ok("admin".to_string()));
+}
+
+#[test]
+fn test_delete_user_keeps_the_last_admin_and_drops_their_grants() {
+    let mut db = Database::new();
+    run(&mut db, CREATE_ANA).unwrap();
+    run(&mut db, CREATE_BO).unwrap();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "notes", "primary_key": "id", "rows": { "id": { "type": "int" } } }"#).unwrap();
+    for grant in [
+        r#"{ "command": "grant", "table": "notes", "privileges": ["read", "insert"], "user": "bo" }"#,
+        r#"{ "command": "grant", "table": "notes", "privileges": ["read"], "role": "bo" }"#,
+    ] {
+        run(&mut db, grant).unwrap();
+    }
+    let (mut admin, mut bo) = (Session::login(&db, "ana", "s3cret").unwrap(), Session::login(&db, "bo", "hunter2").unwrap());
+
+    let delete_ana = r#"{ "command": "delete", "type": "user", "username": "ana" }"#;
+    let delete_bo = r#"{ "command": "delete", "type": "user", "username": "bo" }"#;
+    assert!(denied(exec(&mut db, &mut bo, delete_bo), "admin role"));
+    assert!(matches!(exec(&mut db, &mut admin, delete_ana), Err(ExecError::InvalidQuery(reason)) if reason.contains("last admin")));
+    exec(&mut db, &mut admin, delete_bo).unwrap();
+    assert!(matches!(exec(&mut db, &mut admin, delete_bo), Err(ExecError::InvalidQuery(reason)) if reason.contains("does not exist")));
+    assert!(db.authenticate("bo", "hunter2").is_err());
+    let users: Vec<_> = rows(run(&mut db, LIST_USERS).unwrap()).iter().map(|user| user["username"].clone()).collect();
+    assert_eq!(users, vec![json!("ana")]);
+    // only the grants to the user go, not those to a role of the same name
+    let grants = rows(run(&mut db, r#"{ "command": "read", "table": "_grants" }"#).unwrap());
+    assert_eq!(grants.len(), 1);
+    assert_eq!(grants[0]["kind"], json!("role"));
+}
+
+#[test]
+fn test_backup_restore_and_verify_need_the_admin() {
+    let dir = temp_dir("users-backup");
+    let mut db = Database::new();
+    run(&mut db, CREATE_ANA).unwrap();
+    run(&mut db, CREATE_BO).unwrap();
+    let (mut admin, mut bo) = (Session::login(&db, "ana", "s3cret").unwrap(), Session::login(&db, "bo", "hunter2").unwrap());
+    let path = json!(dir.join("backup.json"));
+    let backup = format!(r#"{{ "command": "backup", "path": {} }}"#, path);
+    let restore = format!(r#"{{ "command": "restore", "path": {} }}"#, path);
+    let verify = format!(r#"{{ "command": "verify", "dir": {} }}"#, json!(dir));
+    // the client names the path, so even without grants these are the admin's
+    for cmd in [&backup, &restore, &verify] {
+        assert!(denied(exec(&mut db, &mut bo, cmd), "need the admin role"), "{}", cmd);
+        assert!(denied(exec(&mut db, &mut Session::new(), cmd), "need the admin role"), "{}", cmd);
+    }
+    exec(&mut db, &mut admin, &backup).unwrap();
+    exec(&mut db, &mut admin, &verify).unwrap();
+
+    run(&mut db, r#"{ "command": "delete", "type": "user", "username": "bo" }"#).unwrap();
+    exec(&mut db, &mut admin, &restore).unwrap();
+    assert_eq!(db.authenticate("bo", "hunter2"), Ok("staff".to_string()));
+}
@@ -0,0 +1,84 @@
+use serde_json::json;
+
+use super::{rows, run};
+use crate::database::*;
+use crate::result::ColumnType;
+use crate::value::{json_row, Value};
+
+fn prices() -> Database {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "prices", "primary_key": "id", "rows": {
+        "id": { "type": "int" }, "amount": { "type": "float" }, "label": { "type": "string" }, "meta": { "type": "json" }
+    } }"#).unwrap();
+    db
+}
+
+#[test]
+fn test_ints_and_floats_keep_their_type_through_a_typed_row() {
+    let mut db = prices();
+    run(&mut db, r#"{ "command": "insert", "table": "prices", "rows": { "id": 22, "amount": 22.19, "label": "a", "meta": [1, 2] } }"#).unwrap();
+    let row = rows(run(&mut db, r#"{ "command": "read", "table": "prices" }"#).unwrap()).remove(0);
+
+    let typed = db.table("prices").unwrap().typed_row("prices", &row).unwrap();
+    assert!(matches!(typed["id"], Value::Int(22)));
+    assert!(matches!(typed["amount"], Value::Float(amount) if amount == 22.19));
+    assert_eq!(typed["label"], Value::Text("a".to_string()));
+    assert_eq!(typed["meta"], Value::Json(json!([1, 2])));
+    assert_eq!(json_row(&typed), row);
+    assert_eq!(json_row(&typed)["id"].to_string(), "22");
+
+    // the column type decides, not the JSON's shape
+    assert!(matches!(Value::from_json(ColumnType::Float, &json!(22)), Some(Value::Float(_))));
+    assert_eq!(Value::from_json(ColumnType::Int, &json!(22.19)), None);
+    assert_eq!(Value::from_json(ColumnType::String, &json!(null)), Some(Value::Null));
+}
+
+#[test]
+fn test_typed_values_order_and_aggregate_by_type() {
+    let mut values = vec![Value::Text("b".to_string()), Value::Float(1.5), Value::Null, Value::Int(1), Value::Bool(true)];
+    values.sort();
+    assert_eq!(values, vec![Value::Null, Value::Bool(true), Value::Int(1), Value::Float(1.5), Value::Text("b".to_string())]);
+    assert_eq!(Value::Int(2), Value::Float(2.0));
+
+    let mut db = prices();
+    run(&mut db, r#"{ "command": "insert", "table": "prices", "rows": { "id": 1, "amount": 2 } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "prices", "rows": { "id": 2, "amount": 3 } }"#).unwrap();
+    let totals = rows(run(&mut db, r#"{ "command": "read", "table": "prices", "aggregates": [
+        { "function": "sum", "column": "id" }, { "function": "sum", "column": "amount" }, { "function": "max", "column": "amount" }
+    ] }"#).unwrap());
+    // a float column sums to a float even when every value is whole
+    assert_eq!(totals[0]["sum(id)"], json!(3));
+    assert_eq!(totals[0]["sum(amount)"], json!(5.0));
+    assert_eq!(totals[0]["max(amount)"], json!(3.0));
+}
+
+#[test]
+fn test_filters_and_sorts_compare_typed_values() {
+    let mut db = prices();
+    for (id, amount, label) in [(1, "2", "b"), (2, "1.5", "a"), (3, "null", "c"), (4, "10", "B")] {
+        let insert = format!(r#"{{ "command": "insert", "table": "prices", "rows": {{ "id": {}, "amount": {}, "label": "{}" }} }}"#, id, amount, label);
+        run(&mut db, &insert).unwrap();
+    }
+    let ids = |db: &mut Database, read: &str| -> Vec<serde_json::Value> {
+        rows(run(db, read).unwrap()).iter().map(|row| row["id"].clone()).collect()
+    };
+
+    // a float operand on an int column and an int one on a float column
+    assert_eq!(ids(&mut db, r#"{ "command": "read", "table": "prices", "filter": { "id": { "$gt": 1.5, "$lt": 4 } } }"#), vec![json!(2), json!(3)]);
+    assert_eq!(ids(&mut db, r#"{ "command": "read", "table": "prices", "filter": { "amount": { "$in": [2, 10.0] } } }"#), vec![json!(1), json!(4)]);
+    assert_eq!(ids(&mut db, r#"{ "command": "read", "table": "prices", "filter": { "amount": { "$between": [1, 2] } } }"#), vec![json!(1), json!(2)]);
+    // text doesn't compare with numbers, nulls not at all
+    assert_eq!(ids(&mut db, r#"{ "command": "read", "table": "prices", "filter": { "label": { "$gt": 1 } } }"#), Vec::<serde_json::Value>::new());
+    assert_eq!(ids(&mut db, r#"{ "command": "read", "table": "prices", "filter": { "amount": { "$ne": 2 } } }"#), vec![json!(2), json!(4)]);
+
+    // nulls first, then by value whether the JSON held an int or a float
+    let sorted = r#"{ "command": "read", "table": "prices", "order_by": [{ "column": "amount" }] }"#;
+    assert_eq!(ids(&mut db, sorted), vec![json!(3), json!(2), json!(1), json!(4)]);
+    let by_label = r#"{ "command": "read", "table": "prices", "order_by": [{ "column": "label", "direction": "desc" }] }"#;
+    assert_eq!(ids(&mut db, by_label), vec![json!(3), json!(1), json!(2), json!(4)]);
+
+    assert_eq!(Value::of(Some(ColumnType::Int), &json!(1.5)), Value::Float(1.5));
+    assert_eq!(Value::of(Some(ColumnType::Json), &json!("a")), Value::Text("a".to_string()));
+    assert_eq!(Value::Text("1".to_string()).compare_same_kind(&Value::Int(1)), None);
+    assert_eq!(Value::Int(2).compare_same_kind(&Value::Float(1.5)), Some(std::cmp::Ordering::Greater));
+}
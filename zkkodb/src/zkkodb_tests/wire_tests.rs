@@ -0,0 +1,69 @@
+use serde_json::json;
+
+use super::run;
+use crate::database::*;
+use crate::parser::Command;
+use crate::wire::WireFormat;
+
+const COMMANDS: [&str; 4] = [
+    r#"{ "command": "create", "type": "table", "table": "products", "primary_key": ["id", "sku"], "rows": { "id": { "type": "int" }, "sku": { "type": "string", "default": "none" } } }"#,
+    r#"{ "command": "insert", "table": "products", "rows": { "id": 1, "sku": "A-1", "price": 2.5, "tags": ["x", null] } }"#,
+    r#"{ "command": "read", "table": "products", "filter": { "price": { "$between": [1, 3] } }, "limit": 10 }"#,
+    r#"{ "command": "delete", "type": "content", "table": "products", "filter": "id = 1" }"#,
+];
+
+#[test]
+fn test_messagepack_round_trip_matches_json() {
+    for input in COMMANDS {
+        let from_json = WireFormat::Json.decode_command(input.as_bytes()).unwrap();
+        let bytes = WireFormat::MessagePack.encode(&from_json).unwrap();
+        let from_msgpack: Command = WireFormat::MessagePack.decode_command(&bytes).unwrap();
+        assert_eq!(
+            serde_json::to_value(&from_msgpack).unwrap(),
+            serde_json::to_value(&from_json).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_encode_output() {
+    let mut db = Database::new();
+    run(&mut db, r#"{ "command": "create", "type": "table", "table": "t", "primary_key": "id", "rows": { "id": { "type": "int" } } }"#).unwrap();
+    run(&mut db, r#"{ "command": "insert", "table": "t", "rows": { "id": 7 } }"#).unwrap();
+    let output = run(&mut db, r#"{ "command": "read", "table": "t" }"#).unwrap();
+
+    let bytes = WireFormat::MessagePack.encode(&output).unwrap();
+    let decoded: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+    assert_eq!(decoded, json!([{ "id": 7 }]));
+    assert_eq!(WireFormat::Json.encode(&output).unwrap(), br#"[{"id":7}]"#);
+}
+
+#[test]
+fn test_format_from_content_type() {
+    assert_eq!(WireFormat::from_content_type("application/msgpack"), Some(WireFormat::MessagePack));
+    assert_eq!(WireFormat::from_content_type("application/json; charset=utf-8"), Some(WireFormat::Json));
+    assert_eq!(WireFormat::from_content_type("text/plain"), None);
+    assert!(WireFormat::MessagePack.decode_command(b"\xc1").is_err());
+}
+
+#[test]
+fn test_messagepack_rejects_non_finite_floats() {
+    #[derive(serde::Serialize)]
+    struct Insert {
+        command: &'static str,
+        table: &'static str,
+        rows: std::collections::BTreeMap<&'static str, f64>,
+    }
+    let insert = |price: f64| Insert { command: "insert", table: "products", rows: [("id", 1.0), ("price", price)].into() };
+
+    for price in [f64::NAN, f64::INFINITY] {
+        let bytes = rmp_serde::to_vec_named(&insert(price)).unwrap();
+        let decoded = WireFormat::MessagePack.decode_command(&bytes);
+        assert!(matches!(&decoded, Err(err) if err.to_string().contains("not a finite number")), "{:?}", decoded);
+    }
+    let bytes = rmp_serde::to_vec_named(&insert(2.5)).unwrap();
+    let Command::Insert(insert) = WireFormat::MessagePack.decode_command(&bytes).unwrap() else {
+        panic!("Expected Command::Insert");
+    };
+    assert_eq!(insert.rows["price"], json!(2.5));
+}